@@ -1,11 +1,14 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
 use anyhow::{Context, Result, anyhow};
 use openmls_sqlx_storage::SqliteStorageProvider;
 use serde::{Deserialize, Serialize};
 use svalin_pki::{
-    EncryptedCredential, ExactVerififier, TrustStoreVerifier, UnverifiedCertificate, Verifier,
-    get_current_timestamp, mls::provider::PostcardCodec,
+    Credential, EncryptedCredential, EncryptedKeyPair, ExactVerififier, TrustStoreVerifier,
+    UnverifiedCertificate, Verifier, get_current_timestamp, mls::provider::PostcardCodec,
 };
 use svalin_rpc::{
     commands::{deauthenticate::DeauthenticateHandler, e2e::E2EHandler, ping::PingHandler},
@@ -13,21 +16,32 @@ use svalin_rpc::{
         client::RpcClient,
         command::handler::HandlerCollection,
         connection::{Connection, ServeableConnectionBase},
+        transport_config::QuicTransportConfig,
     },
 };
 use svalin_store::agent_store::AgentStore;
-use tokio::sync::Notify;
+use tokio::sync::{Notify, mpsc};
 use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use tracing::instrument;
 
+pub(crate) mod alerts;
 mod init;
 mod mls;
+mod renewal;
 // pub mod update;
+mod witness;
 
-pub use init::init;
+pub use init::{init, init_with_key_source};
+pub use renewal::prepare_key_rotation;
+pub use witness::run_witness;
 
+use crate::agent::alerts::AlertRules;
 use crate::util::location::{Location, LocationError};
-use crate::util::{key_storage::KeySource, trust_store::save_trust_store};
+use crate::util::{
+    key_storage::{KeySource, KeySourceConfig},
+    trust_store::save_trust_store,
+    witness::WitnessPolicy,
+};
 use crate::{
     client::tunnel_manager::tcp::handler::TcpForwardHandler,
     message_streaming::agent::AgentMessageDispatcher,
@@ -35,6 +49,7 @@ use crate::{
 use crate::{
     installer,
     shared::commands::{
+        compare_chain_position::CompareChainPositionHandler,
         realtime_status::RealtimeStatusHandler, request_system_report::RequestSystemReportHandler,
     },
 };
@@ -42,7 +57,10 @@ use crate::{
     message_streaming::agent::AgentMessageReceiver,
     permissions::default_permission_handler::DefaultPermissionHandler,
 };
-use crate::{mls::MlsAgent, remote_key_retriever::RemoteKeyRetriever};
+use crate::{
+    mls::{KeyUpdateInterval, MlsAgent},
+    remote_key_retriever::RemoteKeyRetriever,
+};
 use crate::{
     shared::commands::{terminal::RemoteTerminalHandler, update_agent::UpdateAgentHandler},
     util::trust_store::update_trust_store,
@@ -64,32 +82,13 @@ pub async fn run(cancel: CancellationToken) -> Result<()> {
 
     let credentials = config
         .key_source
-        .decrypt_credentials(config.encrypted_credentials)
+        .decrypt_credentials(config.encrypted_credentials.clone())
         .await
         .context("error decrypting credentials")?;
 
-    // tracing::trace!("building upstream verifier");
-    let root_certificate = config.root_certificate.use_as_root()?;
-
-    let upstream_certificate = config
-        .upstream_certificate
-        .verify_signature(&root_certificate, get_current_timestamp())
-        .context("error verifying upstream certificate")?;
-
-    let verifier = ExactVerififier::new(upstream_certificate).to_tls_verifier();
-
-    tracing::trace!("trying to connect to server");
-
-    let rpc = RpcClient::connect(
-        &config.upstream_address,
-        Some(&credentials),
-        verifier,
-        cancel.clone(),
-    )
-    .await
-    .context("error connecting rpc")?;
+    let root_certificate = config.root_certificate.clone().use_as_root()?;
 
-    tracing::trace!("connection to server established");
+    let rpc = connect_upstream(&config, &credentials, cancel.clone()).await?;
 
     let tasks = TaskTracker::new();
 
@@ -105,6 +104,8 @@ pub async fn run(cancel: CancellationToken) -> Result<()> {
         trust_store.clone(),
         agent_store.transaction_store().clone(),
         rpc.upstream_connection(),
+        config.upstream_certificate.clone(),
+        config.witnesses,
         cancel.clone(),
         &tasks,
     )
@@ -115,7 +116,14 @@ pub async fn run(cancel: CancellationToken) -> Result<()> {
     let key_retriever =
         RemoteKeyRetriever::new(rpc.upstream_connection(), root_certificate.clone());
 
-    let verifier = TrustStoreVerifier::new(trust_store);
+    tasks.spawn(renewal::manage_renewal(
+        credentials.clone(),
+        trust_store.clone(),
+        rpc.upstream_connection(),
+        cancel.clone(),
+    ));
+
+    let verifier = TrustStoreVerifier::new(trust_store.clone());
 
     let mls = Arc::new(
         MlsAgent::new(
@@ -143,7 +151,12 @@ pub async fn run(cancel: CancellationToken) -> Result<()> {
         .add(UpdateAgentHandler::new())
         .add(RequestSystemReportHandler {
             notify: system_report_notify.clone(),
-        });
+        })
+        .add(CompareChainPositionHandler::new(
+            trust_store.clone(),
+            agent_store.transaction_store().clone(),
+            credentials.clone(),
+        ));
 
     let public_commands = HandlerCollection::new(permission_handler.clone());
 
@@ -171,9 +184,12 @@ pub async fn run(cancel: CancellationToken) -> Result<()> {
         }
     });
 
+    let (instruction_sender, instructions) = mpsc::channel(10);
+
     let receiver = AgentMessageReceiver {
         cancel: cancel.clone(),
         mls: mls.clone(),
+        instructions: instruction_sender,
         sender: messager_handle.clone(),
     };
 
@@ -194,6 +210,47 @@ pub async fn run(cancel: CancellationToken) -> Result<()> {
 
     mls::ensure_group_exists(&mls, &messager_handle).await?;
 
+    tasks.spawn(mls::replenish_key_packages(
+        mls.clone(),
+        connection.clone(),
+        messager_handle.clone(),
+        cancel.clone(),
+    ));
+
+    tasks.spawn(mls::schedule_chain_positions(
+        mls.clone(),
+        credentials.clone(),
+        trust_store,
+        messager_handle.clone(),
+        cancel.clone(),
+    ));
+
+    tasks.spawn(mls::remove_revoked_members(
+        mls.clone(),
+        messager_handle.clone(),
+        cancel.clone(),
+    ));
+
+    tasks.spawn(mls::schedule_key_updates(
+        mls.clone(),
+        messager_handle.clone(),
+        config.key_update_interval,
+        cancel.clone(),
+    ));
+
+    tasks.spawn(alerts::watch_alerts(
+        mls.clone(),
+        messager_handle.clone(),
+        config.alert_rules.clone(),
+        cancel.clone(),
+    ));
+
+    tasks.spawn(mls::execute_instructions(
+        instructions,
+        system_report_notify.clone(),
+        cancel.clone(),
+    ));
+
     tasks.spawn(mls::schedule_system_reports(
         mls,
         messager_handle,
@@ -211,6 +268,70 @@ pub async fn run(cancel: CancellationToken) -> Result<()> {
     Ok(())
 }
 
+async fn connect_upstream(
+    config: &AgentConfig,
+    credentials: &Credential,
+    cancel: CancellationToken,
+) -> Result<RpcClient> {
+    // tracing::trace!("building upstream verifier");
+    let root_certificate = config.root_certificate.clone().use_as_root()?;
+
+    let upstream_certificate = config
+        .upstream_certificate
+        .clone()
+        .verify_signature(&root_certificate, get_current_timestamp())
+        .context("error verifying upstream certificate")?;
+
+    let verifier = ExactVerififier::new(upstream_certificate).to_tls_verifier();
+
+    tracing::trace!("trying to connect to server");
+
+    // a partial transport section keeps the client defaults for the rest
+    let transport = config
+        .transport
+        .clone()
+        .or(QuicTransportConfig::client_default());
+
+    let rpc = RpcClient::connect_with_transport(
+        &config.upstream_address,
+        Some(credentials),
+        verifier,
+        &transport,
+        cancel,
+    )
+    .await
+    .context("error connecting rpc")?;
+
+    tracing::trace!("connection to server established");
+
+    Ok(rpc)
+}
+
+/// Sets the witnesses which have to countersign new trust store heads, the
+/// agent has to be restarted to use them.
+pub async fn set_witness_policy(witnesses: WitnessPolicy) -> Result<()> {
+    witnesses.validate()?;
+
+    let mut config = get_config()
+        .await?
+        .ok_or_else(|| anyhow!("agent is not yet initialized"))?;
+    config.witnesses = witnesses;
+    save_config(&config).await
+}
+
+/// Forgets the trust store conflicts other members reported to the agent, once
+/// an admin made sure the server presents the same history to everyone again.
+pub async fn reset_chain_conflict() -> Result<()> {
+    let agent_store = AgentStore::open(data_dir()?.push("agent-store.sqlite")).await?;
+    agent_store
+        .transaction_store()
+        .reset_conflict_reports()
+        .await?;
+    agent_store.close_handle().close().await;
+
+    Ok(())
+}
+
 async fn cleanup_on_start() -> anyhow::Result<()> {
     tracing::trace!("deleting temp files");
     let temp_dir = temp_dir()?;
@@ -223,8 +344,12 @@ async fn cleanup_on_start() -> anyhow::Result<()> {
     Ok(())
 }
 
-pub async fn init_with(data: AgentInitPayload) -> Result<()> {
-    let key_source = KeySource::generate_builtin()?;
+pub async fn init_with(data: AgentInitPayload, key_source: &KeySourceConfig) -> Result<()> {
+    if get_config().await?.is_some() {
+        return Err(anyhow!("Agent configuration already exists"));
+    }
+
+    let key_source = key_source.generate().await?;
 
     let config = AgentConfig {
         root_certificate: data.root.to_unverified(),
@@ -232,12 +357,13 @@ pub async fn init_with(data: AgentInitPayload) -> Result<()> {
         encrypted_credentials: key_source.encrypt_credential(&data.credentials).await?,
         upstream_address: data.address,
         key_source,
+        transport: QuicTransportConfig::client_default(),
+        pending_key: None,
+        witnesses: WitnessPolicy::default(),
+        key_update_interval: KeyUpdateInterval::default(),
+        alert_rules: AlertRules::default(),
     };
 
-    if get_config().await?.is_some() {
-        return Err(anyhow!("Agent configuration already exists"));
-    }
-
     save_config(&config).await?;
 
     save_trust_store(trust_store_path()?.as_path(), &data.trust_store).await?;
@@ -245,8 +371,43 @@ pub async fn init_with(data: AgentInitPayload) -> Result<()> {
     Ok(())
 }
 
+/// Name of the data directory, only set for witnesses.
+static DATA_DIR_NAME: OnceLock<&'static str> = OnceLock::new();
+
+/// Keeps the configuration, credentials and stores of this process in the
+/// witness data directory, so a witness and an agent on the same machine
+/// don't share their identity or trust store.
+///
+/// Has to be called before anything else touches the data directory.
+pub fn use_witness_data_dir() {
+    let _ = DATA_DIR_NAME.set("witness");
+}
+
+/// The data directory of the agent.
+///
+/// Older versions used a nested agent directory, which is still used if it
+/// contains a configuration and the new location doesn't.
 pub fn data_dir() -> Result<Location, LocationError> {
-    Ok(Location::system_data_dir()?.push("agent"))
+    if let Some(name) = DATA_DIR_NAME.get() {
+        return Ok(Location::system_data_dir()?.push(*name));
+    }
+
+    let data_dir = Location::system_data_dir()?.push("agent");
+
+    #[cfg(target_os = "linux")]
+    {
+        let legacy = data_dir.clone().push("agent");
+        if !data_dir.clone().push("config.json").as_path().exists()
+            && legacy.clone().push("config.json").as_path().exists()
+        {
+            tracing::warn!(
+                "using legacy agent data directory {legacy}, consider moving it to {data_dir}"
+            );
+            return Ok(legacy);
+        }
+    }
+
+    Ok(data_dir)
 }
 
 pub fn temp_dir() -> Result<Location, LocationError> {
@@ -300,6 +461,20 @@ struct AgentConfig {
     root_certificate: UnverifiedCertificate,
     encrypted_credentials: EncryptedCredential,
     key_source: KeySource,
+    #[serde(default = "QuicTransportConfig::client_default")]
+    transport: QuicTransportConfig,
+    /// A new key waiting for its certificate, see [`prepare_key_rotation`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pending_key: Option<EncryptedKeyPair>,
+    /// Witnesses which have to countersign new trust store heads.
+    #[serde(default, skip_serializing_if = "WitnessPolicy::is_disabled")]
+    witnesses: WitnessPolicy,
+    /// How often the agent commits a new key to its device group.
+    #[serde(default, skip_serializing_if = "KeyUpdateInterval::is_default")]
+    key_update_interval: KeyUpdateInterval,
+    /// When the agent alerts the users of its device group.
+    #[serde(default, skip_serializing_if = "AlertRules::is_default")]
+    alert_rules: AlertRules,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -0,0 +1,166 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use futures::{FutureExt, select};
+use serde::{Deserialize, Serialize};
+use svalin_pki::get_current_timestamp;
+use svalin_store::client_store::persistent::{AlertKind, AlertSeverity, SvalinAlert};
+use svalin_sysctl::health::HealthStatus;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    message_streaming::{MessageFromAgent, agent::AgentMessageDispatcherHandle},
+    mls::MlsAgent,
+};
+
+/// The rules the agent checks periodically, an alert is sent to the device
+/// group as soon as one of them is triggered.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct AlertRules {
+    /// Alerts once less than this share of a disk is available, in percent.
+    pub min_free_disk_percent: u8,
+    /// Alerts once the five minute load average per cpu thread exceeds this.
+    pub max_load_per_thread: f64,
+    /// Processes which have to be running, alerts once one of them is gone.
+    pub services: Vec<String>,
+    pub check_interval_secs: u64,
+}
+
+impl Default for AlertRules {
+    fn default() -> Self {
+        Self {
+            min_free_disk_percent: 10,
+            max_load_per_thread: 2.0,
+            services: Vec::new(),
+            check_interval_secs: 60,
+        }
+    }
+}
+
+impl AlertRules {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// What an alert was raised for, each condition is only alerted once until
+/// it clears again.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Condition {
+    Disk(String),
+    Service(String),
+    Load,
+}
+
+pub(crate) struct AlertWatcher {
+    rules: AlertRules,
+    active: HashSet<Condition>,
+}
+
+impl AlertWatcher {
+    pub fn new(rules: AlertRules) -> Self {
+        Self {
+            rules,
+            active: HashSet::new(),
+        }
+    }
+
+    pub fn rules(&self) -> &AlertRules {
+        &self.rules
+    }
+
+    /// Returns alerts for the conditions which started since the last check.
+    pub fn check(&mut self, status: &HealthStatus, now: u64) -> Vec<SvalinAlert> {
+        let mut triggered = Vec::new();
+
+        for disk in status.disks.iter().filter(|disk| disk.can_fill_up()) {
+            let free_percent = disk.available_space as f64 / disk.total_space as f64 * 100.0;
+            if free_percent < self.rules.min_free_disk_percent as f64 {
+                triggered.push((
+                    Condition::Disk(disk.mount_point.clone()),
+                    AlertSeverity::Warning,
+                    AlertKind::DiskAlmostFull {
+                        mount_point: disk.mount_point.clone(),
+                        available_space: disk.available_space,
+                        total_space: disk.total_space,
+                    },
+                ));
+            }
+        }
+
+        for service in &self.rules.services {
+            if !status.running.contains(service) {
+                triggered.push((
+                    Condition::Service(service.clone()),
+                    AlertSeverity::Critical,
+                    AlertKind::ServiceDown {
+                        service: service.clone(),
+                    },
+                ));
+            }
+        }
+
+        if status.threads > 0
+            && status.load_average / status.threads as f64 > self.rules.max_load_per_thread
+        {
+            triggered.push((
+                Condition::Load,
+                AlertSeverity::Warning,
+                AlertKind::HighLoad {
+                    load_average: status.load_average,
+                    threads: status.threads,
+                },
+            ));
+        }
+
+        // cleared conditions can be alerted again
+        let current: HashSet<Condition> = triggered
+            .iter()
+            .map(|(condition, _, _)| condition.clone())
+            .collect();
+        let previous = std::mem::replace(&mut self.active, current);
+
+        triggered
+            .into_iter()
+            .filter(|(condition, _, _)| !previous.contains(condition))
+            .map(|(_, severity, kind)| SvalinAlert {
+                id: uuid::Uuid::new_v4(),
+                raised_at: now,
+                severity,
+                kind,
+            })
+            .collect()
+    }
+}
+
+/// Checks the alert rules and sends every new alert to the device group
+/// right away.
+pub(super) async fn watch_alerts(
+    mls: Arc<MlsAgent>,
+    messager_handle: AgentMessageDispatcherHandle,
+    rules: AlertRules,
+    cancel: CancellationToken,
+) {
+    let interval = Duration::from_secs(rules.check_interval_secs);
+    let mut watcher = AlertWatcher::new(rules);
+
+    loop {
+        match HealthStatus::collect(watcher.rules().services.clone()).await {
+            Ok(status) => {
+                for alert in watcher.check(&status, get_current_timestamp()) {
+                    tracing::info!("raising alert: {:?}", alert.kind);
+                    match mls.send_message(&alert).await {
+                        Ok(message) => messager_handle.send(MessageFromAgent::Mls(message)).await,
+                        Err(err) => tracing::error!("Failed to send alert: {err}"),
+                    }
+                }
+            }
+            Err(err) => tracing::error!("Failed to check alert rules: {err:#}"),
+        }
+
+        select! {
+            _ = cancel.cancelled().fuse() => return,
+            _ = tokio::time::sleep(interval).fuse() => continue,
+        }
+    }
+}
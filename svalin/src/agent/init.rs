@@ -7,8 +7,16 @@ use tokio_util::sync::CancellationToken;
 use crate::shared::commands::public_server_status::GetPutblicStatus;
 use crate::shared::join_agent::AgentInitPayload;
 use crate::shared::join_agent::request_handler::RequestJoin;
+use crate::util::key_storage::KeySourceConfig;
 
 pub async fn init(address: String) -> Result<WaitingForInit> {
+    init_with_key_source(address, KeySourceConfig::default()).await
+}
+
+pub async fn init_with_key_source(
+    address: String,
+    key_source: KeySourceConfig,
+) -> Result<WaitingForInit> {
     if super::get_config().await?.is_some() {
         return Err(anyhow!("Agent is already initialized"));
     }
@@ -74,6 +82,7 @@ pub async fn init(address: String) -> Result<WaitingForInit> {
                 join_code,
                 confirm_code_recv,
                 join_success_recv,
+                key_source,
             ))
         }
     }
@@ -83,6 +92,7 @@ pub struct WaitingForInit {
     join_code: String,
     confirm_channel: oneshot::Receiver<String>,
     success_channel: oneshot::Receiver<AgentInitPayload>,
+    key_source: KeySourceConfig,
 }
 
 impl WaitingForInit {
@@ -90,11 +100,13 @@ impl WaitingForInit {
         join_code: String,
         confirm_channel: oneshot::Receiver<String>,
         success_channel: oneshot::Receiver<AgentInitPayload>,
+        key_source: KeySourceConfig,
     ) -> Self {
         Self {
             join_code,
             confirm_channel,
             success_channel,
+            key_source,
         }
     }
 
@@ -109,6 +121,7 @@ impl WaitingForInit {
             join_code: self.join_code,
             confirm_code,
             success_channel: self.success_channel,
+            key_source: self.key_source,
         })
     }
 }
@@ -117,6 +130,7 @@ pub struct WaitForConfirm {
     join_code: String,
     confirm_code: String,
     success_channel: oneshot::Receiver<AgentInitPayload>,
+    key_source: KeySourceConfig,
 }
 
 impl WaitForConfirm {
@@ -132,7 +146,7 @@ impl WaitForConfirm {
         // TODO: handle cancellation
         let init_data = self.success_channel.await?;
 
-        super::init_with(init_data)
+        super::init_with(init_data, &self.key_source)
             .await
             .context("error saving init data")?;
 
@@ -1,15 +1,24 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
 use anyhow::anyhow;
 use futures::{FutureExt, select};
+use svalin_pki::{
+    Credential, SpkiHash, mls::transport_types::MessageToServerTransport, trust_store::TrustStore,
+};
+use svalin_rpc::rpc::connection::Connection;
 use svalin_store::client_store::persistent::SvalinReport;
 use svalin_sysctl::sytem_report::SystemReport;
-use tokio::sync::Notify;
+use tokio::sync::{Notify, mpsc};
 use tokio_util::sync::CancellationToken;
 
 use crate::{
     message_streaming::{MessageFromAgent, agent::AgentMessageDispatcherHandle},
-    mls::MlsAgent,
+    mls::{AgentInstruction, KeyUpdateInterval, MlsAgent},
+    shared::commands::count_key_packages::CountKeyPackages,
+    util::chain_gossip::POSITION_INTERVAL,
 };
 
 // Todo: repeat this periodically if it fails - also needs to account for dropping the welcome message to the server
@@ -29,6 +38,63 @@ pub(super) async fn ensure_group_exists(
     Ok(())
 }
 
+/// Regular key packages the agent keeps on the server, so it can be added to
+/// groups.
+const WANTED_KEY_PACKAGES: u64 = 10;
+/// The key packages are only topped up once fewer than this are left.
+const MIN_KEY_PACKAGES: u64 = 5;
+/// How often the agent checks how many key packages the server still holds.
+const KEY_PACKAGE_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Periodically tops up the key packages on the server once they run low,
+/// including a last resort key package for when all others are used up.
+pub(super) async fn replenish_key_packages(
+    mls: Arc<MlsAgent>,
+    connection: impl Connection,
+    messager_handle: AgentMessageDispatcherHandle,
+    cancel: CancellationToken,
+) {
+    loop {
+        if let Err(err) = upload_key_packages(&mls, &connection, &messager_handle).await {
+            tracing::error!("Failed to replenish key packages: {err:#}");
+        }
+
+        select! {
+            _ = cancel.cancelled().fuse() => return,
+            _ = tokio::time::sleep(KEY_PACKAGE_CHECK_INTERVAL).fuse() => continue,
+        }
+    }
+}
+
+async fn upload_key_packages(
+    mls: &MlsAgent,
+    connection: &impl Connection,
+    messager_handle: &AgentMessageDispatcherHandle,
+) -> Result<(), anyhow::Error> {
+    let count = connection.dispatch(CountKeyPackages).await?;
+
+    let mut key_packages = Vec::new();
+    if count.available < MIN_KEY_PACKAGES {
+        for _ in count.available..WANTED_KEY_PACKAGES {
+            key_packages.push(mls.create_key_package().await?.to_unverified());
+        }
+    }
+    if !count.last_resort {
+        key_packages.push(mls.create_last_resort_key_package().await?.to_unverified());
+    }
+
+    if key_packages.is_empty() {
+        return Ok(());
+    }
+
+    tracing::debug!("uploading {} key packages", key_packages.len());
+    messager_handle
+        .send(MessageFromAgent::KeyPackages(key_packages))
+        .await;
+
+    Ok(())
+}
+
 const SYSTEM_REPORT_INTERVAL: Duration = Duration::from_secs(60 * 30); // 24 hours
 pub(super) async fn schedule_system_reports(
     mls: Arc<MlsAgent>,
@@ -49,6 +115,130 @@ pub(super) async fn schedule_system_reports(
     }
 }
 
+/// Periodically sends the signed trust store position to the users of the
+/// device group, which compare it with their own to detect a split view.
+pub(super) async fn schedule_chain_positions(
+    mls: Arc<MlsAgent>,
+    credential: Credential,
+    trust_store: Arc<RwLock<TrustStore>>,
+    messager_handle: AgentMessageDispatcherHandle,
+    cancel: CancellationToken,
+) {
+    loop {
+        let position = trust_store.read().unwrap().checkpoint(&credential);
+        match mls.send_chain_position(position).await {
+            Ok(message) => messager_handle.send(MessageFromAgent::Mls(message)).await,
+            Err(err) => tracing::error!("Failed to send chain position: {}", err),
+        }
+
+        select! {
+            _ = cancel.cancelled().fuse() => return,
+            _ = tokio::time::sleep(POSITION_INTERVAL).fuse() => continue,
+        }
+    }
+}
+
+/// How often the agent checks its device group for revoked members.
+const REVOCATION_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 5);
+
+/// Periodically removes users and sessions whose certificates were dropped
+/// from the trust store from the device group.
+pub(super) async fn remove_revoked_members(
+    mls: Arc<MlsAgent>,
+    messager_handle: AgentMessageDispatcherHandle,
+    cancel: CancellationToken,
+) {
+    loop {
+        match mls.remove_revoked_members().await {
+            Ok(Some(message)) => send_commit(&mls, &messager_handle, message).await,
+            Ok(None) => {}
+            Err(err) => tracing::error!("Failed to remove revoked members: {err:#}"),
+        }
+
+        select! {
+            _ = cancel.cancelled().fuse() => return,
+            _ = tokio::time::sleep(REVOCATION_CHECK_INTERVAL).fuse() => continue,
+        }
+    }
+}
+
+/// Periodically commits a new key to the device group, so a leaked epoch
+/// secret only reveals the reports sent within one interval.
+pub(super) async fn schedule_key_updates(
+    mls: Arc<MlsAgent>,
+    messager_handle: AgentMessageDispatcherHandle,
+    interval: KeyUpdateInterval,
+    cancel: CancellationToken,
+) {
+    // agents starting at the same time don't all commit at once
+    select! {
+        _ = cancel.cancelled().fuse() => return,
+        _ = tokio::time::sleep(interval.first_update_delay()).fuse() => {},
+    }
+
+    loop {
+        match mls.update_key().await {
+            Ok(Some(message)) => send_commit(&mls, &messager_handle, message).await,
+            Ok(None) => {}
+            Err(err) => tracing::error!("Failed to update key: {err:#}"),
+        }
+
+        select! {
+            _ = cancel.cancelled().fuse() => return,
+            _ = tokio::time::sleep(interval.duration()).fuse() => continue,
+        }
+    }
+}
+
+/// Sends a commit to the device group. It is merged once the server delivers
+/// it back, if the server refuses it another commit came first and it's
+/// dropped, the next one is created on top of the new epoch.
+async fn send_commit(
+    mls: &MlsAgent,
+    messager_handle: &AgentMessageDispatcherHandle,
+    message: MessageToServerTransport,
+) {
+    if messager_handle
+        .try_send(MessageFromAgent::Mls(message))
+        .await
+        .is_err()
+    {
+        tracing::debug!("commit was refused by the server, dropping it");
+        if let Err(err) = mls.clear_pending_commit().await {
+            tracing::error!("Failed to drop refused commit: {err:#}");
+        }
+    }
+}
+
+/// Executes the instructions users send to the device group and root sends to
+/// the global group.
+pub(super) async fn execute_instructions(
+    mut instructions: mpsc::Receiver<(SpkiHash, AgentInstruction)>,
+    system_report_notify: Arc<Notify>,
+    cancel: CancellationToken,
+) {
+    while let Some(Some((sender, instruction))) =
+        cancel.run_until_cancelled(instructions.recv()).await
+    {
+        tracing::debug!("executing instruction from {sender}: {instruction:?}");
+        match instruction {
+            AgentInstruction::SendSystemReport => system_report_notify.notify_one(),
+            AgentInstruction::Announcement(announcement) => {
+                tracing::info!("announcement from {sender}: {announcement}");
+            }
+            AgentInstruction::SetWitnessPolicy(witnesses) => {
+                if let Err(err) = super::set_witness_policy(witnesses).await {
+                    tracing::error!("Failed to set witness policy: {err:#}");
+                } else {
+                    tracing::info!(
+                        "witness policy changed by {sender}, restart the agent to apply it"
+                    );
+                }
+            }
+        }
+    }
+}
+
 async fn send_system_report(
     mls: &MlsAgent,
     messager_handle: &AgentMessageDispatcherHandle,
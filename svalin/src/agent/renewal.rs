@@ -0,0 +1,204 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow};
+use svalin_pki::{
+    Credential, KeyPair, RenewalRequest, SpkiHash, get_current_timestamp, trust_store::TrustStore,
+};
+use svalin_rpc::rpc::connection::Connection;
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    shared::commands::renewal::RequestRenewal,
+    util::renewal::{renewed_certificate, renewed_credential},
+};
+
+use super::{get_config, save_config};
+
+/// How often the agent checks for renewed certificates.
+const RENEWAL_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Requests the renewal of the agent certificate once it is due and saves
+/// renewed certificates of the agent and its upstream server to the config.
+///
+/// If a key rotation was prepared, the rotation is requested instead and the
+/// new key is installed once a user approved it.
+///
+/// The renewed certificates are used after the next restart.
+pub(super) async fn manage_renewal(
+    mut credentials: Credential,
+    trust_store: Arc<RwLock<TrustStore>>,
+    connection: impl Connection,
+    cancel: CancellationToken,
+) {
+    let mut requested = false;
+    let mut rotation_requested = false;
+
+    loop {
+        match save_renewed_certificates(&credentials, &trust_store).await {
+            Ok(Some(renewed)) => {
+                tracing::info!("agent certificate was renewed, it will be used after a restart");
+                credentials = renewed;
+                requested = false;
+            }
+            Ok(None) => {}
+            Err(err) => tracing::error!("failed to save renewed certificates: {err:#}"),
+        }
+
+        let pending_key = match install_rotated_key(&credentials, &trust_store).await {
+            Ok(Rotation::Installed(rotated)) => {
+                tracing::info!(
+                    "agent key was rotated to {}, restart the agent to use it",
+                    rotated.certificate().spki_hash()
+                );
+                credentials = rotated;
+                requested = false;
+                rotation_requested = false;
+                None
+            }
+            Ok(Rotation::Pending(pending_key)) => Some(pending_key),
+            Ok(Rotation::None) => None,
+            Err(err) => {
+                tracing::error!("failed to install rotated key: {err:#}");
+                None
+            }
+        };
+
+        if let Some(pending_key) = pending_key {
+            if !rotation_requested {
+                tracing::info!("requesting rotation of the agent key");
+                match connection
+                    .dispatch(RequestRenewal(RenewalRequest::create_rotated(
+                        &credentials,
+                        &pending_key,
+                    )))
+                    .await
+                {
+                    Ok(()) => rotation_requested = true,
+                    Err(err) => tracing::error!("failed to request key rotation: {err}"),
+                }
+            }
+        } else if !requested
+            && credentials.certificate().renewal_due_at() <= get_current_timestamp()
+        {
+            tracing::warn!(
+                "agent certificate expires at {}, requesting renewal",
+                credentials.certificate().not_after()
+            );
+            match connection
+                .dispatch(RequestRenewal(RenewalRequest::create(&credentials)))
+                .await
+            {
+                Ok(()) => requested = true,
+                Err(err) => tracing::error!("failed to request renewal: {err}"),
+            }
+        }
+
+        select! {
+            _ = cancel.cancelled() => return,
+            _ = tokio::time::sleep(RENEWAL_CHECK_INTERVAL) => {}
+        }
+    }
+}
+
+/// Updates the agent config with renewed certificates from the trust store.
+/// Returns the renewed credential of the agent, if there is one.
+async fn save_renewed_certificates(
+    credentials: &Credential,
+    trust_store: &RwLock<TrustStore>,
+) -> Result<Option<Credential>> {
+    let mut config = get_config()
+        .await?
+        .ok_or_else(|| anyhow!("agent config is missing"))?;
+
+    let (renewed, upstream) = {
+        let trust_store = trust_store.read().unwrap();
+        let upstream = renewed_certificate(&config.upstream_certificate, &trust_store);
+
+        (renewed_credential(credentials, &trust_store)?, upstream)
+    };
+
+    if renewed.is_none() && upstream.is_none() {
+        return Ok(None);
+    }
+
+    if let Some(renewed) = &renewed {
+        config.encrypted_credentials = config.key_source.encrypt_credential(renewed).await?;
+    }
+    if let Some(upstream) = upstream {
+        tracing::info!("upstream certificate was renewed");
+        config.upstream_certificate = upstream.to_unverified();
+    }
+
+    save_config(&config).await?;
+
+    Ok(renewed)
+}
+
+enum Rotation {
+    None,
+    Pending(KeyPair),
+    Installed(Credential),
+}
+
+/// Checks whether the pending key of the agent was approved and replaces the
+/// credential in the config if so.
+async fn install_rotated_key(
+    credentials: &Credential,
+    trust_store: &RwLock<TrustStore>,
+) -> Result<Rotation> {
+    let mut config = get_config()
+        .await?
+        .ok_or_else(|| anyhow!("agent config is missing"))?;
+
+    let Some(pending_key) = config.pending_key.clone() else {
+        return Ok(Rotation::None);
+    };
+    let pending_key = config.key_source.decrypt_keypair(pending_key).await?;
+
+    let rotated = {
+        let trust_store = trust_store.read().unwrap();
+        trust_store
+            .successor(credentials.certificate().spki_hash())
+            .and_then(|successor| trust_store.get(successor))
+            .cloned()
+    };
+    let Some(rotated) = rotated else {
+        return Ok(Rotation::Pending(pending_key));
+    };
+
+    let rotated = pending_key
+        .upgrade(rotated.to_unverified())
+        .context("rotated certificate does not belong to the pending key")?;
+
+    config.encrypted_credentials = config.key_source.encrypt_credential(&rotated).await?;
+    config.pending_key = None;
+    save_config(&config).await?;
+
+    Ok(Rotation::Installed(rotated))
+}
+
+/// Generates a new key for the agent and stores it in the config. The running
+/// agent requests the rotation, which then has to be approved by a user.
+///
+/// Returns the spki hash of the new key.
+pub async fn prepare_key_rotation() -> Result<SpkiHash> {
+    let mut config = get_config()
+        .await?
+        .ok_or_else(|| anyhow!("agent is not yet initialized"))?;
+
+    if config.pending_key.is_some() {
+        return Err(anyhow!("a key rotation is already pending"));
+    }
+
+    let keypair = KeyPair::generate();
+    let spki_hash = keypair.spki_hash();
+
+    config.pending_key = Some(config.key_source.encrypt_keypair(&keypair).await?);
+    save_config(&config).await?;
+
+    Ok(spki_hash)
+}
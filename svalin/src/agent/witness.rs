@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow};
+use svalin_rpc::rpc::connection::{Connection, ServeableConnectionBase};
+use svalin_store::agent_store::AgentStore;
+use tokio::select;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use crate::{
+    shared::commands::witness_signature::SubmitWitnessSignature,
+    util::{
+        trust_store::{load_trust_store, update_trust_store},
+        witness::{WITNESS_INTERVAL, WitnessPolicy},
+    },
+};
+
+use super::{connect_upstream, data_dir, get_config, trust_store_path};
+
+/// Runs the agent as a witness node.
+///
+/// A witness doesn't serve any commands, it only follows the trust store of
+/// the server, verifies every block and countersigns the new heads. It is
+/// enrolled like any other agent, but keeps its data apart, see
+/// [`super::use_witness_data_dir`]. Members require its signatures by adding
+/// its key to their [`WitnessPolicy`].
+pub async fn run_witness(cancel: CancellationToken) -> Result<()> {
+    let config = get_config()
+        .await
+        .context("error loading config")?
+        .ok_or_else(|| anyhow!("witness is not yet initialized"))?;
+
+    let credentials = config
+        .key_source
+        .decrypt_credentials(config.encrypted_credentials.clone())
+        .await
+        .context("error decrypting credentials")?;
+
+    let rpc = connect_upstream(&config, &credentials, cancel.clone()).await?;
+
+    let tasks = TaskTracker::new();
+
+    let agent_store = AgentStore::open(data_dir()?.push("agent-store.sqlite")).await?;
+    let trust_store = load_trust_store(
+        trust_store_path()?.to_path_buf(),
+        agent_store.transaction_store().as_ref(),
+        cancel.clone(),
+        &tasks,
+    )
+    .await?;
+    // the witness verifies every block itself instead of waiting for others
+    update_trust_store(
+        trust_store.clone(),
+        agent_store.transaction_store().clone(),
+        rpc.upstream_connection(),
+        config.upstream_certificate.clone(),
+        WitnessPolicy::default(),
+        cancel.clone(),
+        &tasks,
+    )
+    .await?;
+
+    tracing::info!(
+        "running as witness {}",
+        credentials.certificate().spki_hash()
+    );
+
+    let connection = rpc.upstream_connection();
+    let mut handled = None;
+
+    loop {
+        let signature = {
+            let guard = trust_store.read().unwrap();
+            if handled == Some(guard.sequence()) {
+                None
+            } else if guard.conflict().is_some() {
+                tracing::error!(
+                    "refusing to countersign the trust store at sequence {}, it conflicts with another member",
+                    guard.sequence()
+                );
+                // don't repeat the error until the head changes
+                handled = Some(guard.sequence());
+                None
+            } else {
+                Some(guard.checkpoint(&credentials))
+            }
+        };
+
+        if let Some(signature) = signature {
+            let sequence = signature.sequence();
+            match connection.dispatch(SubmitWitnessSignature(signature)).await {
+                Ok(()) => {
+                    tracing::debug!("countersigned trust store at sequence {sequence}");
+                    handled = Some(sequence);
+                }
+                Err(err) => tracing::error!("failed to submit witness signature: {err:#}"),
+            }
+        }
+
+        select! {
+            _ = cancel.cancelled() => break,
+            _ = tokio::time::sleep(WITNESS_INTERVAL) => {}
+        }
+    }
+
+    tasks.close();
+    tokio::time::timeout(Duration::from_secs(3), tasks.wait()).await?;
+
+    ServeableConnectionBase::close(&connection).await;
+
+    Ok(())
+}
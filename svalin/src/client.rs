@@ -9,18 +9,23 @@ mod first_connect;
 pub mod tunnel_manager;
 
 pub mod add_agent;
+pub mod chain_gossip;
 pub mod device;
+pub mod expiry;
 mod profile;
+pub mod renewal;
 pub mod state;
 
 pub use first_connect::*;
 use svalin_pki::trust_store::TrustStore;
-use svalin_pki::{Certificate, Credential, RootCertificate, SpkiHash, TrustStoreVerifier};
+use svalin_pki::{
+    Certificate, CertificateType, Credential, RootCertificate, SpkiHash, TrustStoreVerifier,
+};
 use svalin_rpc::commands::ping::Ping;
 use svalin_rpc::rpc::client::RpcClient;
 use svalin_rpc::rpc::connection::Connection;
 use svalin_store::client_store::ClientStore;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, mpsc};
 use tokio::time::error::Elapsed;
 use tokio::time::timeout;
 use tokio_util::sync::CancellationToken;
@@ -31,7 +36,8 @@ use crate::client::device::DeviceHandle;
 use crate::client::state::{ClientState, ClientStateUpdate};
 use crate::message_streaming::MessageFromClient;
 use crate::message_streaming::client::{ClientMessageDispatcherHandle, ClientStateHandle};
-use crate::mls::MlsClient;
+use crate::mls::{AgentInstruction, MlsClient};
+use crate::shared::commands::update_user_mls::UserMlsMessage;
 
 pub struct Client {
     rpc: RpcClient,
@@ -46,6 +52,7 @@ pub struct Client {
     tunnel_manager: TunnelManager,
     message_sender: ClientMessageDispatcherHandle,
     state_handle: ClientStateHandle,
+    user_mls: mpsc::Sender<UserMlsMessage>,
     background_tasks: TaskTracker,
     cancel: CancellationToken,
     verifier: TrustStoreVerifier,
@@ -96,6 +103,29 @@ impl Client {
         DeviceHandle::new(self, spki_hash)
     }
 
+    /// Sends an instruction to all agents through the global group, only
+    /// available to root.
+    ///
+    /// The instruction is encrypted and sent with the next update of the
+    /// user's MLS state.
+    pub async fn broadcast_instruction(&self, instruction: AgentInstruction) -> Result<()> {
+        if self.user_credential.certificate().certificate_type() != CertificateType::Root {
+            return Err(anyhow!("only root can broadcast instructions"));
+        }
+
+        self.send_as_user(UserMlsMessage::Broadcast(instruction))
+            .await
+    }
+
+    /// Queues a message for the groups only the user is a member of, it is
+    /// sent with the next update of the user's MLS state.
+    pub(crate) async fn send_as_user(&self, message: UserMlsMessage) -> Result<()> {
+        self.user_mls
+            .send(message)
+            .await
+            .map_err(|_| anyhow!("user mls update task stopped"))
+    }
+
     pub async fn close(&self, timeout_duration: Duration) -> Result<(), Elapsed> {
         self.cancel.cancel();
         self.background_tasks.close();
@@ -3,7 +3,7 @@ use crate::{message_streaming::MessageFromClient, shared::join_agent::accept_han
 use super::Client;
 
 use anyhow::Result;
-use svalin_pki::{Certificate, trust_store};
+use svalin_pki::{Certificate, secure_chain::CheckedBlock, trust_store};
 use svalin_rpc::rpc::connection::Connection;
 use svalin_store::trust_store_transaction_store::TransactionStoreError;
 use tokio::sync::oneshot;
@@ -36,6 +36,15 @@ impl Client {
             .write()
             .unwrap()
             .add(cert, &self.user_credential)?;
+        self.publish_trust_store_block(block).await
+    }
+
+    /// Uploads a block created from the local trust store to the server and
+    /// applies it locally once the server accepted it.
+    pub(crate) async fn publish_trust_store_block(
+        &self,
+        block: CheckedBlock<trust_store::Transaction>,
+    ) -> Result<(), AddToTrustStoreError> {
         self.message_sender
             .send_with_feedback(MessageFromClient::TrustStore(block.as_unchecked().clone()))
             .await
@@ -0,0 +1,68 @@
+use std::{sync::Weak, time::Duration};
+
+use rand::RngExt;
+use svalin_pki::secure_chain::Checkpoint;
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+
+use crate::util::chain_gossip::{POSITION_INTERVAL, reset_conflict};
+
+use super::{Client, state::ClientStateUpdate};
+
+impl Client {
+    /// The trust store position of another member which conflicts with the
+    /// local one. If present, the server presents a split view and the trust
+    /// store can no longer be used.
+    pub fn chain_conflict(&self) -> Option<Checkpoint> {
+        self.trust_store.read().unwrap().conflict().cloned()
+    }
+
+    /// Forgets the reported trust store conflicts, once an admin made sure
+    /// the server presents the same history to everyone again.
+    pub async fn reset_chain_conflict(&self) -> anyhow::Result<()> {
+        reset_conflict(&self.trust_store, self.store.transaction_store()).await?;
+        self.state_handle
+            .update(ClientStateUpdate::ChainConflictReset)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Compares the trust store position with a random online agent.
+    async fn compare_chain_position_with_online_agent(&self) -> anyhow::Result<()> {
+        let (state, _) = self.subscribe_state().await?;
+        let online: Vec<_> = state.agents_online().cloned().collect();
+        if online.is_empty() {
+            return Ok(());
+        }
+
+        let agent = online[rand::rng().random_range(0..online.len())].clone();
+        self.device(agent).compare_chain_position().await
+    }
+}
+
+/// Periodically exchanges trust store positions with online agents.
+///
+/// Agents also send their position to their device group, this additionally
+/// lets the agents compare the position of the client.
+pub(super) async fn compare_chain_positions(client: Weak<Client>, cancel: CancellationToken) {
+    loop {
+        select! {
+            _ = cancel.cancelled() => return,
+            _ = tokio::time::sleep(POSITION_INTERVAL) => {}
+        }
+
+        let Some(client) = client.upgrade() else {
+            return;
+        };
+        if let Err(err) = client.compare_chain_position_with_online_agent().await {
+            tracing::error!("failed to compare trust store position: {err:#}");
+        }
+        if let Some(conflict) = client.chain_conflict() {
+            let update = ClientStateUpdate::ChainConflict(conflict.signer().clone());
+            if let Err(err) = client.state_handle.update(update).await {
+                tracing::error!("failed to report trust store conflict: {err}");
+            }
+        }
+    }
+}
@@ -5,11 +5,16 @@ use svalin_rpc::{
     commands::{forward::ForwardConnection, ping::Ping},
     rpc::connection::{Connection, direct_connection::DirectConnection},
 };
-use svalin_store::client_store::persistent::{self, SvalinMetaInfo};
+use svalin_store::client_store::persistent::{self, AlertAcknowledgement, SvalinMetaInfo};
+use uuid::Uuid;
 
 use crate::{
     client::state::ClientStateUpdate,
-    shared::commands::{request_system_report::RequestSystemReport, update_agent::UpdateAgent},
+    mls::AgentInstruction,
+    shared::commands::{
+        compare_chain_position::CompareChainPosition, request_system_report::RequestSystemReport,
+        update_agent::UpdateAgent, update_user_mls::UserMlsMessage,
+    },
 };
 
 pub struct DeviceHandle<'a>(&'a super::Client, SpkiHash);
@@ -47,6 +52,38 @@ impl<'a> DeviceHandle<'a> {
         Ok(())
     }
 
+    /// Sends an end-to-end encrypted instruction to the device group, which
+    /// the agent executes once it receives it.
+    ///
+    /// Only the user is a member of the device group, so the instruction is
+    /// sent with the next update of the user's MLS state.
+    pub async fn send_instruction(&self, instruction: AgentInstruction) -> anyhow::Result<()> {
+        self.0
+            .send_as_user(UserMlsMessage::Instruction(self.1.clone(), instruction))
+            .await
+    }
+
+    /// Marks an alert of the device as handled. The acknowledgement is sent
+    /// to the device group with the user's credential, so the other sessions
+    /// and users stop showing it as well.
+    pub async fn acknowledge_alert(&self, alert: Uuid) -> anyhow::Result<()> {
+        let acknowledgement = AlertAcknowledgement {
+            alert,
+            acknowledged_at: get_current_timestamp(),
+        };
+        let message = UserMlsMessage::AcknowledgeAlert(self.1.clone(), acknowledgement);
+
+        // shown right away, the user's state follows with its next update
+        if let Some(update) = message.own_update(self.0.user_credential.certificate().spki_hash()) {
+            self.0
+                .state_handle
+                .update(ClientStateUpdate::Persistent(update))
+                .await?;
+        }
+
+        self.0.send_as_user(message).await
+    }
+
     pub async fn update_agent(&self, url: String) -> anyhow::Result<()> {
         self.connection()
             .await?
@@ -57,6 +94,20 @@ impl<'a> DeviceHandle<'a> {
         Ok(())
     }
 
+    /// Exchanges trust store positions with the agent, so both can detect if
+    /// the server presents them different histories.
+    pub async fn compare_chain_position(&self) -> anyhow::Result<()> {
+        self.connection()
+            .await?
+            .dispatch(CompareChainPosition::new(
+                self.0.trust_store.clone(),
+                self.0.store.transaction_store().clone(),
+                &self.0.user_credential,
+            ))
+            .await
+            .map_err(|err| anyhow!("{err:#}"))
+    }
+
     async fn connection(&self) -> anyhow::Result<ForwardConnection<DirectConnection>> {
         let cert = self
             .0
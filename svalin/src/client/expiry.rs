@@ -0,0 +1,77 @@
+use std::{collections::HashMap, sync::Weak, time::Duration};
+
+use svalin_pki::{Certificate, CertificateType, get_current_timestamp};
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+
+use crate::util::expiry::EXPIRY_WARNING_WINDOW;
+
+use super::{Client, add_agent::AddToTrustStoreError};
+
+/// How often the client checks the trust store for expired certificates.
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+impl Client {
+    /// Returns the certificates in the trust store which expired or expire
+    /// within [`EXPIRY_WARNING_WINDOW`], grouped by their type.
+    pub fn expiring_certificates(&self) -> HashMap<CertificateType, Vec<Certificate>> {
+        self.trust_store
+            .read()
+            .unwrap()
+            .expiring_within(EXPIRY_WARNING_WINDOW.as_secs(), get_current_timestamp())
+            .into_iter()
+            .map(|(certificate_type, certificates)| {
+                (
+                    certificate_type,
+                    certificates.into_iter().cloned().collect(),
+                )
+            })
+            .collect()
+    }
+
+    /// Removes all certificates from the trust store which already expired.
+    pub async fn remove_expired_certificates(&self) -> Result<(), AddToTrustStoreError> {
+        let expired: Vec<_> = self
+            .trust_store
+            .read()
+            .unwrap()
+            .expired(get_current_timestamp())
+            .into_iter()
+            .cloned()
+            .collect();
+
+        for certificate in expired {
+            tracing::info!(
+                "removing expired {:?} certificate {}",
+                certificate.certificate_type(),
+                certificate.spki_hash()
+            );
+            let block = self
+                .trust_store
+                .write()
+                .unwrap()
+                .remove_expired(&certificate, &self.user_credential)?;
+            self.publish_trust_store_block(block).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Periodically removes expired certificates from the trust store.
+pub(super) async fn remove_expired_certificates(client: Weak<Client>, cancel: CancellationToken) {
+    loop {
+        let Some(client) = client.upgrade() else {
+            return;
+        };
+        if let Err(err) = client.remove_expired_certificates().await {
+            tracing::error!("failed to remove expired certificates: {err}");
+        }
+        drop(client);
+
+        select! {
+            _ = cancel.cancelled() => return,
+            _ = tokio::time::sleep(EXPIRY_CHECK_INTERVAL) => {}
+        }
+    }
+}
@@ -10,17 +10,21 @@ use svalin_pki::{
 };
 use svalin_rpc::rpc::{client::RpcClient, connection::Connection};
 use svalin_store::client_store::ClientStore;
+use tokio::sync::mpsc;
 use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use tracing::error;
 
 use crate::{
     client::tunnel_manager::TunnelManager,
     message_streaming::client::{ClientMessageDispatcher, ClientMessageReceiver},
+    mls::KeyUpdateInterval,
     remote_key_retriever::RemoteKeyRetriever,
     shared::commands::{get_user_credentials::GetUserCredential, update_user_mls::UpdateUserMls},
     util::{
         location::{Location, LocationError},
+        renewal::renewed_certificate,
         trust_store::{load_trust_store, save_trust_store, update_trust_store},
+        witness::WitnessPolicy,
     },
 };
 
@@ -34,6 +38,12 @@ pub(crate) struct Profile {
     pub(crate) root_certificate: UnverifiedCertificate,
     pub(crate) local_credential_params: ArgonParams,
     pub(crate) device_credential: EncryptedCredential,
+    /// Witnesses which have to countersign new trust store heads.
+    #[serde(default, skip_serializing_if = "WitnessPolicy::is_disabled")]
+    pub(crate) witnesses: WitnessPolicy,
+    /// How often the user commits a new key to the groups it is part of.
+    #[serde(default, skip_serializing_if = "KeyUpdateInterval::is_default")]
+    pub(crate) key_update_interval: KeyUpdateInterval,
 }
 
 impl Profile {
@@ -52,6 +62,8 @@ impl Profile {
             root_certificate: root_certificate.to_unverified(),
             local_credential_params,
             device_credential,
+            witnesses: WitnessPolicy::default(),
+            key_update_interval: KeyUpdateInterval::default(),
         }
     }
 
@@ -173,6 +185,19 @@ impl Client {
         }
     }
 
+    /// Sets the witnesses which have to countersign new trust store heads, it
+    /// is used the next time the profile is opened.
+    pub async fn set_witness_policy(profile_name: &str, witnesses: WitnessPolicy) -> Result<()> {
+        witnesses.validate()?;
+
+        let Some(mut profile) = Self::get_profile(profile_name).await? else {
+            return Err(anyhow!("Profile is empty"));
+        };
+        profile.witnesses = witnesses;
+
+        Self::save_profile(&profile).await
+    }
+
     pub async fn remove_profile(profile_name: &str) -> Result<()> {
         let location = Self::profile_dir(profile_name).await?;
 
@@ -186,11 +211,11 @@ impl Client {
         password: Vec<u8>,
         cancel: CancellationToken,
     ) -> Result<Arc<Self>> {
-        let Some(profile) = Self::get_profile(&profile_key).await? else {
+        let Some(mut profile) = Self::get_profile(&profile_key).await? else {
             return Err(anyhow!("Profile is empty"));
         };
 
-        let key = profile
+        let local_key = profile
             .local_credential_params
             .derive_encryption_key(password.clone())
             .await?;
@@ -199,11 +224,8 @@ impl Client {
         let client_db_path = profile.profile_dir().await?.push("client-store.sqlite");
         let trust_store_path = profile.trust_store_path().await?.to_pathbuf();
         // tracing::trace!("unlocking profile");
-        let device_credential = profile.device_credential.decrypt(&key)?;
-        let root_certificate = profile.root_certificate.use_as_root()?;
-        let upstream_certificate = profile
-            .upstream_certificate
-            .verify_signature(&root_certificate, get_current_timestamp())?;
+        let device_credential = profile.device_credential.clone().decrypt(&local_key)?;
+        let root_certificate = profile.root_certificate.clone().use_as_root()?;
 
         let client_store = Arc::new(ClientStore::open(client_db_path).await?);
         // Starting Background Tasks
@@ -217,6 +239,17 @@ impl Client {
         .await
         .context("Failed to load trust store")?;
 
+        // a renewed upstream certificate might already be in the trust store
+        let renewed_upstream =
+            renewed_certificate(&profile.upstream_certificate, &trust_store.read().unwrap());
+        let upstream_certificate = match renewed_upstream {
+            Some(renewed) => renewed,
+            None => profile
+                .upstream_certificate
+                .clone()
+                .verify_signature(&root_certificate, get_current_timestamp())?,
+        };
+
         // tracing::trace!("creating verifier");
         let verifier = ExactVerififier::new(upstream_certificate.clone()).to_tls_verifier();
 
@@ -244,11 +277,38 @@ impl Client {
             trust_store.clone(),
             client_store.transaction_store().clone(),
             rpc.upstream_connection(),
+            upstream_certificate.clone().to_unverified(),
+            profile.witnesses.clone(),
             cancel.clone(),
             &background_tasks,
         )
         .await?;
 
+        let renewed_device_credential = super::renewal::renew_session_if_due(
+            &device_credential,
+            &user_credential,
+            &rpc.upstream_connection(),
+        )
+        .await
+        .unwrap_or_else(|err| {
+            error!("failed to renew session: {err:#}");
+            None
+        });
+        let upstream_certificate =
+            renewed_certificate(&upstream_certificate, &trust_store.read().unwrap())
+                .unwrap_or(upstream_certificate);
+
+        if renewed_device_credential.is_some()
+            || profile.upstream_certificate != upstream_certificate
+        {
+            if let Some(renewed) = &renewed_device_credential {
+                profile.device_credential = renewed.export(&local_key)?;
+            }
+            profile.upstream_certificate = upstream_certificate.clone().to_unverified();
+            Self::save_profile(&profile).await?;
+        }
+        let device_credential = renewed_device_credential.unwrap_or(device_credential);
+
         let verifier = TrustStoreVerifier::new(trust_store.clone());
 
         // tracing::trace!("connected to server");
@@ -284,6 +344,7 @@ impl Client {
         let (message_receiver, client_state_handle) = ClientMessageReceiver::initialize(
             dispatcher_handle.clone(),
             mls.clone(),
+            trust_store.clone(),
             cancel.clone(),
             client_store.clone(),
         )
@@ -296,6 +357,9 @@ impl Client {
             }
         });
 
+        let (user_mls, user_mls_receiver) = mpsc::channel(10);
+        let key_update_interval = profile.key_update_interval;
+
         let client = Arc::new(Self {
             rpc,
             _upstream_address: profile.upstream_address,
@@ -310,15 +374,38 @@ impl Client {
             store: client_store,
             message_sender: dispatcher_handle.clone(),
             state_handle: client_state_handle,
+            user_mls,
             background_tasks,
             cancel,
         });
 
+        client
+            .background_tasks
+            .spawn(super::expiry::remove_expired_certificates(
+                Arc::downgrade(&client),
+                client.cancel.clone(),
+            ));
+        client
+            .background_tasks
+            .spawn(super::chain_gossip::compare_chain_positions(
+                Arc::downgrade(&client),
+                client.cancel.clone(),
+            ));
+        client
+            .background_tasks
+            .spawn(super::renewal::migrate_rotated_devices(
+                client.trust_store.clone(),
+                client.state_handle.clone(),
+                client.cancel.clone(),
+            ));
+
         let connection = client.rpc.upstream_connection();
         let cancel = client.cancel.clone();
         let user_credential = client.user_credential.clone();
         let session_mls = mls.clone();
         let state_handle = client.state_handle.clone();
+        let trust_store = client.trust_store.clone();
+        let history = client.store.transaction_store().clone();
         client.background_tasks.spawn(async move {
             tracing::trace!("starting user mls update task");
             let verifier = verifier;
@@ -331,6 +418,10 @@ impl Client {
                     session_mls: session_mls,
                     cancel,
                     state_handle,
+                    trust_store,
+                    history,
+                    outgoing: user_mls_receiver,
+                    key_update_interval,
                 })
                 .await
             {
@@ -0,0 +1,161 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+use svalin_pki::{
+    CreateCertificateError, Credential, RenewalRequest, RenewalRequestError, get_current_timestamp,
+    trust_store::{self, TrustStore},
+};
+use svalin_rpc::rpc::connection::Connection;
+use svalin_store::client_store::persistent;
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    message_streaming::client::ClientStateHandle,
+    shared::commands::renewal::{ListRenewalRequests, RenewSession},
+};
+
+use super::state::ClientStateUpdate;
+
+/// How often the client checks the trust store for rotated device keys.
+const ROTATION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+use super::{Client, add_agent::AddToTrustStoreError};
+
+impl Client {
+    /// Loads the renewal requests waiting for approval.
+    pub async fn renewal_requests(&self) -> Result<Vec<RenewalRequest>> {
+        self.rpc
+            .upstream_connection()
+            .dispatch(ListRenewalRequests)
+            .await
+            .map_err(|err| anyhow!(err))
+    }
+
+    /// Issues the requested certificate and publishes it in the trust store.
+    ///
+    /// Certificates for the same key replace the previous certificate. For a
+    /// rotated key the request is published as proof of the handover and the
+    /// certificate of the previous key is removed.
+    pub async fn approve_renewal(
+        &self,
+        request: &RenewalRequest,
+    ) -> Result<(), ApproveRenewalError> {
+        let current = self
+            .trust_store
+            .read()
+            .unwrap()
+            .get(request.certificate().spki_hash())
+            .cloned()
+            .ok_or(ApproveRenewalError::CertificateNotFound)?;
+        request.verify(&current)?;
+
+        let certificate = self.user_credential.create_renewed_certificate(request)?;
+
+        let block = {
+            let mut trust_store = self.trust_store.write().unwrap();
+            if request.is_rotation() {
+                trust_store.rotate(request, certificate, &self.user_credential)?
+            } else {
+                trust_store.renew(certificate, &self.user_credential)?
+            }
+        };
+
+        self.publish_trust_store_block(block).await?;
+
+        Ok(())
+    }
+}
+
+/// Renews the session certificate of the device if it is due and uploads it
+/// to the server. Returns the renewed device credential.
+pub(super) async fn renew_session_if_due(
+    device_credential: &Credential,
+    user_credential: &Credential,
+    connection: &impl Connection,
+) -> Result<Option<Credential>> {
+    if device_credential.certificate().renewal_due_at() > get_current_timestamp() {
+        return Ok(None);
+    }
+
+    let request = RenewalRequest::create(device_credential);
+    let certificate = user_credential.create_renewed_certificate(&request)?;
+
+    connection
+        .dispatch(RenewSession(certificate.clone().to_unverified()))
+        .await
+        .map_err(|err| anyhow!(err))?;
+
+    Ok(Some(
+        device_credential.with_renewed_certificate(certificate.to_unverified())?,
+    ))
+}
+
+/// Moves the stored data of devices to their new key once the rotation of
+/// their key shows up in the trust store.
+pub(super) async fn migrate_rotated_devices(
+    trust_store: Arc<RwLock<TrustStore>>,
+    state_handle: ClientStateHandle,
+    cancel: CancellationToken,
+) {
+    loop {
+        if let Err(err) = migrate_devices(&trust_store, &state_handle).await {
+            tracing::error!("failed to migrate rotated devices: {err:#}");
+        }
+
+        select! {
+            _ = cancel.cancelled() => return,
+            _ = tokio::time::sleep(ROTATION_CHECK_INTERVAL) => {}
+        }
+    }
+}
+
+async fn migrate_devices(
+    trust_store: &RwLock<TrustStore>,
+    state_handle: &ClientStateHandle,
+) -> Result<()> {
+    let (state, _) = state_handle.subscribe().await?;
+
+    let migrations: Vec<_> = {
+        let trust_store = trust_store.read().unwrap();
+        state
+            .persistent()
+            .keys()
+            .filter_map(|previous| {
+                let successor = trust_store.latest_successor(previous);
+                (successor != previous).then(|| (previous.clone(), successor.clone()))
+            })
+            .collect()
+    };
+
+    for (previous, successor) in migrations {
+        tracing::info!("device {previous} rotated its key to {successor}");
+        state_handle
+            .update(ClientStateUpdate::Persistent(
+                persistent::Message::MigrateDevice {
+                    previous,
+                    successor,
+                },
+            ))
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ApproveRenewalError {
+    #[error("the certificate to renew is not in the trust store")]
+    CertificateNotFound,
+    #[error("invalid renewal request: {0}")]
+    InvalidRequest(#[from] RenewalRequestError),
+    #[error("error creating certificate: {0}")]
+    CreateCertificateError(#[from] CreateCertificateError),
+    #[error("error creating block: {0}")]
+    CreateBlockError(#[from] trust_store::CreateBlockError),
+    #[error("error publishing block: {0}")]
+    AddToTrustStoreError(#[from] AddToTrustStoreError),
+}
@@ -7,12 +7,18 @@ use svalin_store::client_store::persistent::{self};
 pub struct ClientState {
     persistent: persistent::State,
     agents_online: HashSet<SpkiHash>,
+    chain_conflict: Option<SpkiHash>,
 }
 
 #[derive(Clone, Debug)]
 pub enum ClientStateUpdate {
     Persistent(persistent::Message),
     AgentOnlineStatus(SpkiHash, bool),
+    /// The trust store position of the given member conflicts with the
+    /// local one.
+    ChainConflict(SpkiHash),
+    /// An admin reset the reported conflicts of the trust store.
+    ChainConflictReset,
 }
 
 impl ClientState {
@@ -24,6 +30,7 @@ impl ClientState {
         Self {
             persistent: persistent,
             agents_online: HashSet::new(),
+            chain_conflict: None,
         }
     }
 
@@ -37,6 +44,10 @@ impl ClientState {
                     self.agents_online.remove(&spki_hash);
                 }
             }
+            ClientStateUpdate::ChainConflict(spki_hash) => {
+                self.chain_conflict.get_or_insert(spki_hash);
+            }
+            ClientStateUpdate::ChainConflictReset => self.chain_conflict = None,
         }
     }
 
@@ -44,7 +55,21 @@ impl ClientState {
         self.agents_online.contains(spki_hash)
     }
 
+    pub fn agents_online(&self) -> impl Iterator<Item = &SpkiHash> {
+        self.agents_online.iter()
+    }
+
+    /// The member whose trust store position conflicts with the local one.
+    pub fn chain_conflict(&self) -> Option<&SpkiHash> {
+        self.chain_conflict.as_ref()
+    }
+
     pub fn persistent(&self) -> &HashMap<SpkiHash, persistent::DeviceState> {
         &self.persistent.devices()
     }
+
+    /// Alerts of all devices which no session acknowledged yet.
+    pub fn pending_alerts(&self) -> Vec<&persistent::AlertState> {
+        self.persistent.pending_alerts()
+    }
 }
@@ -1,10 +1,22 @@
 use std::{
+    net::SocketAddr,
+    path::PathBuf,
     sync::{Arc, Mutex},
     time::Duration,
 };
 
-use clap::{Parser, Subcommand};
-use svalin::{agent, installer, server::Server};
+use clap::{Args, Parser, Subcommand};
+use svalin::{
+    agent, installer,
+    server::{
+        Server,
+        admin::{Admin, format_digest},
+        backup::{create_backup, restore_backup},
+        config_file::ServerConfigFile,
+    },
+    util::{key_storage::KeySourceConfig, logging::LogFormat, witness::WitnessPolicy},
+};
+use svalin_pki::SpkiHash;
 
 use tokio::runtime;
 use tokio_util::sync::CancellationToken;
@@ -22,16 +34,112 @@ pub struct App {
 #[derive(Debug, Subcommand)]
 enum Command {
     /// Run in server mode
-    Server { address: String },
+    Server {
+        #[clap(flatten)]
+        options: ServerOptions,
+        #[clap(subcommand)]
+        action: Option<ServerAction>,
+    },
     /// Commands for running the agent
     Agent {
         #[clap(subcommand)]
         action: AgentAction,
     },
+    /// Run as witness node, countersigning the trust store of the server
+    ///
+    /// The witness has its own data directory and has to be initialized with
+    /// `svalin witness init` first.
+    Witness {
+        #[clap(subcommand)]
+        action: Option<WitnessAction>,
+    },
     /// Get version information
     Version,
 }
 
+#[derive(Debug, Args)]
+struct ServerOptions {
+    /// Address to listen on, overrides the addresses from the config file
+    address: Option<SocketAddr>,
+    /// Path of the config file [default: /etc/svalin/server.toml]
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+    /// Directory containing the server data
+    #[clap(long, global = true)]
+    data_dir: Option<PathBuf>,
+    /// Address to listen on, can be given multiple times
+    #[clap(long, global = true)]
+    bind: Vec<SocketAddr>,
+    /// Log filter, e.g. `info` or `svalin=trace`
+    #[clap(long, global = true)]
+    log_level: Option<String>,
+    #[clap(long, global = true, value_enum)]
+    log_format: Option<LogFormat>,
+}
+
+impl ServerOptions {
+    /// Loads the config file and applies the command line overrides.
+    async fn load_config(self) -> anyhow::Result<ServerConfigFile> {
+        let mut config = ServerConfigFile::load(self.config.as_deref()).await?;
+
+        if let Some(data_dir) = self.data_dir {
+            config.data_dir = Some(data_dir);
+        }
+
+        let bind: Vec<_> = self.address.into_iter().chain(self.bind).collect();
+        if !bind.is_empty() {
+            config.bind = bind;
+        }
+
+        if let Some(log_level) = self.log_level {
+            config.log.level = Some(log_level);
+        }
+
+        if let Some(log_format) = self.log_format {
+            config.log.format = log_format;
+        }
+
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum ServerAction {
+    /// Validate the configuration and print the effective values
+    CheckConfig,
+    /// Write an encrypted backup of the data directory, works while the server runs
+    ///
+    /// The passphrase is read from `SVALIN_BACKUP_PASSPHRASE` or from stdin.
+    Backup { output: PathBuf },
+    /// Restore a backup into the data directory, the server has to be stopped
+    ///
+    /// The passphrase is read from `SVALIN_BACKUP_PASSPHRASE` or from stdin.
+    Restore { input: PathBuf },
+    /// Inspect and maintain the server state, the server should be stopped
+    Admin {
+        #[clap(subcommand)]
+        action: AdminAction,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum AdminAction {
+    /// List all users
+    Users,
+    /// List all sessions with their issuing user
+    Sessions,
+    /// Show the number of stored key packages per owner
+    KeyPackages,
+    /// Show the number of pending MLS messages per receiver
+    Messages,
+    /// Print the sequence and digest of the trust store
+    TrustStore,
+    /// Replay and verify the stored trust store transactions
+    VerifyChain,
+    /// Delete sessions of missing users and dangling MLS messages
+    Prune,
+}
+
 #[derive(Debug, Subcommand)]
 enum AgentAction {
     /// Run the agent with the already initiallized config
@@ -46,7 +154,102 @@ enum AgentAction {
     /// Uninstall the agent and delete all data
     Uninstall,
     /// Initialize the agent by connecting to a server
-    Init { address: String },
+    Init {
+        address: String,
+        #[clap(flatten)]
+        key_source: KeySourceArgs,
+    },
+    /// Generate a new agent key, the running agent requests its approval
+    ///
+    /// Once a user approved the rotation, the agent has to be restarted to
+    /// use the new key.
+    RotateKey,
+    /// Require witness signatures before accepting new trust store heads
+    ///
+    /// The agent has to be restarted to use the new witnesses.
+    RequireWitnesses {
+        /// Number of witnesses which have to countersign a new head, 0 disables the check
+        #[clap(long)]
+        required: usize,
+        /// Keys of the accepted witnesses, as printed by `svalin witness`
+        witnesses: Vec<SpkiHash>,
+    },
+    /// Forget reported trust store conflicts once the server was investigated
+    ///
+    /// The agent has to be restarted to trust the trust store again.
+    ResetChainConflict,
+}
+
+#[derive(Debug, Subcommand)]
+enum WitnessAction {
+    /// Initialize the witness by connecting to a server
+    Init {
+        address: String,
+        #[clap(flatten)]
+        key_source: KeySourceArgs,
+    },
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum KeySourceKind {
+    /// Store the key next to the encrypted credentials
+    BuiltIn,
+    /// Store the key in a separate file only readable by its owner
+    KeyFile,
+    /// Derive the key from a passphrase in an environment variable
+    Environment,
+    /// Derive the key from a passphrase entered on every start
+    Passphrase,
+    /// Wrap the key with a key stored on a PKCS#11 token
+    Pkcs11,
+}
+
+#[derive(Debug, Args)]
+struct KeySourceArgs {
+    /// How the key protecting the agent credentials is stored
+    #[clap(long, value_enum, default_value = "built-in")]
+    key_source: KeySourceKind,
+    /// Path of the key file
+    #[clap(long)]
+    key_file: Option<PathBuf>,
+    /// Environment variable containing the passphrase
+    #[clap(long)]
+    key_env: Option<String>,
+    /// Path of the PKCS#11 module
+    #[clap(long)]
+    pkcs11_module: Option<PathBuf>,
+    /// Label of the PKCS#11 token
+    #[clap(long)]
+    pkcs11_token: Option<String>,
+    /// Label of the wrapping key on the PKCS#11 token
+    #[clap(long, default_value = "svalin-agent")]
+    pkcs11_key_label: String,
+    /// Environment variable containing the PIN of the PKCS#11 token
+    #[clap(long, default_value = "SVALIN_PKCS11_PIN")]
+    pkcs11_pin_env: String,
+}
+
+impl KeySourceArgs {
+    fn into_config(self) -> anyhow::Result<KeySourceConfig> {
+        let missing = |flag: &str| anyhow::anyhow!("--{flag} is required for this key source");
+
+        Ok(match self.key_source {
+            KeySourceKind::BuiltIn => KeySourceConfig::BuiltIn,
+            KeySourceKind::KeyFile => KeySourceConfig::KeyFile {
+                path: self.key_file.ok_or_else(|| missing("key-file"))?,
+            },
+            KeySourceKind::Environment => KeySourceConfig::Environment {
+                variable: self.key_env.ok_or_else(|| missing("key-env"))?,
+            },
+            KeySourceKind::Passphrase => KeySourceConfig::Passphrase,
+            KeySourceKind::Pkcs11 => KeySourceConfig::Pkcs11 {
+                module: self.pkcs11_module.ok_or_else(|| missing("pkcs11-module"))?,
+                token: self.pkcs11_token.ok_or_else(|| missing("pkcs11-token"))?,
+                key_label: self.pkcs11_key_label,
+                pin_variable: self.pkcs11_pin_env,
+            },
+        })
+    }
 }
 
 #[cfg(target_os = "windows")]
@@ -136,11 +339,15 @@ fn run_service_agent() -> anyhow::Result<()> {
 }
 
 fn main() {
-    tracing_subscriber::fmt::init();
-
     let app = App::parse();
+
+    // the server configures logging from its config file
+    if !matches!(app.command, Command::Server { .. }) {
+        tracing_subscriber::fmt::init();
+    }
+
     match app.command {
-        Command::Server { address } => run_async(start_server(address)).unwrap(),
+        Command::Server { options, action } => run_async(server(options, action)).unwrap(),
         Command::Agent { action } => match action {
             AgentAction::Run {
                 #[cfg(target_os = "windows")]
@@ -160,8 +367,31 @@ fn main() {
             }
             AgentAction::Install => run_async(installer::install_agent()).unwrap(),
             AgentAction::Uninstall => run_async(installer::uninstall_agent()).unwrap(),
-            AgentAction::Init { address } => run_async(init_agent(address)).unwrap(),
+            AgentAction::Init {
+                address,
+                key_source,
+            } => run_async(init_agent(address, key_source)).unwrap(),
+            AgentAction::RotateKey => run_async(rotate_agent_key()).unwrap(),
+            AgentAction::RequireWitnesses {
+                required,
+                witnesses,
+            } => run_async(agent::set_witness_policy(WitnessPolicy {
+                witnesses,
+                required,
+            }))
+            .unwrap(),
+            AgentAction::ResetChainConflict => run_async(agent::reset_chain_conflict()).unwrap(),
         },
+        Command::Witness { action } => {
+            agent::use_witness_data_dir();
+            match action {
+                None => run_async(run_witness(CancellationToken::new())).unwrap(),
+                Some(WitnessAction::Init {
+                    address,
+                    key_source,
+                }) => run_async(init_agent(address, key_source)).unwrap(),
+            }
+        }
         Command::Version => {
             println!("Commit: {}", svalin::commit())
         }
@@ -175,11 +405,166 @@ fn run_async(fut: impl Future<Output = anyhow::Result<()>>) -> anyhow::Result<()
     Ok(())
 }
 
-async fn start_server(address: String) -> anyhow::Result<()> {
+async fn server(options: ServerOptions, action: Option<ServerAction>) -> anyhow::Result<()> {
+    let config = options.load_config().await?;
+
+    match action {
+        None => {
+            config.log.init()?;
+            start_server(config).await
+        }
+        Some(ServerAction::CheckConfig) => check_config(config),
+        Some(ServerAction::Backup { output }) => {
+            let passphrase = read_backup_passphrase()?;
+            let info = create_backup(&config.data_dir()?, &output, passphrase).await?;
+            println!(
+                "backup written to {} at trust store sequence {}",
+                output.display(),
+                info.trust_store_sequence
+            );
+            Ok(())
+        }
+        Some(ServerAction::Restore { input }) => {
+            let passphrase = read_backup_passphrase()?;
+            let data_dir = config.data_dir()?;
+            let restored = restore_backup(&input, &data_dir, passphrase).await?;
+            println!(
+                "restored backup created at {} (unix time) into {data_dir}",
+                restored.backup.created_at
+            );
+            println!(
+                "trust store sequence {}, digest {}",
+                restored.backup.trust_store_sequence,
+                format_digest(&restored.backup.trust_store_digest)
+            );
+            if let Some(previous) = restored.previous_data_dir {
+                println!("the previous data was moved to {}", previous.display());
+            }
+            Ok(())
+        }
+        Some(ServerAction::Admin { action }) => {
+            tracing_subscriber::fmt()
+                .with_env_filter(config.log.filter()?)
+                .with_writer(std::io::stderr)
+                .init();
+            admin(config, action).await
+        }
+    }
+}
+
+fn read_backup_passphrase() -> anyhow::Result<Vec<u8>> {
+    if let Ok(passphrase) = std::env::var("SVALIN_BACKUP_PASSPHRASE") {
+        return Ok(passphrase.into_bytes());
+    }
+
+    println!("backup passphrase:");
+    let mut passphrase = String::new();
+    std::io::stdin().read_line(&mut passphrase)?;
+    let passphrase = passphrase.trim_end_matches(['\r', '\n']);
+
+    if passphrase.is_empty() {
+        return Err(anyhow::anyhow!("the backup passphrase must not be empty"));
+    }
+
+    Ok(passphrase.as_bytes().to_vec())
+}
+
+async fn admin(config: ServerConfigFile, action: AdminAction) -> anyhow::Result<()> {
+    let admin = Admin::open(config.data_dir()?).await?;
+    let result = run_admin_action(&admin, action).await;
+    admin.close().await;
+
+    result
+}
+
+async fn run_admin_action(admin: &Admin, action: AdminAction) -> anyhow::Result<()> {
+    match action {
+        AdminAction::Users => {
+            for (spki_hash, username) in admin.store.users.list_users().await? {
+                println!("{spki_hash}  {}", String::from_utf8_lossy(&username));
+            }
+        }
+        AdminAction::Sessions => {
+            for (spki_hash, issuer) in admin.store.sessions.list_sessions().await? {
+                println!("{spki_hash}  issued by {issuer}");
+            }
+        }
+        AdminAction::KeyPackages => {
+            for (spki_hash, count) in admin.store.key_packages.count_all_key_packages().await? {
+                println!("{spki_hash}  {count}");
+            }
+        }
+        AdminAction::Messages => {
+            for (spki_hash, count) in admin.store.messages.pending_counts().await? {
+                println!("{spki_hash}  {count}");
+            }
+        }
+        AdminAction::TrustStore => {
+            let trust_store = admin.load_trust_store().await?;
+            println!("root: {}", trust_store.root().spki_hash());
+            println!("sequence: {}", trust_store.sequence());
+            println!("digest: {}", format_digest(&trust_store.digest()));
+        }
+        AdminAction::VerifyChain => {
+            let report = admin.verify_chain().await?;
+            if let Some(checkpoint) = report.checkpoint_sequence {
+                println!("starting from checkpoint at sequence {checkpoint}");
+            }
+            println!("verified {} blocks", report.blocks);
+            println!("sequence: {}", report.sequence);
+            println!("digest: {}", format_digest(&report.digest));
+            match report.snapshot_matches {
+                Some(true) => println!(
+                    "trust store snapshot at sequence {} matches",
+                    report.snapshot_sequence
+                ),
+                Some(false) => {
+                    return Err(anyhow::anyhow!(
+                        "trust store snapshot at sequence {} does not match the transactions",
+                        report.snapshot_sequence
+                    ));
+                }
+                None => println!(
+                    "trust store snapshot at sequence {} is not covered by the stored transactions",
+                    report.snapshot_sequence
+                ),
+            }
+        }
+        AdminAction::Prune => {
+            let report = admin.prune_orphans().await?;
+            println!("deleted {} orphaned sessions", report.sessions);
+            println!(
+                "deleted {} orphaned message receivers",
+                report.message_receivers
+            );
+            println!("deleted {} messages without receivers", report.messages);
+        }
+    }
+
+    Ok(())
+}
+
+fn check_config(config: ServerConfigFile) -> anyhow::Result<()> {
+    config.validate()?;
+
+    println!("data directory: {}", config.data_dir()?);
+    for address in config.bind_addresses() {
+        println!("listening on: {address}");
+    }
+    println!();
+    println!("{}", config.to_toml()?);
+    println!("configuration is valid");
+
+    Ok(())
+}
+
+async fn start_server(config: ServerConfigFile) -> anyhow::Result<()> {
     tracing::trace!("User wants to run server");
 
-    let address = address.parse()?;
-    tracing::trace!("Server address parsed successfully");
+    config.validate()?;
+    let addrs = config.bind_addresses();
+    let data_dir = config.data_dir()?;
+
     let mutex = Arc::new(Mutex::<Option<Server>>::new(None));
     let mutex2 = mutex.clone();
 
@@ -192,8 +577,14 @@ async fn start_server(address: String) -> anyhow::Result<()> {
         // This needs to be in a seperate task since the init server will block on
         // start_server
         let server = Server::build()
-            .addr(address)
+            .addrs(addrs)
             .cancel(cancel2)
+            .data_dir(data_dir)
+            .transport_config(config.transport)
+            .message_retention(config.messages)
+            .trust_store_retention(config.trust_store)
+            .login_limits(config.login)
+            .key_source(config.key_source)
             .start_server()
             .await
             .unwrap();
@@ -222,7 +613,9 @@ async fn start_server(address: String) -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn init_agent(address: String) -> anyhow::Result<()> {
+async fn init_agent(address: String, key_source: KeySourceArgs) -> anyhow::Result<()> {
+    let key_source = key_source.into_config()?;
+
     let mut welcome_message = "-".repeat(40);
     welcome_message.push_str("Svalin Agent");
     welcome_message.push_str("-".repeat(40).as_str());
@@ -230,7 +623,7 @@ async fn init_agent(address: String) -> anyhow::Result<()> {
 
     println!("connecting to {address}...");
 
-    let waiting_for_init = agent::init(address).await?;
+    let waiting_for_init = agent::init_with_key_source(address, key_source).await?;
 
     let cancel = CancellationToken::new();
 
@@ -243,6 +636,26 @@ async fn init_agent(address: String) -> anyhow::Result<()> {
     Ok(())
 }
 
+async fn rotate_agent_key() -> anyhow::Result<()> {
+    let spki_hash = agent::prepare_key_rotation().await?;
+    println!("generated new agent key {spki_hash}");
+    println!("the running agent will request the rotation, it has to be approved by a user");
+    Ok(())
+}
+
+async fn run_witness(cancel: CancellationToken) -> anyhow::Result<()> {
+    let cancel2 = cancel.clone();
+    tokio::spawn(async move {
+        // Wait for shutdown signal
+        tokio::signal::ctrl_c().await.unwrap();
+
+        cancel2.cancel();
+    });
+
+    agent::run_witness(cancel).await?;
+    Ok(())
+}
+
 async fn run_agent(cancel: CancellationToken) -> anyhow::Result<()> {
     let cancel2 = cancel.clone();
     tokio::spawn(async move {
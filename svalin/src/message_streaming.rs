@@ -3,7 +3,10 @@ use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 use svalin_pki::{
     SpkiHash,
-    mls::transport_types::{MessageToMemberTransport, MessageToServerTransport},
+    mls::{
+        key_package::UnverifiedKeyPackage,
+        transport_types::{MessageToMemberTransport, MessageToServerTransport},
+    },
     secure_chain::UncheckedBlock,
     trust_store,
 };
@@ -23,6 +26,8 @@ pub enum MessageToAgent {
 #[derive(Serialize, Deserialize, Debug)]
 pub enum MessageFromAgent {
     Mls(MessageToServerTransport),
+    /// Key packages for being added to the global group.
+    KeyPackages(Vec<UnverifiedKeyPackage>),
     Goodbye,
 }
 
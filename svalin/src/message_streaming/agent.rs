@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
 use anyhow::anyhow;
+use svalin_pki::{SpkiHash, mls::agent::AgentMessageContent};
 use svalin_rpc::rpc::command::{dispatcher::CommandDispatcher, handler::CommandHandler};
 use tokio::sync::{mpsc, oneshot};
 use tokio_util::sync::CancellationToken;
@@ -10,7 +11,7 @@ use crate::{
         MessageFromAgent, MessageToAgent,
         with_agent::{MessageHandler, MessageSender},
     },
-    mls::MlsAgent,
+    mls::{AgentInstruction, MlsAgent},
 };
 
 #[derive(Clone)]
@@ -79,6 +80,7 @@ impl CommandDispatcher for AgentMessageDispatcher {
 pub struct AgentMessageReceiver {
     pub sender: AgentMessageDispatcherHandle,
     pub mls: Arc<MlsAgent>,
+    pub instructions: mpsc::Sender<(SpkiHash, AgentInstruction)>,
     pub cancel: CancellationToken,
 }
 
@@ -130,10 +132,28 @@ impl AgentMessageReceiver {
     async fn handle(&self, message: MessageToAgent) -> Result<bool, anyhow::Error> {
         match message {
             MessageToAgent::Mls(message) => {
-                self.mls
+                let content = self
+                    .mls
                     .handle_message(&message)
                     .await
                     .map_err(|err| anyhow!(err))?;
+
+                match content {
+                    AgentMessageContent::Instruction(sender, instruction) => {
+                        self.instructions
+                            .send((sender, instruction))
+                            .await
+                            .map_err(|_| anyhow!("instruction handler stopped"))?;
+                    }
+                    AgentMessageContent::Other(sender, message) => {
+                        tracing::debug!(
+                            "ignoring message of type {} in version {} from {sender}",
+                            message.tag(),
+                            message.version()
+                        );
+                    }
+                    AgentMessageContent::Internal => {}
+                }
             }
             MessageToAgent::Goodbye => return Ok(true),
         }
@@ -1,7 +1,7 @@
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 use anyhow::{Context, anyhow};
-use svalin_pki::mls::client::MessageDataContent;
+use svalin_pki::{mls::client::MessageDataContent, trust_store::TrustStore};
 use svalin_rpc::rpc::command::{dispatcher::CommandDispatcher, handler::CommandHandler};
 use svalin_store::client_store::{ClientStore, persistent};
 use tokio::sync::{broadcast, mpsc, oneshot};
@@ -14,6 +14,7 @@ use crate::{
         with_client::{MessageHandler, MessageSender},
     },
     mls::MlsClient,
+    util::chain_gossip::{ComparePositionError, compare_position},
 };
 
 #[derive(Clone)]
@@ -94,6 +95,8 @@ impl CommandDispatcher for ClientMessageDispatcher {
 pub struct ClientMessageReceiver {
     _to_server: ClientMessageDispatcherHandle,
     mls: Arc<MlsClient>,
+    trust_store: Arc<RwLock<TrustStore>>,
+    store: Arc<ClientStore>,
     cancel: CancellationToken,
     update_sender: mpsc::Sender<ClientStateRequest>,
 }
@@ -102,14 +105,17 @@ impl ClientMessageReceiver {
     pub async fn initialize(
         sender: ClientMessageDispatcherHandle,
         mls: Arc<MlsClient>,
+        trust_store: Arc<RwLock<TrustStore>>,
         cancel: CancellationToken,
         store: Arc<ClientStore>,
     ) -> Result<(Self, ClientStateHandle), anyhow::Error> {
-        let state_handle = ClientStateHandle::initialize(store).await?;
+        let state_handle = ClientStateHandle::initialize(store.clone()).await?;
 
         let me = Self {
             _to_server: sender,
             mls,
+            trust_store,
+            store,
             cancel,
             update_sender: state_handle.channel.clone(),
         };
@@ -179,6 +185,18 @@ impl ClientMessageReceiver {
 
                 match message.content {
                     MessageDataContent::Internal => {}
+                    MessageDataContent::Other(sender, payload) => {
+                        self.update_client_state(ClientStateUpdate::Persistent(
+                            persistent::Message::Received {
+                                group: message.group,
+                                sender,
+                                message: payload,
+                            },
+                        ))
+                        .await;
+                    }
+                    // instructions of other users are only executed by the agent
+                    MessageDataContent::Instruction(_, _) => {}
                     MessageDataContent::Report(spki_hash, report) => {
                         self.update_client_state(ClientStateUpdate::Persistent(
                             persistent::Message::UpdateSystemReport(spki_hash, report),
@@ -191,6 +209,21 @@ impl ClientMessageReceiver {
                         ))
                         .await;
                     }
+                    MessageDataContent::ChainPosition(_, position) => {
+                        let result = compare_position(
+                            &self.trust_store,
+                            self.store.transaction_store(),
+                            &position,
+                        )
+                        .await;
+                        if let Err(ComparePositionError::SplitView(peer)) = &result {
+                            self.update_client_state(ClientStateUpdate::ChainConflict(
+                                peer.clone(),
+                            ))
+                            .await;
+                        }
+                        result?;
+                    }
                 }
             }
             MessageToClient::AgentOnlineStatus(spki_hash, online) => {
@@ -1,7 +1,10 @@
 use std::sync::Arc;
 
-use anyhow::anyhow;
-use svalin_pki::{Certificate, TrustStoreVerifier, mls::transport_types::MessageToServerTransport};
+use anyhow::{Context, anyhow};
+use svalin_pki::{
+    Certificate, TrustStoreVerifier,
+    mls::{key_package::UnverifiedKeyPackage, transport_types::MessageToServerTransport},
+};
 use svalin_store::server_store::{KeyPackageStore, MessageStore};
 
 pub struct MlsMessageHandler {
@@ -23,10 +26,27 @@ impl MlsMessageHandler {
             .await
             .map_err(|err| anyhow!(err))?;
         for mut to_send in messages_to_send {
-            to_send.remove_receiver(sender.spki_hash());
+            to_send.remove_sender(sender.spki_hash());
             self.message_store.add_message(to_send).await?;
         }
 
         Ok(())
     }
+
+    pub async fn add_key_packages(
+        &self,
+        sender: &Certificate,
+        key_packages: Vec<UnverifiedKeyPackage>,
+    ) -> Result<(), anyhow::Error> {
+        for key_package in key_packages {
+            let key_package = self
+                .mls_server
+                .verify_key_package(key_package, sender.spki_hash())
+                .await
+                .context("error verifying key package")?;
+            self.key_package_store.add_key_package(key_package).await?;
+        }
+
+        Ok(())
+    }
 }
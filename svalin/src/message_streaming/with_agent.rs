@@ -26,6 +26,11 @@ impl MessageHandler {
         tracing::trace!("handling agent message: {:?}", message);
         match message {
             MessageFromAgent::Mls(mls) => self.mls_handler.handle(agent, mls).await.map(|_| false),
+            MessageFromAgent::KeyPackages(key_packages) => self
+                .mls_handler
+                .add_key_packages(agent, key_packages)
+                .await
+                .map(|_| false),
             MessageFromAgent::Goodbye => Ok(true),
         }
     }
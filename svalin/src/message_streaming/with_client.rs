@@ -276,24 +276,11 @@ async fn stream_mls_messages_inner<Message>(
     message_store: Arc<MessageStore>,
     transform: fn(Arc<MessageToMemberTransport>) -> Message,
 ) -> Result<(), anyhow::Error> {
-    let current = message_store.load_all_for(&receiver).await?;
-    let mut subscription = message_store.subscribe(receiver.clone()).await;
-    for message in current {
-        let (send, recv) = oneshot::channel();
-
-        let _ = sender
-            .send((transform(Arc::new(message.1)), Some(send)))
-            .await;
-
-        recv.await?
-            .map_err(|_| anyhow!("client encountered error with message"))?;
-
-        message_store
-            .aknowledge_single_message(&receiver, message.0)
-            .await?;
-    }
+    // subscribing first loads the stored messages, so nothing sent in between
+    // is missed
+    let mut subscription = message_store.subscribe(receiver.clone());
 
-    while let Some(message) = subscription.recv().await {
+    while let Some(message) = subscription.recv().await? {
         tracing::trace!("received message from subscription: {:?}", message);
         let (send, recv) = oneshot::channel();
 
@@ -1,9 +1,13 @@
+use std::time::Duration;
+
+use rand::RngExt;
 use serde::{Deserialize, Serialize};
-use svalin_pki::TrustStoreVerifier;
+use svalin_pki::{TrustStoreVerifier, mls::transport_types::MessagePayload};
 use svalin_store::client_store::persistent::{SvalinMetaInfo, SvalinReport};
 
 use crate::{
     remote_key_retriever::RemoteKeyRetriever, server::local_key_retriever::LocalKeyRetriever,
+    util::witness::WitnessPolicy,
 };
 
 #[derive(Serialize, Deserialize)]
@@ -13,6 +17,63 @@ impl svalin_pki::mls::transport_types::MessageTypes for MlsTypes {
     type Report = SvalinReport;
 
     type MetaInfo = SvalinMetaInfo;
+
+    type Instruction = AgentInstruction;
+}
+
+/// End-to-end encrypted instructions, sent by users to the device group of an
+/// agent, or by root to all agents through the global group.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum AgentInstruction {
+    /// Sends a new system report right away.
+    SendSystemReport,
+    /// Changes the witnesses the agent requires, applied on the next restart.
+    SetWitnessPolicy(WitnessPolicy),
+    /// An organization wide announcement, which is logged by the agent.
+    Announcement(String),
+}
+
+/// How often members commit a new key to their groups. A leaked epoch secret
+/// only reveals the messages sent until the next update.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct KeyUpdateInterval {
+    seconds: u64,
+}
+
+impl KeyUpdateInterval {
+    const DEFAULT: Self = Self::from_secs(60 * 60 * 24);
+
+    pub const fn from_secs(seconds: u64) -> Self {
+        Self { seconds }
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.seconds)
+    }
+
+    /// A random delay of up to one interval, at most an hour, before the
+    /// first update. Members starting together would otherwise all commit
+    /// on the same epoch, and all but one of the commits are refused.
+    pub fn first_update_delay(&self) -> Duration {
+        let max = self.seconds.min(60 * 60);
+        Duration::from_secs(rand::rng().random_range(0..=max))
+    }
+
+    pub fn is_default(&self) -> bool {
+        self == &Self::DEFAULT
+    }
+}
+
+impl Default for KeyUpdateInterval {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl MessagePayload for AgentInstruction {
+    const TAG: &'static str = "instruction";
+    const VERSION: u16 = 1;
 }
 
 pub type MlsClient =
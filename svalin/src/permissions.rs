@@ -11,6 +11,7 @@ use svalin_rpc::{
 use crate::{
     message_streaming::{with_agent, with_client},
     shared::commands::{
+        compare_chain_position::CompareChainPositionHandler,
         get_key_packages::GetKeyPackagesHandler, get_user_credentials::GetUserCredentialHandler,
         load_certificate_chain::LoadCertificateChainHandler,
         public_server_status::PublicStatusHandler,
@@ -122,6 +123,12 @@ impl From<&PermissionPrecursor<RequestSystemReportHandler>> for Permission {
     }
 }
 
+impl From<&PermissionPrecursor<CompareChainPositionHandler>> for Permission {
+    fn from(_value: &PermissionPrecursor<CompareChainPositionHandler>) -> Self {
+        Permission::SessionOnly
+    }
+}
+
 impl From<&PermissionPrecursor<UpdateAgentHandler>> for Permission {
     fn from(_value: &PermissionPrecursor<UpdateAgentHandler>) -> Self {
         Permission::SessionOnly
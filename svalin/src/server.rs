@@ -11,16 +11,19 @@ use openmls_sqlx_storage::SqliteStorageProvider;
 use rand::RngExt;
 use serde::{Deserialize, Serialize};
 use svalin_pki::{
-    Credential, EncryptedCredential, TrustStoreVerifier, Verifier,
+    Credential, EncryptedCredential, TrustStoreVerifier, Verifier, get_current_timestamp,
     trust_store::{self, TrustStore},
 };
 use svalin_rpc::{
     permissions::{DummyPermission, anonymous_permission_handler::AnonymousPermissionHandler},
-    rpc::{command::handler::HandlerCollection, server::Socket},
+    rpc::{
+        command::handler::HandlerCollection, server::Socket, transport_config::QuicTransportConfig,
+    },
     verifiers::skip_verify::SkipClientVerification,
 };
-use svalin_store::server_store::{ServerStore, UserStore};
+use svalin_store::server_store::{KeyPackageStore, ServerStore, UserStore};
 use tokio::{
+    select,
     sync::oneshot,
     time::{error::Elapsed, timeout},
 };
@@ -31,11 +34,15 @@ use crate::{
     server::local_key_retriever::LocalKeyRetriever,
     shared::commands::{
         init::{InitHandler, ServerInitSuccess},
+        login::LoginLimits,
         public_server_status::{PublicStatus, PublicStatusHandler},
+        witness_signature::WitnessSignatures,
     },
     util::{
-        key_storage::KeySource,
+        expiry::log_expiring_certificates,
+        key_storage::{KeySource, KeySourceConfig},
         location::{Location, LocationError},
+        renewal::renewed_credential,
         trust_store::save_trust_store,
     },
     verifier::tls_optional_wrapper::TlsOptionalWrapper,
@@ -43,17 +50,34 @@ use crate::{
 
 use svalin_rpc::rpc::server::RpcServer;
 
+pub mod admin;
+pub mod backup;
 pub mod chain_loader;
+mod checkpoint;
 pub mod command_builder;
 pub mod config_builder;
+pub mod config_file;
 pub mod local_key_retriever;
+mod renewal;
+pub(crate) mod retention;
+
+use config_file::{MessageRetention, TrustStoreRetention};
 
 pub type MlsServer = svalin_pki::mls::server::MlsServer<LocalKeyRetriever, TrustStoreVerifier>;
 
+/// How often key packages whose lifetime ended are deleted.
+const KEY_PACKAGE_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 #[derive(Debug)]
 pub struct ServerConfig {
-    addr: SocketAddr,
+    addrs: Vec<SocketAddr>,
     cancelation_token: CancellationToken,
+    data_dir: Location,
+    transport: QuicTransportConfig,
+    message_retention: MessageRetention,
+    trust_store_retention: TrustStoreRetention,
+    login_limits: LoginLimits,
+    key_source: KeySourceConfig,
 }
 
 pub const INIT_SERVER_SHUTDOWN_COUNTDOWN: Duration = Duration::from_secs(1);
@@ -84,20 +108,47 @@ impl Server {
         config_builder::new()
     }
 
-    fn data_dir() -> Result<Location, LocationError> {
-        Ok(Location::system_data_dir()?.push("server"))
+    /// The data directory used if none is configured.
+    ///
+    /// Older versions stored the server data inside the agent directory, which
+    /// is still used if it exists and the new location doesn't.
+    pub fn default_data_dir() -> Result<Location, LocationError> {
+        let data_dir = Location::system_data_dir()?.push("server");
+
+        #[cfg(target_os = "linux")]
+        {
+            let legacy = Location::system_data_dir()?.push("agent").push("server");
+            if !data_dir.as_path().exists() && legacy.as_path().exists() {
+                tracing::warn!(
+                    "using legacy server data directory {legacy}, consider moving it to {data_dir}"
+                );
+                return Ok(legacy);
+            }
+        }
+
+        Ok(data_dir)
     }
 
-    fn base_config_path() -> Result<Location, LocationError> {
-        Ok(Self::data_dir()?.push("base_config.json"))
+    fn base_config_path(data_dir: &Location) -> Location {
+        data_dir.clone().push("base_config.json")
     }
 
-    fn trust_store_path() -> Result<Location, LocationError> {
-        Ok(Self::data_dir()?.push("trust_store.json"))
+    fn trust_store_path(data_dir: &Location) -> Location {
+        data_dir.clone().push("trust_store.json")
     }
 
-    async fn get_base_config() -> anyhow::Result<Option<BaseConfig>> {
-        let location = Self::base_config_path()?.ensure_parent_exists().await?;
+    fn db_path(data_dir: &Location) -> Location {
+        data_dir.clone().push("db.sqlite")
+    }
+
+    fn mls_store_path(data_dir: &Location) -> Location {
+        data_dir.clone().push("mls-store.sqlite")
+    }
+
+    async fn get_base_config(data_dir: &Location) -> anyhow::Result<Option<BaseConfig>> {
+        let location = Self::base_config_path(data_dir)
+            .ensure_parent_exists()
+            .await?;
         if tokio::fs::try_exists(&location).await? {
             let config = tokio::fs::read(&location).await?;
             let config: SavedConfig = serde_json::from_slice(&config)?;
@@ -106,7 +157,7 @@ impl Server {
                 .decrypt_credentials(config.credential)
                 .await?;
 
-            let trust_store = tokio::fs::read(Self::trust_store_path()?).await?;
+            let trust_store = tokio::fs::read(Self::trust_store_path(data_dir)).await?;
             let trust_store: trust_store::Exported =
                 serde_json::from_slice(trust_store.as_slice())?;
             let trust_store = TrustStore::import(trust_store)?;
@@ -123,9 +174,13 @@ impl Server {
         }
     }
 
-    async fn save_base_config(config: &BaseConfig) -> Result<()> {
-        let location = Self::base_config_path()?;
-        let key_source = KeySource::generate_builtin()?;
+    async fn save_base_config(
+        data_dir: &Location,
+        config: &BaseConfig,
+        key_source: &KeySourceConfig,
+    ) -> Result<()> {
+        let location = Self::base_config_path(data_dir);
+        let key_source = key_source.generate().await?;
         let trust_store = config.trust_store.read().unwrap().export();
         let config = SavedConfig {
             credential: key_source.encrypt_credential(&config.credential).await?,
@@ -134,15 +189,27 @@ impl Server {
         };
         let config = serde_json::to_vec_pretty(&config)?;
         tokio::fs::write(&location, config).await?;
-        save_trust_store(&Self::trust_store_path()?, &trust_store).await?;
+        save_trust_store(&Self::trust_store_path(data_dir), &trust_store).await?;
+        Ok(())
+    }
+
+    /// Replaces the saved credential, keeping the key source.
+    async fn save_credential(data_dir: &Location, credential: &Credential) -> Result<()> {
+        let location = Self::base_config_path(data_dir);
+        let config = tokio::fs::read(&location).await?;
+        let mut config: SavedConfig = serde_json::from_slice(&config)?;
+        config.credential = config.key_source.encrypt_credential(credential).await?;
+        let config = serde_json::to_vec_pretty(&config)?;
+        tokio::fs::write(&location, config).await?;
         Ok(())
     }
 
     async fn open_mls_server(
+        data_dir: &Location,
         verifier: TrustStoreVerifier,
         key_retriever: LocalKeyRetriever,
     ) -> Result<Arc<MlsServer>> {
-        let location = Self::data_dir()?.push("mls-store.sqlite");
+        let location = Self::mls_store_path(data_dir);
         let storage_provider = SqliteStorageProvider::open(location.as_path()).await?;
 
         let mls = MlsServer::new(storage_provider, verifier, key_retriever);
@@ -151,12 +218,15 @@ impl Server {
     }
 
     async fn start(config: ServerConfig) -> Result<Self> {
-        let base_config = Self::get_base_config()
+        let data_dir = &config.data_dir;
+        tracing::info!("using data directory {data_dir}");
+
+        let base_config = Self::get_base_config(data_dir)
             .await
             .context("error opening config")?;
 
         // tracing::trace!("opening DB");
-        let db_path = Self::data_dir()?.push("db.sqlite");
+        let db_path = Self::db_path(data_dir);
         tracing::trace!("opening server db: {db_path}");
         // tracing::trace!("opening server store at: {}", &db_path);
         let store = ServerStore::open(&db_path)
@@ -165,7 +235,14 @@ impl Server {
 
         // tracing::trace!("creating socket");
 
-        let socket = RpcServer::create_socket(config.addr).context("failed to create socket")?;
+        let sockets = config
+            .addrs
+            .iter()
+            .map(|addr| {
+                RpcServer::create_socket(*addr)
+                    .with_context(|| format!("failed to create socket on {addr}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
 
         let base_config = match base_config {
             Some(conf) => conf,
@@ -175,7 +252,7 @@ impl Server {
                 tracing::trace!("Server is not yet initialized, starting initialization routine");
 
                 let init_success = Self::init_server(
-                    socket.clone(),
+                    sockets.clone(),
                     config.cancelation_token.child_token(),
                     store.users.clone(),
                 )
@@ -198,7 +275,7 @@ impl Server {
                     pseudo_data_seed,
                 };
 
-                Self::save_base_config(&conf).await?;
+                Self::save_base_config(data_dir, &conf, &config.key_source).await?;
 
                 conf
             }
@@ -207,7 +284,53 @@ impl Server {
         let trust_store = base_config.trust_store;
         let root = trust_store.read().unwrap().root().clone();
 
-        let credentials = base_config.credential;
+        // the blocks of the snapshot aren't stored, but the Merkle index
+        // needs their leaves
+        let snapshot_block = trust_store.read().unwrap().last_block().cloned();
+        if let Some(block) = snapshot_block {
+            store
+                .trust_store_transactions
+                .keep_leaf(&block)
+                .await
+                .context("failed to keep the leaf of the trust store snapshot")?;
+        }
+
+        // the snapshot is only written on initialization, later transactions
+        // have to be replayed from the store, starting at the latest checkpoint
+        // if the older ones were pruned
+        let checkpoint = store
+            .trust_store_transactions
+            .latest_checkpoint()
+            .await
+            .context("failed to load trust store checkpoint")?
+            .filter(|(checkpoint, _)| {
+                checkpoint.sequence() > trust_store.read().unwrap().sequence()
+            });
+        if let Some((checkpoint, state)) = checkpoint {
+            *trust_store.write().unwrap() =
+                TrustStore::restore_checkpoint(state, &checkpoint, &root)
+                    .context("failed to import trust store checkpoint")?;
+        }
+        let sequence = trust_store.read().unwrap().sequence();
+        let (blocks, _) = store
+            .trust_store_transactions
+            .load_all_after(sequence)
+            .await
+            .context("failed to load trust store transactions")?;
+        for block in blocks {
+            let mut guard = trust_store.write().unwrap();
+            let block = guard.check(block)?;
+            guard.apply(block);
+        }
+        log_expiring_certificates(&trust_store.read().unwrap());
+
+        let mut credentials = base_config.credential;
+        let renewed = renewed_credential(&credentials, &trust_store.read().unwrap())?;
+        if let Some(renewed) = renewed {
+            tracing::info!("using renewed server certificate");
+            Self::save_credential(data_dir, &renewed).await?;
+            credentials = renewed;
+        }
 
         let verifier = TrustStoreVerifier::new(trust_store.clone());
 
@@ -217,29 +340,75 @@ impl Server {
             store.key_packages.clone(),
         );
 
-        let mls = Self::open_mls_server(verifier.clone(), key_retriever).await?;
+        let mls = Self::open_mls_server(data_dir, verifier.clone(), key_retriever).await?;
 
         let tls_verifier = TlsOptionalWrapper::new(verifier.clone().to_tls_verifier());
 
+        let witnesses = WitnessSignatures::load(store.trust_store_transactions.clone())
+            .await
+            .context("failed to load witness signatures")?;
+
         let command_builder = SvalinCommandBuilder {
             trust_store: trust_store.clone(),
             root_cert: root,
             server_cert: credentials.certificate().clone(),
             store,
             mls: mls.clone(),
+            witnesses: Arc::new(witnesses),
+            login_limits: config.login_limits.clone(),
         };
 
         let tasks = TaskTracker::new();
 
         let store_close_handle = command_builder.store.close_handle();
 
+        command_builder
+            .store
+            .messages
+            .set_limits(config.message_retention.limits());
+
+        tasks.spawn(retention::sweep_messages(
+            command_builder.store.messages.clone(),
+            command_builder.store.sessions.clone(),
+            trust_store.clone(),
+            config.message_retention.clone(),
+            config.cancelation_token.clone(),
+        ));
+
+        tasks.spawn(Self::sweep_key_packages(
+            command_builder.store.key_packages.clone(),
+            KEY_PACKAGE_SWEEP_INTERVAL,
+            config.cancelation_token.clone(),
+        ));
+
+        if config.trust_store_retention.checkpoint_interval_secs > 0 {
+            tasks.spawn(checkpoint::manage_checkpoints(
+                credentials.clone(),
+                trust_store.clone(),
+                command_builder.store.trust_store_transactions.clone(),
+                Duration::from_secs(config.trust_store_retention.checkpoint_interval_secs),
+                config.trust_store_retention.keep_checkpoints,
+                config.cancelation_token.clone(),
+            ));
+        }
+
+        tasks.spawn(renewal::manage_renewals(
+            data_dir.clone(),
+            credentials.clone(),
+            trust_store.clone(),
+            command_builder.store.renewals.clone(),
+            command_builder.store.trust_store_transactions.clone(),
+            config.cancelation_token.clone(),
+        ));
+
         let rpc = RpcServer::build()
             .credentials(credentials.clone())
             .client_cert_verifier(tls_verifier)
             .cancellation_token(config.cancelation_token.clone())
             .commands(command_builder)
             .task_tracker(tasks.clone())
-            .start_server(socket)
+            .transport_config(config.transport.clone())
+            .start_server_on(sockets)
             .await?;
 
         Ok(Self {
@@ -251,7 +420,7 @@ impl Server {
     }
 
     async fn init_server(
-        socket: Socket,
+        sockets: Vec<Socket>,
         cancel: CancellationToken,
         user_store: Arc<UserStore>,
     ) -> Result<ServerInitSuccess> {
@@ -275,7 +444,7 @@ impl Server {
             .client_cert_verifier(SkipClientVerification::new())
             .commands(commands)
             .task_tracker(TaskTracker::new())
-            .start_server(socket)
+            .start_server_on(sockets)
             .await?;
 
         tracing::trace!("init server running");
@@ -291,6 +460,30 @@ impl Server {
         }
     }
 
+    /// Deletes key packages whose lifetime ended, members can't join groups
+    /// with them anymore.
+    async fn sweep_key_packages(
+        key_packages: Arc<KeyPackageStore>,
+        interval: Duration,
+        cancel: CancellationToken,
+    ) {
+        loop {
+            match key_packages
+                .delete_expired_key_packages(get_current_timestamp())
+                .await
+            {
+                Ok(0) => {}
+                Ok(deleted) => tracing::info!("deleted {deleted} expired key packages"),
+                Err(err) => tracing::error!("failed to delete expired key packages: {err}"),
+            }
+
+            select! {
+                _ = cancel.cancelled() => return,
+                _ = tokio::time::sleep(interval) => {}
+            }
+        }
+    }
+
     pub async fn close(&self, timeout_duration: Duration) -> Result<(), Elapsed> {
         self.config.cancelation_token.cancel();
         let result1 = self.rpc.close(timeout_duration).await;
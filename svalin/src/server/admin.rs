@@ -0,0 +1,148 @@
+use anyhow::{Context, Result, anyhow};
+use svalin_pki::{
+    secure_chain::ChainDigest,
+    trust_store::{self, TrustStore},
+};
+use svalin_store::server_store::ServerStore;
+
+use crate::util::location::Location;
+
+use super::Server;
+
+/// Offline access to the state of a server, used by `svalin server admin`.
+///
+/// The server should not be running while the state is modified.
+pub struct Admin {
+    pub store: ServerStore,
+    data_dir: Location,
+}
+
+/// Result of replaying the stored trust store transactions.
+#[derive(Debug)]
+pub struct ChainReport {
+    /// Number of blocks in the transaction store.
+    pub blocks: u64,
+    /// Sequence of the checkpoint the replay started from, if older
+    /// transactions were pruned.
+    pub checkpoint_sequence: Option<u64>,
+    pub sequence: u64,
+    pub digest: ChainDigest,
+    /// Sequence of the trust store snapshot in the data directory.
+    pub snapshot_sequence: u64,
+    /// Whether the snapshot matches the replayed chain at its sequence.
+    /// `None` if the stored transactions don't cover the snapshot sequence.
+    pub snapshot_matches: Option<bool>,
+}
+
+#[derive(Debug, Default)]
+pub struct PruneReport {
+    pub sessions: u64,
+    pub message_receivers: u64,
+    pub messages: u64,
+}
+
+impl Admin {
+    pub async fn open(data_dir: Location) -> Result<Self> {
+        let db_path = Server::db_path(&data_dir);
+        if !db_path.as_path().exists() {
+            return Err(anyhow!("no server database found at {db_path}"));
+        }
+
+        let store = ServerStore::open(&db_path)
+            .await
+            .context("error opening server store")?;
+
+        Ok(Self { store, data_dir })
+    }
+
+    pub async fn close(self) {
+        self.store.close_handle().close().await
+    }
+
+    /// Loads the trust store snapshot from the data directory.
+    pub async fn load_trust_store(&self) -> Result<TrustStore> {
+        let path = Server::trust_store_path(&self.data_dir);
+        let exported = tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("failed to read trust store {path}"))?;
+        let exported: trust_store::Exported =
+            serde_json::from_slice(&exported).context("failed to deserialize trust store")?;
+
+        TrustStore::import(exported).context("failed to import trust store")
+    }
+
+    /// Replays every stored transaction starting from the root certificate,
+    /// or from the oldest checkpoint if the history was pruned, and compares
+    /// the result with the trust store snapshot.
+    pub async fn verify_chain(&self) -> Result<ChainReport> {
+        let snapshot = self.load_trust_store().await?;
+        let snapshot_sequence = snapshot.sequence();
+
+        let checkpoint = self
+            .store
+            .trust_store_transactions
+            .oldest_checkpoint()
+            .await
+            .context("failed to load trust store checkpoint")?;
+        let (mut chain, checkpoint_sequence) = match checkpoint {
+            Some((checkpoint, state)) => (
+                TrustStore::restore_checkpoint(state, &checkpoint, snapshot.root())
+                    .context("checkpoint failed verification")?,
+                Some(checkpoint.sequence()),
+            ),
+            None => (TrustStore::initialize(snapshot.root().clone()), None),
+        };
+
+        let (blocks, _) = self
+            .store
+            .trust_store_transactions
+            .load_all_after(chain.sequence())
+            .await
+            .context("failed to load trust store transactions")?;
+
+        let mut snapshot_matches =
+            (snapshot_sequence == chain.sequence()).then(|| chain.digest() == snapshot.digest());
+        let block_count = blocks.len() as u64;
+
+        for block in blocks {
+            let sequence = block.sequence();
+            let block = chain
+                .check(block)
+                .with_context(|| format!("block {sequence} failed verification"))?;
+            chain.apply(block);
+
+            if sequence == snapshot_sequence {
+                snapshot_matches = Some(chain.digest() == snapshot.digest());
+            }
+        }
+
+        Ok(ChainReport {
+            blocks: block_count,
+            checkpoint_sequence,
+            sequence: chain.sequence(),
+            digest: chain.digest(),
+            snapshot_sequence,
+            snapshot_matches,
+        })
+    }
+
+    /// Deletes rows which reference data that no longer exists.
+    pub async fn prune_orphans(&self) -> Result<PruneReport> {
+        let sessions = self.store.sessions.prune_orphans().await?;
+        let (message_receivers, messages) = self.store.messages.prune_orphans().await?;
+
+        Ok(PruneReport {
+            sessions,
+            message_receivers,
+            messages,
+        })
+    }
+}
+
+pub fn format_digest(digest: &ChainDigest) -> String {
+    digest
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect()
+}
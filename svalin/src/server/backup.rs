@@ -0,0 +1,248 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use svalin_pki::{ArgonParams, EncryptedObject, get_current_timestamp, secure_chain::ChainDigest};
+use tokio::io::AsyncWriteExt;
+
+use crate::util::location::Location;
+
+use super::{
+    Server,
+    admin::{Admin, ChainReport},
+};
+
+const BACKUP_VERSION: u32 = 1;
+
+/// The file written by [`create_backup`]. Only the parameters needed to
+/// derive the key from the passphrase are stored unencrypted.
+#[derive(Serialize, Deserialize)]
+struct BackupFile {
+    version: u32,
+    params: ArgonParams,
+    contents: EncryptedObject<BackupContents>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BackupContents {
+    created_at: u64,
+    /// Digest of the trust store after replaying the transactions in `db`.
+    trust_store_digest: ChainDigest,
+    base_config: Vec<u8>,
+    trust_store: Vec<u8>,
+    db: Vec<u8>,
+    mls_store: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct BackupInfo {
+    pub created_at: u64,
+    pub trust_store_sequence: u64,
+    pub trust_store_digest: ChainDigest,
+}
+
+#[derive(Debug)]
+pub struct Restored {
+    pub backup: BackupInfo,
+    /// Where the replaced data directory was moved to, if there was one.
+    pub previous_data_dir: Option<PathBuf>,
+}
+
+/// Writes an encrypted backup of the server data directory to `target`.
+///
+/// The databases are copied with [`svalin_store::snapshot_database`], so the
+/// server may keep running while the backup is created. The recorded trust
+/// store state is replayed from the copied database.
+pub async fn create_backup(
+    data_dir: &Location,
+    target: &Path,
+    passphrase: Vec<u8>,
+) -> Result<BackupInfo> {
+    let snapshot_dir =
+        Location::system_temp_dir()?.push(format!("backup-{}", uuid::Uuid::new_v4()));
+    tokio::fs::create_dir_all(&snapshot_dir).await?;
+
+    let result = snapshot_data_dir(data_dir, &snapshot_dir).await;
+    let _ = tokio::fs::remove_dir_all(&snapshot_dir).await;
+    let (contents, info) = result?;
+
+    let params = ArgonParams::strong();
+    let key = params.derive_encryption_key(passphrase).await?;
+    let backup = BackupFile {
+        version: BACKUP_VERSION,
+        params,
+        contents: EncryptedObject::encrypt(&contents, &key)?,
+    };
+    let backup = postcard::to_stdvec(&backup)?;
+
+    let mut file = tokio::fs::File::options()
+        .create_new(true)
+        .write(true)
+        .open(target)
+        .await
+        .with_context(|| format!("failed to create backup file {}", target.display()))?;
+    file.write_all(&backup).await?;
+    file.flush().await?;
+    file.sync_all().await?;
+
+    Ok(info)
+}
+
+async fn snapshot_data_dir(
+    data_dir: &Location,
+    snapshot_dir: &Location,
+) -> Result<(BackupContents, BackupInfo)> {
+    let db = Server::db_path(snapshot_dir);
+    svalin_store::snapshot_database(Server::db_path(data_dir), &db)
+        .await
+        .context("failed to snapshot server database")?;
+
+    let mls_store = Server::mls_store_path(snapshot_dir);
+    svalin_store::snapshot_database(Server::mls_store_path(data_dir), &mls_store)
+        .await
+        .context("failed to snapshot mls store")?;
+
+    let base_config = tokio::fs::read(Server::base_config_path(data_dir))
+        .await
+        .context("failed to read base config")?;
+    let trust_store = tokio::fs::read(Server::trust_store_path(data_dir))
+        .await
+        .context("failed to read trust store")?;
+    let db = tokio::fs::read(&db).await?;
+
+    // the server only writes the trust store snapshot on initialization, its
+    // current state is the one of the transactions in the database snapshot
+    tokio::fs::write(Server::trust_store_path(snapshot_dir), &trust_store).await?;
+    let report = replay_trust_store(snapshot_dir)
+        .await
+        .context("backed up trust store is invalid")?;
+
+    let created_at = get_current_timestamp();
+    let contents = BackupContents {
+        created_at,
+        trust_store_digest: report.digest.clone(),
+        base_config,
+        trust_store,
+        db,
+        mls_store: tokio::fs::read(&mls_store).await?,
+    };
+
+    let info = BackupInfo {
+        created_at,
+        trust_store_sequence: report.sequence,
+        trust_store_digest: report.digest,
+    };
+
+    Ok((contents, info))
+}
+
+/// Restores the backup at `source` into `data_dir`.
+///
+/// The backup is unpacked next to the data directory and only activated once
+/// the stored transaction chain verifies and ends at the recorded digest. An
+/// existing data directory is moved aside, not deleted. The server must not be
+/// running.
+pub async fn restore_backup(
+    source: &Path,
+    data_dir: &Location,
+    passphrase: Vec<u8>,
+) -> Result<Restored> {
+    let backup = tokio::fs::read(source)
+        .await
+        .with_context(|| format!("failed to read backup file {}", source.display()))?;
+    let backup: BackupFile = postcard::from_bytes(&backup).context("invalid backup file")?;
+
+    if backup.version != BACKUP_VERSION {
+        return Err(anyhow!("unsupported backup version {}", backup.version));
+    }
+
+    let key = backup.params.derive_encryption_key(passphrase).await?;
+    let contents = backup
+        .contents
+        .decrypt(&key)
+        .map_err(|_| anyhow!("failed to decrypt backup, wrong passphrase?"))?;
+
+    let staging = sibling(data_dir, ".restore");
+    if staging.as_path().exists() {
+        tokio::fs::remove_dir_all(&staging).await?;
+    }
+    tokio::fs::create_dir_all(&staging).await?;
+
+    tokio::fs::write(Server::base_config_path(&staging), &contents.base_config).await?;
+    tokio::fs::write(Server::trust_store_path(&staging), &contents.trust_store).await?;
+    tokio::fs::write(Server::db_path(&staging), &contents.db).await?;
+    tokio::fs::write(Server::mls_store_path(&staging), &contents.mls_store).await?;
+
+    let report = replay_trust_store(&staging)
+        .await
+        .context("restored trust store is invalid")?;
+    if report.digest != contents.trust_store_digest {
+        return Err(anyhow!(
+            "restored transactions don't match the trust store digest recorded in the backup"
+        ));
+    }
+
+    let previous_data_dir = if data_dir.as_path().exists() {
+        let previous = sibling(
+            data_dir,
+            &format!(".pre-restore-{}", get_current_timestamp()),
+        );
+        tokio::fs::rename(data_dir, &previous).await?;
+        Some(previous.to_pathbuf())
+    } else {
+        data_dir.clone().ensure_parent_exists().await?;
+        None
+    };
+    tokio::fs::rename(&staging, data_dir).await?;
+
+    Ok(Restored {
+        backup: BackupInfo {
+            created_at: contents.created_at,
+            trust_store_sequence: report.sequence,
+            trust_store_digest: report.digest,
+        },
+        previous_data_dir,
+    })
+}
+
+/// Replays the stored transactions of the data directory and checks that they
+/// agree with its trust store snapshot.
+async fn replay_trust_store(data_dir: &Location) -> Result<ChainReport> {
+    let admin = Admin::open(data_dir.clone()).await?;
+    let report = admin.verify_chain().await;
+    admin.close().await;
+    let report = report.context("trust store chain is invalid")?;
+
+    match report.snapshot_matches {
+        Some(true) => {}
+        Some(false) => {
+            return Err(anyhow!(
+                "trust store snapshot does not match the stored transactions"
+            ));
+        }
+        None if report.snapshot_sequence > report.sequence => {
+            return Err(anyhow!(
+                "trust store snapshot at sequence {} is ahead of the stored transactions, which end at {}",
+                report.snapshot_sequence,
+                report.sequence
+            ));
+        }
+        // the transactions of the snapshot were pruned
+        None => tracing::warn!(
+            "trust store snapshot at sequence {} predates the oldest checkpoint {:?} and can't be compared with the stored transactions",
+            report.snapshot_sequence,
+            report.checkpoint_sequence
+        ),
+    }
+
+    Ok(report)
+}
+
+fn sibling(location: &Location, suffix: &str) -> Location {
+    let mut path = OsString::from(location.as_path());
+    path.push(suffix);
+    Location::new(PathBuf::from(path))
+}
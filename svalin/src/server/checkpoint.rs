@@ -0,0 +1,72 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::Result;
+use svalin_pki::{Credential, trust_store::TrustStore};
+use svalin_store::server_store::TrustStoreTransactionStore;
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+
+/// Periodically stores a signed checkpoint of the trust store and deletes the
+/// transactions which are covered by older checkpoints.
+///
+/// Members which are behind the pruned history receive the latest checkpoint
+/// instead of the deleted transactions.
+pub(super) async fn manage_checkpoints(
+    credential: Credential,
+    trust_store: Arc<RwLock<TrustStore>>,
+    transactions: Arc<TrustStoreTransactionStore>,
+    interval: Duration,
+    keep_checkpoints: u32,
+    cancel: CancellationToken,
+) {
+    loop {
+        select! {
+            _ = cancel.cancelled() => return,
+            _ = tokio::time::sleep(interval) => {}
+        }
+
+        match create_checkpoint(&credential, &trust_store, &transactions, keep_checkpoints).await {
+            Ok(0) => {}
+            Ok(deleted) => tracing::info!("pruned {deleted} trust store transactions"),
+            Err(err) => tracing::error!("failed to checkpoint trust store: {err:#}"),
+        }
+    }
+}
+
+/// Stores a checkpoint of the current trust store and prunes the history.
+///
+/// Returns the number of deleted transactions.
+pub(super) async fn create_checkpoint(
+    credential: &Credential,
+    trust_store: &RwLock<TrustStore>,
+    transactions: &TrustStoreTransactionStore,
+    keep_checkpoints: u32,
+) -> Result<u64> {
+    let history = transactions.merkle_index().await?;
+    let (checkpoint, state) = {
+        let guard = trust_store.read().unwrap();
+        // without the history, only empty members can bootstrap from it
+        let checkpoint = history
+            .as_ref()
+            .and_then(|history| guard.checkpoint_with_history(credential, history))
+            .unwrap_or_else(|| guard.checkpoint(credential));
+        (checkpoint, guard.export())
+    };
+
+    let latest = transactions.latest_checkpoint().await?;
+    let unchanged = latest
+        .as_ref()
+        .is_some_and(|(latest, _)| latest.sequence() == checkpoint.sequence());
+    if !unchanged {
+        transactions.add_checkpoint(&checkpoint, &state).await?;
+        tracing::debug!(
+            "created trust store checkpoint at {}",
+            checkpoint.sequence()
+        );
+    }
+
+    Ok(transactions.prune(keep_checkpoints).await?)
+}
@@ -19,13 +19,16 @@ use crate::{
     permissions::default_permission_handler::DefaultPermissionHandler,
     server::{MlsServer, chain_loader::ChainLoader},
     shared::commands::{
+        count_key_packages::CountKeyPackagesHandler,
         get_key_packages::GetKeyPackagesHandler,
         get_user_credentials::GetUserCredentialHandler,
         load_certificate_chain::LoadCertificateChainHandler,
-        login::LoginHandler,
+        login::{LoginHandler, LoginLimits},
         public_server_status::{PublicStatus, PublicStatusHandler},
+        renewal::{ListRenewalRequestsHandler, RenewSessionHandler, RequestRenewalHandler},
         update_trust_store::UpdateTrustStoreHandler,
         update_user_mls::UpdateUserMlsHandler,
+        witness_signature::{SubmitWitnessSignatureHandler, WitnessSignatures},
     },
 };
 
@@ -35,6 +38,8 @@ pub struct SvalinCommandBuilder {
     pub store: ServerStore,
     pub mls: Arc<MlsServer>,
     pub trust_store: Arc<RwLock<TrustStore>>,
+    pub witnesses: Arc<WitnessSignatures>,
+    pub login_limits: LoginLimits,
 }
 
 impl RpcCommandBuilder for SvalinCommandBuilder {
@@ -81,6 +86,7 @@ impl RpcCommandBuilder for SvalinCommandBuilder {
                 self.store.sessions.clone(),
                 self.root_cert.clone(),
                 self.server_cert.clone(),
+                self.login_limits,
             ))
             .add(GetUserCredentialHandler {
                 store: self.store.users.clone(),
@@ -95,6 +101,9 @@ impl RpcCommandBuilder for SvalinCommandBuilder {
             .add(GetKeyPackagesHandler {
                 key_package_store: self.store.key_packages.clone(),
             })
+            .add(CountKeyPackagesHandler {
+                key_package_store: self.store.key_packages.clone(),
+            })
             .add(UpdateUserMlsHandler::new(
                 self.store.users.clone(),
                 self.store.messages.clone(),
@@ -108,9 +117,25 @@ impl RpcCommandBuilder for SvalinCommandBuilder {
             .add(client_message_handler)
             .add(agent_sender)
             .add(client_sender)
+            .add(RequestRenewalHandler::new(
+                self.trust_store.clone(),
+                self.store.renewals.clone(),
+            ))
+            .add(ListRenewalRequestsHandler {
+                renewal_store: self.store.renewals.clone(),
+            })
+            .add(RenewSessionHandler::new(
+                self.trust_store.clone(),
+                self.store.sessions.clone(),
+            ))
+            .add(SubmitWitnessSignatureHandler::new(
+                self.trust_store.clone(),
+                self.witnesses.clone(),
+            ))
             .add(UpdateTrustStoreHandler::new(
                 self.trust_store,
                 self.store.trust_store_transactions.clone(),
+                self.witnesses,
             ));
 
         Ok(commands)
@@ -1,49 +1,133 @@
 use core::net::SocketAddr;
 
 use anyhow::Result;
+use svalin_rpc::rpc::transport_config::QuicTransportConfig;
 use tokio_util::sync::CancellationToken;
 
-use super::{Server, ServerConfig};
+use crate::{
+    shared::commands::login::LoginLimits,
+    util::{key_storage::KeySourceConfig, location::Location},
+};
+
+use super::{
+    Server, ServerConfig,
+    config_file::{MessageRetention, TrustStoreRetention},
+};
 
 pub struct ServerConfigBuilder<A, B> {
-    addr: A,
+    addrs: A,
     cancel: B,
+    data_dir: Option<Location>,
+    transport: QuicTransportConfig,
+    message_retention: MessageRetention,
+    trust_store_retention: TrustStoreRetention,
+    login_limits: LoginLimits,
+    key_source: KeySourceConfig,
 }
 
 pub(super) fn new() -> ServerConfigBuilder<(), ()> {
     ServerConfigBuilder {
-        addr: (),
+        addrs: (),
         cancel: (),
+        data_dir: None,
+        transport: QuicTransportConfig::default(),
+        message_retention: MessageRetention::default(),
+        trust_store_retention: TrustStoreRetention::default(),
+        login_limits: LoginLimits::default(),
+        key_source: KeySourceConfig::default(),
     }
 }
 
 impl<A, B> ServerConfigBuilder<A, B> {
-    pub fn addr(self, addr: SocketAddr) -> ServerConfigBuilder<SocketAddr, B> {
+    pub fn addr(self, addr: SocketAddr) -> ServerConfigBuilder<Vec<SocketAddr>, B> {
+        self.addrs(vec![addr])
+    }
+
+    pub fn addrs(self, addrs: Vec<SocketAddr>) -> ServerConfigBuilder<Vec<SocketAddr>, B> {
         ServerConfigBuilder {
-            addr,
+            addrs,
             cancel: self.cancel,
+            data_dir: self.data_dir,
+            transport: self.transport,
+            message_retention: self.message_retention,
+            trust_store_retention: self.trust_store_retention,
+            login_limits: self.login_limits,
+            key_source: self.key_source,
         }
     }
 
     pub fn cancel(self, cancel: CancellationToken) -> ServerConfigBuilder<A, CancellationToken> {
         ServerConfigBuilder {
-            addr: self.addr,
+            addrs: self.addrs,
             cancel,
+            data_dir: self.data_dir,
+            transport: self.transport,
+            message_retention: self.message_retention,
+            trust_store_retention: self.trust_store_retention,
+            login_limits: self.login_limits,
+            key_source: self.key_source,
+        }
+    }
+
+    pub fn data_dir(self, data_dir: Location) -> Self {
+        Self {
+            data_dir: Some(data_dir),
+            ..self
+        }
+    }
+
+    pub fn transport_config(self, transport: QuicTransportConfig) -> Self {
+        Self { transport, ..self }
+    }
+
+    pub fn message_retention(self, message_retention: MessageRetention) -> Self {
+        Self {
+            message_retention,
+            ..self
         }
     }
+
+    pub fn trust_store_retention(self, trust_store_retention: TrustStoreRetention) -> Self {
+        Self {
+            trust_store_retention,
+            ..self
+        }
+    }
+
+    pub fn login_limits(self, login_limits: LoginLimits) -> Self {
+        Self {
+            login_limits,
+            ..self
+        }
+    }
+
+    pub fn key_source(self, key_source: KeySourceConfig) -> Self {
+        Self { key_source, ..self }
+    }
 }
 
-impl ServerConfigBuilder<SocketAddr, CancellationToken> {
+impl ServerConfigBuilder<Vec<SocketAddr>, CancellationToken> {
     pub async fn start_server(self) -> Result<Server> {
-        let config = self.to_config();
+        let config = self.to_config()?;
 
         Server::start(config).await
     }
 
-    fn to_config(self) -> ServerConfig {
-        ServerConfig {
-            addr: self.addr,
+    fn to_config(self) -> Result<ServerConfig> {
+        let data_dir = match self.data_dir {
+            Some(data_dir) => data_dir,
+            None => Server::default_data_dir()?,
+        };
+
+        Ok(ServerConfig {
+            addrs: self.addrs,
             cancelation_token: self.cancel,
-        }
+            data_dir,
+            transport: self.transport,
+            message_retention: self.message_retention,
+            trust_store_retention: self.trust_store_retention,
+            login_limits: self.login_limits,
+            key_source: self.key_source,
+        })
     }
 }
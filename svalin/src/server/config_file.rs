@@ -0,0 +1,185 @@
+use std::{
+    net::{Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use svalin_rpc::rpc::transport_config::QuicTransportConfig;
+use svalin_store::server_store::MessageLimits;
+
+use crate::{
+    shared::commands::login::LoginLimits,
+    util::{
+        key_storage::KeySourceConfig,
+        location::{Location, LocationError},
+        logging::LogConfig,
+    },
+};
+
+pub const DEFAULT_PORT: u16 = 55411;
+
+/// The server configuration file, usually located at `/etc/svalin/server.toml`.
+///
+/// Every value is optional, so an empty file is a valid configuration.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfigFile {
+    /// Directory containing the databases, keys and trust store.
+    pub data_dir: Option<PathBuf>,
+    /// Addresses the server listens on. Defaults to `[::]:55411`, which
+    /// accepts both IPv4 and IPv6 connections.
+    pub bind: Vec<SocketAddr>,
+    pub log: LogConfig,
+    pub transport: QuicTransportConfig,
+    pub messages: MessageRetention,
+    pub trust_store: TrustStoreRetention,
+    pub login: LoginLimits,
+    /// How the key protecting the server credentials is stored. Only used
+    /// when the server is initialized.
+    pub key_source: KeySourceConfig,
+}
+
+/// How long undelivered MLS messages are kept on the server.
+///
+/// Commits and welcomes are kept until every receiver acknowledged them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MessageRetention {
+    /// Messages older than this are deleted even if not every receiver has
+    /// acknowledged them. `0` keeps messages forever.
+    pub max_age_secs: u64,
+    /// Lifetime stored with every new message. Unlike `max_age_secs`, changing
+    /// it doesn't affect messages which are already stored. `0` disables it.
+    pub ttl_secs: u64,
+    /// Maximum number of unacknowledged messages per receiver, the oldest
+    /// messages are dropped once it is exceeded. `0` disables the limit.
+    pub max_pending_per_receiver: u64,
+    /// Interval in which expired messages are deleted.
+    pub sweep_interval_secs: u64,
+}
+
+impl Default for MessageRetention {
+    fn default() -> Self {
+        Self {
+            max_age_secs: 30 * 24 * 60 * 60,
+            ttl_secs: 14 * 24 * 60 * 60,
+            max_pending_per_receiver: 10_000,
+            sweep_interval_secs: 60 * 60,
+        }
+    }
+}
+
+impl MessageRetention {
+    pub fn limits(&self) -> MessageLimits {
+        MessageLimits {
+            ttl: (self.ttl_secs > 0).then(|| Duration::from_secs(self.ttl_secs)),
+            max_pending_per_receiver: (self.max_pending_per_receiver > 0)
+                .then_some(self.max_pending_per_receiver),
+        }
+    }
+}
+
+/// How often the trust store is checkpointed and how much of its transaction
+/// history is kept.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrustStoreRetention {
+    /// Interval in which a signed checkpoint of the trust store is created.
+    /// `0` disables checkpoints.
+    pub checkpoint_interval_secs: u64,
+    /// Number of checkpoints to keep. Transactions older than the oldest kept
+    /// checkpoint are deleted. `0` keeps the whole history.
+    pub keep_checkpoints: u32,
+}
+
+impl Default for TrustStoreRetention {
+    fn default() -> Self {
+        Self {
+            checkpoint_interval_secs: 24 * 60 * 60,
+            keep_checkpoints: 7,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigFileError {
+    #[error("error getting config location: {0}")]
+    LocationError(#[from] LocationError),
+    #[error("failed to read config file {0}: {1}")]
+    IoError(PathBuf, std::io::Error),
+    #[error("failed to parse config file {0}: {1}")]
+    ParseError(PathBuf, toml::de::Error),
+}
+
+impl ServerConfigFile {
+    pub fn default_path() -> Result<Location, LocationError> {
+        Ok(Location::system_config_dir()?.push("server.toml"))
+    }
+
+    /// Loads the config file at the given path.
+    ///
+    /// Without an explicit path the default location is used, and a missing
+    /// file there results in the default configuration.
+    pub async fn load(path: Option<&Path>) -> Result<Self, ConfigFileError> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => (Self::default_path()?.to_pathbuf(), false),
+        };
+
+        let content = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => content,
+            Err(err) if !required && err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::default());
+            }
+            Err(err) => return Err(ConfigFileError::IoError(path, err)),
+        };
+
+        toml::from_str(&content).map_err(|err| ConfigFileError::ParseError(path, err))
+    }
+
+    pub fn bind_addresses(&self) -> Vec<SocketAddr> {
+        if self.bind.is_empty() {
+            vec![SocketAddr::from((Ipv6Addr::UNSPECIFIED, DEFAULT_PORT))]
+        } else {
+            self.bind.clone()
+        }
+    }
+
+    pub fn data_dir(&self) -> Result<Location, LocationError> {
+        match &self.data_dir {
+            Some(dir) => Ok(Location::new(dir)),
+            None => super::Server::default_data_dir(),
+        }
+    }
+
+    /// Checks the values which can't be validated while parsing.
+    pub fn validate(&self) -> Result<()> {
+        self.log.filter()?;
+        self.transport.to_quinn()?;
+
+        if self.login.max_concurrent == 0 {
+            return Err(anyhow!("login.max_concurrent has to be at least 1"));
+        }
+
+        if self.login.step_timeout_secs == 0 {
+            return Err(anyhow!("login.step_timeout_secs has to be at least 1"));
+        }
+
+        if self.messages.sweep_interval_secs == 0 {
+            return Err(anyhow!("messages.sweep_interval_secs has to be at least 1"));
+        }
+
+        let data_dir = self.data_dir()?;
+        if data_dir.as_path().exists() && !data_dir.as_path().is_dir() {
+            return Err(anyhow!("data directory {data_dir} is not a directory"));
+        }
+
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+}
@@ -8,6 +8,7 @@ use svalin_pki::{
 };
 use svalin_store::server_store::KeyPackageStore;
 
+#[derive(Clone)]
 pub struct LocalKeyRetriever {
     root: RootCertificate,
     trust_store: Arc<RwLock<TrustStore>>,
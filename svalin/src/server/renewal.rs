@@ -0,0 +1,155 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::Result;
+use svalin_pki::{
+    Credential, RenewalRequest, UnverifiedCertificate, get_current_timestamp,
+    secure_chain::UncheckedBlock,
+    trust_store::{self, TrustStore},
+};
+use svalin_store::server_store::{RenewalStore, TrustStoreTransactionStore};
+use tokio::{select, sync::broadcast::error::RecvError, time::Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::util::location::Location;
+
+use super::Server;
+
+/// How often the server checks whether its certificate is due for renewal.
+const RENEWAL_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// Requests the renewal of the server certificate once it is due and removes
+/// pending renewal requests once a user resolved them in the trust store.
+///
+/// A renewed server certificate is saved to the base config and used after
+/// the next restart.
+pub(super) async fn manage_renewals(
+    data_dir: Location,
+    mut credential: Credential,
+    trust_store: Arc<RwLock<TrustStore>>,
+    renewals: Arc<RenewalStore>,
+    transactions: Arc<TrustStoreTransactionStore>,
+    cancel: CancellationToken,
+) {
+    let sequence = trust_store.read().unwrap().sequence();
+    let mut receiver = match transactions.load_all_after(sequence).await {
+        Ok((_, receiver)) => receiver,
+        Err(err) => {
+            tracing::error!("failed to subscribe to trust store transactions: {err}");
+            return;
+        }
+    };
+
+    let mut next_check = Instant::now();
+
+    loop {
+        select! {
+            _ = cancel.cancelled() => return,
+            _ = tokio::time::sleep_until(next_check) => {
+                next_check = Instant::now() + RENEWAL_CHECK_INTERVAL;
+                if let Err(err) = request_renewal_if_due(&credential, &renewals).await {
+                    tracing::error!("failed to request renewal of server certificate: {err:#}");
+                }
+            }
+            block = receiver.recv() => match block {
+                Ok(block) => {
+                    if let Err(err) = resolve_requests(&block, &renewals).await {
+                        tracing::error!("failed to remove resolved renewal requests: {err:#}");
+                    }
+                    if let Some(renewed) = renewed_certificate(&block, &credential) {
+                        match swap_credential(&data_dir, &credential, renewed).await {
+                            Ok(renewed) => {
+                                tracing::info!(
+                                    "server certificate was renewed, it will be used after a restart"
+                                );
+                                credential = renewed;
+                            }
+                            Err(err) => {
+                                tracing::error!("failed to save renewed server certificate: {err:#}")
+                            }
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("renewal manager missed {skipped} trust store transactions");
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+}
+
+async fn request_renewal_if_due(credential: &Credential, renewals: &RenewalStore) -> Result<()> {
+    if credential.certificate().renewal_due_at() > get_current_timestamp() {
+        return Ok(());
+    }
+
+    tracing::warn!(
+        "server certificate expires at {}, requesting renewal",
+        credential.certificate().not_after()
+    );
+    renewals
+        .add_request(&RenewalRequest::create(credential))
+        .await?;
+
+    Ok(())
+}
+
+/// Removes the pending requests which are resolved by the given block.
+async fn resolve_requests(
+    block: &UncheckedBlock<trust_store::Transaction>,
+    renewals: &RenewalStore,
+) -> Result<()> {
+    match block.transaction() {
+        trust_store::Transaction::Renew { renewed, .. } => {
+            renewals.remove_request(renewed.spki_hash()).await?;
+        }
+        trust_store::Transaction::Add(certificate) => {
+            // a rotated key might also get a new certificate by a plain add
+            for request in renewals.list_requests().await? {
+                if &request.renewed_spki_hash() == certificate.spki_hash() {
+                    renewals
+                        .remove_request(request.certificate().spki_hash())
+                        .await?;
+                }
+            }
+        }
+        trust_store::Transaction::Rotate { proof, .. } => {
+            renewals
+                .remove_request(proof.certificate().spki_hash())
+                .await?;
+        }
+        trust_store::Transaction::RemoveExpired(certificate) => {
+            renewals.remove_request(certificate.spki_hash()).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the renewed certificate of the server if the block contains one.
+fn renewed_certificate(
+    block: &UncheckedBlock<trust_store::Transaction>,
+    credential: &Credential,
+) -> Option<UnverifiedCertificate> {
+    let trust_store::Transaction::Renew { renewed, .. } = block.transaction() else {
+        return None;
+    };
+    let current = credential.certificate();
+
+    (renewed.spki_hash() == current.spki_hash() && renewed.not_after() > current.not_after())
+        .then(|| renewed.clone())
+}
+
+async fn swap_credential(
+    data_dir: &Location,
+    credential: &Credential,
+    renewed: UnverifiedCertificate,
+) -> Result<Credential> {
+    let credential = credential.with_renewed_certificate(renewed)?;
+    Server::save_credential(data_dir, &credential).await?;
+
+    Ok(credential)
+}
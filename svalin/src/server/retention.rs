@@ -0,0 +1,151 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::Result;
+use svalin_pki::{SpkiHash, get_current_timestamp, trust_store::TrustStore};
+use svalin_store::server_store::{MessageStore, QueueMetrics, SessionStore};
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+
+use super::config_file::MessageRetention;
+
+/// What a single sweep deleted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct SweepReport {
+    /// Messages older than the maximum age.
+    pub too_old: u64,
+    /// Messages whose TTL ended.
+    pub expired: u64,
+    /// Receivers which are no longer part of the trust store.
+    pub removed_receivers: Vec<SpkiHash>,
+    /// Messages deleted together with the removed receivers.
+    pub orphaned: u64,
+}
+
+/// Periodically deletes undeliverable messages and logs the queue depth of
+/// every receiver.
+pub(super) async fn sweep_messages(
+    messages: Arc<MessageStore>,
+    sessions: Arc<SessionStore>,
+    trust_store: Arc<RwLock<TrustStore>>,
+    retention: MessageRetention,
+    cancel: CancellationToken,
+) {
+    let interval = Duration::from_secs(retention.sweep_interval_secs);
+
+    loop {
+        match sweep_once(&messages, &sessions, &trust_store, &retention).await {
+            Ok(report) => log_report(&report),
+            Err(err) => tracing::error!("failed to sweep messages: {err:#}"),
+        }
+
+        match messages.queue_metrics().await {
+            Ok(snapshot) => log_metrics(&snapshot, &retention),
+            Err(err) => tracing::error!("failed to collect message queue metrics: {err}"),
+        }
+
+        select! {
+            _ = cancel.cancelled() => return,
+            _ = tokio::time::sleep(interval) => {}
+        }
+    }
+}
+
+/// Deletes expired messages and the queues of receivers whose certificates
+/// were removed from the trust store.
+pub(crate) async fn sweep_once(
+    messages: &MessageStore,
+    sessions: &SessionStore,
+    trust_store: &RwLock<TrustStore>,
+    retention: &MessageRetention,
+) -> Result<SweepReport> {
+    let now = get_current_timestamp();
+    let mut report = SweepReport::default();
+
+    if retention.max_age_secs > 0 {
+        let cutoff = now.saturating_sub(retention.max_age_secs);
+        report.too_old = messages.delete_messages_older_than(cutoff).await?;
+    }
+
+    report.expired = messages.delete_expired_messages(now).await?;
+
+    for receiver in removed_receivers(messages, sessions, trust_store).await? {
+        report.orphaned += messages.delete_receiver(&receiver).await?;
+        report.removed_receivers.push(receiver);
+    }
+
+    Ok(report)
+}
+
+/// Receivers which can't connect anymore, because neither their certificate
+/// nor, for sessions, the certificate of the issuing user is known.
+async fn removed_receivers(
+    messages: &MessageStore,
+    sessions: &SessionStore,
+    trust_store: &RwLock<TrustStore>,
+) -> Result<Vec<SpkiHash>> {
+    let receivers = messages.pending_counts().await?;
+    if receivers.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let issuers: HashMap<SpkiHash, SpkiHash> =
+        sessions.list_sessions().await?.into_iter().collect();
+
+    let guard = trust_store.read().unwrap();
+    let known = |spki_hash: &SpkiHash| {
+        spki_hash == guard.root().spki_hash() || guard.get(spki_hash).is_some()
+    };
+
+    Ok(receivers
+        .into_iter()
+        .map(|(receiver, _)| receiver)
+        .filter(|receiver| match issuers.get(receiver) {
+            Some(issuer) => !known(issuer),
+            None => !known(receiver),
+        })
+        .collect())
+}
+
+fn log_report(report: &SweepReport) {
+    if report.too_old > 0 {
+        tracing::info!(
+            "deleted {} messages exceeding the maximum age",
+            report.too_old
+        );
+    }
+    if report.expired > 0 {
+        tracing::info!("deleted {} expired messages", report.expired);
+    }
+    for receiver in &report.removed_receivers {
+        tracing::info!("deleted message queue of removed receiver {receiver}");
+    }
+    if report.orphaned > 0 {
+        tracing::info!("deleted {} messages of removed receivers", report.orphaned);
+    }
+}
+
+fn log_metrics(metrics: &QueueMetrics, retention: &MessageRetention) {
+    tracing::debug!(
+        "{} messages pending for {} receivers, {} deliveries outstanding",
+        metrics.messages,
+        metrics.depths.len(),
+        metrics.pending()
+    );
+
+    for (receiver, depth) in &metrics.depths {
+        tracing::trace!("message queue depth of {receiver}: {depth}");
+
+        if retention.max_pending_per_receiver > 0
+            && *depth * 10 >= retention.max_pending_per_receiver * 9
+        {
+            tracing::warn!(
+                "message queue of {receiver} is almost full: {depth} of {}",
+                retention.max_pending_per_receiver
+            );
+        }
+    }
+}
@@ -1,3 +1,5 @@
+pub mod compare_chain_position;
+pub mod count_key_packages;
 pub mod get_key_packages;
 pub mod get_user_credentials;
 pub mod init;
@@ -6,8 +8,10 @@ pub mod load_certificate_chain;
 pub mod login;
 pub mod public_server_status;
 pub mod realtime_status;
+pub mod renewal;
 pub mod request_system_report;
 pub mod terminal;
 pub mod update_agent;
 pub mod update_trust_store;
 pub mod update_user_mls;
+pub mod witness_signature;
@@ -0,0 +1,105 @@
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use svalin_pki::{Credential, secure_chain::Checkpoint, trust_store::TrustStore};
+use svalin_rpc::rpc::{
+    command::{dispatcher::CommandDispatcher, handler::CommandHandler},
+    session::Session,
+};
+use svalin_store::trust_store_transaction_store::TrustStoreTransactionStore;
+use tokio_util::sync::CancellationToken;
+
+use crate::util::chain_gossip::compare_position;
+
+/// Exchanges signed trust store positions with another member, so both can
+/// detect if the server presents them different histories.
+pub struct CompareChainPositionHandler {
+    trust_store: Arc<RwLock<TrustStore>>,
+    history: Arc<TrustStoreTransactionStore>,
+    credential: Credential,
+}
+
+impl CompareChainPositionHandler {
+    pub fn new(
+        trust_store: Arc<RwLock<TrustStore>>,
+        history: Arc<TrustStoreTransactionStore>,
+        credential: Credential,
+    ) -> Self {
+        Self {
+            trust_store,
+            history,
+            credential,
+        }
+    }
+}
+
+#[async_trait]
+impl CommandHandler for CompareChainPositionHandler {
+    type Request = Checkpoint;
+
+    fn key() -> String {
+        "compare-chain-position".into()
+    }
+
+    async fn handle(
+        &self,
+        session: &mut Session,
+        request: Self::Request,
+        _cancel: CancellationToken,
+    ) -> anyhow::Result<()> {
+        // answer first, so the other side also notices a conflict
+        let position = self
+            .trust_store
+            .read()
+            .unwrap()
+            .checkpoint(&self.credential);
+        session.write_object(&position).await?;
+
+        compare_position(&self.trust_store, &self.history, &request).await?;
+
+        Ok(())
+    }
+}
+
+pub struct CompareChainPosition {
+    trust_store: Arc<RwLock<TrustStore>>,
+    history: Arc<TrustStoreTransactionStore>,
+    position: Checkpoint,
+}
+
+impl CompareChainPosition {
+    pub fn new(
+        trust_store: Arc<RwLock<TrustStore>>,
+        history: Arc<TrustStoreTransactionStore>,
+        credential: &Credential,
+    ) -> Self {
+        let position = trust_store.read().unwrap().checkpoint(credential);
+        Self {
+            trust_store,
+            history,
+            position,
+        }
+    }
+}
+
+impl CommandDispatcher for CompareChainPosition {
+    type Output = ();
+    type Error = anyhow::Error;
+    type Request = Checkpoint;
+
+    fn key() -> String {
+        CompareChainPositionHandler::key()
+    }
+
+    fn get_request(&self) -> &Self::Request {
+        &self.position
+    }
+
+    async fn dispatch(self, session: &mut Session) -> Result<Self::Output, Self::Error> {
+        let position: Checkpoint = session.read_object().await?;
+
+        compare_position(&self.trust_store, &self.history, &position).await?;
+
+        Ok(())
+    }
+}
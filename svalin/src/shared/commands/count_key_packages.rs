@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use svalin_pki::SpkiHash;
+use svalin_rpc::rpc::{
+    command::{
+        dispatcher::CommandDispatcher,
+        handler::{CommandHandler, PermissionPrecursor},
+    },
+    session::Session,
+};
+use svalin_store::server_store::KeyPackageStore;
+use tokio_util::sync::CancellationToken;
+
+use crate::permissions::Permission;
+
+/// The key packages the server still holds for one owner.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct KeyPackageCount {
+    /// Regular key packages which haven't expired yet.
+    pub available: u64,
+    /// Whether a valid last resort key package is stored.
+    pub last_resort: bool,
+}
+
+impl KeyPackageCount {
+    pub async fn load(store: &KeyPackageStore, owner: &SpkiHash) -> anyhow::Result<Self> {
+        Ok(Self {
+            available: store.count_key_packages(owner).await?,
+            last_resort: store.has_last_resort_key_package(owner).await?,
+        })
+    }
+}
+
+/// Counts the key packages the server holds for the connected peer.
+pub struct CountKeyPackages;
+
+impl CommandDispatcher for CountKeyPackages {
+    type Output = KeyPackageCount;
+
+    type Error = anyhow::Error;
+
+    type Request = ();
+
+    fn key() -> String {
+        CountKeyPackagesHandler::key()
+    }
+
+    fn get_request(&self) -> &Self::Request {
+        &()
+    }
+
+    async fn dispatch(self, session: &mut Session) -> Result<Self::Output, Self::Error> {
+        let count: Option<KeyPackageCount> = session.read_object().await?;
+
+        count.ok_or_else(|| anyhow::anyhow!("server failed to count key packages"))
+    }
+}
+
+pub struct CountKeyPackagesHandler {
+    pub key_package_store: Arc<KeyPackageStore>,
+}
+
+impl From<&PermissionPrecursor<CountKeyPackagesHandler>> for Permission {
+    fn from(_value: &PermissionPrecursor<CountKeyPackagesHandler>) -> Self {
+        Permission::AuthenticatedOnly
+    }
+}
+
+#[async_trait]
+impl CommandHandler for CountKeyPackagesHandler {
+    type Request = ();
+
+    fn key() -> String {
+        "count_key_packages".into()
+    }
+
+    async fn handle(
+        &self,
+        session: &mut Session,
+        _request: Self::Request,
+        _cancel: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let peer = session.peer().certificate()?.spki_hash().clone();
+
+        match KeyPackageCount::load(&self.key_package_store, &peer).await {
+            Ok(count) => {
+                session.write_object(&Some(count)).await?;
+                Ok(())
+            }
+            Err(err) => {
+                session.write_object(&None::<KeyPackageCount>).await?;
+                Err(err)
+            }
+        }
+    }
+}
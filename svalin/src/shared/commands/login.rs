@@ -1,4 +1,8 @@
-use std::sync::RwLock;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
 use std::{str, sync::Arc};
 
 use anyhow::{Context, Result, anyhow};
@@ -37,7 +41,7 @@ use svalin_rpc::{
     verifiers::skip_verify::{SkipClientVerification, SkipServerVerification},
 };
 use svalin_store::server_store::{SessionStore, UserStore};
-use tokio::sync::oneshot;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, oneshot};
 use tokio_util::sync::CancellationToken;
 
 use crate::permissions::Permission;
@@ -58,12 +62,119 @@ impl From<&PermissionPrecursor<LoginHandler>> for Permission {
     }
 }
 
+/// Limits applied by the server to login attempts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoginLimits {
+    /// Maximum number of logins processed at the same time.
+    ///
+    /// Every login runs an expensive password hash, so this bounds the CPU
+    /// an attacker can consume.
+    pub max_concurrent: usize,
+    /// Number of failed attempts from one address after which the username
+    /// is locked for that address.
+    pub max_failed_attempts: u32,
+    /// Time in seconds a username stays locked.
+    pub lockout_secs: u64,
+    /// Time in seconds the server waits for each message of the client before
+    /// aborting the login.
+    pub step_timeout_secs: u64,
+}
+
+impl Default for LoginLimits {
+    fn default() -> Self {
+        Self {
+            max_concurrent: 8,
+            max_failed_attempts: 5,
+            lockout_secs: 300,
+            step_timeout_secs: 30,
+        }
+    }
+}
+
+struct FailedLogins {
+    count: u32,
+    last_attempt: Instant,
+}
+
+/// Failed attempts are counted per username and remote address, so other
+/// peers can't lock a user out. Tunneled logins don't have an address and
+/// share one counter per username.
+type FailedLoginKey = (Option<IpAddr>, Vec<u8>);
+
+struct LoginLimiter {
+    limits: LoginLimits,
+    running: Arc<Semaphore>,
+    failed: Mutex<HashMap<FailedLoginKey, FailedLogins>>,
+}
+
+impl LoginLimiter {
+    fn new(limits: LoginLimits) -> Self {
+        Self {
+            running: Arc::new(Semaphore::new(limits.max_concurrent)),
+            failed: Mutex::new(HashMap::new()),
+            limits,
+        }
+    }
+
+    fn lockout(&self) -> Duration {
+        Duration::from_secs(self.limits.lockout_secs)
+    }
+
+    /// Runs a single step of the login, aborting it if the client stalls.
+    async fn step<T, E>(&self, step: impl Future<Output = Result<T, E>>) -> Result<T>
+    where
+        anyhow::Error: From<E>,
+    {
+        let result = tokio::time::timeout(Duration::from_secs(self.limits.step_timeout_secs), step)
+            .await
+            .map_err(|_| anyhow!("login step timed out"))?;
+
+        Ok(result?)
+    }
+
+    fn try_start(&self) -> Result<OwnedSemaphorePermit> {
+        self.running
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| anyhow!("too many concurrent login attempts"))
+    }
+
+    fn check(&self, key: &FailedLoginKey) -> Result<()> {
+        let mut failed = self.failed.lock().unwrap();
+        let lockout = self.lockout();
+        failed.retain(|_, entry| entry.last_attempt.elapsed() < lockout);
+
+        match failed.get(key) {
+            Some(entry) if entry.count >= self.limits.max_failed_attempts => {
+                Err(anyhow!("too many failed login attempts, user is locked"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn record_failure(&self, key: &FailedLoginKey) {
+        let mut failed = self.failed.lock().unwrap();
+        let entry = failed.entry(key.clone()).or_insert_with(|| FailedLogins {
+            count: 0,
+            last_attempt: Instant::now(),
+        });
+        entry.count += 1;
+        entry.last_attempt = Instant::now();
+    }
+
+    fn record_success(&self, key: &FailedLoginKey) {
+        self.failed.lock().unwrap().remove(key);
+    }
+}
+
 pub struct LoginHandler {
     trust_store: Arc<RwLock<TrustStore>>,
     user_store: Arc<UserStore>,
     session_store: Arc<SessionStore>,
     root_cert: RootCertificate,
     server_cert: Certificate,
+    limiter: LoginLimiter,
 }
 
 impl LoginHandler {
@@ -73,6 +184,7 @@ impl LoginHandler {
         session_store: Arc<SessionStore>,
         root_cert: RootCertificate,
         server_cert: Certificate,
+        limits: LoginLimits,
     ) -> Self {
         Self {
             trust_store,
@@ -80,6 +192,7 @@ impl LoginHandler {
             session_store,
             root_cert,
             server_cert,
+            limiter: LoginLimiter::new(limits),
         }
     }
 }
@@ -118,14 +231,16 @@ impl TakeableCommandHandler for LoginHandler {
         _cancel: CancellationToken,
     ) -> Result<()> {
         if let Some(mut session) = session.take() {
-            // TODO: Really important!!!
-            // implement rate limiting!!!
+            let remote_ip = session.remote_address().map(|address| address.ip());
+            let limiter = &self.limiter;
 
             let tls_server_nonce = Nonce::generate();
 
-            session.write_object(&tls_server_nonce).await?;
+            limiter
+                .step(session.write_object(&tls_server_nonce))
+                .await?;
 
-            let tls_client_nonce: Nonce = session.read_object().await?;
+            let tls_client_nonce: Nonce = limiter.step(session.read_object()).await?;
 
             let tls_combined_nonce = tls_server_nonce.combine(tls_client_nonce);
 
@@ -136,10 +251,14 @@ impl TakeableCommandHandler for LoginHandler {
             let temp_credentials = Credential::generate_temporary()
                 .context("Failed to generate temporary credentials")?;
 
-            let tls_transport =
-                TlsTransport::server(transport, SkipClientVerification::new(), &temp_credentials)
-                    .await
-                    .context("Failed to establish TLS connection")?;
+            let tls_transport = limiter
+                .step(TlsTransport::server(
+                    transport,
+                    SkipClientVerification::new(),
+                    &temp_credentials,
+                ))
+                .await
+                .context("Failed to establish TLS connection")?;
 
             let mut key_material = [0u8; 32];
             let key_material = tls_transport
@@ -158,16 +277,16 @@ impl TakeableCommandHandler for LoginHandler {
 
             let (pake_server, server_nonce) = pake_server.begin();
 
-            session
-                .write_object(&Nonce::try_from(server_nonce)?)
+            limiter
+                .step(session.write_object(&Nonce::try_from(server_nonce)?))
                 .await
                 .context("Failed to write server nonce")?;
 
             // tracing::trace!("reading for client nonce");
 
             // ===== Augmentation Layer =====
-            let client_nonce: Nonce = session
-                .read_object()
+            let client_nonce: Nonce = limiter
+                .step(session.read_object())
                 .await
                 .context("Failed to read client nonce")?;
 
@@ -175,12 +294,19 @@ impl TakeableCommandHandler for LoginHandler {
 
             // tracing::trace!("reading for strong username");
 
-            let strong_username: StrongUsername = session
-                .read_object()
+            let strong_username: StrongUsername = limiter
+                .step(session.read_object())
                 .await
                 .context("Failed to read strong username")?;
 
             let username = strong_username.username.clone();
+            let failed_key = (remote_ip, username.clone());
+
+            limiter.check(&failed_key)?;
+
+            // only the password hash is expensive, so waiting clients don't
+            // block the slots
+            let permit = limiter.try_start()?;
 
             let user_store = self.user_store.clone();
 
@@ -196,14 +322,16 @@ impl TakeableCommandHandler for LoginHandler {
             })
             .await??;
 
+            drop(permit);
+
             let mut client_info = StrongClientInfo::try_from(client_info)?;
             if client_info.hash_params.is_empty() {
                 client_info.hash_params = ArgonCost::strong().get_params().try_into()?;
             }
             // tracing::trace!("hash params: {}", &client_info.hash_params);
 
-            session
-                .write_object(&client_info)
+            limiter
+                .step(session.write_object(&client_info))
                 .await
                 .context("Failed to write client info")?;
 
@@ -211,10 +339,12 @@ impl TakeableCommandHandler for LoginHandler {
             let (pake_server, public_key) = pake_server.generate_public_key(key_material);
             let server_public_key = PublicKey::try_from(public_key)?;
 
-            session.write_object(&server_public_key).await?;
+            limiter
+                .step(session.write_object(&server_public_key))
+                .await?;
 
-            let client_public_key: PublicKey = session
-                .read_object()
+            let client_public_key: PublicKey = limiter
+                .step(session.read_object())
                 .await
                 .context("Failed to read client public key")?;
 
@@ -225,8 +355,8 @@ impl TakeableCommandHandler for LoginHandler {
 
             // ===== Explicit Mutual Authentication =====
 
-            let client_authenticator: Authenticator = session
-                .read_object()
+            let client_authenticator: Authenticator = limiter
+                .step(session.read_object())
                 .await
                 .context("Failed to read client authenticator")?;
 
@@ -236,15 +366,16 @@ impl TakeableCommandHandler for LoginHandler {
             let key = match client_auth_result {
                 Ok((key, server_authenticator)) => {
                     let server_authenticator = Authenticator::try_from(server_authenticator)?;
-                    session
-                        .write_object::<Result<_, ()>>(&Ok(server_authenticator))
+                    limiter
+                        .step(session.write_object::<Result<_, ()>>(&Ok(server_authenticator)))
                         .await
                         .context("Failed to write server authenticator")?;
                     key_to_array(key)
                 }
                 Err(err) => {
-                    session
-                        .write_object::<Result<Authenticator, ()>>(&Err(()))
+                    limiter.record_failure(&failed_key);
+                    limiter
+                        .step(session.write_object::<Result<Authenticator, ()>>(&Err(())))
                         .await
                         .context("Failed to inform client about authentication failure")?;
 
@@ -256,7 +387,9 @@ impl TakeableCommandHandler for LoginHandler {
             // Encrypt & Authenticate session
 
             let (transport, _) = session.destructure();
-            let tls_transport = TlsTransport::server_preshared(transport, &key).await?;
+            let tls_transport = limiter
+                .step(TlsTransport::server_preshared(transport, &key))
+                .await?;
             let mut session = Session::new(Box::new(tls_transport), Peer::Anonymous);
 
             // ===== TOTP =====
@@ -267,20 +400,26 @@ impl TakeableCommandHandler for LoginHandler {
                 .await?
                 .ok_or_else(|| anyhow!("failed to get user by username"))?;
 
-            let totp: String = session.read_object().await.context("Failed to read totp")?;
+            let totp: String = limiter
+                .step(session.read_object())
+                .await
+                .context("Failed to read totp")?;
 
             // Todo: fix only allow using each step once
             let totp_success = user.totp_secret.check_current(&totp).is_some();
 
-            session
-                .write_object(&totp_success)
+            limiter
+                .step(session.write_object(&totp_success))
                 .await
                 .context("Failed to write totp success")?;
 
             if !totp_success {
+                limiter.record_failure(&failed_key);
                 return Err(anyhow!("failed to verify totp"));
             }
 
+            limiter.record_success(&failed_key);
+
             let success = LoginApproval {
                 encrypted_user_credentials: user.encrypted_credential,
                 credential_key_params: user.credential_key_params,
@@ -291,13 +430,13 @@ impl TakeableCommandHandler for LoginHandler {
             };
             let user_cert = success.encrypted_user_credentials.certificate();
 
-            session
-                .write_object(&success)
+            limiter
+                .step(session.write_object(&success))
                 .await
                 .context("Failed to write encrypted success")?;
 
-            let session_certificate: UnverifiedCertificate = session
-                .read_object()
+            let session_certificate: UnverifiedCertificate = limiter
+                .step(session.read_object())
                 .await
                 .context("Failed to read session certificate")?;
 
@@ -348,7 +487,7 @@ impl TakeableCommandHandler for LoginHandler {
                 Err(_) => Err(()),
             };
 
-            let send_result = session.write_object(&cleaned_result).await;
+            let send_result = limiter.step(session.write_object(&cleaned_result)).await;
 
             store_result?;
             send_result?;
@@ -0,0 +1,305 @@
+use std::sync::{Arc, RwLock};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use svalin_pki::{
+    RenewalRequest, UnverifiedCertificate, get_current_timestamp, trust_store::TrustStore,
+};
+use svalin_rpc::rpc::{
+    command::{
+        dispatcher::CommandDispatcher,
+        handler::{CommandHandler, PermissionPrecursor},
+    },
+    peer::Peer,
+    session::{Session, SessionReadError, SessionWriteError},
+};
+use svalin_store::server_store::{RenewalStore, SessionStore};
+use tokio_util::sync::CancellationToken;
+
+use crate::permissions::Permission;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RenewalDispatchError {
+    #[error("Session read error: {0}")]
+    SessionRead(#[from] SessionReadError),
+    #[error("Session write error: {0}")]
+    SessionWrite(#[from] SessionWriteError),
+    #[error("the server rejected the renewal")]
+    Rejected,
+}
+
+/// Submits a renewal request for the certificate used on this connection.
+/// It is stored by the server until a user approves it.
+pub struct RequestRenewal(pub RenewalRequest);
+
+impl CommandDispatcher for RequestRenewal {
+    type Output = ();
+
+    type Error = RenewalDispatchError;
+
+    type Request = RenewalRequest;
+
+    fn key() -> String {
+        RequestRenewalHandler::key()
+    }
+
+    fn get_request(&self) -> &Self::Request {
+        &self.0
+    }
+
+    async fn dispatch(self, session: &mut Session) -> Result<Self::Output, Self::Error> {
+        let accepted: bool = session.read_object().await?;
+        if accepted {
+            Ok(())
+        } else {
+            Err(RenewalDispatchError::Rejected)
+        }
+    }
+}
+
+pub struct RequestRenewalHandler {
+    trust_store: Arc<RwLock<TrustStore>>,
+    renewal_store: Arc<RenewalStore>,
+}
+
+impl RequestRenewalHandler {
+    pub fn new(trust_store: Arc<RwLock<TrustStore>>, renewal_store: Arc<RenewalStore>) -> Self {
+        Self {
+            trust_store,
+            renewal_store,
+        }
+    }
+
+    async fn add_request(&self, peer: &Peer, request: &RenewalRequest) -> anyhow::Result<()> {
+        let Peer::Certificate(peer) = peer else {
+            return Err(anyhow!("expected peer to be a certificate"));
+        };
+
+        if peer.spki_hash() != request.certificate().spki_hash() {
+            return Err(anyhow!(
+                "peers may only request renewal of their own certificate"
+            ));
+        }
+
+        let current = self
+            .trust_store
+            .read()
+            .unwrap()
+            .get(request.certificate().spki_hash())
+            .cloned()
+            .ok_or_else(|| anyhow!("certificate is not in the trust store"))?;
+        request.verify(&current)?;
+
+        self.renewal_store.add_request(request).await?;
+
+        tracing::info!(
+            "received renewal request for {}",
+            request.certificate().spki_hash()
+        );
+
+        Ok(())
+    }
+}
+
+impl From<&PermissionPrecursor<RequestRenewalHandler>> for Permission {
+    fn from(_value: &PermissionPrecursor<RequestRenewalHandler>) -> Self {
+        Permission::AuthenticatedOnly
+    }
+}
+
+#[async_trait]
+impl CommandHandler for RequestRenewalHandler {
+    type Request = RenewalRequest;
+
+    fn key() -> String {
+        "request-renewal".into()
+    }
+
+    async fn handle(
+        &self,
+        session: &mut Session,
+        request: Self::Request,
+        _cancel: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let peer = session.peer().clone();
+        match self.add_request(&peer, &request).await {
+            Ok(()) => {
+                session.write_object(&true).await?;
+                Ok(())
+            }
+            Err(err) => {
+                session.write_object(&false).await?;
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Loads the pending renewal requests, so a user can approve them.
+pub struct ListRenewalRequests;
+
+impl CommandDispatcher for ListRenewalRequests {
+    type Output = Vec<RenewalRequest>;
+
+    type Error = anyhow::Error;
+
+    type Request = ();
+
+    fn key() -> String {
+        ListRenewalRequestsHandler::key()
+    }
+
+    fn get_request(&self) -> &Self::Request {
+        &()
+    }
+
+    async fn dispatch(self, session: &mut Session) -> Result<Self::Output, Self::Error> {
+        let requests = session.read_object().await?;
+
+        session.write_object(&()).await?;
+
+        Ok(requests)
+    }
+}
+
+pub struct ListRenewalRequestsHandler {
+    pub renewal_store: Arc<RenewalStore>,
+}
+
+impl From<&PermissionPrecursor<ListRenewalRequestsHandler>> for Permission {
+    fn from(_value: &PermissionPrecursor<ListRenewalRequestsHandler>) -> Self {
+        Permission::SessionOnly
+    }
+}
+
+#[async_trait]
+impl CommandHandler for ListRenewalRequestsHandler {
+    type Request = ();
+
+    fn key() -> String {
+        "list-renewal-requests".into()
+    }
+
+    async fn handle(
+        &self,
+        session: &mut Session,
+        _request: Self::Request,
+        _cancel: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let requests = self.renewal_store.list_requests().await?;
+
+        session.write_object(&requests).await?;
+
+        session.read_object::<()>().await?;
+
+        Ok(())
+    }
+}
+
+/// Replaces the session certificate used on this connection by its renewal.
+pub struct RenewSession(pub UnverifiedCertificate);
+
+impl CommandDispatcher for RenewSession {
+    type Output = ();
+
+    type Error = RenewalDispatchError;
+
+    type Request = UnverifiedCertificate;
+
+    fn key() -> String {
+        RenewSessionHandler::key()
+    }
+
+    fn get_request(&self) -> &Self::Request {
+        &self.0
+    }
+
+    async fn dispatch(self, session: &mut Session) -> Result<Self::Output, Self::Error> {
+        let accepted: bool = session.read_object().await?;
+        if accepted {
+            Ok(())
+        } else {
+            Err(RenewalDispatchError::Rejected)
+        }
+    }
+}
+
+pub struct RenewSessionHandler {
+    trust_store: Arc<RwLock<TrustStore>>,
+    session_store: Arc<SessionStore>,
+}
+
+impl RenewSessionHandler {
+    pub fn new(trust_store: Arc<RwLock<TrustStore>>, session_store: Arc<SessionStore>) -> Self {
+        Self {
+            trust_store,
+            session_store,
+        }
+    }
+
+    async fn renew(&self, peer: &Peer, renewed: UnverifiedCertificate) -> anyhow::Result<()> {
+        let Peer::Certificate(peer) = peer else {
+            return Err(anyhow!("expected peer to be a certificate"));
+        };
+
+        if peer.spki_hash() != renewed.spki_hash() || peer.issuer() != renewed.issuer() {
+            return Err(anyhow!(
+                "renewed session does not match the current session"
+            ));
+        }
+
+        if renewed.not_after() <= peer.not_after() {
+            return Err(anyhow!("renewed session does not expire later"));
+        }
+
+        let issuer = self
+            .trust_store
+            .read()
+            .unwrap()
+            .get(renewed.issuer())
+            .cloned()
+            .ok_or_else(|| anyhow!("issuer of the session is not known"))?;
+
+        let renewed = renewed.verify_signature(&issuer, get_current_timestamp())?;
+
+        if !self.session_store.renew_session(renewed).await? {
+            return Err(anyhow!("session not found"));
+        }
+
+        Ok(())
+    }
+}
+
+impl From<&PermissionPrecursor<RenewSessionHandler>> for Permission {
+    fn from(_value: &PermissionPrecursor<RenewSessionHandler>) -> Self {
+        Permission::SessionOnly
+    }
+}
+
+#[async_trait]
+impl CommandHandler for RenewSessionHandler {
+    type Request = UnverifiedCertificate;
+
+    fn key() -> String {
+        "renew-session".into()
+    }
+
+    async fn handle(
+        &self,
+        session: &mut Session,
+        request: Self::Request,
+        _cancel: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let peer = session.peer().clone();
+        match self.renew(&peer, request).await {
+            Ok(()) => {
+                session.write_object(&true).await?;
+                Ok(())
+            }
+            Err(err) => {
+                session.write_object(&false).await?;
+                Err(err)
+            }
+        }
+    }
+}
@@ -4,7 +4,11 @@ use anyhow::{Context, anyhow};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use svalin_pki::{
-    secure_chain::{ChainDigest, UncheckedBlock},
+    UnverifiedCertificate,
+    secure_chain::{
+        ChainDigest, Checkpoint, UncheckedBlock,
+        merkle::{MerkleHash, MerkleIndex},
+    },
     trust_store::{self, TrustStore},
 };
 use svalin_rpc::rpc::{
@@ -12,20 +16,64 @@ use svalin_rpc::rpc::{
     session::Session,
 };
 use svalin_store::{server_store, trust_store_transaction_store::TrustStoreTransactionStore};
-use tokio::{select, sync::oneshot};
+use tokio::{
+    select,
+    sync::{broadcast::error::RecvError, oneshot},
+};
 use tokio_util::sync::CancellationToken;
 
+use crate::{
+    shared::commands::witness_signature::WitnessSignatures,
+    util::{
+        chain_gossip::compare_position,
+        witness::{WitnessGate, WitnessPolicy, Witnessed},
+    },
+};
+
 #[derive(Serialize, Deserialize)]
 pub enum TrustStoreUpdate {
+    /// Sent instead of the transactions the server already pruned.
+    Checkpoint(Box<CheckpointUpdate>),
     Transaction(Arc<UncheckedBlock<trust_store::Transaction>>),
+    /// The latest head countersigned by a witness.
+    Witness(Checkpoint),
     UpToDate(ChainDigest),
     Close,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct CheckpointUpdate {
+    checkpoint: Checkpoint,
+    state: trust_store::Exported,
+    /// The leaves of all blocks up to the checkpoint, which prove that it
+    /// extends the history of the member. Empty if the server doesn't know
+    /// them, in which case only empty members accept the checkpoint.
+    history: Vec<MerkleHash>,
+}
+
+/// An update from the server waiting in the [`WitnessGate`].
+enum PendingUpdate {
+    Checkpoint(Box<CheckpointUpdate>),
+    Block(UncheckedBlock<trust_store::Transaction>),
+}
+
+impl Witnessed for PendingUpdate {
+    fn witnessed_by(&self, signature: &Checkpoint) -> bool {
+        match self {
+            PendingUpdate::Checkpoint(update) => update.checkpoint.witnessed_by(signature),
+            PendingUpdate::Block(block) => block.witnessed_by(signature),
+        }
+    }
+}
+
 pub struct UpdateTrustStore {
     trust_store: Arc<RwLock<TrustStore>>,
     store: Arc<TrustStoreTransactionStore>,
+    /// The server certificate, which may sign checkpoints for members that
+    /// don't know it yet.
+    upstream: UnverifiedCertificate,
     sequence: u64,
+    gate: WitnessGate<PendingUpdate>,
     ready: oneshot::Sender<()>,
     cancel: CancellationToken,
 }
@@ -34,6 +82,8 @@ impl UpdateTrustStore {
     pub fn new(
         trust_store: Arc<RwLock<TrustStore>>,
         store: Arc<TrustStoreTransactionStore>,
+        upstream: UnverifiedCertificate,
+        witnesses: WitnessPolicy,
         ready: oneshot::Sender<()>,
         cancel: CancellationToken,
     ) -> Self {
@@ -41,7 +91,9 @@ impl UpdateTrustStore {
         Self {
             trust_store,
             store,
+            upstream,
             sequence,
+            gate: WitnessGate::new(witnesses),
             ready,
             cancel,
         }
@@ -63,23 +115,60 @@ impl CommandDispatcher for UpdateTrustStore {
         &self.sequence
     }
 
-    async fn dispatch(self, session: &mut Session) -> Result<Self::Output, Self::Error> {
+    async fn dispatch(mut self, session: &mut Session) -> Result<Self::Output, Self::Error> {
         // load all newer blocks from server
         loop {
             let update: TrustStoreUpdate = session.read_object().await?;
             match update {
+                // checkpoints are only sent to members behind the pruned
+                // history and wait for witnesses just like blocks
+                TrustStoreUpdate::Checkpoint(update) => {
+                    tracing::debug!(
+                        "received trust store checkpoint at {}",
+                        update.checkpoint.sequence()
+                    );
+                    receive_update(
+                        PendingUpdate::Checkpoint(update),
+                        &mut self.gate,
+                        &self.upstream,
+                        &self.store,
+                        &self.trust_store,
+                    )
+                    .await
+                    .context("error applying checkpoint from server")?;
+                }
                 TrustStoreUpdate::Transaction(unchecked_block) => {
                     tracing::trace!("received block from server {:?}", &unchecked_block);
-                    apply_block(
-                        Arc::into_inner(unchecked_block).expect("arc has not been clones yet"),
+                    receive_update(
+                        PendingUpdate::Block(
+                            Arc::into_inner(unchecked_block).expect("arc has not been clones yet"),
+                        ),
+                        &mut self.gate,
+                        &self.upstream,
                         &self.store,
                         &self.trust_store,
                     )
                     .await
                     .context("error applying old block from server")?;
                 }
+                TrustStoreUpdate::Witness(signature) => {
+                    receive_witness_signature(
+                        signature,
+                        &mut self.gate,
+                        &self.upstream,
+                        &self.store,
+                        &self.trust_store,
+                    )
+                    .await
+                    .context("error applying witnessed blocks from server")?;
+                }
                 TrustStoreUpdate::UpToDate(server_digest) => {
-                    if server_digest != self.trust_store.read().unwrap().digest() {
+                    if self.gate.pending() > 0 {
+                        tracing::info!(
+                            "{} trust store blocks are waiting for witness signatures",
+                            self.gate.pending()
+                        );
+                    } else if server_digest != self.trust_store.read().unwrap().digest() {
                         return Err(anyhow!("server sent wrong digest"));
                     }
                     break;
@@ -105,13 +194,28 @@ impl CommandDispatcher for UpdateTrustStore {
                     match update {
                         TrustStoreUpdate::Transaction(unchecked_block) => {
                             tracing::trace!("received block from server {:?}", &unchecked_block);
-                            apply_block(
-                                Arc::into_inner(unchecked_block).expect("arc has not been clones yet"),
+                            receive_update(
+                                PendingUpdate::Block(
+                                    Arc::into_inner(unchecked_block).expect("arc has not been clones yet"),
+                                ),
+                                &mut self.gate,
+                                &self.upstream,
                                 &self.store,
                                 &self.trust_store,
                             )
                             .await.context("error applying live block from server")?;
                         },
+                        TrustStoreUpdate::Witness(signature) => {
+                            receive_witness_signature(
+                                signature,
+                                &mut self.gate,
+                                &self.upstream,
+                                &self.store,
+                                &self.trust_store,
+                            )
+                            .await.context("error applying witnessed blocks from server")?;
+                        },
+                        TrustStoreUpdate::Checkpoint(_) => return Err(anyhow!("server sent checkpoint after up to date info")),
                         TrustStoreUpdate::UpToDate(_) => return Err(anyhow!("server already sent up to date info")),
                         TrustStoreUpdate::Close => return Ok(()),
                     }
@@ -122,6 +226,61 @@ impl CommandDispatcher for UpdateTrustStore {
     }
 }
 
+/// Applies the block or checkpoint once enough witnesses signed it.
+async fn receive_update(
+    update: PendingUpdate,
+    gate: &mut WitnessGate<PendingUpdate>,
+    upstream: &UnverifiedCertificate,
+    store: &TrustStoreTransactionStore,
+    trust_store: &RwLock<TrustStore>,
+) -> anyhow::Result<()> {
+    for update in gate.add(update) {
+        apply_update(update, upstream, store, trust_store).await?;
+    }
+
+    Ok(())
+}
+
+/// Verifies the signature of a witness and applies the blocks it vouches for.
+///
+/// A signature conflicting with the local history marks the trust store as
+/// conflicting, just like the position of any other member.
+async fn receive_witness_signature(
+    signature: Checkpoint,
+    gate: &mut WitnessGate<PendingUpdate>,
+    upstream: &UnverifiedCertificate,
+    store: &TrustStoreTransactionStore,
+    trust_store: &RwLock<TrustStore>,
+) -> anyhow::Result<()> {
+    if let Err(err) = compare_position(trust_store, store, &signature).await {
+        tracing::warn!(
+            "rejected witness signature of {}: {err}",
+            signature.signer()
+        );
+        return Ok(());
+    }
+
+    for update in gate.add_signature(signature) {
+        apply_update(update, upstream, store, trust_store).await?;
+    }
+
+    Ok(())
+}
+
+async fn apply_update(
+    update: PendingUpdate,
+    upstream: &UnverifiedCertificate,
+    store: &TrustStoreTransactionStore,
+    trust_store: &RwLock<TrustStore>,
+) -> anyhow::Result<()> {
+    match update {
+        PendingUpdate::Checkpoint(update) => {
+            apply_checkpoint(*update, upstream, store, trust_store).await
+        }
+        PendingUpdate::Block(block) => apply_block(block, store, trust_store).await,
+    }
+}
+
 async fn apply_block(
     block: UncheckedBlock<trust_store::Transaction>,
     store: &TrustStoreTransactionStore,
@@ -142,17 +301,66 @@ async fn apply_block(
     Ok(())
 }
 
+/// Replaces the local trust store with a checkpoint, see
+/// [`TrustStore::accept_checkpoint`].
+async fn apply_checkpoint(
+    update: CheckpointUpdate,
+    upstream: &UnverifiedCertificate,
+    store: &TrustStoreTransactionStore,
+    trust_store: &RwLock<TrustStore>,
+) -> anyhow::Result<()> {
+    let CheckpointUpdate {
+        checkpoint,
+        state,
+        history,
+    } = update;
+    let history = (!history.is_empty()).then(|| MerkleIndex::from_leaves(history));
+
+    // the accepted checkpoint only proves the current block, the leaves known
+    // before have to stay the same as well
+    if let (Some(history), Some(local)) = (&history, store.merkle_index().await?) {
+        if !history.leaves().starts_with(local.leaves()) {
+            return Err(anyhow!("checkpoint history differs from the local one"));
+        }
+    }
+
+    let mut imported = trust_store.read().unwrap().accept_checkpoint(
+        state.clone(),
+        &checkpoint,
+        Some(upstream),
+        history.as_ref(),
+    )?;
+
+    store
+        .add_checkpoint(&checkpoint, &state, history.as_ref())
+        .await?;
+
+    let mut guard = trust_store.write().unwrap();
+    for position in guard.conflict_reports() {
+        imported.report_conflict(position.clone());
+    }
+    *guard = imported;
+
+    Ok(())
+}
+
 pub struct UpdateTrustStoreHandler {
     trust_store: Arc<RwLock<TrustStore>>,
     store: Arc<server_store::TrustStoreTransactionStore>,
+    witnesses: Arc<WitnessSignatures>,
 }
 
 impl UpdateTrustStoreHandler {
     pub fn new(
         trust_store: Arc<RwLock<TrustStore>>,
         store: Arc<server_store::TrustStoreTransactionStore>,
+        witnesses: Arc<WitnessSignatures>,
     ) -> Self {
-        Self { trust_store, store }
+        Self {
+            trust_store,
+            store,
+            witnesses,
+        }
     }
 }
 
@@ -170,7 +378,28 @@ impl CommandHandler for UpdateTrustStoreHandler {
         request: Self::Request,
         cancel: CancellationToken,
     ) -> anyhow::Result<()> {
-        let (current, mut receiver) = self.store.load_all_after(request).await?;
+        let mut after = request;
+        if let Some((checkpoint, state)) = self.store.checkpoint_for(request).await? {
+            after = checkpoint.sequence();
+            let history = match self.store.merkle_index().await? {
+                Some(index) if checkpoint.history_root().is_some() => index
+                    .leaves()
+                    .get(..after as usize)
+                    .map(<[_]>::to_vec)
+                    .unwrap_or_default(),
+                _ => Vec::new(),
+            };
+            let update = CheckpointUpdate {
+                checkpoint,
+                state,
+                history,
+            };
+            session
+                .write_object(&TrustStoreUpdate::Checkpoint(Box::new(update)))
+                .await?;
+        }
+
+        let (current, mut receiver) = self.store.load_all_after(after).await?;
         for block in current {
             session
                 .write_object(&TrustStoreUpdate::Transaction(Arc::new(block)))
@@ -181,6 +410,12 @@ impl CommandHandler for UpdateTrustStoreHandler {
                 .write_object(&TrustStoreUpdate::Transaction(block))
                 .await?;
         }
+        let (signatures, mut witness_receiver) = self.witnesses.subscribe();
+        for signature in signatures {
+            session
+                .write_object(&TrustStoreUpdate::Witness(signature))
+                .await?;
+        }
         let digest = self.trust_store.read().unwrap().digest();
         session
             .write_object(&TrustStoreUpdate::UpToDate(digest))
@@ -203,6 +438,23 @@ impl CommandHandler for UpdateTrustStoreHandler {
                         }
                     }
                 }
+                signature = witness_receiver.recv() => {
+                    match signature {
+                        Ok(signature) => {
+                            session.write_object(&TrustStoreUpdate::Witness(signature)).await?;
+                        }
+                        // only the latest signature of each witness matters
+                        Err(RecvError::Lagged(_)) => {
+                            for signature in self.witnesses.latest() {
+                                session.write_object(&TrustStoreUpdate::Witness(signature)).await?;
+                            }
+                        }
+                        Err(RecvError::Closed) => {
+                            session.write_object(&TrustStoreUpdate::Close).await?;
+                            return Ok(());
+                        }
+                    }
+                }
             }
         }
     }
@@ -1,7 +1,7 @@
 use std::{
-    collections::HashMap,
+    collections::VecDeque,
     mem,
-    sync::{Arc, Mutex},
+    sync::{Arc, RwLock},
     time::Duration,
 };
 
@@ -11,27 +11,37 @@ use futures::{FutureExt, select};
 use serde::{Deserialize, Serialize};
 use svalin_pki::{
     CertificateType, Credential, EncryptedObject, EncryptionKey, SpkiHash, TrustStoreVerifier,
+    get_current_timestamp,
     mls::{
         SvalinGroupId,
-        client::MessageDataContent,
+        client::{MessageData, MessageDataContent},
         key_package::UnverifiedKeyPackage,
         provider::{ExportedMlsStore, SvalinStorage},
-        transport_types::{MessageToMemberTransport, MessageToServerTransport},
+        transport_types::{MessageToMemberTransport, MessageToServerTransport, SvalinMessage},
     },
+    secure_chain::Checkpoint,
+    trust_store::TrustStore,
 };
 use svalin_rpc::rpc::command::{dispatcher::CommandDispatcher, handler::CommandHandler};
-use svalin_store::client_store::persistent;
-use svalin_store::server_store::{KeyPackageStore, MessageStore, UserStore};
+use svalin_store::client_store::persistent::{self, AlertAcknowledgement};
+use svalin_store::server_store::{KeyPackageStore, MessageStore, UpdateMlsDataError, UserStore};
+use svalin_store::trust_store_transaction_store::TrustStoreTransactionStore;
 use tokio::{
     sync::mpsc,
+    task::JoinHandle,
     time::{Instant, sleep_until},
 };
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 use crate::{
-    client::state::ClientStateUpdate, message_streaming::client::ClientStateHandle, mls::MlsClient,
-    remote_key_retriever::RemoteKeyRetriever, server::MlsServer,
+    client::state::ClientStateUpdate,
+    message_streaming::client::ClientStateHandle,
+    mls::{AgentInstruction, KeyUpdateInterval, MlsClient, MlsTypes},
+    remote_key_retriever::RemoteKeyRetriever,
+    server::MlsServer,
+    shared::commands::count_key_packages::KeyPackageCount,
+    util::chain_gossip::{ComparePositionError, compare_position},
 };
 
 pub struct UpdateUserMlsHandler {
@@ -39,7 +49,6 @@ pub struct UpdateUserMlsHandler {
     message_store: Arc<MessageStore>,
     key_package_store: Arc<KeyPackageStore>,
     mls: Arc<MlsServer>,
-    user_lock: Mutex<HashMap<SpkiHash, Arc<tokio::sync::Mutex<()>>>>,
 }
 
 impl UpdateUserMlsHandler {
@@ -54,7 +63,6 @@ impl UpdateUserMlsHandler {
             message_store,
             key_package_store,
             mls,
-            user_lock: Mutex::new(HashMap::new()),
         }
     }
 }
@@ -79,45 +87,32 @@ impl CommandHandler for UpdateUserMlsHandler {
         }
         let user_hash = peer.issuer().clone();
 
-        let user_arc = {
-            self.user_lock
-                .lock()
-                .unwrap()
-                .entry(user_hash.clone())
-                .or_default()
-                .clone()
-        };
-        let _user_lock = user_arc.lock().await;
-
-        let user = self
-            .user_store
-            .get_user(&user_hash)
-            .await?
-            .ok_or_else(|| anyhow!("User not found"))?;
-
         // Ok, so at this point I need a way to load all messages which should be delivered in order.
         // Once all of them are sent over, the dispatcher should get a signal that we're done, so we'll porbably be sending Options.
         // The Dispatcher should then send an updated version of the user's MlsState to the server.
         // I might want to somehow verify that new MlsState too. Maybe with a SignedObject?
         // Either way, the dispatcher should send both the updated MlsState, but also the ids of all messages which were processed.
 
-        let saved_state = SavedState {
-            mls_store: user.mls_store,
-            persistent_data: user.persistent_data,
-        };
-        session.write_object(&saved_state).await?;
-
-        let (send, mut recv) = mpsc::channel(100);
-        tokio::spawn(stream_mls_messages(
-            user_hash.clone(),
-            send,
-            self.message_store.clone(),
-        ));
+        // Several sessions of the same user may run this concurrently. Each
+        // update names the version it is based on, the first one wins and the
+        // others start over from the new state.
+        self.send_saved_state(session, &user_hash).await?;
+        let (mut recv, mut stream) = self.stream_messages(&user_hash);
+        let mut resync = false;
 
         // Yield not implemented yet
         let mut next_key_package_update = Instant::now();
 
         loop {
+            if resync {
+                resync = false;
+                // messages which were only handled by the outdated state are
+                // delivered again
+                stream.abort();
+                (recv, stream) = self.stream_messages(&user_hash);
+                self.send_saved_state(session, &user_hash).await?;
+            }
+
             let update = select! {
                 received = recv.recv().fuse() => {
                     if let Some((uuid, message)) = received {
@@ -139,82 +134,149 @@ impl CommandHandler for UpdateUserMlsHandler {
                     }
 
                     let handle_result = self.handle_response(&user_hash, response).await;
-                    let send_result = handle_result.as_ref().map(|_| ()).map_err(|_| ());
-                    session.write_object(&send_result).await?;
+                    let result = match &handle_result {
+                        Ok(result) => *result,
+                        Err(_) => StateUpdateResult::Failed,
+                    };
+                    session.write_object(&Update::StateUpdated(result)).await?;
                     handle_result?;
 
+                    resync = result == StateUpdateResult::Conflict;
                     continue;
                 }
                 _ = sleep_until(next_key_package_update).fuse() => {
                     next_key_package_update = Instant::now() + Duration::from_secs(60);
-                    Update::KeyPackageCount(self.key_package_store.count_key_packages(&user_hash).await?)
+                    let count = KeyPackageCount::load(&self.key_package_store, &user_hash).await?;
+                    Update::KeyPackageCount(count)
                 }
             };
 
             session.write_object(&update).await?;
-
-            // let response = session.read_object::<ToServer>().await?;
-            // tracing::trace!("user mls respone: {response:?}");
-
-            // let handle_result = self.handle_response(&user_hash, response).await;
-            // let send_result = handle_result.as_ref().map(|_| ()).map_err(|_| ());
-            // session.write_object(&send_result).await?;
-            // handle_result?;
         }
 
+        stream.abort();
         session.write_object(&Update::Goodbye).await?;
 
-        // explicit drop, so I don't accidentally drop it beforehand
-        drop(_user_lock);
         Ok(())
     }
 }
 
 impl UpdateUserMlsHandler {
+    async fn send_saved_state(
+        &self,
+        session: &mut svalin_rpc::rpc::session::Session,
+        user_hash: &SpkiHash,
+    ) -> anyhow::Result<()> {
+        let stored = self
+            .user_store
+            .get_mls_data(user_hash)
+            .await?
+            .ok_or_else(|| anyhow!("User not found"))?;
+
+        let saved_state = SavedState {
+            mls_store: stored.mls_store,
+            persistent_data: stored.persistent_data,
+            version: stored.version,
+        };
+        session.write_object(&saved_state).await?;
+
+        Ok(())
+    }
+
+    fn stream_messages(
+        &self,
+        user_hash: &SpkiHash,
+    ) -> (
+        mpsc::Receiver<(Uuid, Arc<MessageToMemberTransport>)>,
+        JoinHandle<()>,
+    ) {
+        let (send, recv) = mpsc::channel(100);
+        let stream = tokio::spawn(stream_mls_messages(
+            user_hash.clone(),
+            send,
+            self.message_store.clone(),
+        ));
+
+        (recv, stream)
+    }
+
     async fn handle_response(
         &self,
         user_hash: &SpkiHash,
         response: ToServer,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<StateUpdateResult> {
         match response {
             ToServer::StateUpdate {
+                version,
                 mls_store,
                 persistent_data,
                 key_packages,
                 aknowledged,
                 messages,
             } => {
+                // nothing is stored before the version is checked, key packages
+                // of an outdated state can't be used
+                let mut verified_key_packages = Vec::with_capacity(key_packages.len());
                 for key_package in key_packages {
                     let key_package = self
                         .mls
                         .verify_key_package(key_package, user_hash)
                         .await
                         .context("error verifying key package")?;
-                    self.key_package_store.add_key_package(key_package).await?;
+                    verified_key_packages.push(key_package);
                 }
 
-                self.user_store
-                    .update_mls_data(&user_hash, mls_store, persistent_data)
-                    .await?;
+                let version = match self
+                    .user_store
+                    .update_mls_data(&user_hash, version, mls_store, persistent_data)
+                    .await
+                {
+                    Ok(version) => version,
+                    Err(UpdateMlsDataError::VersionConflict { expected, current }) => {
+                        tracing::debug!(
+                            "rejecting mls state of {user_hash} based on version {expected}, current version is {current}"
+                        );
+                        return Ok(StateUpdateResult::Conflict);
+                    }
+                    Err(err) => return Err(err.into()),
+                };
+
+                for key_package in verified_key_packages {
+                    self.key_package_store.add_key_package(key_package).await?;
+                }
 
                 self.message_store
                     .aknowledge_messages(&user_hash, &aknowledged)
                     .await?;
 
+                // A refused commit doesn't stop the other messages, its sender
+                // drops it once the commit which was distributed first arrives
+                let mut failed = false;
                 for message in messages {
                     tracing::trace!("received message from user mls: {message:?}");
-                    let to_send = self.mls.process_message(message).await?;
+                    let to_send = match self.mls.process_message(message).await {
+                        Ok(to_send) => to_send,
+                        Err(err) => {
+                            tracing::warn!("refusing user mls message of {user_hash}: {err:#}");
+                            failed = true;
+                            continue;
+                        }
+                    };
                     tracing::trace!("processing resulted in messages: {to_send:?}");
                     for mut message in to_send {
-                        message.remove_receiver(&user_hash);
+                        message.remove_sender(&user_hash);
                         self.message_store.add_message(message).await?;
                     }
                 }
+
+                if failed {
+                    return Ok(StateUpdateResult::Failed);
+                }
+
+                Ok(StateUpdateResult::Accepted { version })
             }
             ToServer::Goodbye => unreachable!(),
         }
-
-        Ok(())
     }
 }
 
@@ -233,18 +295,14 @@ async fn stream_mls_messages_inner(
     sender: mpsc::Sender<(Uuid, Arc<MessageToMemberTransport>)>,
     message_store: Arc<MessageStore>,
 ) -> Result<(), anyhow::Error> {
-    let current = message_store.load_all_for(&receiver).await?;
-    let mut subscription = message_store.subscribe(receiver.clone()).await;
-    for (uuid, message) in current {
-        if let Err(e) = sender.send((uuid, Arc::new(message))).await {
+    let mut subscription = message_store.subscribe(receiver.clone());
+
+    while let Some(message) = subscription.recv().await? {
+        if let Err(e) = sender.send(message).await {
             anyhow::bail!("Error sending message: {}", e);
         }
     }
 
-    while let Some(message) = subscription.recv().await {
-        let _ = sender.send(message).await;
-    }
-
     Ok(())
 }
 
@@ -255,11 +313,150 @@ pub struct UpdateUserMls {
     pub verifier: TrustStoreVerifier,
     pub session_mls: Arc<MlsClient>,
     pub state_handle: ClientStateHandle,
+    pub trust_store: Arc<RwLock<TrustStore>>,
+    /// Stored trust store blocks, to compare the positions of agents with.
+    pub history: Arc<TrustStoreTransactionStore>,
+    /// Messages the sessions send with the user's credential.
+    pub outgoing: mpsc::Receiver<UserMlsMessage>,
+    pub key_update_interval: KeyUpdateInterval,
     pub cancel: CancellationToken,
 }
 
 const WANTED_KEY_PACKAGES: u64 = 100;
 
+impl UpdateUserMls {
+    /// Compares the trust store position an agent sent to its device group
+    /// and shows a confirmed split view to the sessions.
+    async fn compare_position(&self, position: &Checkpoint) {
+        match compare_position(&self.trust_store, &self.history, position).await {
+            Ok(()) => {}
+            Err(ComparePositionError::SplitView(peer)) => {
+                if let Err(err) = self
+                    .state_handle
+                    .update(ClientStateUpdate::ChainConflict(peer))
+                    .await
+                {
+                    tracing::error!("failed to report trust store conflict: {err}");
+                }
+            }
+            Err(err) => tracing::warn!("failed to compare trust store position: {err}"),
+        }
+    }
+}
+
+/// A message a session sends with the user's credential, since only the
+/// user is a member of the groups. It is encrypted by the user's MLS state
+/// and sent with its next update.
+#[derive(Debug, Clone)]
+pub enum UserMlsMessage {
+    /// An instruction for all agents through the global group, only root
+    /// sends these.
+    Broadcast(AgentInstruction),
+    /// An instruction for the device group of an agent.
+    Instruction(SpkiHash, AgentInstruction),
+    /// Marks an alert of the device as handled for all sessions and users.
+    AcknowledgeAlert(SpkiHash, AlertAcknowledgement),
+}
+
+impl UserMlsMessage {
+    fn group(&self) -> SvalinGroupId {
+        match self {
+            UserMlsMessage::Broadcast(_) => SvalinGroupId::GlobalGroup,
+            UserMlsMessage::Instruction(device, _)
+            | UserMlsMessage::AcknowledgeAlert(device, _) => {
+                SvalinGroupId::DeviceGroup(device.clone())
+            }
+        }
+    }
+
+    pub(crate) async fn encrypt<KeyRetriever, Verifier>(
+        &self,
+        client: &svalin_pki::mls::client::MlsClient<MlsTypes, KeyRetriever, Verifier>,
+    ) -> anyhow::Result<MessageToServerTransport>
+    where
+        KeyRetriever: svalin_pki::mls::key_retriever::KeyRetriever,
+        Verifier: svalin_pki::Verifier,
+    {
+        match self {
+            UserMlsMessage::Broadcast(instruction) => {
+                client.broadcast_instruction(instruction.clone()).await
+            }
+            UserMlsMessage::Instruction(device, instruction) => {
+                client
+                    .send_instruction(device.clone(), instruction.clone())
+                    .await
+            }
+            UserMlsMessage::AcknowledgeAlert(_, acknowledgement) => {
+                client.send_message(&self.group(), acknowledgement).await
+            }
+        }
+    }
+
+    /// The server doesn't deliver a message back to its sender, so the user's
+    /// own acknowledgements are applied to its state directly.
+    pub(crate) fn own_update(&self, user: &SpkiHash) -> Option<persistent::Message> {
+        let UserMlsMessage::AcknowledgeAlert(_, acknowledgement) = self else {
+            return None;
+        };
+
+        Some(persistent::Message::Received {
+            group: self.group(),
+            sender: user.clone(),
+            message: SvalinMessage::new(acknowledgement).ok()?,
+        })
+    }
+}
+
+/// Applies a message the user received to its main state, returns whether
+/// the state changed.
+pub(crate) fn update_main_state(
+    persistent_data: &mut persistent::State,
+    handled: MessageData<MlsTypes>,
+) -> bool {
+    let message = match handled.content {
+        MessageDataContent::Report(spki_hash, report) => {
+            persistent::Message::UpdateSystemReport(spki_hash, report)
+        }
+        MessageDataContent::MetaInfo(spki_hash, meta_info) => {
+            persistent::Message::UpdateMetaInfo(spki_hash, meta_info)
+        }
+        // alerts and acknowledgements, the state ignores types it doesn't know
+        MessageDataContent::Other(sender, message) => persistent::Message::Received {
+            group: handled.group,
+            sender,
+            message,
+        },
+        // positions are compared by the user's MLS state before
+        MessageDataContent::ChainPosition(_, _) => return false,
+        // instructions are only executed by the agent
+        MessageDataContent::Instruction(_, _) => return false,
+        MessageDataContent::Internal => return false,
+    };
+    persistent_data.update(message);
+
+    true
+}
+
+/// What woke up the user's MLS state.
+enum Wake {
+    Update(Update),
+    Outgoing(UserMlsMessage),
+    /// Nothing arrived for a while, so the state is at the newest messages.
+    Idle,
+}
+
+/// Users and agents of the trust store, which root keeps in the global group.
+fn global_group_members(trust_store: &TrustStore) -> Vec<SpkiHash> {
+    let now = get_current_timestamp();
+
+    [CertificateType::User, CertificateType::Agent]
+        .into_iter()
+        .flat_map(|certificate_type| trust_store.certificates_of_type(certificate_type))
+        .filter(|certificate| certificate.not_after() >= now)
+        .map(|certificate| certificate.spki_hash().clone())
+        .collect()
+}
+
 impl CommandDispatcher for UpdateUserMls {
     type Output = ();
 
@@ -276,12 +473,19 @@ impl CommandDispatcher for UpdateUserMls {
     }
 
     async fn dispatch(
-        self,
+        mut self,
         session: &mut svalin_rpc::rpc::session::Session,
     ) -> Result<Self::Output, Self::Error> {
         tracing::trace!("Updating user MLS");
 
-        loop {
+        // sessions starting at the same time don't all commit at once
+        let mut next_key_update = Instant::now() + self.key_update_interval.first_update_delay();
+        // messages of the sessions which weren't part of an accepted update
+        // yet, they're encrypted again after a resync
+        let mut unsent = VecDeque::new();
+        let mut in_flight = Vec::new();
+
+        'resync: loop {
             let Some(state) = self
                 .cancel
                 .run_until_cancelled(session.read_object::<SavedState>())
@@ -290,12 +494,13 @@ impl CommandDispatcher for UpdateUserMls {
                 return Ok(());
             };
             let state = state?;
+            let mut version = state.version;
 
             let (mls_store, export_handle) = SvalinStorage::import(state.mls_store, &self.key)
                 .context("error importing mls storage")?;
             let mut persistent_data = state
                 .persistent_data
-                .decrypt(&self.key)
+                .decrypt_with_legacy::<persistent::LegacyState>(&self.key)
                 .context("error decrypting persistent data")?;
             let client = MlsClient::new(
                 self.user_credential.clone(),
@@ -308,122 +513,301 @@ impl CommandDispatcher for UpdateUserMls {
             let mut aknowledge = Vec::new();
             let mut messages = Vec::new();
             let mut key_packages = Vec::new();
+            // updates received while waiting for the result of a state update
+            let mut pending = VecDeque::new();
             let mut should_yield = false;
+            // changes of the persistent data the sessions haven't seen yet
+            let mut persistent_changed = false;
             let mut timeout_duration = Duration::from_secs(3);
 
             while !should_yield {
-                let Some(update) = self
-                    .cancel
-                    .run_until_cancelled(tokio::time::timeout(
-                        timeout_duration,
-                        session.read_object::<Update>(),
-                    ))
-                    .await
-                else {
-                    session.write_object(&ToServer::Goodbye).await?;
-                    return Ok(());
+                let wake = match pending.pop_front() {
+                    Some(update) => Wake::Update(update),
+                    None => select! {
+                        _ = self.cancel.cancelled().fuse() => {
+                            session.write_object(&ToServer::Goodbye).await?;
+                            return Ok(());
+                        }
+                        message = self.outgoing.recv().fuse() => match message {
+                            Some(message) => Wake::Outgoing(message),
+                            // the client is shutting down
+                            None => {
+                                session.write_object(&ToServer::Goodbye).await?;
+                                return Ok(());
+                            }
+                        },
+                        update = tokio::time::timeout(
+                            timeout_duration,
+                            session.read_object::<Update>(),
+                        ).fuse() => match update {
+                            Ok(update) => Wake::Update(update?),
+                            Err(_) => Wake::Idle,
+                        },
+                    },
                 };
 
                 let send_update;
 
-                if let Ok(update) = update {
-                    // No timeout, so other messages might follow directly afterwards
-                    let update = update?;
-                    // Next timeout should be short, so we can send out updates as soon as possible
-                    // A bit of timeout is still good, so we have some debounce.
-                    timeout_duration = Duration::from_secs(3);
-                    tracing::trace!("user mls update: {update:?}");
-                    match update {
-                        Update::Message(uuid, message_to_member_transport) => {
-                            let handled =
-                                client.handle_message(&message_to_member_transport).await?;
-
-                            match handled.content {
-                                MessageDataContent::Report(spki_hash, report) => {
-                                    persistent_data.update(
-                                        persistent::Message::UpdateSystemReport(spki_hash, report),
-                                    );
+                match wake {
+                    Wake::Update(update) => {
+                        // No timeout, so other messages might follow directly afterwards
+                        // Next timeout should be short, so we can send out updates as soon as possible
+                        // A bit of timeout is still good, so we have some debounce.
+                        timeout_duration = Duration::from_secs(3);
+                        tracing::trace!("user mls update: {update:?}");
+                        match update {
+                            Update::Message(uuid, message_to_member_transport) => {
+                                let handled =
+                                    client.handle_message(&message_to_member_transport).await?;
+
+                                // agents send their positions to their device
+                                // group, only the user is a member of it
+                                if let MessageDataContent::ChainPosition(_, position) =
+                                    &handled.content
+                                {
+                                    self.compare_position(position).await;
                                 }
-                                MessageDataContent::MetaInfo(spki_hash, meta_info) => {
-                                    persistent_data.update(persistent::Message::UpdateMetaInfo(
-                                        spki_hash, meta_info,
-                                    ));
+
+                                persistent_changed |=
+                                    update_main_state(&mut persistent_data, handled);
+
+                                aknowledge.push(uuid);
+                                send_update = aknowledge.len() >= 10;
+                            }
+                            Update::KeyPackageCount(count) => {
+                                send_update =
+                                    count.available < WANTED_KEY_PACKAGES || !count.last_resort;
+                                let mut key_package_count = count.available;
+                                while key_package_count < WANTED_KEY_PACKAGES {
+                                    let key_package =
+                                        client.create_key_package().await?.to_unverified();
+                                    key_packages.push(key_package);
+                                    key_package_count += 1;
                                 }
-                                MessageDataContent::Internal => {}
+                                // keeps the user joinable, even if all regular packages are used up
+                                if !count.last_resort {
+                                    let key_package = client
+                                        .create_last_resort_key_package()
+                                        .await?
+                                        .to_unverified();
+                                    key_packages.push(key_package);
+                                }
+                            }
+                            Update::StateUpdated(result) => {
+                                tracing::warn!("unexpected user mls update result: {result:?}");
+                                send_update = false;
                             }
+                            Update::YieldRequest => {
+                                send_update = true;
+                                should_yield = true;
+                            }
+                            Update::Goodbye => {
+                                return Ok(());
+                            }
+                        }
+                    }
+                    Wake::Outgoing(message) => {
+                        unsent.push_back(message);
+                        send_update = true;
+                    }
+                    Wake::Idle => {
+                        // Timeout, so probably at the newest messages
+                        //
+                        // long next timeout, since everything is taken care of.
+                        // Technically, there's not even a timeout neccesary.
+                        timeout_duration = Duration::from_secs(60);
+
+                        // Here we check if we want to add our session to any needed groups
+                        // for (device, _) in persistent_data.devices() {
+                        //     let group = SvalinGroupId::DeviceGroup(device.clone());
+                        //     if !client
+                        //         .is_member(&group, self.session_mls.me().clone())
+                        //         .await?
+                        //     {
+                        //         let key_package = self.session_mls.create_key_package().await?;
+                        //         let message = client.add_member(&group, key_package).await?;
+                        //         messages.push(message);
+                        //     }
+
+                        //     if let Some(message) = client
+                        //         .create_meta_group_if_missing(device.clone())
+                        //         .await
+                        //         .map_err(|err| anyhow!(err))?
+                        //     {
+                        //         tracing::trace!("new meta group: {message:?}");
+                        //         messages.push(message);
+                        //     }
+                        //     let meta_group = SvalinGroupId::DeviceMetaGroup(device.clone());
+                        //     if !client
+                        //         .is_member(&meta_group, self.session_mls.me().clone())
+                        //         .await?
+                        //     {
+                        //         tracing::trace!("adding client to meta group");
+                        //         let key_package = self.session_mls.create_key_package().await?;
+                        //         let message = client.add_member(&meta_group, key_package).await?;
+                        //         messages.push(message);
+                        //     } else {
+                        //         tracing::trace!("already in meta group");
+                        //     }
+                        // }
+
+                        // Devices which rotated their key continue in the groups of
+                        // the new key, the agent creates its device group on start.
+                        // The old leaf can't be verified anymore, so the groups of
+                        // the previous key are dropped.
+                        let rotations: Vec<_> = {
+                            let trust_store = self.trust_store.read().unwrap();
+                            persistent_data
+                                .devices()
+                                .keys()
+                                .filter_map(|previous| {
+                                    let successor = trust_store.latest_successor(previous);
+                                    (successor != previous)
+                                        .then(|| (previous.clone(), successor.clone()))
+                                })
+                                .collect()
+                        };
+                        let migrated = !rotations.is_empty();
+                        for (previous, successor) in rotations {
+                            tracing::info!(
+                                "moving device {previous} to its rotated key {successor}"
+                            );
+                            let had_meta_group = client
+                                .group_exists(&SvalinGroupId::DeviceMetaGroup(previous.clone()))
+                                .await?;
+                            client.forget_rotated_device(&previous).await?;
+                            persistent_data.update(persistent::Message::MigrateDevice {
+                                previous,
+                                successor: successor.clone(),
+                            });
+
+                            if had_meta_group {
+                                match client.create_meta_group_if_missing(successor.clone()).await {
+                                    Ok(Some(message)) => messages.push(message),
+                                    Ok(None) => {}
+                                    Err(err) => {
+                                        tracing::warn!(
+                                            "failed to create meta group of {successor}: {err:#}"
+                                        );
+                                        continue;
+                                    }
+                                }
+                                let meta_info = persistent_data
+                                    .devices()
+                                    .get(&successor)
+                                    .and_then(|device| device.meta_info())
+                                    .cloned();
+                                if let Some(meta_info) = meta_info {
+                                    messages
+                                        .push(client.send_meta_info(successor, meta_info).await?);
+                                }
+                            }
+                        }
 
-                            aknowledge.push(uuid);
-                            send_update = aknowledge.len() >= 10;
+                        // Members whose certificates were dropped from the trust store
+                        // are removed, so they can't decrypt anything sent afterwards
+                        let is_root = self.user_credential.certificate().certificate_type()
+                            == CertificateType::Root;
+                        let mut groups = persistent_data
+                            .devices()
+                            .keys()
+                            .flat_map(|device| {
+                                [
+                                    SvalinGroupId::DeviceGroup(device.clone()),
+                                    SvalinGroupId::DeviceMetaGroup(device.clone()),
+                                ]
+                            })
+                            .collect::<Vec<_>>();
+                        if is_root {
+                            groups.push(SvalinGroupId::GlobalGroup);
                         }
-                        Update::KeyPackageCount(mut key_package_count) => {
-                            send_update = key_package_count < WANTED_KEY_PACKAGES;
-                            while key_package_count < WANTED_KEY_PACKAGES {
-                                let key_package =
-                                    client.create_key_package().await?.to_unverified();
-                                key_packages.push(key_package);
-                                key_package_count += 1;
+                        // only one commit per group can be pending until the
+                        // server delivers it back
+                        let mut committed = Vec::new();
+                        for group in &groups {
+                            match client.remove_revoked_members(group).await {
+                                Ok(Some(message)) => {
+                                    messages.push(message);
+                                    committed.push(group.clone());
+                                }
+                                Ok(None) => {}
+                                Err(err) => tracing::warn!(
+                                    "failed to remove revoked members from {group:?}: {err:#}"
+                                ),
                             }
                         }
-                        Update::YieldRequest => {
-                            send_update = true;
-                            should_yield = true;
+
+                        // Regular key updates bound what a leaked epoch secret reveals,
+                        // a removal already moved the group to new keys
+                        if Instant::now() >= next_key_update {
+                            next_key_update = Instant::now() + self.key_update_interval.duration();
+                            for group in groups.iter().filter(|group| !committed.contains(group)) {
+                                match client.update_key(group).await {
+                                    Ok(Some(message)) => {
+                                        messages.push(message);
+                                        committed.push(group.clone());
+                                    }
+                                    Ok(None) => {}
+                                    Err(err) => {
+                                        tracing::warn!("failed to update key in {group:?}: {err:#}")
+                                    }
+                                }
+                            }
                         }
-                        Update::Goodbye => {
-                            return Ok(());
+
+                        // Root keeps the global group in sync with the trust store,
+                        // once the commits of this round were delivered
+                        if is_root && !committed.contains(&SvalinGroupId::GlobalGroup) {
+                            if let Some(message) = client.create_global_group_if_missing().await? {
+                                tracing::debug!("created global group");
+                                messages.push(message);
+                            }
+
+                            let members = global_group_members(&self.trust_store.read().unwrap());
+                            match client.sync_global_group(&members).await {
+                                Ok(message) => messages.extend(message),
+                                Err(err) => {
+                                    tracing::warn!("failed to sync the global group: {err:#}")
+                                }
+                            }
                         }
+
+                        send_update = !aknowledge.is_empty() || !messages.is_empty() || migrated;
+                        persistent_changed |= migrated;
                     }
-                } else {
-                    // Timeout, so probably at the newest messages
-                    //
-                    // long next timeout, since everything is taken care of.
-                    // Technically, there's not even a timeout neccesary.
-                    timeout_duration = Duration::from_secs(60);
-
-                    // Here we check if we want to add our session to any needed groups
-                    // for (device, _) in persistent_data.devices() {
-                    //     let group = SvalinGroupId::DeviceGroup(device.clone());
-                    //     if !client
-                    //         .is_member(&group, self.session_mls.me().clone())
-                    //         .await?
-                    //     {
-                    //         let key_package = self.session_mls.create_key_package().await?;
-                    //         let message = client.add_member(&group, key_package).await?;
-                    //         messages.push(message);
-                    //     }
-
-                    //     if let Some(message) = client
-                    //         .create_meta_group_if_missing(device.clone())
-                    //         .await
-                    //         .map_err(|err| anyhow!(err))?
-                    //     {
-                    //         tracing::trace!("new meta group: {message:?}");
-                    //         messages.push(message);
-                    //     }
-                    //     let meta_group = SvalinGroupId::DeviceMetaGroup(device.clone());
-                    //     if !client
-                    //         .is_member(&meta_group, self.session_mls.me().clone())
-                    //         .await?
-                    //     {
-                    //         tracing::trace!("adding client to meta group");
-                    //         let key_package = self.session_mls.create_key_package().await?;
-                    //         let message = client.add_member(&meta_group, key_package).await?;
-                    //         messages.push(message);
-                    //     } else {
-                    //         tracing::trace!("already in meta group");
-                    //     }
-                    // }
-
-                    send_update = !aknowledge.is_empty() || !messages.is_empty();
                 }
 
                 if send_update {
                     tracing::trace!("sending user mls update");
+                    // messages of the sessions are kept until the update is
+                    // accepted, a group with a pending commit takes them later
+                    for message in mem::take(&mut unsent) {
+                        match message.encrypt(&client).await {
+                            Ok(encrypted) => {
+                                messages.push(encrypted);
+                                if let Some(update) = message
+                                    .own_update(self.user_credential.certificate().spki_hash())
+                                {
+                                    persistent_data.update(update);
+                                    persistent_changed = true;
+                                }
+                                in_flight.push(message);
+                            }
+                            Err(err) if client.group_exists(&message.group()).await? => {
+                                tracing::debug!("sending {message:?} later: {err:#}");
+                                unsent.push_back(message);
+                            }
+                            Err(err) => tracing::error!("failed to send {message:?}: {err:#}"),
+                        }
+                    }
                     // We likely just found a group which contains data we don't have yet.
                     // So it's a good idea to send that update to the session's state
-                    let update_session_state = !messages.is_empty();
+                    let update_session_state =
+                        !messages.is_empty() || mem::take(&mut persistent_changed);
 
                     let mls_store = export_handle.export(&self.key)?;
                     let state_update = ToServer::StateUpdate {
+                        version,
                         mls_store,
                         persistent_data: EncryptedObject::encrypt(&persistent_data, &self.key)?,
                         aknowledged: mem::replace(&mut aknowledge, Vec::new()),
@@ -433,8 +817,35 @@ impl CommandDispatcher for UpdateUserMls {
                     };
 
                     session.write_object(&state_update).await?;
-                    if session.read_object::<Result<(), ()>>().await.is_err() {
-                        tracing::error!("server warned about error in user mls update");
+
+                    let result = loop {
+                        match session.read_object::<Update>().await? {
+                            Update::StateUpdated(result) => break result,
+                            Update::Goodbye => return Ok(()),
+                            update => pending.push_back(update),
+                        }
+                    };
+                    match result {
+                        StateUpdateResult::Accepted { version: accepted } => {
+                            version = accepted;
+                            in_flight.clear();
+                        }
+                        StateUpdateResult::Conflict => {
+                            // Another session of this user was faster. Everything
+                            // since the last accepted update is dropped and redone
+                            // on top of the current state.
+                            tracing::info!(
+                                "user mls state was updated by another session, resyncing"
+                            );
+                            for message in in_flight.drain(..).rev() {
+                                unsent.push_front(message);
+                            }
+                            continue 'resync;
+                        }
+                        StateUpdateResult::Failed => {
+                            tracing::error!("server warned about error in user mls update");
+                            in_flight.clear();
+                        }
                     }
 
                     if update_session_state {
@@ -451,22 +862,38 @@ impl CommandDispatcher for UpdateUserMls {
 }
 
 #[derive(Serialize, Deserialize)]
-struct SavedState {
-    mls_store: ExportedMlsStore,
-    persistent_data: EncryptedObject<persistent::State>,
+pub(crate) struct SavedState {
+    pub(crate) mls_store: ExportedMlsStore,
+    pub(crate) persistent_data: EncryptedObject<persistent::State>,
+    /// Has to be sent back with the next update of this state.
+    pub(crate) version: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-enum Update {
+pub(crate) enum Update {
     Message(Uuid, Arc<MessageToMemberTransport>),
-    KeyPackageCount(u64),
+    KeyPackageCount(KeyPackageCount),
+    StateUpdated(StateUpdateResult),
     YieldRequest,
     Goodbye,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StateUpdateResult {
+    Accepted {
+        version: u64,
+    },
+    /// Another session of the user updated the state first. The update was
+    /// dropped and the current state is sent next.
+    Conflict,
+    Failed,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
-enum ToServer {
+pub(crate) enum ToServer {
     StateUpdate {
+        /// The version of the state this update is based on.
+        version: u64,
         mls_store: ExportedMlsStore,
         persistent_data: EncryptedObject<persistent::State>,
         key_packages: Vec<UnverifiedKeyPackage>,
@@ -0,0 +1,201 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use svalin_pki::{
+    SpkiHash,
+    secure_chain::{Checkpoint, PositionComparison},
+    trust_store::TrustStore,
+};
+use svalin_rpc::rpc::{
+    command::{
+        dispatcher::CommandDispatcher,
+        handler::{CommandHandler, PermissionPrecursor},
+    },
+    peer::Peer,
+    session::Session,
+};
+use svalin_store::{
+    server_store::{TransactionStoreError, TrustStoreTransactionStore},
+    trust_store_transaction_store::LoadTransactionError,
+};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+use crate::permissions::Permission;
+
+/// The latest trust store head countersigned by each witness.
+///
+/// The signatures are persisted, so members can verify the current head right
+/// after a restart instead of waiting for every witness to sign it again.
+pub struct WitnessSignatures {
+    store: Arc<TrustStoreTransactionStore>,
+    latest: RwLock<HashMap<SpkiHash, Checkpoint>>,
+    broadcast: broadcast::Sender<Checkpoint>,
+}
+
+impl WitnessSignatures {
+    pub async fn load(
+        store: Arc<TrustStoreTransactionStore>,
+    ) -> Result<Self, LoadTransactionError> {
+        let latest = store
+            .witness_signatures()
+            .await?
+            .into_iter()
+            .map(|signature| (signature.signer().clone(), signature))
+            .collect();
+        let (broadcast, _) = broadcast::channel(10);
+        Ok(Self {
+            store,
+            latest: RwLock::new(latest),
+            broadcast,
+        })
+    }
+
+    pub async fn add(&self, signature: Checkpoint) -> Result<(), TransactionStoreError> {
+        self.store.add_witness_signature(&signature).await?;
+
+        let mut latest = self.latest.write().unwrap();
+        latest.insert(signature.signer().clone(), signature.clone());
+        // nobody might be listening
+        let _ = self.broadcast.send(signature);
+
+        Ok(())
+    }
+
+    pub fn latest(&self) -> Vec<Checkpoint> {
+        self.latest.read().unwrap().values().cloned().collect()
+    }
+
+    /// Returns the current signatures and a receiver for all following ones.
+    pub fn subscribe(&self) -> (Vec<Checkpoint>, broadcast::Receiver<Checkpoint>) {
+        let latest = self.latest.read().unwrap();
+        let receiver = self.broadcast.subscribe();
+        (latest.values().cloned().collect(), receiver)
+    }
+}
+
+/// Submits the countersigned trust store head of a witness.
+pub struct SubmitWitnessSignature(pub Checkpoint);
+
+impl CommandDispatcher for SubmitWitnessSignature {
+    type Output = ();
+
+    type Error = anyhow::Error;
+
+    type Request = Checkpoint;
+
+    fn key() -> String {
+        SubmitWitnessSignatureHandler::key()
+    }
+
+    fn get_request(&self) -> &Self::Request {
+        &self.0
+    }
+
+    async fn dispatch(self, session: &mut Session) -> Result<Self::Output, Self::Error> {
+        let accepted: bool = session.read_object().await?;
+        if accepted {
+            Ok(())
+        } else {
+            Err(anyhow!("the server rejected the witness signature"))
+        }
+    }
+}
+
+pub struct SubmitWitnessSignatureHandler {
+    trust_store: Arc<RwLock<TrustStore>>,
+    witnesses: Arc<WitnessSignatures>,
+}
+
+impl SubmitWitnessSignatureHandler {
+    pub fn new(trust_store: Arc<RwLock<TrustStore>>, witnesses: Arc<WitnessSignatures>) -> Self {
+        Self {
+            trust_store,
+            witnesses,
+        }
+    }
+
+    async fn add_signature(&self, peer: &Peer, signature: &Checkpoint) -> anyhow::Result<()> {
+        let Peer::Certificate(peer) = peer else {
+            return Err(anyhow!("expected peer to be a certificate"));
+        };
+
+        if peer.spki_hash() != signature.signer() {
+            return Err(anyhow!("witnesses may only submit their own signatures"));
+        }
+
+        {
+            let guard = self.trust_store.read().unwrap();
+            let certificate = guard
+                .get(signature.signer())
+                .ok_or_else(|| anyhow!("witness is not in the trust store"))?;
+            signature.verify(certificate)?;
+
+            match guard.compare_position(signature) {
+                PositionComparison::Equal | PositionComparison::PeerBehind => {}
+                PositionComparison::PeerAhead => {
+                    return Err(anyhow!(
+                        "witness signed sequence {}, which the server doesn't know",
+                        signature.sequence()
+                    ));
+                }
+                PositionComparison::Conflict => {
+                    tracing::error!(
+                        "witness {} signed a different trust store at sequence {}",
+                        signature.signer(),
+                        signature.sequence()
+                    );
+                    return Err(anyhow!("witness signed a conflicting trust store"));
+                }
+            }
+        }
+
+        self.witnesses.add(signature.clone()).await?;
+
+        tracing::debug!(
+            "witness {} countersigned sequence {}",
+            signature.signer(),
+            signature.sequence()
+        );
+
+        Ok(())
+    }
+}
+
+impl From<&PermissionPrecursor<SubmitWitnessSignatureHandler>> for Permission {
+    fn from(_value: &PermissionPrecursor<SubmitWitnessSignatureHandler>) -> Self {
+        Permission::AgentOnlyPlaceholder
+    }
+}
+
+#[async_trait]
+impl CommandHandler for SubmitWitnessSignatureHandler {
+    type Request = Checkpoint;
+
+    fn key() -> String {
+        "submit-witness-signature".into()
+    }
+
+    async fn handle(
+        &self,
+        session: &mut Session,
+        request: Self::Request,
+        _cancel: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let peer = session.peer().clone();
+        match self.add_signature(&peer, &request).await {
+            Ok(()) => {
+                session.write_object(&true).await?;
+                Ok(())
+            }
+            Err(err) => {
+                session.write_object(&false).await?;
+                Err(err)
+            }
+        }
+    }
+}
@@ -1,2 +1,84 @@
+mod admin;
+mod alerts;
+mod backup;
+mod chain_block_store;
+mod chain_conflict;
+mod config_file;
 mod debug;
 mod integration;
+mod key_storage;
+mod message_fanout;
+mod message_retention;
+mod user_mls_state;
+mod witness;
+
+use std::ops::Deref;
+
+use svalin_pki::{
+    Credential, KeyPair,
+    secure_chain::CheckedBlock,
+    trust_store::{Transaction, TrustStore},
+};
+use svalin_store::{client_store::ClientStore, server_store::ServerStore};
+
+use crate::util::location::Location;
+
+/// A temporary directory of a single test. It is deleted on drop, so it is
+/// also removed when the test panics.
+pub(crate) struct TestDir(Location);
+
+impl TestDir {
+    pub(crate) fn new(name: &str) -> Self {
+        let dir = Location::new(std::env::temp_dir())
+            .push(format!("svalin-{name}-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    pub(crate) fn path(&self, file: &str) -> Location {
+        self.0.clone().push(file)
+    }
+
+    /// Opens the server store where the server keeps it in its data
+    /// directory.
+    pub(crate) async fn server_store(&self) -> ServerStore {
+        ServerStore::open(self.path("db.sqlite")).await.unwrap()
+    }
+
+    pub(crate) async fn client_store(&self) -> ClientStore {
+        ClientStore::open(self.path("client-store.sqlite"))
+            .await
+            .unwrap()
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Location;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_dir_all(&self.0) {
+            tracing::warn!("failed to remove test directory {}: {err}", self.0);
+        }
+    }
+}
+
+/// Adds a new agent to the trust store. Returns its credential and the block
+/// which added it.
+pub(crate) fn add_agent(
+    store: &mut TrustStore,
+    root: &Credential,
+) -> (Credential, CheckedBlock<Transaction>) {
+    let key = KeyPair::generate();
+    let cert = root
+        .create_agent_certificate_for_key(&key.export_public_key())
+        .unwrap();
+    let block = store.add(cert.clone(), root).unwrap();
+    store.apply(block.clone());
+    (key.upgrade(cert.to_unverified()).unwrap(), block)
+}
@@ -0,0 +1,132 @@
+use svalin_pki::{Credential, KeyPair, trust_store::TrustStore};
+use test_log::test;
+
+use crate::{server::admin::Admin, test::TestDir, util::trust_store::save_trust_store};
+
+#[test(tokio::test)]
+async fn admin_on_fresh_server() {
+    let data_dir = TestDir::new("admin");
+
+    assert!(Admin::open(data_dir.clone()).await.is_err());
+
+    let store = data_dir.server_store().await;
+    store.close_handle().close().await;
+
+    let root = Credential::generate_root().unwrap();
+    let root = root
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+    let trust_store = TrustStore::initialize(root);
+    save_trust_store(&data_dir.path("trust_store.json"), &trust_store.export())
+        .await
+        .unwrap();
+
+    let admin = Admin::open(data_dir.clone()).await.unwrap();
+
+    assert!(admin.store.users.list_users().await.unwrap().is_empty());
+    assert!(
+        admin
+            .store
+            .sessions
+            .list_sessions()
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(
+        admin
+            .store
+            .messages
+            .pending_counts()
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    let report = admin.verify_chain().await.unwrap();
+    assert_eq!(report.blocks, 0);
+    assert_eq!(report.sequence, 0);
+    assert_eq!(report.digest, trust_store.digest());
+    assert_eq!(report.snapshot_matches, Some(true));
+
+    let pruned = admin.prune_orphans().await.unwrap();
+    assert_eq!(pruned.sessions, 0);
+    assert_eq!(pruned.messages, 0);
+
+    admin.close().await;
+}
+
+#[test(tokio::test)]
+async fn verify_chain_after_pruning() {
+    let data_dir = TestDir::new("admin");
+
+    let root_credential = Credential::generate_root().unwrap();
+    let root = root_credential
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+    let mut trust_store = TrustStore::initialize(root);
+
+    // the initialized server snapshot contains the server certificate
+    let server = KeyPair::generate();
+    let cert = root_credential
+        .create_server_certificate_for_key(&server.export_public_key())
+        .unwrap();
+    let block = trust_store.add(cert.clone(), &root_credential).unwrap();
+    trust_store.apply(block);
+    let server = server.upgrade(cert.to_unverified()).unwrap();
+    save_trust_store(&data_dir.path("trust_store.json"), &trust_store.export())
+        .await
+        .unwrap();
+
+    let store = data_dir.server_store().await;
+    let transactions = store.trust_store_transactions.clone();
+    let (_, _receiver) = transactions.load_all_after(0).await.unwrap();
+    transactions
+        .keep_leaf(trust_store.last_block().unwrap())
+        .await
+        .unwrap();
+
+    for _ in 0..3 {
+        let agent = KeyPair::generate();
+        let cert = root_credential
+            .create_agent_certificate_for_key(&agent.export_public_key())
+            .unwrap();
+        let block = trust_store.add(cert, &root_credential).unwrap();
+        transactions.add_and_broadcast(block.clone()).await.unwrap();
+        trust_store.apply(block);
+
+        if trust_store.sequence() >= 3 {
+            transactions
+                .add_checkpoint(&trust_store.checkpoint(&server), &trust_store.export())
+                .await
+                .unwrap();
+        }
+    }
+
+    let index = transactions.merkle_index().await.unwrap().unwrap();
+    assert_eq!(index.size(), 4);
+
+    // the checkpoints at 3 and 4 are kept, transactions up to 3 are deleted
+    assert_eq!(transactions.prune(2).await.unwrap(), 2);
+    let pruned = transactions.merkle_index().await.unwrap().unwrap();
+    assert_eq!(pruned.root(), index.root());
+    assert!(transactions.checkpoint_for(1).await.unwrap().is_some());
+    assert!(transactions.checkpoint_for(3).await.unwrap().is_none());
+    store.close_handle().close().await;
+
+    let admin = Admin::open(data_dir.clone()).await.unwrap();
+    let report = admin.verify_chain().await.unwrap();
+    assert_eq!(report.checkpoint_sequence, Some(3));
+    assert_eq!(report.blocks, 1);
+    assert_eq!(report.sequence, 4);
+    assert_eq!(report.digest, trust_store.digest());
+    assert_eq!(report.snapshot_matches, None);
+
+    admin.close().await;
+}
@@ -0,0 +1,366 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+};
+
+use openmls_sqlx_storage::SqliteStorageProvider;
+use serde::Serialize;
+use svalin_pki::{
+    Credential, EncryptedObject, EncryptionKey, KeyPair, SpkiHash, TrustStoreVerifier,
+    mls::{
+        SvalinGroupId,
+        agent::MlsAgent,
+        client::{MessageDataContent, MlsClient},
+        provider::{PostcardCodec, SvalinStorage},
+        server::MlsServer,
+        transport_types::SvalinMessage,
+    },
+    trust_store::TrustStore,
+};
+use svalin_store::client_store::persistent::{
+    Acknowledged, AlertAcknowledgement, AlertKind, AlertSeverity, DeviceState, LegacyState,
+    Message, State, SvalinAlert,
+};
+use svalin_sysctl::health::{DiskUsage, HealthStatus};
+use test_log::test;
+
+use crate::{
+    agent::alerts::{AlertRules, AlertWatcher},
+    mls::MlsTypes,
+    server::local_key_retriever::LocalKeyRetriever,
+    shared::commands::update_user_mls::{UserMlsMessage, update_main_state},
+    test::TestDir,
+    util::location::Location,
+};
+
+fn status(free_space: u64, load_average: f64, running: &[&str]) -> HealthStatus {
+    HealthStatus {
+        disks: vec![
+            DiskUsage {
+                mount_point: "/".into(),
+                file_system: "ext4".into(),
+                total_space: 100,
+                available_space: free_space,
+                read_only: false,
+            },
+            DiskUsage {
+                mount_point: "/data".into(),
+                file_system: "xfs".into(),
+                total_space: 100,
+                available_space: 50,
+                read_only: false,
+            },
+            // read-only and in memory mounts are never alerted
+            DiskUsage {
+                mount_point: "/snap/core22/1380".into(),
+                file_system: "squashfs".into(),
+                total_space: 100,
+                available_space: 0,
+                read_only: true,
+            },
+            DiskUsage {
+                mount_point: "/media/cdrom".into(),
+                file_system: "ext4".into(),
+                total_space: 100,
+                available_space: 0,
+                read_only: true,
+            },
+            DiskUsage {
+                mount_point: "/run/user/1000".into(),
+                file_system: "tmpfs".into(),
+                total_space: 100,
+                available_space: 0,
+                read_only: false,
+            },
+        ],
+        load_average,
+        threads: 4,
+        running: running.iter().map(|name| name.to_string()).collect(),
+    }
+}
+
+fn received(device: &SpkiHash, sender: &SpkiHash, message: SvalinMessage) -> Message {
+    Message::Received {
+        group: SvalinGroupId::DeviceGroup(device.clone()),
+        sender: sender.clone(),
+        message,
+    }
+}
+
+#[test]
+fn alert_rules() {
+    let mut watcher = AlertWatcher::new(AlertRules {
+        services: vec!["sshd".into()],
+        ..Default::default()
+    });
+
+    let alerts = watcher.check(&status(5, 10.0, &[]), 1);
+    let kinds: Vec<&AlertKind> = alerts.iter().map(|alert| &alert.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            &AlertKind::DiskAlmostFull {
+                mount_point: "/".into(),
+                available_space: 5,
+                total_space: 100,
+            },
+            &AlertKind::ServiceDown {
+                service: "sshd".into()
+            },
+            &AlertKind::HighLoad {
+                load_average: 10.0,
+                threads: 4,
+            },
+        ]
+    );
+    assert_eq!(alerts[1].severity, AlertSeverity::Critical);
+    let ids: HashSet<_> = alerts.iter().map(|alert| alert.id).collect();
+    assert_eq!(ids.len(), 3);
+
+    // ongoing conditions are only alerted once
+    assert!(watcher.check(&status(5, 10.0, &[]), 2).is_empty());
+
+    // cleared conditions are alerted again once they return
+    assert!(watcher.check(&status(5, 1.0, &["sshd"]), 3).is_empty());
+    let alerts = watcher.check(&status(5, 1.0, &[]), 4);
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(
+        alerts[0].kind,
+        AlertKind::ServiceDown {
+            service: "sshd".into()
+        }
+    );
+    assert_eq!(alerts[0].raised_at, 4);
+}
+
+#[test(tokio::test)]
+async fn acknowledgements_sync_across_sessions() {
+    let data_dir = TestDir::new("alerts");
+    let store = data_dir.client_store().await;
+
+    let device = KeyPair::generate().spki_hash();
+    let session = KeyPair::generate().spki_hash();
+    let other_session = KeyPair::generate().spki_hash();
+
+    let alert = SvalinAlert {
+        id: uuid::Uuid::new_v4(),
+        raised_at: 10,
+        severity: AlertSeverity::Warning,
+        kind: AlertKind::HighLoad {
+            load_average: 12.0,
+            threads: 4,
+        },
+    };
+    let alert_message = SvalinMessage::new(&alert).unwrap();
+
+    let mut state = State::empty();
+
+    // alerts are only accepted from the device itself
+    let forged = received(&device, &session, alert_message.clone());
+    assert!(store.update(&forged).await.is_err());
+    state.update(forged);
+    assert!(state.pending_alerts().is_empty());
+
+    let message = received(&device, &device, alert_message.clone());
+    store.update(&message).await.unwrap();
+    state.update(message);
+    assert_eq!(state.pending_alerts().len(), 1);
+    assert_eq!(state.pending_alerts()[0].alert, alert);
+
+    // the acknowledgement of another session of the user
+    let acknowledgement = AlertAcknowledgement {
+        alert: alert.id,
+        acknowledged_at: 20,
+    };
+    let message = received(
+        &device,
+        &other_session,
+        SvalinMessage::new(&acknowledgement).unwrap(),
+    );
+    store.update(&message).await.unwrap();
+    state.update(message);
+    assert!(state.pending_alerts().is_empty());
+
+    // later acknowledgements and redelivered alerts don't change it
+    let later = AlertAcknowledgement {
+        alert: alert.id,
+        acknowledged_at: 30,
+    };
+    for message in [
+        received(&device, &session, SvalinMessage::new(&later).unwrap()),
+        received(&device, &device, alert_message),
+    ] {
+        store.update(&message).await.unwrap();
+        state.update(message);
+    }
+
+    let expected = Some(Acknowledged {
+        by: other_session.clone(),
+        at: 20,
+    });
+    assert_eq!(state.alerts()[&alert.id].acknowledged, expected);
+
+    let loaded = store.load_persistent().await.unwrap();
+    assert!(loaded.pending_alerts().is_empty());
+    assert_eq!(loaded.alerts()[&alert.id].acknowledged, expected);
+    assert_eq!(loaded.alerts()[&alert.id].device, device);
+
+    store.close_handle().close().await;
+}
+
+async fn open_mls_store(path: Location) -> SqliteStorageProvider<PostcardCodec> {
+    SqliteStorageProvider::open(path.as_path().to_str().unwrap())
+        .await
+        .unwrap()
+}
+
+/// The layout of the user's main state before it kept the alerts.
+#[derive(Serialize)]
+struct PreAlertsState {
+    devices: HashMap<SpkiHash, DeviceState>,
+}
+
+#[test(tokio::test)]
+async fn alerts_reach_the_user_state() {
+    let data_dir = TestDir::new("alerts");
+    let server_store = data_dir.server_store().await;
+    let client_store = data_dir.client_store().await;
+
+    let user = Credential::generate_root().unwrap();
+    let user_hash = user.certificate().spki_hash().clone();
+    let root = user
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+    let keypair = KeyPair::generate();
+    let agent_certificate = user
+        .create_agent_certificate_for_key(&keypair.export_public_key())
+        .unwrap();
+    let agent_credential = keypair.upgrade(agent_certificate.to_unverified()).unwrap();
+    let device = agent_credential.certificate().spki_hash().clone();
+
+    let trust_store = Arc::new(RwLock::new(TrustStore::initialize(root.clone())));
+    {
+        let mut trust_store = trust_store.write().unwrap();
+        let block = trust_store
+            .add(agent_credential.certificate().clone(), &user)
+            .unwrap();
+        trust_store.apply(block);
+    }
+    let verifier = TrustStoreVerifier::new(trust_store.clone());
+    let key_retriever =
+        LocalKeyRetriever::new(root, trust_store.clone(), server_store.key_packages.clone());
+
+    let server = MlsServer::new(
+        open_mls_store(data_dir.path("server-mls.sqlite")).await,
+        verifier.clone(),
+        key_retriever.clone(),
+    );
+    let (storage, _) = SvalinStorage::new_memory();
+    let client = MlsClient::<MlsTypes, _, _>::new(
+        user.clone(),
+        storage,
+        key_retriever.clone(),
+        verifier.clone(),
+    )
+    .unwrap();
+    let key_package = client.create_key_package().await.unwrap().to_unverified();
+    let key_package = server
+        .verify_key_package(key_package, &user_hash)
+        .await
+        .unwrap();
+    server_store
+        .key_packages
+        .add_key_package(key_package)
+        .await
+        .unwrap();
+
+    let agent = MlsAgent::<MlsTypes, _, _>::new(
+        agent_credential,
+        open_mls_store(data_dir.path("agent-mls.sqlite")).await,
+        key_retriever,
+        verifier,
+    )
+    .await
+    .unwrap();
+    let new_group = agent
+        .create_device_group_if_missing()
+        .await
+        .unwrap()
+        .unwrap();
+    let welcome = server.process_message(new_group).await.unwrap();
+    client.handle_message(&welcome[0].message).await.unwrap();
+
+    // the agent raises an alert, which the user's main state keeps
+    let alert = SvalinAlert {
+        id: uuid::Uuid::new_v4(),
+        raised_at: 10,
+        severity: AlertSeverity::Critical,
+        kind: AlertKind::ServiceDown {
+            service: "sshd".into(),
+        },
+    };
+    let to_server = agent.send_message(&alert).await.unwrap();
+    let to_send = server.process_message(to_server).await.unwrap();
+    assert!(to_send[0].receivers.contains(&user_hash));
+    let handled = client.handle_message(&to_send[0].message).await.unwrap();
+    assert!(matches!(handled.content, MessageDataContent::Other(_, _)));
+
+    let mut main_state = State::empty();
+    assert!(update_main_state(&mut main_state, handled));
+    assert_eq!(main_state.pending_alerts().len(), 1);
+    assert_eq!(main_state.pending_alerts()[0].alert, alert);
+
+    // the acknowledgement is sent with the user's credential
+    let message = UserMlsMessage::AcknowledgeAlert(
+        device.clone(),
+        AlertAcknowledgement {
+            alert: alert.id,
+            acknowledged_at: 20,
+        },
+    );
+    let to_server = message.encrypt(&client).await.unwrap();
+    let to_send = server.process_message(to_server).await.unwrap();
+    assert!(to_send[0].receivers.contains(&device));
+    main_state.update(message.own_update(&user_hash).unwrap());
+    assert!(main_state.pending_alerts().is_empty());
+
+    // the main state keeps the alerts when it is stored
+    let key = EncryptionKey::dangerous_from_bytes([7; 32]);
+    let stored = EncryptedObject::encrypt(&main_state, &key)
+        .unwrap()
+        .decrypt_with_legacy::<LegacyState>(&key)
+        .unwrap();
+    let expected = Some(Acknowledged {
+        by: user_hash.clone(),
+        at: 20,
+    });
+    assert_eq!(stored.alerts()[&alert.id].acknowledged, expected);
+
+    // states stored before alerts were kept still load
+    let legacy = EncryptedObject::encrypt(
+        &PreAlertsState {
+            devices: HashMap::new(),
+        },
+        &key,
+    )
+    .unwrap();
+    let legacy: EncryptedObject<State> =
+        postcard::from_bytes(&postcard::to_stdvec(&legacy).unwrap()).unwrap();
+    let legacy = legacy.decrypt_with_legacy::<LegacyState>(&key).unwrap();
+    assert!(legacy.alerts().is_empty());
+
+    // sessions receive and store the alerts of the main state
+    client_store
+        .update(&Message::UpdateFromMainState(stored))
+        .await
+        .unwrap();
+    let loaded = client_store.load_persistent().await.unwrap();
+    assert_eq!(loaded.alerts()[&alert.id].device, device);
+    assert_eq!(loaded.alerts()[&alert.id].acknowledged, expected);
+
+    server_store.close_handle().close().await;
+    client_store.close_handle().close().await;
+}
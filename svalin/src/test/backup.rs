@@ -0,0 +1,100 @@
+use svalin_pki::{Credential, KeyPair, trust_store::TrustStore};
+use svalin_store::server_store::ServerStore;
+use test_log::test;
+
+use crate::{
+    server::backup::{create_backup, restore_backup},
+    util::{location::Location, trust_store::save_trust_store},
+};
+
+#[test(tokio::test)]
+async fn backup_and_restore() {
+    let test_dir = Location::new(std::env::temp_dir())
+        .push(format!("svalin-backup-test-{}", uuid::Uuid::new_v4()));
+    let data_dir = test_dir.clone().push("server");
+    tokio::fs::create_dir_all(&data_dir).await.unwrap();
+
+    // the backup treats both databases as opaque SQLite files
+    for db in ["db.sqlite", "mls-store.sqlite"] {
+        let store = ServerStore::open(data_dir.clone().push(db)).await.unwrap();
+        store.close_handle().close().await;
+    }
+
+    let base_config = b"{\"test\": true}".to_vec();
+    tokio::fs::write(data_dir.clone().push("base_config.json"), &base_config)
+        .await
+        .unwrap();
+
+    let root_credential = Credential::generate_root().unwrap();
+    let root = root_credential
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+    let mut trust_store = TrustStore::initialize(root);
+    save_trust_store(
+        &data_dir.clone().push("trust_store.json"),
+        &trust_store.export(),
+    )
+    .await
+    .unwrap();
+
+    // later transactions are only stored in the database
+    let agent = KeyPair::generate();
+    let cert = root_credential
+        .create_agent_certificate_for_key(&agent.export_public_key())
+        .unwrap();
+    let block = trust_store.add(cert, &root_credential).unwrap();
+    let store = ServerStore::open(data_dir.clone().push("db.sqlite"))
+        .await
+        .unwrap();
+    store.trust_store_transactions.add(&block).await.unwrap();
+    store.close_handle().close().await;
+    trust_store.apply(block);
+
+    let backup_file = test_dir.clone().push("backup.svalin");
+    let info = create_backup(&data_dir, &backup_file, b"correct horse".to_vec())
+        .await
+        .unwrap();
+    assert_eq!(info.trust_store_sequence, 1);
+    assert_eq!(info.trust_store_digest, trust_store.digest());
+
+    // existing backups are never overwritten
+    create_backup(&data_dir, &backup_file, b"correct horse".to_vec())
+        .await
+        .unwrap_err();
+
+    let restore_dir = test_dir.clone().push("restored");
+    restore_backup(&backup_file, &restore_dir, b"wrong".to_vec())
+        .await
+        .unwrap_err();
+    assert!(!restore_dir.as_path().exists());
+
+    let restored = restore_backup(&backup_file, &restore_dir, b"correct horse".to_vec())
+        .await
+        .unwrap();
+    assert!(restored.previous_data_dir.is_none());
+    assert_eq!(restored.backup.trust_store_sequence, 1);
+    assert_eq!(restored.backup.trust_store_digest, trust_store.digest());
+    assert_eq!(
+        tokio::fs::read(restore_dir.clone().push("base_config.json"))
+            .await
+            .unwrap(),
+        base_config
+    );
+    ServerStore::open(restore_dir.clone().push("db.sqlite"))
+        .await
+        .unwrap()
+        .close_handle()
+        .close()
+        .await;
+
+    let restored = restore_backup(&backup_file, &restore_dir, b"correct horse".to_vec())
+        .await
+        .unwrap();
+    let previous = restored.previous_data_dir.unwrap();
+    assert!(previous.join("trust_store.json").exists());
+
+    tokio::fs::remove_dir_all(&test_dir).await.unwrap();
+}
@@ -0,0 +1,100 @@
+use svalin_pki::{
+    Credential,
+    secure_chain::store::BlockStore,
+    trust_store::{self, TrustStore},
+};
+use svalin_store::chain_block_store::{ChainBlockStore, ChainBlockStoreError};
+use test_log::test;
+
+use crate::test::{TestDir, add_agent};
+
+#[test(tokio::test)]
+async fn chain_block_store_detects_tampering() {
+    let data_dir = TestDir::new("chain-block-store");
+    let path = data_dir.path("chain.sqlite");
+
+    let root_credential = Credential::generate_root().unwrap();
+    let root = root_credential
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+    let initial = TrustStore::initialize(root);
+    let mut local = TrustStore::import(initial.export()).unwrap();
+
+    let store = ChainBlockStore::<trust_store::Transaction>::open_file(&path, "trust-store")
+        .await
+        .unwrap();
+    assert_eq!(store.last_sequence().await.unwrap(), 0);
+    let mut blocks = Vec::new();
+    for _ in 0..3 {
+        let (_, block) = add_agent(&mut local, &root_credential);
+        store.append(&block).await.unwrap();
+        blocks.push(block);
+    }
+
+    // blocks have to be appended in order
+    let result = store.append(&blocks[1]).await;
+    assert!(matches!(
+        result,
+        Err(ChainBlockStoreError::SequenceMismatch { .. })
+    ));
+    // other chains in the same database are separate
+    let other = ChainBlockStore::<trust_store::Transaction>::open_file(&path, "other")
+        .await
+        .unwrap();
+    assert_eq!(other.last_sequence().await.unwrap(), 0);
+
+    let loaded = store.load_range(2..=3).await.unwrap();
+    assert_eq!(loaded.len(), 2);
+    assert_eq!(loaded[0].sequence(), 2);
+    assert_eq!(loaded[1].sequence(), 3);
+
+    // reopening continues after the stored blocks
+    let reopened = ChainBlockStore::<trust_store::Transaction>::open_file(&path, "trust-store")
+        .await
+        .unwrap();
+    assert_eq!(reopened.last_sequence().await.unwrap(), 3);
+    let mut replayed = TrustStore::import(initial.export()).unwrap();
+    for block in reopened.load_range(1..=3).await.unwrap() {
+        let block = replayed.check(block).unwrap();
+        replayed.apply(block);
+    }
+    assert_eq!(replayed.digest(), local.digest());
+
+    // replace the second block with a valid block of another history
+    let mut forked = TrustStore::import(initial.export()).unwrap();
+    forked.apply(blocks[0].clone());
+    let (_, fork) = add_agent(&mut forked, &root_credential);
+    let data = postcard::to_stdvec(fork.as_unchecked()).unwrap();
+    let pool = sqlx::SqlitePool::connect(&format!("sqlite://{path}"))
+        .await
+        .unwrap();
+    sqlx::query("UPDATE chain_blocks SET data = ? WHERE chain = ? AND sequence = ?")
+        .bind(data)
+        .bind("trust-store")
+        .bind(2_i64)
+        .execute(&pool)
+        .await
+        .unwrap();
+    pool.close().await;
+
+    // the store doesn't notice, but the following block no longer fits
+    let reopened = ChainBlockStore::<trust_store::Transaction>::open_file(&path, "trust-store")
+        .await
+        .unwrap();
+    let mut replayed = TrustStore::import(initial.export()).unwrap();
+    let mut rejected = None;
+    for block in reopened.load_range(1..=3).await.unwrap() {
+        let sequence = block.sequence();
+        match replayed.check(block) {
+            Ok(block) => replayed.apply(block),
+            Err(_) => {
+                rejected = Some(sequence);
+                break;
+            }
+        }
+    }
+    assert_eq!(rejected, Some(3));
+}
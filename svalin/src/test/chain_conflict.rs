@@ -0,0 +1,60 @@
+use std::sync::RwLock;
+
+use svalin_pki::{Credential, trust_store::TrustStore};
+use test_log::test;
+
+use crate::{
+    test::{TestDir, add_agent},
+    util::chain_gossip::{ComparePositionError, compare_position, reset_conflict},
+};
+
+#[test(tokio::test)]
+async fn conflicts_need_confirmation_and_persist() {
+    let data_dir = TestDir::new("chain-conflict");
+    let client_store = data_dir.client_store().await;
+
+    let root_credential = Credential::generate_root().unwrap();
+    let root = root_credential
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+
+    let mut local = TrustStore::initialize(root);
+    let (first, _) = add_agent(&mut local, &root_credential);
+    let (second, _) = add_agent(&mut local, &root_credential);
+
+    // the server shows the agents another head
+    let mut forked = TrustStore::import(local.export()).unwrap();
+    add_agent(&mut forked, &root_credential);
+    add_agent(&mut local, &root_credential);
+    let trust_store = RwLock::new(local);
+    let history = client_store.transaction_store();
+
+    // a single agent can't make the trust store unusable
+    let result = compare_position(&trust_store, history, &forked.checkpoint(&first)).await;
+    assert!(matches!(
+        result,
+        Err(ComparePositionError::UnconfirmedConflict(_))
+    ));
+    assert!(trust_store.read().unwrap().conflict().is_none());
+
+    // reports survive a restart
+    client_store.close_handle().close().await;
+    let client_store = data_dir.client_store().await;
+    let history = client_store.transaction_store();
+    assert_eq!(history.conflict_reports().await.unwrap().len(), 1);
+
+    let result = compare_position(&trust_store, history, &forked.checkpoint(&second)).await;
+    assert!(matches!(result, Err(ComparePositionError::SplitView(_))));
+    assert!(trust_store.read().unwrap().conflict().is_some());
+    assert_eq!(history.conflict_reports().await.unwrap().len(), 2);
+
+    // only an explicit reset clears it
+    reset_conflict(&trust_store, history).await.unwrap();
+    assert!(trust_store.read().unwrap().conflict().is_none());
+    assert!(history.conflict_reports().await.unwrap().is_empty());
+
+    client_store.close_handle().close().await;
+}
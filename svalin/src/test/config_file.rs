@@ -0,0 +1,93 @@
+use std::{net::SocketAddr, time::Duration};
+
+use svalin_rpc::rpc::transport_config::CongestionController;
+use test_log::test;
+
+use crate::{
+    server::config_file::ServerConfigFile,
+    util::{key_storage::KeySourceConfig, logging::LogFormat},
+};
+
+#[test]
+fn empty_config_is_valid() {
+    let config: ServerConfigFile = toml::from_str("").unwrap();
+
+    assert_eq!(config, ServerConfigFile::default());
+    assert_eq!(
+        config.bind_addresses(),
+        vec!["[::]:55411".parse::<SocketAddr>().unwrap()]
+    );
+    config.validate().unwrap();
+}
+
+#[test]
+fn full_config() {
+    let config: ServerConfigFile = toml::from_str(
+        r#"
+        data_dir = "/srv/svalin"
+        bind = ["0.0.0.0:1234", "[::1]:1234"]
+
+        [log]
+        level = "svalin=debug"
+        format = "json"
+
+        [transport]
+        idle_timeout_ms = 60000
+        congestion_controller = "bbr"
+
+        [messages]
+        max_age_secs = 3600
+        max_pending_per_receiver = 0
+
+        [trust_store]
+        keep_checkpoints = 3
+
+        [login]
+        max_failed_attempts = 3
+
+        [key_source]
+        type = "key_file"
+        path = "/etc/svalin/server.key"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.bind.len(), 2);
+    assert_eq!(config.log.format, LogFormat::Json);
+    assert_eq!(config.transport.idle_timeout_ms, Some(60000));
+    assert_eq!(
+        config.transport.congestion_controller,
+        Some(CongestionController::Bbr)
+    );
+    assert_eq!(config.messages.max_age_secs, 3600);
+    assert_eq!(config.messages.limits().max_pending_per_receiver, None);
+    assert_eq!(
+        config.messages.limits().ttl,
+        Some(Duration::from_secs(14 * 24 * 60 * 60))
+    );
+    assert_eq!(config.trust_store.keep_checkpoints, 3);
+    assert_eq!(config.trust_store.checkpoint_interval_secs, 24 * 60 * 60);
+    assert_eq!(config.login.max_failed_attempts, 3);
+    assert_eq!(config.login.max_concurrent, 8);
+    assert_eq!(config.login.step_timeout_secs, 30);
+    assert_eq!(
+        config.key_source,
+        KeySourceConfig::KeyFile {
+            path: "/etc/svalin/server.key".into()
+        }
+    );
+
+    let reparsed: ServerConfigFile = toml::from_str(&config.to_toml().unwrap()).unwrap();
+    assert_eq!(reparsed, config);
+}
+
+#[test]
+fn invalid_config() {
+    toml::from_str::<ServerConfigFile>("unknown_key = 1").unwrap_err();
+
+    let config: ServerConfigFile = toml::from_str("[log]\nlevel = \"svalin=nonsense\"").unwrap();
+    config.validate().unwrap_err();
+
+    let config: ServerConfigFile = toml::from_str("[login]\nmax_concurrent = 0").unwrap();
+    config.validate().unwrap_err();
+}
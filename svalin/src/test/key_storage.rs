@@ -0,0 +1,92 @@
+use svalin_pki::Credential;
+use test_log::test;
+
+use crate::util::{
+    key_storage::{KeySource, KeySourceConfig},
+    location::Location,
+};
+
+async fn roundtrip(config: &KeySourceConfig) {
+    let credential = Credential::generate_root().unwrap();
+
+    let key_source = config.generate().await.unwrap();
+    let encrypted = key_source.encrypt_credential(&credential).await.unwrap();
+
+    // the key source has to survive being saved in the config
+    let key_source = serde_json::to_vec(&key_source).unwrap();
+    let key_source: KeySource = serde_json::from_slice(&key_source).unwrap();
+
+    let decrypted = key_source.decrypt_credentials(encrypted).await.unwrap();
+    assert_eq!(decrypted.certificate(), credential.certificate());
+}
+
+#[test(tokio::test)]
+async fn builtin_key_source() {
+    roundtrip(&KeySourceConfig::BuiltIn).await;
+}
+
+#[test(tokio::test)]
+async fn key_file_key_source() {
+    let dir = Location::new(std::env::temp_dir())
+        .push(format!("svalin-key-test-{}", uuid::Uuid::new_v4()));
+    let path = dir.clone().push("keys").push("test.key").to_pathbuf();
+    let config = KeySourceConfig::KeyFile { path: path.clone() };
+
+    roundtrip(&config).await;
+
+    // an existing key file is never overwritten
+    assert!(config.generate().await.is_err());
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let credential = Credential::generate_root().unwrap();
+        let key_source = KeySource::KeyFile(path.clone());
+        let encrypted = key_source.encrypt_credential(&credential).await.unwrap();
+
+        tokio::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644))
+            .await
+            .unwrap();
+        key_source
+            .decrypt_credentials(encrypted)
+            .await
+            .err()
+            .unwrap();
+    }
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[test(tokio::test)]
+async fn environment_key_source() {
+    // environment variables are shared by all tests, so none are changed here
+    let unset = KeySourceConfig::Environment {
+        variable: format!("SVALIN_TEST_UNSET_{}", uuid::Uuid::new_v4().simple()),
+    };
+    assert!(unset.generate().await.is_err());
+
+    // set by cargo when running the tests
+    roundtrip(&KeySourceConfig::Environment {
+        variable: "CARGO_PKG_NAME".to_string(),
+    })
+    .await;
+}
+
+/// Requires SoftHSM with an initialized token, e.g.
+/// `softhsm2-util --init-token --free --label svalin-test --pin 1234 --so-pin 1234`,
+/// and its user PIN in `SVALIN_TEST_PKCS11_PIN`.
+#[test(tokio::test)]
+#[ignore]
+async fn pkcs11_key_source() {
+    let module = std::env::var("SVALIN_TEST_PKCS11_MODULE")
+        .unwrap_or_else(|_| "/usr/lib/softhsm/libsofthsm2.so".to_string());
+
+    roundtrip(&KeySourceConfig::Pkcs11 {
+        module: module.into(),
+        token: "svalin-test".to_string(),
+        key_label: "svalin-test-key".to_string(),
+        pin_variable: "SVALIN_TEST_PKCS11_PIN".to_string(),
+    })
+    .await;
+}
@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use svalin_pki::{
+    KeyPair, SpkiHash,
+    mls::transport_types::{MessageToMemberTransport, MessageToSend},
+};
+use svalin_store::server_store::{SUBSCRIPTION_BUFFER, Subscription};
+use test_log::test;
+use tokio::{task::JoinSet, time::timeout};
+
+use crate::test::TestDir;
+
+const RECEIVERS: usize = 2000;
+const BROADCASTS: u8 = 20;
+
+fn message(receivers: Vec<SpkiHash>, content: u8) -> MessageToSend {
+    MessageToSend {
+        receivers,
+        message: MessageToMemberTransport::GroupMessage(vec![content]),
+    }
+}
+
+async fn next_content(subscription: &mut Subscription) -> u8 {
+    let (_, message) = subscription.recv().await.unwrap().unwrap();
+    match message.as_ref() {
+        MessageToMemberTransport::GroupMessage(data) => data[0],
+        _ => panic!("unexpected message type"),
+    }
+}
+
+#[test(tokio::test(flavor = "multi_thread"))]
+async fn slow_subscriber_does_not_block_fan_out() {
+    let data_dir = TestDir::new("fanout");
+    let store = data_dir.server_store().await;
+    let messages = store.messages.clone();
+
+    let receivers: Vec<SpkiHash> = (0..RECEIVERS)
+        .map(|_| KeyPair::generate().spki_hash())
+        .collect();
+    let slow_receiver = KeyPair::generate().spki_hash();
+
+    // never read until all broadcasts are delivered
+    let mut slow = messages.subscribe(slow_receiver.clone());
+
+    let mut fast = JoinSet::new();
+    for receiver in &receivers {
+        let mut subscription = messages.subscribe(receiver.clone());
+        fast.spawn(async move {
+            let mut received = Vec::new();
+            while received.len() < BROADCASTS as usize {
+                received.push(next_content(&mut subscription).await);
+            }
+            received
+        });
+    }
+
+    let backlog = SUBSCRIPTION_BUFFER as u8 + 16;
+    for content in 0..backlog {
+        messages
+            .add_message(message(vec![slow_receiver.clone()], 100 + content))
+            .await
+            .unwrap();
+    }
+
+    let mut everyone = receivers.clone();
+    everyone.push(slow_receiver.clone());
+    for content in 0..BROADCASTS {
+        messages
+            .add_message(message(everyone.clone(), content))
+            .await
+            .unwrap();
+    }
+
+    let expected: Vec<u8> = (0..BROADCASTS).collect();
+    let received = timeout(Duration::from_secs(60), fast.join_all())
+        .await
+        .expect("fast subscribers were blocked");
+    assert_eq!(received.len(), RECEIVERS);
+    for received in received {
+        assert_eq!(received, expected);
+    }
+
+    // the overflowing messages are loaded from the database, each only once
+    let mut expected: Vec<u8> = (0..backlog).map(|content| 100 + content).collect();
+    expected.extend(0..BROADCASTS);
+    let mut received = Vec::new();
+    while received.len() < expected.len() {
+        received.push(next_content(&mut slow).await);
+    }
+    assert_eq!(received, expected);
+    assert!(
+        timeout(Duration::from_millis(200), slow.recv())
+            .await
+            .is_err()
+    );
+
+    drop(slow);
+    store.close_handle().close().await;
+}
+
+#[test(tokio::test)]
+async fn resubscribe_after_disconnect() {
+    let data_dir = TestDir::new("fanout");
+    let store = data_dir.server_store().await;
+    let messages = store.messages.clone();
+    let receiver = KeyPair::generate().spki_hash();
+
+    let mut subscription = messages.subscribe(receiver.clone());
+    messages
+        .add_message(message(vec![receiver.clone()], 1))
+        .await
+        .unwrap();
+    let (id, _) = subscription.recv().await.unwrap().unwrap();
+    messages
+        .aknowledge_single_message(&receiver, id)
+        .await
+        .unwrap();
+    drop(subscription);
+
+    // unacknowledged messages sent while disconnected are delivered on the
+    // next subscription
+    messages
+        .add_message(message(vec![receiver.clone()], 2))
+        .await
+        .unwrap();
+    let mut subscription = messages.subscribe(receiver.clone());
+    assert_eq!(next_content(&mut subscription).await, 2);
+
+    messages
+        .add_message(message(vec![receiver.clone()], 3))
+        .await
+        .unwrap();
+    assert_eq!(next_content(&mut subscription).await, 3);
+
+    drop(subscription);
+    store.close_handle().close().await;
+}
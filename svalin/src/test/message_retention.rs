@@ -0,0 +1,263 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use svalin_pki::{
+    Credential, KeyPair, SpkiHash, get_current_timestamp,
+    mls::transport_types::{MessageToMemberTransport, MessageToSend},
+    trust_store::TrustStore,
+};
+use svalin_store::server_store::MessageLimits;
+use test_log::test;
+
+use crate::{
+    server::{config_file::MessageRetention, retention::sweep_once},
+    test::TestDir,
+};
+
+fn message(receivers: &[&SpkiHash], content: u8) -> MessageToSend {
+    MessageToSend {
+        receivers: receivers
+            .iter()
+            .map(|receiver| (*receiver).clone())
+            .collect(),
+        message: MessageToMemberTransport::GroupMessage(vec![content]),
+    }
+}
+
+fn contents(messages: &[(uuid::Uuid, MessageToMemberTransport)]) -> Vec<u8> {
+    let mut contents: Vec<u8> = messages
+        .iter()
+        .map(|(_, message)| match message {
+            MessageToMemberTransport::GroupMessage(data) => data[0],
+            _ => panic!("unexpected message type"),
+        })
+        .collect();
+    contents.sort();
+    contents
+}
+
+#[test(tokio::test)]
+async fn receiver_quota() {
+    let data_dir = TestDir::new("retention");
+    let store = data_dir.server_store().await;
+    let messages = store.messages.clone();
+    messages.set_limits(MessageLimits {
+        ttl: None,
+        max_pending_per_receiver: Some(2),
+    });
+
+    let full = KeyPair::generate().spki_hash();
+    let other = KeyPair::generate().spki_hash();
+
+    messages
+        .add_message(message(&[&full, &other], 1))
+        .await
+        .unwrap();
+    messages.add_message(message(&[&full], 2)).await.unwrap();
+    messages.add_message(message(&[&full], 3)).await.unwrap();
+
+    // the oldest message is dropped for the full queue only
+    assert_eq!(
+        contents(&messages.load_all_for(&full).await.unwrap()),
+        [2, 3]
+    );
+    assert_eq!(contents(&messages.load_all_for(&other).await.unwrap()), [1]);
+
+    let metrics = messages.queue_metrics().await.unwrap();
+    assert_eq!(metrics.depth(&full), 2);
+    assert_eq!(metrics.depth(&other), 1);
+    assert_eq!(metrics.messages, 3);
+    assert_eq!(metrics.pending(), 3);
+    assert_eq!(metrics.deepest(), Some((&full, 2)));
+
+    store.close_handle().close().await;
+}
+
+#[test(tokio::test)]
+async fn message_ttl() {
+    let data_dir = TestDir::new("retention");
+    let store = data_dir.server_store().await;
+    let messages = store.messages.clone();
+    let receiver = KeyPair::generate().spki_hash();
+
+    messages
+        .add_message(message(&[&receiver], 1))
+        .await
+        .unwrap();
+
+    messages.set_limits(MessageLimits {
+        ttl: Some(Duration::from_secs(60)),
+        max_pending_per_receiver: None,
+    });
+    messages
+        .add_message(message(&[&receiver], 2))
+        .await
+        .unwrap();
+
+    let now = get_current_timestamp();
+    assert_eq!(messages.delete_expired_messages(now).await.unwrap(), 0);
+
+    // messages stored without a TTL are only deleted by the maximum age
+    assert_eq!(
+        messages.delete_expired_messages(now + 120).await.unwrap(),
+        1
+    );
+    assert_eq!(
+        contents(&messages.load_all_for(&receiver).await.unwrap()),
+        [1]
+    );
+
+    store.close_handle().close().await;
+}
+
+#[test(tokio::test)]
+async fn sweep_removed_receivers() {
+    let data_dir = TestDir::new("retention");
+    let store = data_dir.server_store().await;
+    let messages = store.messages.clone();
+
+    let root_credential = Credential::generate_root().unwrap();
+    let root = root_credential
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+    let mut trust_store = TrustStore::initialize(root);
+
+    let agent = KeyPair::generate();
+    let cert = root_credential
+        .create_agent_certificate_for_key(&agent.export_public_key())
+        .unwrap();
+    let block = trust_store.add(cert, &root_credential).unwrap();
+    trust_store.apply(block);
+    let trust_store = Arc::new(RwLock::new(trust_store));
+
+    let root = root_credential.certificate().spki_hash().clone();
+    let agent = agent.spki_hash();
+    let removed = KeyPair::generate().spki_hash();
+
+    messages
+        .add_message(message(&[&root, &agent, &removed], 1))
+        .await
+        .unwrap();
+    messages.add_message(message(&[&removed], 2)).await.unwrap();
+
+    let retention = MessageRetention {
+        max_age_secs: 0,
+        ..Default::default()
+    };
+    let report = sweep_once(&messages, &store.sessions, &trust_store, &retention)
+        .await
+        .unwrap();
+
+    assert_eq!(report.removed_receivers, [removed.clone()]);
+    assert_eq!(report.orphaned, 1);
+    assert_eq!(report.expired, 0);
+
+    let metrics = messages.queue_metrics().await.unwrap();
+    assert_eq!(metrics.depth(&root), 1);
+    assert_eq!(metrics.depth(&agent), 1);
+    assert_eq!(metrics.depth(&removed), 0);
+    assert_eq!(metrics.messages, 1);
+
+    store.close_handle().close().await;
+}
+
+#[test(tokio::test)]
+async fn welcomes_are_never_dropped() {
+    let data_dir = TestDir::new("retention");
+    let store = data_dir.server_store().await;
+    let messages = store.messages.clone();
+    messages.set_limits(MessageLimits {
+        ttl: Some(Duration::from_secs(60)),
+        max_pending_per_receiver: Some(1),
+    });
+    let receiver = KeyPair::generate().spki_hash();
+
+    messages
+        .add_message(MessageToSend {
+            receivers: vec![receiver.clone()],
+            message: MessageToMemberTransport::Welcome(vec![0]),
+        })
+        .await
+        .unwrap();
+    messages
+        .add_message(message(&[&receiver], 1))
+        .await
+        .unwrap();
+    messages
+        .add_message(message(&[&receiver], 2))
+        .await
+        .unwrap();
+
+    // the welcome doesn't count against the quota
+    let pending = messages.load_all_for(&receiver).await.unwrap();
+    assert_eq!(pending.len(), 2);
+    assert!(
+        pending
+            .iter()
+            .any(|(_, message)| matches!(message, MessageToMemberTransport::Welcome(_)))
+    );
+
+    // neither the TTL nor the maximum age delete it
+    let later = get_current_timestamp() + 120;
+    assert_eq!(messages.delete_expired_messages(later).await.unwrap(), 1);
+    assert_eq!(messages.delete_messages_older_than(later).await.unwrap(), 0);
+    let pending = messages.load_all_for(&receiver).await.unwrap();
+    assert_eq!(pending.len(), 1);
+    assert!(matches!(pending[0].1, MessageToMemberTransport::Welcome(_)));
+
+    store.close_handle().close().await;
+}
+
+#[test(tokio::test)]
+async fn messages_stored_before_flagging_handshakes_are_checked() {
+    let data_dir = TestDir::new("retention");
+    let store = data_dir.server_store().await;
+    let receiver = KeyPair::generate().spki_hash();
+
+    store
+        .messages
+        .add_message(MessageToSend {
+            receivers: vec![receiver.clone()],
+            message: MessageToMemberTransport::Welcome(vec![0]),
+        })
+        .await
+        .unwrap();
+    store
+        .messages
+        .add_message(message(&[&receiver], 1))
+        .await
+        .unwrap();
+    store.close_handle().close().await;
+
+    // what the migration leaves behind for messages of older servers
+    let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", data_dir.path("db.sqlite")))
+        .await
+        .unwrap();
+    sqlx::query("UPDATE mls_messages SET handshake = 2")
+        .execute(&pool)
+        .await
+        .unwrap();
+    pool.close().await;
+
+    // the store checks them when it opens, only the welcome is kept
+    let store = data_dir.server_store().await;
+    let later = get_current_timestamp() + 120;
+    assert_eq!(
+        store
+            .messages
+            .delete_messages_older_than(later)
+            .await
+            .unwrap(),
+        1
+    );
+    let pending = store.messages.load_all_for(&receiver).await.unwrap();
+    assert_eq!(pending.len(), 1);
+    assert!(matches!(pending[0].1, MessageToMemberTransport::Welcome(_)));
+
+    store.close_handle().close().await;
+}
@@ -0,0 +1,415 @@
+use std::sync::{Arc, RwLock};
+
+use openmls_sqlx_storage::SqliteStorageProvider;
+use svalin_pki::{
+    ArgonParams, Credential, EncryptedObject, EncryptionKey, KeyPair, TrustStoreVerifier,
+    argon2::password_hash::ParamsString,
+    curve25519_dalek::{Scalar, constants::RISTRETTO_BASEPOINT_POINT},
+    mls::{
+        agent::{AgentMessageContent, MlsAgent},
+        client::MlsClient,
+        provider::SvalinStorage,
+        transport_types::SvalinMessage,
+    },
+    trust_store::TrustStore,
+};
+use svalin_rpc::rpc::{command::handler::CommandHandler, peer::Peer, session::Session};
+use svalin_store::{
+    client_store::persistent::{self, AlertAcknowledgement},
+    server_store::UpdateMlsDataError,
+};
+use test_log::test;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use totp_rs::Totp;
+
+use crate::{
+    mls::{MlsServer, MlsTypes},
+    server::local_key_retriever::LocalKeyRetriever,
+    shared::commands::update_user_mls::{
+        SavedState, StateUpdateResult, ToServer, Update, UpdateUserMlsHandler, UserMlsMessage,
+    },
+    test::TestDir,
+};
+
+#[test(tokio::test)]
+async fn concurrent_mls_state_updates() {
+    let data_dir = TestDir::new("user-mls");
+    let store = data_dir.server_store().await;
+
+    let key = EncryptionKey::dangerous_from_bytes([7; 32]);
+    let root = Credential::generate_root().unwrap();
+    let trust_store = TrustStore::initialize(
+        root.certificate()
+            .clone()
+            .to_unverified()
+            .use_as_root()
+            .unwrap(),
+    );
+    let (_, export_handle) = SvalinStorage::new_memory();
+    let empty_state = || EncryptedObject::encrypt(&persistent::State::empty(), &key).unwrap();
+
+    store
+        .users
+        .add_root_user(
+            b"admin".to_vec(),
+            root.export(&key).unwrap(),
+            ArgonParams::basic(),
+            Totp::default(),
+            Scalar::ZERO,
+            ParamsString::new(),
+            RISTRETTO_BASEPOINT_POINT,
+            export_handle.export(&key).unwrap(),
+            empty_state(),
+            trust_store.export(),
+            EncryptedObject::encrypt(&trust_store.digest(), &key).unwrap(),
+        )
+        .await
+        .unwrap();
+
+    let user = root.certificate().spki_hash();
+    let loaded = store.users.get_mls_data(user).await.unwrap().unwrap();
+    assert_eq!(loaded.version, 0);
+
+    // two sessions loaded version 0, the first update wins
+    let version = store
+        .users
+        .update_mls_data(user, 0, export_handle.export(&key).unwrap(), empty_state())
+        .await
+        .unwrap();
+    assert_eq!(version, 1);
+
+    let conflict = store
+        .users
+        .update_mls_data(user, 0, export_handle.export(&key).unwrap(), empty_state())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        conflict,
+        UpdateMlsDataError::VersionConflict {
+            expected: 0,
+            current: 1
+        }
+    ));
+
+    // the losing session resyncs and continues from the current version
+    let loaded = store.users.get_mls_data(user).await.unwrap().unwrap();
+    assert_eq!(loaded.version, 1);
+    let version = store
+        .users
+        .update_mls_data(
+            user,
+            loaded.version,
+            export_handle.export(&key).unwrap(),
+            empty_state(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(version, 2);
+
+    store.close_handle().close().await;
+}
+
+/// Starts the server side of an update_user_mls session. The test takes the
+/// place of the dispatcher on the returned session.
+fn connect(
+    handler: Arc<UpdateUserMlsHandler>,
+    credential: &Credential,
+) -> (Session, JoinHandle<anyhow::Result<()>>) {
+    let (client, server) = tokio::io::duplex(1 << 20);
+    let mut server = Session::new(
+        Box::new(server),
+        Peer::Certificate(credential.certificate().clone()),
+    );
+    let handled = tokio::spawn(async move {
+        handler
+            .handle(&mut server, (), CancellationToken::new())
+            .await
+    });
+
+    (Session::new(Box::new(client), Peer::Anonymous), handled)
+}
+
+/// Reads the saved state and the key package count, which the handler sends
+/// first.
+async fn load_state(session: &mut Session) -> SavedState {
+    let state: SavedState = session.read_object().await.unwrap();
+    let update: Update = session.read_object().await.unwrap();
+    assert!(matches!(update, Update::KeyPackageCount(_)));
+
+    state
+}
+
+struct RacingUser {
+    credential: Credential,
+    key: EncryptionKey,
+    key_retriever: LocalKeyRetriever,
+    verifier: TrustStoreVerifier,
+}
+
+impl RacingUser {
+    /// Sends a message and key packages based on the given state, like the
+    /// dispatcher of a session does.
+    async fn update(
+        &self,
+        session: &mut Session,
+        state: SavedState,
+        message: &UserMlsMessage,
+        key_packages: usize,
+    ) -> StateUpdateResult {
+        let (storage, export_handle) = SvalinStorage::import(state.mls_store, &self.key).unwrap();
+        let client = MlsClient::<MlsTypes, _, _>::new(
+            self.credential.clone(),
+            storage,
+            self.key_retriever.clone(),
+            self.verifier.clone(),
+        )
+        .unwrap();
+
+        let mut created = Vec::new();
+        for _ in 0..key_packages {
+            created.push(client.create_key_package().await.unwrap().to_unverified());
+        }
+        let messages = vec![message.encrypt(&client).await.unwrap()];
+
+        session
+            .write_object(&ToServer::StateUpdate {
+                version: state.version,
+                mls_store: export_handle.export(&self.key).unwrap(),
+                persistent_data: state.persistent_data,
+                key_packages: created,
+                aknowledged: Vec::new(),
+                messages,
+            })
+            .await
+            .unwrap();
+
+        match session.read_object::<Update>().await.unwrap() {
+            Update::StateUpdated(result) => result,
+            update => panic!("expected the result of the update, got {update:?}"),
+        }
+    }
+}
+
+#[test(tokio::test)]
+async fn losing_session_replays_its_update() {
+    let data_dir = TestDir::new("user-mls");
+    let store = data_dir.server_store().await;
+    let key = EncryptionKey::dangerous_from_bytes([7; 32]);
+
+    let user = Credential::generate_root().unwrap();
+    let user_hash = user.certificate().spki_hash().clone();
+    let root = user
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+    let keypair = KeyPair::generate();
+    let agent_certificate = user
+        .create_agent_certificate_for_key(&keypair.export_public_key())
+        .unwrap();
+    let agent_credential = keypair.upgrade(agent_certificate.to_unverified()).unwrap();
+    let device = agent_credential.certificate().spki_hash().clone();
+
+    let trust_store = Arc::new(RwLock::new(TrustStore::initialize(root.clone())));
+    {
+        let mut trust_store = trust_store.write().unwrap();
+        let block = trust_store
+            .add(agent_credential.certificate().clone(), &user)
+            .unwrap();
+        trust_store.apply(block);
+    }
+    let verifier = TrustStoreVerifier::new(trust_store.clone());
+    let key_retriever =
+        LocalKeyRetriever::new(root, trust_store.clone(), store.key_packages.clone());
+    let mls = Arc::new(MlsServer::new(
+        SqliteStorageProvider::open(
+            data_dir
+                .path("server-mls.sqlite")
+                .as_path()
+                .to_str()
+                .unwrap(),
+        )
+        .await
+        .unwrap(),
+        verifier.clone(),
+        key_retriever.clone(),
+    ));
+
+    // the user is a member of the device group in the state both sessions load
+    let (storage, export_handle) = SvalinStorage::new_memory();
+    let client = MlsClient::<MlsTypes, _, _>::new(
+        user.clone(),
+        storage,
+        key_retriever.clone(),
+        verifier.clone(),
+    )
+    .unwrap();
+    let key_package = client.create_key_package().await.unwrap().to_unverified();
+    let key_package = mls
+        .verify_key_package(key_package, &user_hash)
+        .await
+        .unwrap();
+    store
+        .key_packages
+        .add_key_package(key_package)
+        .await
+        .unwrap();
+    let agent = MlsAgent::<MlsTypes, _, _>::new(
+        agent_credential,
+        SqliteStorageProvider::open(
+            data_dir
+                .path("agent-mls.sqlite")
+                .as_path()
+                .to_str()
+                .unwrap(),
+        )
+        .await
+        .unwrap(),
+        key_retriever.clone(),
+        verifier.clone(),
+    )
+    .await
+    .unwrap();
+    let new_group = agent
+        .create_device_group_if_missing()
+        .await
+        .unwrap()
+        .unwrap();
+    let welcome = mls.process_message(new_group).await.unwrap();
+    client.handle_message(&welcome[0].message).await.unwrap();
+
+    store
+        .users
+        .add_root_user(
+            b"admin".to_vec(),
+            user.export(&key).unwrap(),
+            ArgonParams::basic(),
+            Totp::default(),
+            Scalar::ZERO,
+            ParamsString::new(),
+            RISTRETTO_BASEPOINT_POINT,
+            export_handle.export(&key).unwrap(),
+            EncryptedObject::encrypt(&persistent::State::empty(), &key).unwrap(),
+            trust_store.read().unwrap().export(),
+            EncryptedObject::encrypt(&trust_store.read().unwrap().digest(), &key).unwrap(),
+        )
+        .await
+        .unwrap();
+
+    let handler = Arc::new(UpdateUserMlsHandler::new(
+        store.users.clone(),
+        store.messages.clone(),
+        store.key_packages.clone(),
+        mls,
+    ));
+    let racing = RacingUser {
+        credential: user.clone(),
+        key,
+        key_retriever,
+        verifier,
+    };
+    let acknowledgement = |acknowledged_at| AlertAcknowledgement {
+        alert: uuid::Uuid::new_v4(),
+        acknowledged_at,
+    };
+    let first_acknowledgement = acknowledgement(10);
+    let second_acknowledgement = acknowledgement(20);
+    let key_packages = store
+        .key_packages
+        .count_key_packages(&user_hash)
+        .await
+        .unwrap();
+
+    let (mut first, first_handled) = connect(
+        handler.clone(),
+        &user.create_user_device_credential().unwrap(),
+    );
+    let (mut second, second_handled) = connect(
+        handler.clone(),
+        &user.create_user_device_credential().unwrap(),
+    );
+    let first_state = load_state(&mut first).await;
+    let second_state = load_state(&mut second).await;
+    assert_eq!(first_state.version, 0);
+    assert_eq!(second_state.version, 0);
+
+    // both sessions update version 0, the first one wins
+    let result = racing
+        .update(
+            &mut first,
+            first_state,
+            &UserMlsMessage::AcknowledgeAlert(device.clone(), first_acknowledgement.clone()),
+            1,
+        )
+        .await;
+    assert_eq!(result, StateUpdateResult::Accepted { version: 1 });
+    assert_eq!(
+        store
+            .key_packages
+            .count_key_packages(&user_hash)
+            .await
+            .unwrap(),
+        key_packages + 1
+    );
+    assert_eq!(store.messages.load_all_for(&device).await.unwrap().len(), 1);
+
+    // the key packages and messages of the losing update are dropped
+    let lost = UserMlsMessage::AcknowledgeAlert(device.clone(), second_acknowledgement.clone());
+    let result = racing.update(&mut second, second_state, &lost, 1).await;
+    assert_eq!(result, StateUpdateResult::Conflict);
+    assert_eq!(
+        store
+            .key_packages
+            .count_key_packages(&user_hash)
+            .await
+            .unwrap(),
+        key_packages + 1
+    );
+    assert_eq!(store.messages.load_all_for(&device).await.unwrap().len(), 1);
+
+    // the loser gets the winner's state and replays its update on top of it
+    let second_state: SavedState = second.read_object().await.unwrap();
+    assert_eq!(second_state.version, 1);
+    let result = racing.update(&mut second, second_state, &lost, 0).await;
+    assert_eq!(result, StateUpdateResult::Accepted { version: 2 });
+    assert_eq!(
+        store
+            .key_packages
+            .count_key_packages(&user_hash)
+            .await
+            .unwrap(),
+        key_packages + 1
+    );
+
+    // the agent can decrypt both, the replayed message follows the first one
+    let delivered = store.messages.load_all_for(&device).await.unwrap();
+    assert_eq!(delivered.len(), 2);
+    for ((_, message), expected) in delivered
+        .iter()
+        .zip([first_acknowledgement, second_acknowledgement])
+    {
+        let AgentMessageContent::Other(sender, message) =
+            agent.handle_message(message).await.unwrap()
+        else {
+            panic!("expected an acknowledgement");
+        };
+        assert_eq!(sender, user_hash);
+        assert_eq!(
+            message.encode().unwrap(),
+            SvalinMessage::new(&expected).unwrap().encode().unwrap()
+        );
+    }
+
+    for (mut session, handled) in [(first, first_handled), (second, second_handled)] {
+        session.write_object(&ToServer::Goodbye).await.unwrap();
+        assert!(matches!(
+            session.read_object::<Update>().await.unwrap(),
+            Update::Goodbye
+        ));
+        handled.await.unwrap().unwrap();
+    }
+
+    store.close_handle().close().await;
+}
@@ -0,0 +1,150 @@
+use svalin_pki::{Credential, secure_chain::Checkpoint, trust_store::TrustStore};
+
+use test_log::test;
+
+use crate::{
+    shared::commands::witness_signature::WitnessSignatures,
+    test::{TestDir, add_agent},
+    util::witness::{WitnessGate, WitnessPolicy},
+};
+
+#[test]
+fn witness_gate_holds_back_blocks() {
+    let root_credential = Credential::generate_root().unwrap();
+    let root = root_credential
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+
+    // the trust store as seen by the server and the witnesses
+    let mut store = TrustStore::initialize(root);
+    let (first, _) = add_agent(&mut store, &root_credential);
+    let (second, _) = add_agent(&mut store, &root_credential);
+    let (other, _) = add_agent(&mut store, &root_credential);
+
+    let policy = WitnessPolicy {
+        witnesses: vec![
+            first.certificate().spki_hash().clone(),
+            second.certificate().spki_hash().clone(),
+        ],
+        required: 2,
+    };
+    policy.validate().unwrap();
+    WitnessPolicy {
+        required: 3,
+        ..policy.clone()
+    }
+    .validate()
+    .unwrap_err();
+
+    // without a policy blocks pass immediately
+    let mut gate = WitnessGate::new(WitnessPolicy::default());
+    let (_, block) = add_agent(&mut store, &root_credential);
+    assert_eq!(gate.add(block.to_unchecked()).len(), 1);
+
+    let mut gate = WitnessGate::new(policy);
+    let older = add_agent(&mut store, &root_credential).1.to_unchecked();
+    let older_head = store.checkpoint(&first);
+    let newer = add_agent(&mut store, &root_credential).1.to_unchecked();
+
+    assert!(gate.add(older.clone()).is_empty());
+    assert!(gate.add(newer.clone()).is_empty());
+    assert_eq!(gate.pending(), 2);
+
+    // one signature is not enough
+    assert!(gate.add_signature(older_head.clone()).is_empty());
+    // witnesses which aren't part of the policy don't count
+    assert!(gate.add_signature(store.checkpoint(&other)).is_empty());
+
+    // signing the head vouches for all blocks before it
+    assert!(gate.add_signature(store.checkpoint(&first)).is_empty());
+    // an older signature doesn't replace a newer one
+    assert!(gate.add_signature(older_head).is_empty());
+    let released = gate.add_signature(store.checkpoint(&second));
+    assert_eq!(released, vec![older, newer]);
+    assert_eq!(gate.pending(), 0);
+}
+
+#[test]
+fn witness_gate_holds_back_checkpoints() {
+    let root_credential = Credential::generate_root().unwrap();
+    let root = root_credential
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+
+    let mut store = TrustStore::initialize(root);
+    let (first, _) = add_agent(&mut store, &root_credential);
+    let (second, _) = add_agent(&mut store, &root_credential);
+
+    let policy = WitnessPolicy {
+        witnesses: vec![
+            first.certificate().spki_hash().clone(),
+            second.certificate().spki_hash().clone(),
+        ],
+        required: 2,
+    };
+
+    // the checkpoint sent by the server and the witnesses at the same position
+    let checkpoint = store.checkpoint(&root_credential);
+    let first_signature = store.checkpoint(&first);
+    let second_signature = store.checkpoint(&second);
+    add_agent(&mut store, &root_credential);
+
+    // signatures of another position don't vouch for the checkpoint
+    let mut gate = WitnessGate::<Checkpoint>::new(policy.clone());
+    assert!(gate.add(checkpoint.clone()).is_empty());
+    assert!(gate.add_signature(store.checkpoint(&first)).is_empty());
+    assert!(gate.add_signature(store.checkpoint(&second)).is_empty());
+    assert_eq!(gate.pending(), 1);
+
+    let mut gate = WitnessGate::new(policy);
+    assert!(gate.add(checkpoint).is_empty());
+    assert!(gate.add_signature(first_signature).is_empty());
+    let released = gate.add_signature(second_signature);
+    assert_eq!(released.len(), 1);
+    assert_eq!(
+        released[0].signer(),
+        root_credential.certificate().spki_hash()
+    );
+    assert_eq!(gate.pending(), 0);
+}
+
+#[test(tokio::test)]
+async fn witness_signatures_survive_restart() {
+    let data_dir = TestDir::new("witness-signatures");
+
+    let root_credential = Credential::generate_root().unwrap();
+    let root = root_credential
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+    let mut store = TrustStore::initialize(root);
+    let (witness, _) = add_agent(&mut store, &root_credential);
+    let older = store.checkpoint(&witness);
+    add_agent(&mut store, &root_credential);
+    let newer = store.checkpoint(&witness);
+
+    let server_store = data_dir.server_store().await;
+    let signatures = WitnessSignatures::load(server_store.trust_store_transactions.clone())
+        .await
+        .unwrap();
+    assert!(signatures.latest().is_empty());
+    signatures.add(older).await.unwrap();
+    signatures.add(newer.clone()).await.unwrap();
+
+    // only the latest signature of each witness is kept
+    let server_store = data_dir.server_store().await;
+    let signatures = WitnessSignatures::load(server_store.trust_store_transactions.clone())
+        .await
+        .unwrap();
+    let latest = signatures.latest();
+    assert_eq!(latest.len(), 1);
+    assert!(latest[0].matches_position(&newer));
+}
@@ -1,6 +1,11 @@
+pub mod chain_gossip;
+pub mod expiry;
 pub mod key_storage;
 pub mod location;
+pub mod logging;
+pub mod renewal;
 pub mod rpc_subscribe;
 pub mod smart_subscriber;
 pub mod trust_store;
+pub mod witness;
 // mod test;
@@ -0,0 +1,119 @@
+use std::{sync::RwLock, time::Duration};
+
+use svalin_pki::{
+    SpkiHash,
+    secure_chain::{Checkpoint, PositionComparison, VerifyCheckpointError},
+    trust_store::TrustStore,
+};
+use svalin_store::trust_store_transaction_store::{
+    LoadTransactionError, TransactionStoreError, TrustStoreTransactionStore,
+};
+
+/// How often members exchange their trust store positions.
+pub const POSITION_INTERVAL: Duration = Duration::from_secs(60 * 10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ComparePositionError {
+    #[error("signer {0} of the chain position is not known")]
+    UnknownSigner(SpkiHash),
+    #[error("invalid chain position signature: {0}")]
+    InvalidSignature(#[from] VerifyCheckpointError),
+    #[error("failed to load trust store transaction: {0}")]
+    LoadError(#[from] LoadTransactionError),
+    #[error("failed to store conflict report: {0}")]
+    StoreError(#[from] TransactionStoreError),
+    #[error(
+        "trust store of {0} conflicts with the local one, waiting for another member to confirm it"
+    )]
+    UnconfirmedConflict(SpkiHash),
+    #[error("trust store of {0} conflicts with the local one, the server presents a split view")]
+    SplitView(SpkiHash),
+}
+
+/// Compares the signed trust store position of another member with the local
+/// trust store.
+///
+/// If both were shown different histories, the report is stored. Once root or
+/// enough members reported a conflict, the trust store is marked as
+/// conflicting, which makes every operation depending on it fail until an
+/// admin resets it.
+pub async fn compare_position(
+    trust_store: &RwLock<TrustStore>,
+    history: &TrustStoreTransactionStore,
+    position: &Checkpoint,
+) -> Result<(), ComparePositionError> {
+    let comparison = {
+        let guard = trust_store.read().unwrap();
+        let signer = guard
+            .get(position.signer())
+            .ok_or_else(|| ComparePositionError::UnknownSigner(position.signer().clone()))?;
+        position.verify(signer)?;
+
+        guard.compare_position(position)
+    };
+
+    let conflict = match comparison {
+        PositionComparison::Equal => false,
+        PositionComparison::Conflict => true,
+        PositionComparison::PeerAhead => {
+            tracing::debug!(
+                "{} is ahead at sequence {}, comparing later",
+                position.signer(),
+                position.sequence()
+            );
+            false
+        }
+        PositionComparison::PeerBehind => match history.load(position.sequence()).await? {
+            Some(block) => !position.matches_block(&block),
+            None => {
+                tracing::debug!(
+                    "block {} was pruned, can't compare with {}",
+                    position.sequence(),
+                    position.signer()
+                );
+                false
+            }
+        },
+    };
+
+    if conflict {
+        history.add_conflict_report(position).await?;
+        let confirmed = trust_store
+            .write()
+            .unwrap()
+            .report_conflict(position.clone());
+        if !confirmed {
+            tracing::warn!(
+                "{} accepted a different trust store history at sequence {}, \
+                waiting for another member to confirm the split view",
+                position.signer(),
+                position.sequence()
+            );
+            return Err(ComparePositionError::UnconfirmedConflict(
+                position.signer().clone(),
+            ));
+        }
+
+        tracing::error!(
+            "SPLIT VIEW DETECTED: {} accepted a different trust store history at sequence {}. \
+            The server presents different histories to its members, refusing to trust the trust store.",
+            position.signer(),
+            position.sequence()
+        );
+        return Err(ComparePositionError::SplitView(position.signer().clone()));
+    }
+
+    Ok(())
+}
+
+/// Forgets all reported conflicts of the trust store, once an admin resolved
+/// the split view.
+pub async fn reset_conflict(
+    trust_store: &RwLock<TrustStore>,
+    history: &TrustStoreTransactionStore,
+) -> anyhow::Result<()> {
+    history.reset_conflict_reports().await?;
+    trust_store.write().unwrap().reset_conflict();
+
+    Ok(())
+}
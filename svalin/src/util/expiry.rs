@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+use svalin_pki::{get_current_timestamp, trust_store::TrustStore};
+
+/// Certificates expiring within this window are reported as expiring soon.
+pub const EXPIRY_WARNING_WINDOW: Duration = Duration::from_secs(60 * 60 * 24 * 30);
+
+/// Logs a warning for every certificate in the trust store which expired or
+/// expires within [`EXPIRY_WARNING_WINDOW`].
+pub fn log_expiring_certificates(trust_store: &TrustStore) {
+    let now = get_current_timestamp();
+    let expiring = trust_store.expiring_within(EXPIRY_WARNING_WINDOW.as_secs(), now);
+
+    for (certificate_type, certificates) in expiring {
+        for certificate in certificates {
+            if certificate.not_after() < now {
+                tracing::warn!(
+                    "{certificate_type:?} certificate {} expired at {}",
+                    certificate.spki_hash(),
+                    certificate.not_after()
+                );
+            } else {
+                tracing::warn!(
+                    "{certificate_type:?} certificate {} expires at {}",
+                    certificate.spki_hash(),
+                    certificate.not_after()
+                );
+            }
+        }
+    }
+}
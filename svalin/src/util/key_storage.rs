@@ -1,6 +1,27 @@
-use anyhow::Result;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock},
+};
+
+use anyhow::{Context, Result, anyhow};
 use serde::{Deserialize, Serialize};
-use svalin_pki::{Credential, EncryptedCredential, EncryptionKey};
+use svalin_pki::{
+    ArgonParams, Credential, EncryptedCredential, EncryptedKeyPair, EncryptionKey, KeyPair,
+};
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+use zeroize::Zeroize;
+
+mod pkcs11;
+
+pub use pkcs11::Pkcs11Key;
+
+/// Keys of the sources which prompt for a passphrase or need a PKCS#11 token,
+/// see [`KeySource::cache_id`]. They are loaded once and kept for the lifetime
+/// of the process, so daemons don't ask for them again when renewing their
+/// certificates. [`EncryptionKey`] is zeroized on drop.
+static LOADED_KEYS: LazyLock<Mutex<HashMap<Vec<u8>, Arc<EncryptionKey>>>> =
+    LazyLock::new(Default::default);
 
 /// The keysource enum is saved in the configuration and specifies how to
 /// load the key for decrypting the credentials. This will enable the use of
@@ -8,12 +29,128 @@ use svalin_pki::{Credential, EncryptedCredential, EncryptionKey};
 #[derive(Serialize, Deserialize)]
 pub enum KeySource {
     BuiltIn([u8; 32]),
+    /// The key is stored in a separate file which may only be accessible by
+    /// its owner.
+    KeyFile(PathBuf),
+    /// The key is derived from the passphrase in the given environment variable.
+    Environment {
+        variable: String,
+        params: ArgonParams,
+    },
+    /// The key is derived from a passphrase which is read from stdin once per
+    /// process.
+    Passphrase(ArgonParams),
+    /// The key is wrapped by a secret key stored on a PKCS#11 token, it is
+    /// unwrapped once per process.
+    Pkcs11(Pkcs11Key),
+}
+
+/// Selects which kind of [`KeySource`] is generated when credentials are
+/// saved for the first time.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum KeySourceConfig {
+    #[default]
+    BuiltIn,
+    KeyFile {
+        path: PathBuf,
+    },
+    Environment {
+        variable: String,
+    },
+    Passphrase,
+    Pkcs11 {
+        /// Path of the PKCS#11 module, e.g. `/usr/lib/softhsm/libsofthsm2.so`.
+        module: PathBuf,
+        /// Label of the token containing the wrapping key.
+        token: String,
+        /// Label of the wrapping key, it is created if it doesn't exist.
+        key_label: String,
+        /// Environment variable containing the user PIN of the token.
+        pin_variable: String,
+    },
+}
+
+impl KeySourceConfig {
+    pub async fn generate(&self) -> Result<KeySource> {
+        match self {
+            KeySourceConfig::BuiltIn => KeySource::generate_builtin(),
+            KeySourceConfig::KeyFile { path } => {
+                write_key_file(path).await?;
+                Ok(KeySource::KeyFile(path.clone()))
+            }
+            KeySourceConfig::Environment { variable } => {
+                // make sure the variable is actually set before relying on it
+                read_env_passphrase(variable)?.zeroize();
+                Ok(KeySource::Environment {
+                    variable: variable.clone(),
+                    params: ArgonParams::strong(),
+                })
+            }
+            KeySourceConfig::Passphrase => Ok(KeySource::Passphrase(ArgonParams::strong())),
+            KeySourceConfig::Pkcs11 {
+                module,
+                token,
+                key_label,
+                pin_variable,
+            } => Ok(KeySource::Pkcs11(
+                Pkcs11Key::generate(
+                    module.clone(),
+                    token.clone(),
+                    key_label.clone(),
+                    pin_variable.clone(),
+                )
+                .await?,
+            )),
+        }
+    }
 }
 
 impl KeySource {
-    async fn to_key(&self) -> Result<EncryptionKey> {
+    async fn to_key(&self) -> Result<Arc<EncryptionKey>> {
+        let Some(id) = self.cache_id()? else {
+            return Ok(Arc::new(self.load_key().await?));
+        };
+
+        // the lock is held while loading, so concurrent callers don't prompt
+        // twice
+        let mut keys = LOADED_KEYS.lock().await;
+        if let Some(key) = keys.get(&id) {
+            return Ok(key.clone());
+        }
+        let key = Arc::new(self.load_key().await?);
+        keys.insert(id, key.clone());
+
+        Ok(key)
+    }
+
+    /// Identifies the sources whose key is kept in [`LOADED_KEYS`]. The salt
+    /// and the wrapped key are part of the id, so different sources never
+    /// share a key.
+    fn cache_id(&self) -> Result<Option<Vec<u8>>> {
+        match self {
+            KeySource::Passphrase(_) | KeySource::Pkcs11(_) => Ok(Some(serde_json::to_vec(self)?)),
+            KeySource::BuiltIn(_) | KeySource::KeyFile(_) | KeySource::Environment { .. } => {
+                Ok(None)
+            }
+        }
+    }
+
+    async fn load_key(&self) -> Result<EncryptionKey> {
         match self {
             KeySource::BuiltIn(k) => Ok(EncryptionKey::dangerous_from_bytes(k.clone())),
+            KeySource::KeyFile(path) => read_key_file(path).await,
+            KeySource::Environment { variable, params } => {
+                params
+                    .derive_encryption_key(read_env_passphrase(variable)?)
+                    .await
+            }
+            KeySource::Passphrase(params) => {
+                params
+                    .derive_encryption_key(read_stdin_passphrase().await?)
+                    .await
+            }
+            KeySource::Pkcs11(key) => key.unwrap_key().await,
         }
     }
 
@@ -39,4 +176,98 @@ impl KeySource {
         tracing::trace!("headless password loaded, decrypting...");
         Ok(encrypted_credentials.decrypt(&key)?)
     }
+
+    pub async fn encrypt_keypair(&self, keypair: &KeyPair) -> Result<EncryptedKeyPair> {
+        let key = self.to_key().await?;
+
+        Ok(keypair.export(&key)?)
+    }
+
+    pub async fn decrypt_keypair(&self, encrypted_keypair: EncryptedKeyPair) -> Result<KeyPair> {
+        let key = self.to_key().await?;
+
+        Ok(encrypted_keypair.decrypt(&key)?)
+    }
+}
+
+async fn write_key_file(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let key = svalin_pki::generate_key()?;
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options
+        .open(path)
+        .await
+        .with_context(|| format!("failed to create key file {}", path.display()))?;
+    file.write_all(key.as_ref()).await?;
+    file.flush().await?;
+    file.sync_all().await?;
+
+    Ok(())
+}
+
+async fn read_key_file(path: &Path) -> Result<EncryptionKey> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let metadata = tokio::fs::metadata(path)
+            .await
+            .with_context(|| format!("failed to access key file {}", path.display()))?;
+        let mode = metadata.permissions().mode();
+        if mode & 0o077 != 0 {
+            return Err(anyhow!(
+                "key file {} is accessible by other users (mode {:o}), it must only be readable by its owner",
+                path.display(),
+                mode & 0o777
+            ));
+        }
+    }
+
+    let mut content = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("failed to read key file {}", path.display()))?;
+    let key: Result<[u8; 32], _> = content.as_slice().try_into();
+    content.zeroize();
+
+    let key = key.map_err(|_| anyhow!("key file {} has an invalid length", path.display()))?;
+    Ok(EncryptionKey::dangerous_from_bytes(key))
+}
+
+fn read_env_passphrase(variable: &str) -> Result<Vec<u8>> {
+    let passphrase = std::env::var(variable)
+        .with_context(|| format!("environment variable {variable} is not set"))?;
+
+    if passphrase.is_empty() {
+        return Err(anyhow!("environment variable {variable} is empty"));
+    }
+
+    Ok(passphrase.into_bytes())
+}
+
+async fn read_stdin_passphrase() -> Result<Vec<u8>> {
+    tokio::task::spawn_blocking(|| {
+        eprintln!("passphrase for the credentials:");
+        let mut passphrase = String::new();
+        std::io::stdin().read_line(&mut passphrase)?;
+        let trimmed = passphrase
+            .trim_end_matches(['\r', '\n'])
+            .as_bytes()
+            .to_vec();
+        passphrase.zeroize();
+
+        if trimmed.is_empty() {
+            return Err(anyhow!("the passphrase must not be empty"));
+        }
+
+        Ok(trimmed)
+    })
+    .await?
 }
@@ -0,0 +1,144 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use cryptoki::{
+    context::{CInitializeArgs, Pkcs11},
+    mechanism::Mechanism,
+    object::{Attribute, KeyType, ObjectClass, ObjectHandle},
+    session::{Session, UserType},
+    types::AuthPin,
+};
+use rand::RngExt;
+use serde::{Deserialize, Serialize};
+use svalin_pki::EncryptionKey;
+use zeroize::Zeroize;
+
+/// A random credential key, encrypted with an AES key which never leaves the
+/// PKCS#11 token.
+#[derive(Serialize, Deserialize)]
+pub struct Pkcs11Key {
+    module: PathBuf,
+    token: String,
+    key_label: String,
+    pin_variable: String,
+    iv: [u8; 16],
+    wrapped_key: Vec<u8>,
+}
+
+impl Pkcs11Key {
+    /// Generates a new credential key and wraps it with the token key
+    /// `key_label`, which is created if it doesn't exist yet.
+    pub async fn generate(
+        module: PathBuf,
+        token: String,
+        key_label: String,
+        pin_variable: String,
+    ) -> Result<Self> {
+        tokio::task::spawn_blocking(move || {
+            let session = open_session(&module, &token, &pin_variable)?;
+            let wrapping_key = match find_key(&session, &key_label)? {
+                Some(key) => key,
+                None => create_key(&session, &key_label)?,
+            };
+
+            let key = svalin_pki::generate_key()?;
+            let iv: [u8; 16] = rand::rng().random();
+            let wrapped_key = session
+                .encrypt(&Mechanism::AesCbcPad(iv), wrapping_key, key.as_ref())
+                .context("failed to wrap key with PKCS#11 token")?;
+
+            Ok(Self {
+                module,
+                token,
+                key_label,
+                pin_variable,
+                iv,
+                wrapped_key,
+            })
+        })
+        .await?
+    }
+
+    pub(super) async fn unwrap_key(&self) -> Result<EncryptionKey> {
+        let module = self.module.clone();
+        let token = self.token.clone();
+        let key_label = self.key_label.clone();
+        let pin_variable = self.pin_variable.clone();
+        let iv = self.iv;
+        let wrapped_key = self.wrapped_key.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let session = open_session(&module, &token, &pin_variable)?;
+            let wrapping_key = find_key(&session, &key_label)?
+                .ok_or_else(|| anyhow!("key {key_label} not found on token {token}"))?;
+
+            let mut key = session
+                .decrypt(&Mechanism::AesCbcPad(iv), wrapping_key, &wrapped_key)
+                .context("failed to unwrap key with PKCS#11 token")?;
+            let result: Result<[u8; 32], _> = key.as_slice().try_into();
+            key.zeroize();
+
+            let key = result.map_err(|_| anyhow!("unwrapped key has an invalid length"))?;
+            Ok(EncryptionKey::dangerous_from_bytes(key))
+        })
+        .await?
+    }
+}
+
+fn open_session(module: &Path, token: &str, pin_variable: &str) -> Result<Session> {
+    let pin = std::env::var(pin_variable)
+        .with_context(|| format!("environment variable {pin_variable} is not set"))?;
+
+    let pkcs11 = Pkcs11::new(module)
+        .with_context(|| format!("failed to load PKCS#11 module {}", module.display()))?;
+    pkcs11.initialize(CInitializeArgs::OsThreads)?;
+
+    let slot = pkcs11
+        .get_slots_with_token()?
+        .into_iter()
+        .find(|slot| {
+            pkcs11
+                .get_token_info(*slot)
+                .is_ok_and(|info| info.label() == token)
+        })
+        .ok_or_else(|| anyhow!("PKCS#11 token {token} not found"))?;
+
+    let session = pkcs11.open_rw_session(slot)?;
+    session
+        .login(UserType::User, Some(&AuthPin::new(pin)))
+        .context("failed to log in to PKCS#11 token")?;
+
+    Ok(session)
+}
+
+fn find_key(session: &Session, label: &str) -> Result<Option<ObjectHandle>> {
+    let keys = session.find_objects(&[
+        Attribute::Class(ObjectClass::SECRET_KEY),
+        Attribute::KeyType(KeyType::AES),
+        Attribute::Label(label.as_bytes().to_vec()),
+    ])?;
+
+    Ok(keys.into_iter().next())
+}
+
+fn create_key(session: &Session, label: &str) -> Result<ObjectHandle> {
+    let key = session
+        .generate_key(
+            &Mechanism::AesKeyGen,
+            &[
+                Attribute::Class(ObjectClass::SECRET_KEY),
+                Attribute::KeyType(KeyType::AES),
+                Attribute::ValueLen(32.into()),
+                Attribute::Label(label.as_bytes().to_vec()),
+                Attribute::Token(true),
+                Attribute::Private(true),
+                Attribute::Sensitive(true),
+                Attribute::Extractable(false),
+                Attribute::Encrypt(true),
+                Attribute::Decrypt(true),
+            ],
+        )
+        .context("failed to create key on PKCS#11 token")?;
+
+    Ok(key)
+}
@@ -6,7 +6,7 @@ use std::{
 
 use anyhow::Result;
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct Location {
     path: PathBuf,
 }
@@ -51,7 +51,26 @@ impl Location {
 
             #[cfg(target_os = "linux")]
             {
-                Ok(Self::new("/var/lib/svalin/agent"))
+                Ok(Self::new("/var/lib/svalin"))
+            }
+        }
+    }
+
+    pub fn system_config_dir() -> Result<Self, LocationError> {
+        #[cfg(test)]
+        {
+            Ok(Self::new(std::env::current_dir()?).push("test_data"))
+        }
+        #[cfg(not(test))]
+        {
+            #[cfg(target_os = "windows")]
+            {
+                Self::system_data_dir()
+            }
+
+            #[cfg(target_os = "linux")]
+            {
+                Ok(Self::new("/etc/svalin"))
             }
         }
     }
@@ -0,0 +1,49 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Full,
+    Compact,
+    Pretty,
+    Json,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Filter in the same syntax as `RUST_LOG`, e.g. `info` or `svalin=trace`.
+    ///
+    /// Falls back to `RUST_LOG` and then to `info` if unset.
+    pub level: Option<String>,
+    pub format: LogFormat,
+}
+
+impl LogConfig {
+    pub fn filter(&self) -> Result<EnvFilter> {
+        match &self.level {
+            Some(level) => {
+                EnvFilter::try_new(level).map_err(|err| anyhow!("invalid log level {level}: {err}"))
+            }
+            None => {
+                Ok(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+            }
+        }
+    }
+
+    /// Installs the global tracing subscriber.
+    pub fn init(&self) -> Result<()> {
+        let builder = tracing_subscriber::fmt().with_env_filter(self.filter()?);
+
+        match self.format {
+            LogFormat::Full => builder.try_init(),
+            LogFormat::Compact => builder.compact().try_init(),
+            LogFormat::Pretty => builder.pretty().try_init(),
+            LogFormat::Json => builder.json().try_init(),
+        }
+        .map_err(|err| anyhow!("failed to initialize logging: {err}"))
+    }
+}
@@ -0,0 +1,26 @@
+use svalin_pki::{
+    Certificate, CreateCredentialsError, Credential, UnverifiedCertificate, trust_store::TrustStore,
+};
+
+/// Returns the certificate from the trust store if it has the same key as
+/// `certificate` but expires later.
+pub fn renewed_certificate(
+    certificate: &UnverifiedCertificate,
+    trust_store: &TrustStore,
+) -> Option<Certificate> {
+    trust_store
+        .get(certificate.spki_hash())
+        .filter(|renewed| renewed.not_after() > certificate.not_after())
+        .cloned()
+}
+
+/// Returns the credential with the renewed certificate if the trust store
+/// contains a certificate for the same key which expires later.
+pub fn renewed_credential(
+    credential: &Credential,
+    trust_store: &TrustStore,
+) -> Result<Option<Credential>, CreateCredentialsError> {
+    renewed_certificate(credential.certificate(), trust_store)
+        .map(|renewed| credential.with_renewed_certificate(renewed.to_unverified()))
+        .transpose()
+}
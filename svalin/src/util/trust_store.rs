@@ -6,20 +6,36 @@ use std::{
 
 use anyhow::Context;
 use svalin_pki::{
-    secure_chain::UncheckedBlock,
+    UnverifiedCertificate,
+    secure_chain::{Checkpoint, UncheckedBlock},
     trust_store::{self, TrustStore},
 };
 use svalin_rpc::rpc::connection::Connection;
 use tokio::{io::AsyncWriteExt, sync::oneshot};
 use tokio_util::{sync::CancellationToken, task::TaskTracker};
 
-use crate::shared::commands::update_trust_store::UpdateTrustStore;
+use crate::{shared::commands::update_trust_store::UpdateTrustStore, util::witness::WitnessPolicy};
 
 pub trait Store {
     fn load_all_after(
         &self,
         after: u64,
     ) -> impl Future<Output = anyhow::Result<Vec<UncheckedBlock<trust_store::Transaction>>>>;
+
+    fn latest_checkpoint(
+        &self,
+    ) -> impl Future<Output = anyhow::Result<Option<(Checkpoint, trust_store::Exported)>>>;
+
+    /// Conflicting positions of other members, which stay until an admin
+    /// resets them.
+    fn conflict_reports(&self) -> impl Future<Output = anyhow::Result<Vec<Checkpoint>>>;
+
+    /// Keeps the Merkle leaf of a block of the snapshot, which isn't stored
+    /// itself.
+    fn keep_leaf(
+        &self,
+        block: &UncheckedBlock<trust_store::Transaction>,
+    ) -> impl Future<Output = anyhow::Result<()>>;
 }
 
 impl Store for svalin_store::trust_store_transaction_store::TrustStoreTransactionStore {
@@ -29,6 +45,23 @@ impl Store for svalin_store::trust_store_transaction_store::TrustStoreTransactio
     ) -> anyhow::Result<Vec<UncheckedBlock<trust_store::Transaction>>> {
         Ok(self.load_all_after(after).await?)
     }
+
+    async fn latest_checkpoint(
+        &self,
+    ) -> anyhow::Result<Option<(Checkpoint, trust_store::Exported)>> {
+        Ok(self.latest_checkpoint().await?)
+    }
+
+    async fn conflict_reports(&self) -> anyhow::Result<Vec<Checkpoint>> {
+        Ok(self.conflict_reports().await?)
+    }
+
+    async fn keep_leaf(
+        &self,
+        block: &UncheckedBlock<trust_store::Transaction>,
+    ) -> anyhow::Result<()> {
+        Ok(self.keep_leaf(block).await?)
+    }
 }
 
 impl Store for svalin_store::server_store::TrustStoreTransactionStore {
@@ -38,6 +71,24 @@ impl Store for svalin_store::server_store::TrustStoreTransactionStore {
     ) -> anyhow::Result<Vec<UncheckedBlock<trust_store::Transaction>>> {
         Ok(self.load_all_after(after).await?.0)
     }
+
+    async fn latest_checkpoint(
+        &self,
+    ) -> anyhow::Result<Option<(Checkpoint, trust_store::Exported)>> {
+        Ok(self.latest_checkpoint().await?)
+    }
+
+    /// The server doesn't compare positions with other members.
+    async fn conflict_reports(&self) -> anyhow::Result<Vec<Checkpoint>> {
+        Ok(Vec::new())
+    }
+
+    async fn keep_leaf(
+        &self,
+        block: &UncheckedBlock<trust_store::Transaction>,
+    ) -> anyhow::Result<()> {
+        Ok(self.keep_leaf(block).await?)
+    }
 }
 
 pub async fn load_trust_store(
@@ -53,6 +104,26 @@ pub async fn load_trust_store(
         serde_json::from_slice(&exported).context("failed to deserialize trust store")?;
 
     let mut trust_store = TrustStore::import(exported).context("failed to import trust store")?;
+
+    if let Some(block) = trust_store.last_block() {
+        store
+            .keep_leaf(block)
+            .await
+            .context("failed to keep the leaf of the trust store snapshot")?;
+    }
+
+    // the transactions following the snapshot might have been replaced by a
+    // checkpoint
+    let checkpoint = store
+        .latest_checkpoint()
+        .await
+        .context("failed to load trust store checkpoint")?
+        .filter(|(checkpoint, _)| checkpoint.sequence() > trust_store.sequence());
+    if let Some((checkpoint, state)) = checkpoint {
+        trust_store = TrustStore::restore_checkpoint(state, &checkpoint, trust_store.root())
+            .context("failed to import trust store checkpoint")?;
+    }
+
     let transactions = store
         .load_all_after(trust_store.sequence())
         .await
@@ -63,6 +134,14 @@ pub async fn load_trust_store(
         trust_store.apply(block);
     }
 
+    let conflicts = store
+        .conflict_reports()
+        .await
+        .context("failed to load trust store conflicts")?;
+    for position in conflicts {
+        trust_store.report_conflict(position);
+    }
+
     let exported = trust_store.export();
     let exported = serde_json::to_vec_pretty(&exported)?;
     tokio::fs::write(&file_location, exported).await?;
@@ -102,10 +181,15 @@ pub async fn load_trust_store(
 /// This function uses the given connection to download updates for the Trust Store.
 /// It will return once all current updates have been downloaded and applied,
 /// but it will continue to download and apply updates in a background task.
+///
+/// New blocks are only applied once the witnesses required by the policy
+/// countersigned them.
 pub async fn update_trust_store(
     trust_store: Arc<RwLock<TrustStore>>,
     store: Arc<svalin_store::trust_store_transaction_store::TrustStoreTransactionStore>,
     connection: impl Connection + 'static,
+    upstream: UnverifiedCertificate,
+    witnesses: WitnessPolicy,
     cancel: CancellationToken,
     task_tracker: &TaskTracker,
 ) -> anyhow::Result<()> {
@@ -113,7 +197,14 @@ pub async fn update_trust_store(
 
     task_tracker.spawn(async move {
         if let Err(err) = connection
-            .dispatch(UpdateTrustStore::new(trust_store, store, send, cancel))
+            .dispatch(UpdateTrustStore::new(
+                trust_store,
+                store,
+                upstream,
+                witnesses,
+                send,
+                cancel,
+            ))
             .await
         {
             eprintln!("Error updating trust store: {}", err);
@@ -0,0 +1,150 @@
+use std::{collections::HashMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use svalin_pki::{
+    SpkiHash,
+    secure_chain::{Checkpoint, UncheckedBlock},
+    trust_store,
+};
+
+/// How often a witness checks whether the trust store head changed.
+pub const WITNESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Witnesses which have to countersign new trust store heads before a member
+/// accepts them.
+///
+/// Without witnesses a compromised server can still fork or freeze the trust
+/// store until members compare their positions. Requiring independent
+/// signatures limits that to servers which also control the witnesses.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WitnessPolicy {
+    /// Witnesses whose signatures are counted.
+    #[serde(default)]
+    pub witnesses: Vec<SpkiHash>,
+    /// Number of distinct witness signatures a new head needs, 0 disables
+    /// the check.
+    #[serde(default)]
+    pub required: usize,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WitnessPolicyError {
+    #[error(
+        "{required} witness signatures are required, but only {configured} witnesses are known"
+    )]
+    NotEnoughWitnesses { required: usize, configured: usize },
+}
+
+impl WitnessPolicy {
+    pub fn is_disabled(&self) -> bool {
+        self.required == 0
+    }
+
+    pub fn validate(&self) -> Result<(), WitnessPolicyError> {
+        let mut witnesses = self.witnesses.clone();
+        witnesses.sort();
+        witnesses.dedup();
+        if witnesses.len() < self.required {
+            return Err(WitnessPolicyError::NotEnoughWitnesses {
+                required: self.required,
+                configured: witnesses.len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Something received from the server which a witness signature can vouch
+/// for.
+pub trait Witnessed {
+    fn witnessed_by(&self, signature: &Checkpoint) -> bool;
+}
+
+impl Witnessed for UncheckedBlock<trust_store::Transaction> {
+    fn witnessed_by(&self, signature: &Checkpoint) -> bool {
+        signature.matches_block(self)
+    }
+}
+
+impl Witnessed for Checkpoint {
+    fn witnessed_by(&self, signature: &Checkpoint) -> bool {
+        signature.matches_position(self)
+    }
+}
+
+/// Holds back blocks and checkpoints received from the server until enough
+/// witnesses signed them or a later head.
+///
+/// Signatures have to be verified before they are added, the gate only counts
+/// them.
+pub struct WitnessGate<T = UncheckedBlock<trust_store::Transaction>> {
+    policy: WitnessPolicy,
+    pending: Vec<T>,
+    signatures: HashMap<SpkiHash, Checkpoint>,
+}
+
+impl<T: Witnessed> WitnessGate<T> {
+    pub fn new(policy: WitnessPolicy) -> Self {
+        Self {
+            policy,
+            pending: Vec::new(),
+            signatures: HashMap::new(),
+        }
+    }
+
+    /// Queues an update, returns the updates which may be applied now.
+    pub fn add(&mut self, update: T) -> Vec<T> {
+        self.pending.push(update);
+        self.release()
+    }
+
+    /// Records the latest signature of a witness, returns the updates which
+    /// may be applied now.
+    pub fn add_signature(&mut self, signature: Checkpoint) -> Vec<T> {
+        if !self.policy.witnesses.contains(signature.signer()) {
+            tracing::debug!(
+                "ignoring signature of unknown witness {}",
+                signature.signer()
+            );
+            return Vec::new();
+        }
+
+        let newer = self
+            .signatures
+            .get(signature.signer())
+            .is_none_or(|current| current.sequence() < signature.sequence());
+        if newer {
+            self.signatures
+                .insert(signature.signer().clone(), signature);
+        }
+
+        self.release()
+    }
+
+    /// Number of updates waiting for witness signatures.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn release(&mut self) -> Vec<T> {
+        if self.policy.is_disabled() {
+            return std::mem::take(&mut self.pending);
+        }
+
+        // signing a head also vouches for all updates before it, as applying
+        // the later blocks checks their resulting state
+        let witnessed = self.pending.iter().rposition(|update| {
+            let signatures = self
+                .signatures
+                .values()
+                .filter(|signature| update.witnessed_by(signature))
+                .count();
+            signatures >= self.policy.required
+        });
+
+        match witnessed {
+            Some(index) => self.pending.drain(..=index).collect(),
+            None => Vec::new(),
+        }
+    }
+}
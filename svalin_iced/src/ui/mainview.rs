@@ -1,22 +1,26 @@
-use std::{process, sync::Arc, time::Duration};
+use std::{collections::HashMap, process, sync::Arc, time::Duration};
 
 use iced::{
     Subscription, Task,
     task::sipper,
-    widget::{self},
+    widget::{self, column},
 };
 use svalin::client::{
     Client,
     state::{ClientState, ClientStateUpdate},
 };
-use svalin_pki::SpkiHash;
+use svalin_pki::{Certificate, CertificateType, SpkiHash};
 use tokio::sync::broadcast;
+use uuid::Uuid;
 
 use crate::ui::widgets::{error_display, loading};
 
 mod add_device;
+mod alert_notification;
+mod chain_conflict;
 mod device_list;
 mod device_view;
+mod expiry_warning;
 
 #[derive(Debug, Clone)]
 pub enum Message {
@@ -25,6 +29,8 @@ pub enum Message {
     UpdateState(ClientStateUpdate),
     OpenAddDevice,
     SelectDevice(SpkiHash),
+    AcknowledgeAlert(SpkiHash, Uuid),
+    ResetChainConflict,
     AddDevice(add_device::Message),
     DeviceView(device_view::Message),
 }
@@ -50,6 +56,7 @@ pub enum Context {
 pub struct MainView {
     screen: Screen,
     state: ClientState,
+    expiring: HashMap<CertificateType, Vec<Certificate>>,
     context: Context,
     client: Arc<Client>,
     error: Option<Arc<anyhow::Error>>,
@@ -63,6 +70,7 @@ impl MainView {
             Self {
                 screen: Screen::Loading("Loading devices...".into()),
                 state: ClientState::empty(),
+                expiring: HashMap::new(),
                 context: Context::None,
                 client,
                 error: None,
@@ -87,6 +95,7 @@ impl MainView {
             Message::InitState(state) => {
                 let (state, receiver) = Arc::into_inner(state).unwrap();
                 self.state = state;
+                self.expiring = self.client.expiring_certificates();
                 self.screen = Screen::DeviceList;
 
                 let (update_task, abort_handle) =
@@ -104,12 +113,37 @@ impl MainView {
             }
             Message::UpdateState(update) => {
                 self.state.update(update);
+                self.expiring = self.client.expiring_certificates();
                 Action::None
             }
             Message::SelectDevice(spki_hash) => {
                 self.screen = Screen::DeviceView(device_view::State::new(spki_hash));
                 Action::None
             }
+            Message::AcknowledgeAlert(spki_hash, alert) => {
+                let client = self.client.clone();
+                Action::Run(
+                    Task::future(async move {
+                        if let Err(err) = client.device(spki_hash).acknowledge_alert(alert).await {
+                            // TODO: Show error to user
+                            tracing::error!(?err, "Failed to acknowledge alert");
+                        }
+                    })
+                    .discard(),
+                )
+            }
+            Message::ResetChainConflict => {
+                let client = self.client.clone();
+                Action::Run(
+                    Task::future(async move {
+                        if let Err(err) = client.reset_chain_conflict().await {
+                            // TODO: Show error to user
+                            tracing::error!(?err, "Failed to reset trust store conflict");
+                        }
+                    })
+                    .discard(),
+                )
+            }
             Message::OpenAddDevice => {
                 let (add_device, task) = add_device::AddDevice::new();
                 self.screen = Screen::AddDevice(add_device);
@@ -157,10 +191,38 @@ impl MainView {
 
         match &self.screen {
             Screen::Loading(text) => loading(text).into(),
-            Screen::DeviceList => device_list::DeviceList::new(&self.state)
-                .on_new(Message::OpenAddDevice)
-                .on_select(Message::SelectDevice)
-                .into(),
+            Screen::DeviceList => {
+                let device_list = device_list::DeviceList::new(&self.state)
+                    .on_new(Message::OpenAddDevice)
+                    .on_select(Message::SelectDevice);
+
+                let mut warnings: Vec<crate::Element<'_, Message>> = Vec::new();
+                if let Some(peer) = self.state.chain_conflict() {
+                    warnings.push(
+                        chain_conflict::ChainConflictAlert::new(peer, &self.state)
+                            .on_reset(Message::ResetChainConflict)
+                            .into(),
+                    );
+                }
+                if !self.state.pending_alerts().is_empty() {
+                    warnings.push(
+                        alert_notification::AlertNotification::new(&self.state)
+                            .on_acknowledge(Message::AcknowledgeAlert)
+                            .into(),
+                    );
+                }
+                if !self.expiring.is_empty() {
+                    warnings.push(
+                        expiry_warning::ExpiryWarning::new(&self.expiring, &self.state).into(),
+                    );
+                }
+
+                if warnings.is_empty() {
+                    device_list.into()
+                } else {
+                    column(warnings).push(device_list).spacing(10).into()
+                }
+            }
             Screen::AddDevice(add_device) => add_device.view().map(Message::AddDevice),
             Screen::DeviceView(device_view) => {
                 device_view.view(&self.state).map(Message::DeviceView)
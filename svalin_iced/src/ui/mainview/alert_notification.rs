@@ -0,0 +1,123 @@
+use chrono::DateTime;
+use iced::{
+    Alignment::Center,
+    widget::{button, column, row, space, text},
+};
+use svalin::client::state::ClientState;
+use svalin_pki::SpkiHash;
+use svalin_store::client_store::persistent::{AlertKind, AlertSeverity, AlertState};
+use uuid::Uuid;
+
+use crate::{Element, bootstrap, ui::widgets::card};
+
+/// Shows the alerts of all devices which no session acknowledged yet.
+pub struct AlertNotification<'a, Message> {
+    state: &'a ClientState,
+    on_acknowledge: Option<Box<dyn Fn(SpkiHash, Uuid) -> Message + 'a>>,
+}
+
+impl<'a, Message> AlertNotification<'a, Message> {
+    pub fn new(state: &'a ClientState) -> Self {
+        Self {
+            state,
+            on_acknowledge: None,
+        }
+    }
+
+    pub fn on_acknowledge(
+        mut self,
+        on_acknowledge: impl Fn(SpkiHash, Uuid) -> Message + 'a,
+    ) -> Self {
+        self.on_acknowledge = Some(Box::new(on_acknowledge));
+        self
+    }
+
+    fn device_name(&self, alert: &AlertState) -> String {
+        self.state
+            .persistent()
+            .get(&alert.device)
+            .map(|device| device.name().into_owned())
+            .unwrap_or_else(|| alert.device.to_string())
+    }
+}
+
+impl<'a, Message: Clone + 'a> From<AlertNotification<'a, Message>> for Element<'a, Message> {
+    fn from(notification: AlertNotification<'a, Message>) -> Self {
+        let notification = &notification;
+
+        let lines = notification
+            .state
+            .pending_alerts()
+            .into_iter()
+            .map(|alert| {
+                let icon = match alert.alert.severity {
+                    AlertSeverity::Warning => bootstrap::exclamation_triangle(),
+                    AlertSeverity::Critical => bootstrap::exclamation_octagon(),
+                };
+                let raised_at = DateTime::from_timestamp_secs(alert.alert.raised_at as i64)
+                    .map(|datetime| datetime.naive_local().format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_default();
+
+                let line: Element<'a, Message> = row![
+                    icon,
+                    text(notification.device_name(alert)),
+                    text(description(alert)),
+                    space::horizontal(),
+                    text(raised_at),
+                    button(
+                        row![bootstrap::check(), text(t!("alerts.acknowledge"))]
+                            .align_y(Center)
+                            .spacing(10)
+                    )
+                    .on_press_maybe(
+                        notification
+                            .on_acknowledge
+                            .as_ref()
+                            .map(|f| f(alert.device.clone(), alert.alert.id))
+                    ),
+                ]
+                .align_y(Center)
+                .spacing(20)
+                .into();
+                line
+            })
+            .collect::<Vec<_>>();
+
+        card(column(lines).spacing(10))
+            .title(
+                row![bootstrap::bell(), text(t!("alerts.title"))]
+                    .align_y(Center)
+                    .spacing(10),
+            )
+            .into()
+    }
+}
+
+fn description(alert: &AlertState) -> String {
+    match &alert.alert.kind {
+        AlertKind::DiskAlmostFull {
+            mount_point,
+            available_space,
+            total_space,
+        } => {
+            let percent = *available_space as f64 / *total_space as f64 * 100.0;
+            t!(
+                "alerts.disk-almost-full",
+                "mount_point" => mount_point.as_str(),
+                "percent" => format!("{percent:.1}")
+            )
+        }
+        AlertKind::ServiceDown { service } => {
+            t!("alerts.service-down", "service" => service.as_str())
+        }
+        AlertKind::HighLoad {
+            load_average,
+            threads,
+        } => t!(
+            "alerts.high-load",
+            "load" => format!("{load_average:.2}"),
+            "threads" => threads.to_string()
+        ),
+    }
+    .into()
+}
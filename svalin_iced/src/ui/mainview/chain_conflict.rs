@@ -0,0 +1,61 @@
+use iced::{
+    Alignment::Center,
+    widget::{button, column, row, text},
+};
+use svalin::client::state::ClientState;
+use svalin_pki::SpkiHash;
+
+use crate::{Element, bootstrap, ui::widgets::card};
+
+/// Alerts the user that another member was shown a different trust store
+/// history, so the server can no longer be trusted.
+pub struct ChainConflictAlert<'a, Message> {
+    peer: &'a SpkiHash,
+    state: &'a ClientState,
+    on_reset: Option<Message>,
+}
+
+impl<'a, Message> ChainConflictAlert<'a, Message> {
+    pub fn new(peer: &'a SpkiHash, state: &'a ClientState) -> Self {
+        Self {
+            peer,
+            state,
+            on_reset: None,
+        }
+    }
+
+    /// Lets an admin reset the conflict once the server was investigated.
+    pub fn on_reset(mut self, on_reset: Message) -> Self {
+        self.on_reset = Some(on_reset);
+        self
+    }
+}
+
+impl<'a, Message: Clone + 'a> From<ChainConflictAlert<'a, Message>> for Element<'a, Message> {
+    fn from(alert: ChainConflictAlert<'a, Message>) -> Self {
+        let peer = alert
+            .state
+            .persistent()
+            .get(alert.peer)
+            .map(|device| device.name().into_owned())
+            .unwrap_or_else(|| alert.peer.to_string());
+
+        card(
+            column![
+                text(t!("chain-conflict.description", "peer" => peer)),
+                text(t!("chain-conflict.consequence")),
+                button(text(t!("chain-conflict.reset"))).on_press_maybe(alert.on_reset),
+            ]
+            .spacing(10),
+        )
+        .title(
+            row![
+                bootstrap::exclamation_triangle(),
+                text(t!("chain-conflict.title"))
+            ]
+            .align_y(Center)
+            .spacing(10),
+        )
+        .into()
+    }
+}
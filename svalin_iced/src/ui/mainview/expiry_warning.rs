@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use chrono::DateTime;
+use iced::{
+    Alignment::Center,
+    widget::{column, row, space, text},
+};
+use svalin::client::state::ClientState;
+use svalin_pki::{Certificate, CertificateType, get_current_timestamp};
+
+use crate::{Element, bootstrap, ui::widgets::card};
+
+/// Lists the certificates of the trust store which expired or expire soon.
+pub struct ExpiryWarning<'a> {
+    expiring: &'a HashMap<CertificateType, Vec<Certificate>>,
+    state: &'a ClientState,
+}
+
+impl<'a> ExpiryWarning<'a> {
+    pub fn new(
+        expiring: &'a HashMap<CertificateType, Vec<Certificate>>,
+        state: &'a ClientState,
+    ) -> Self {
+        Self { expiring, state }
+    }
+
+    fn type_name(certificate_type: CertificateType) -> String {
+        match certificate_type {
+            CertificateType::Root => t!("expiry.type.root"),
+            CertificateType::User => t!("expiry.type.user"),
+            CertificateType::UserSession => t!("expiry.type.session"),
+            CertificateType::Agent => t!("expiry.type.agent"),
+            CertificateType::Server => t!("expiry.type.server"),
+            CertificateType::Temporary => t!("expiry.type.temporary"),
+        }
+        .into()
+    }
+
+    fn certificate_name(&self, certificate: &Certificate) -> String {
+        self.state
+            .persistent()
+            .get(certificate.spki_hash())
+            .map(|device| device.name().into_owned())
+            .unwrap_or_else(|| certificate.spki_hash().to_string())
+    }
+}
+
+impl<'a, Message: 'a> From<ExpiryWarning<'a>> for Element<'a, Message> {
+    fn from(warning: ExpiryWarning<'a>) -> Self {
+        let now = get_current_timestamp();
+        let warning = &warning;
+
+        let mut types: Vec<_> = warning.expiring.iter().collect();
+        types.sort_by_key(|(_, certificates)| certificates.first().map(|c| c.not_after()));
+
+        let lines = types
+            .into_iter()
+            .flat_map(|(certificate_type, certificates)| {
+                let type_name = ExpiryWarning::type_name(*certificate_type);
+                certificates.iter().map(move |certificate| {
+                    let date = DateTime::from_timestamp_secs(certificate.not_after() as i64)
+                        .map(|datetime| datetime.naive_local().format("%Y-%m-%d").to_string())
+                        .unwrap_or_default();
+                    let status = if certificate.not_after() < now {
+                        t!("expiry.expired", "date" => date)
+                    } else {
+                        t!("expiry.expires", "date" => date)
+                    };
+
+                    let line: Element<'a, Message> = row![
+                        text(type_name.clone()),
+                        text(warning.certificate_name(certificate)),
+                        space::horizontal(),
+                        text(status),
+                    ]
+                    .spacing(20)
+                    .into();
+                    line
+                })
+            });
+
+        card(column(lines).spacing(10))
+            .title(
+                row![bootstrap::exclamation_triangle(), text(t!("expiry.title"))]
+                    .align_y(Center)
+                    .spacing(10),
+            )
+            .into()
+    }
+}
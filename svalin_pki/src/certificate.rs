@@ -16,7 +16,7 @@ use x509_parser::error::X509Error;
 use x509_parser::prelude::Validity;
 use x509_parser::{certificate::X509Certificate, oid_registry::asn1_rs::FromDer};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum CertificateType {
     Root,
     Temporary,
@@ -238,6 +238,18 @@ impl SpkiHash {
     }
 }
 
+#[derive(Debug, Error)]
+#[error("invalid spki hash, expected 64 hex characters")]
+pub struct ParseSpkiHashError;
+
+impl FromStr for SpkiHash {
+    type Err = ParseSpkiHashError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Self::from_hex(s.as_bytes()).map_err(|_| ParseSpkiHashError)
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum UseAsRootError {
     #[error("certificate is not a root certificate")]
@@ -425,6 +437,18 @@ impl CertificateData {
 
         Ok(())
     }
+
+    /// Unix timestamp after which the certificate is expired.
+    pub fn not_after(&self) -> u64 {
+        self.validity.not_after.timestamp().try_into().unwrap()
+    }
+
+    /// Unix timestamp from which on the holder should request a renewed
+    /// certificate. This is the case for the last quarter of its lifetime.
+    pub fn renewal_due_at(&self) -> u64 {
+        let lifetime = self.certificate_type.validity_duration().whole_seconds() as u64;
+        self.not_after().saturating_sub(lifetime / 4)
+    }
 }
 
 impl Certificate {
@@ -11,6 +11,7 @@ use crate::{
     certificate::{CertificateType, UnverifiedCertificate},
     encrypt::{EncryptedObject, EncryptionKey},
     keypair::{DecodeKeypairError, ExportedPublicKey, SavedKeypair},
+    renewal::RenewalRequest,
 };
 
 #[derive(Debug)]
@@ -70,6 +71,8 @@ pub enum CreateCredentialsError {
     CreateCertificateError(#[from] CreateCertificateError),
     #[error("the given certificates spki hash does not match the keypair")]
     KeyMismatch,
+    #[error("the renewed certificate has a different type")]
+    CertificateTypeMismatch,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -263,6 +266,22 @@ impl Credential {
             .mark_as_trusted())
     }
 
+    /// Issues the certificate requested by a [`RenewalRequest`]. It has the
+    /// same type as the certificate being renewed.
+    ///
+    /// The request must be verified by the caller.
+    pub fn create_renewed_certificate(
+        &self,
+        request: &RenewalRequest,
+    ) -> Result<Certificate, CreateCertificateError> {
+        Ok(self
+            .create_certificate_for_key(
+                request.public_key(),
+                request.certificate().certificate_type(),
+            )?
+            .mark_as_trusted())
+    }
+
     pub fn create_user_device_credential(&self) -> Result<Self, CreateCredentialsError> {
         let keypair = KeyPair::generate();
 
@@ -287,6 +306,18 @@ impl Credential {
         Ok(on_disk)
     }
 
+    /// Returns a credential using the renewed certificate for the same key.
+    pub fn with_renewed_certificate(
+        &self,
+        certificate: UnverifiedCertificate,
+    ) -> Result<Self, CreateCredentialsError> {
+        if certificate.certificate_type() != self.data.certificate.certificate_type() {
+            return Err(CreateCredentialsError::CertificateTypeMismatch);
+        }
+
+        Self::new(self.data.keypair.clone(), certificate)
+    }
+
     pub fn certificate(&self) -> &Certificate {
         &self.data.certificate
     }
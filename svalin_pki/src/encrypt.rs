@@ -92,6 +92,26 @@ where
         let encoded = self.ciphertext.decrypt(encryption_key)?;
         Ok(postcard::from_bytes(&encoded)?)
     }
+
+    /// Decrypts an object which may still be encrypted in an earlier layout.
+    /// Postcard can't tell the layouts apart, so the current one is tried
+    /// first.
+    pub fn decrypt_with_legacy<Legacy>(
+        self,
+        encryption_key: &EncryptionKey,
+    ) -> Result<T, DecryptError>
+    where
+        Legacy: DeserializeOwned + Into<T>,
+    {
+        let encoded = self.ciphertext.decrypt(encryption_key)?;
+        match postcard::from_bytes(&encoded) {
+            Ok(object) => Ok(object),
+            Err(err) => match postcard::from_bytes::<Legacy>(&encoded) {
+                Ok(legacy) => Ok(legacy.into()),
+                Err(_) => Err(err.into()),
+            },
+        }
+    }
 }
 
 pub struct NonceCounter {
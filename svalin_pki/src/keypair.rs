@@ -1,7 +1,7 @@
 use std::fmt::Debug;
 
 use crate::{
-    CreateCredentialsError, Credential, EncryptError, UnverifiedCertificate,
+    CreateCredentialsError, Credential, EncryptError, SpkiHash, UnverifiedCertificate,
     encrypt::{EncryptedObject, EncryptionKey},
 };
 use anyhow::Result;
@@ -34,6 +34,18 @@ impl Debug for KeyPair {
     }
 }
 
+impl Clone for KeyPair {
+    fn clone(&self) -> Self {
+        let keypair = self.rcgen_clone();
+        let sign_keypair = Ed25519KeyPair::from_pkcs8(keypair.serialized_der()).unwrap();
+
+        Self {
+            keypair,
+            sign_keypair,
+        }
+    }
+}
+
 impl KeyPair {
     pub fn generate() -> Self {
         let keypair = rcgen::KeyPair::generate_for(&rcgen::PKCS_ED25519).unwrap();
@@ -52,6 +64,10 @@ impl KeyPair {
         }
     }
 
+    pub fn spki_hash(&self) -> SpkiHash {
+        UnverifiedCertificate::compute_spki_hash(&self.keypair.subject_public_key_info())
+    }
+
     pub fn upgrade(
         self,
         certificate: UnverifiedCertificate,
@@ -59,6 +75,12 @@ impl KeyPair {
         Credential::new(self, certificate)
     }
 
+    /// Encrypts the keypair, e.g. to keep a new key until its certificate
+    /// was issued.
+    pub fn export(&self, encryption_key: &EncryptionKey) -> Result<EncryptedKeyPair, EncryptError> {
+        Ok(EncryptedKeyPair(self.encrypt(encryption_key)?))
+    }
+
     pub(crate) fn encrypt(
         &self,
         encryption_key: &EncryptionKey,
@@ -115,6 +137,16 @@ impl KeyPair {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(transparent)]
+pub struct EncryptedKeyPair(EncryptedObject<SavedKeypair>);
+
+impl EncryptedKeyPair {
+    pub fn decrypt(self, encryption_key: &EncryptionKey) -> Result<KeyPair, DecodeKeypairError> {
+        KeyPair::decrypt(self.0, encryption_key)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Default, Clone, Zeroize, PartialEq)]
 enum Algorithm {
     #[default]
@@ -143,12 +175,22 @@ pub(crate) struct SavedKeypair {
     alg: Algorithm,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct ExportedPublicKey {
     der: Vec<u8>,
     alg: Algorithm,
 }
 
+impl ExportedPublicKey {
+    /// The public key contained in the given certificate.
+    pub fn from_certificate(certificate: &UnverifiedCertificate) -> Self {
+        Self {
+            der: certificate.public_key().to_vec(),
+            alg: Algorithm::PkcsEd25519,
+        }
+    }
+}
+
 impl PublicKeyData for ExportedPublicKey {
     fn der_bytes(&self) -> &[u8] {
         &self.der
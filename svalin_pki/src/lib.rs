@@ -6,6 +6,7 @@ mod credential;
 mod encrypt;
 mod keypair;
 pub mod mls;
+mod renewal;
 pub mod secure_chain;
 pub mod serde_paramsstring;
 pub mod serde_saltstring;
@@ -22,7 +23,7 @@ pub use sha2::Sha512;
 pub use argon::{ArgonCost, ArgonParams, DeriveKeyError, ParamsStringParseError, PasswordHash};
 pub use argon2;
 pub use certificate::{
-    Certificate, CertificateParseError, CertificateType, RootCertificate,
+    Certificate, CertificateParseError, CertificateType, ParseSpkiHashError, RootCertificate,
     SignatureVerificationError, SpkiHash, UnverifiedCertificate, UseAsRootError, ValidityError,
 };
 pub use certificate_chain::{
@@ -34,7 +35,8 @@ pub use credential::{
     EncryptedCredential,
 };
 pub use encrypt::{DecryptError, EncryptError, EncryptedData, EncryptedObject, EncryptionKey};
-pub use keypair::{ExportedPublicKey, KeyPair};
+pub use keypair::{DecodeKeypairError, EncryptedKeyPair, ExportedPublicKey, KeyPair};
+pub use renewal::{RenewalRequest, RenewalRequestError};
 // pub use signed_object::{SignedObject, VerifiedObject};
 // pub use signed_object::{SignedObject, VerifiedObject};
 pub use verifier::{
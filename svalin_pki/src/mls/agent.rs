@@ -1,15 +1,15 @@
-use std::marker::PhantomData;
+use std::{collections::HashSet, marker::PhantomData};
 
-use anyhow::anyhow;
+use anyhow::{Context, anyhow};
 use openmls::{
     error::LibraryError,
-    prelude::{PublicMessageIn, tls_codec},
+    prelude::{ProtocolMessage, PublicMessageIn, Welcome, tls_codec},
 };
 use openmls_sqlx_storage::SqliteStorageProvider;
 use tokio::task::JoinError;
 
 use crate::{
-    Certificate, CertificateType, Credential, VerifyError,
+    Certificate, CertificateType, Credential, SpkiHash, VerifyError, get_current_timestamp,
     mls::{
         SvalinGroupId,
         group_id::ParseGroupIdError,
@@ -21,10 +21,11 @@ use crate::{
         },
         provider::{PostcardCodec, SvalinProvider},
         transport_types::{
-            MessageToMember, MessageToMemberTransport, MessageToServerTransport, MessageTypes,
-            SvalinMessage,
+            MessagePayload, MessageToMember, MessageToMemberTransport, MessageToServerTransport,
+            MessageTypes, SvalinMessage,
         },
     },
+    secure_chain::Checkpoint,
 };
 
 pub struct MlsAgent<Types: MessageTypes, KeyRetriever, Verifier> {
@@ -34,6 +35,16 @@ pub struct MlsAgent<Types: MessageTypes, KeyRetriever, Verifier> {
     _types: PhantomData<Types>,
 }
 
+/// Content of a message received by an agent.
+pub enum AgentMessageContent<Types: MessageTypes> {
+    /// An instruction from a user in the device group or from root in the
+    /// global group, with the sender.
+    Instruction(SpkiHash, Types::Instruction),
+    /// A payload type the agent doesn't handle itself, with its sender.
+    Other(SpkiHash, SvalinMessage),
+    Internal,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum MlsAgentCreateError {
     #[error("given certificate is not an agent: {0:?}")]
@@ -79,20 +90,96 @@ where
         self.harness.processor().create_key_package().await
     }
 
+    /// Creates a key package the server keeps handing out once all regular
+    /// key packages are used up.
+    pub async fn create_last_resort_key_package(
+        &self,
+    ) -> Result<KeyPackage, CreateKeyPackageError> {
+        self.harness
+            .processor()
+            .create_last_resort_key_package()
+            .await
+    }
+
     pub async fn handle_message(
         &self,
         message: &MessageToMemberTransport,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<AgentMessageContent<Types>, anyhow::Error> {
         let message = message.unpack()?;
 
         match message {
-            MessageToMember::Welcome(_welcome) => {
-                todo!("Don't have a use case for an agent joining another group yet")
+            MessageToMember::Welcome(welcome) => {
+                self.handle_welcome(welcome)
+                    .await
+                    .map_err(|err| anyhow!("{err}"))
+                    .context("handle welcome error")?;
+                Ok(AgentMessageContent::Internal)
             }
-            MessageToMember::GroupMessage(_private_message_in) => {
-                todo!("There aren't any reasons for an agent to receive a message yet")
+            MessageToMember::GroupMessage(message) => self.handle_group_message(message).await,
+            MessageToMember::AddToGroup(message) => {
+                self.handle_add_to_group(message).await?;
+                Ok(AgentMessageContent::Internal)
+            }
+        }
+    }
+
+    async fn handle_group_message(
+        &self,
+        message: ProtocolMessage,
+    ) -> Result<AgentMessageContent<Types>, anyhow::Error> {
+        let processed = self
+            .harness
+            .processor()
+            .process_message(message)
+            .await
+            .context("error processing group message")?;
+        let group_id = processed.group_id()?;
+
+        if group_id != self.my_device_group && group_id != SvalinGroupId::GlobalGroup {
+            anyhow::bail!("received message for unexpected group: {:?}", group_id)
+        }
+
+        let decrypted = match processed.content {
+            ProcessedContent::Message(decrypted) => decrypted,
+            ProcessedContent::Commit(commit) => {
+                if commit.add_proposals().count() != 0 {
+                    anyhow::bail!("members can only be added with a welcome")
+                }
+                self.harness.check_commit(&group_id, &commit).await?;
+                self.harness.processor().commit(commit).await?;
+                return Ok(AgentMessageContent::Internal);
+            }
+            ProcessedContent::OwnCommit => return Ok(AgentMessageContent::Internal),
+        };
+
+        let decoded = SvalinMessage::decode(&decrypted).context("error decoding message")?;
+
+        if !decoded.is::<Types::Instruction>() {
+            return Ok(AgentMessageContent::Other(processed.sender, decoded));
+        }
+        let instruction = decoded.payload::<Types::Instruction>()?;
+
+        let sender = self
+            .harness
+            .verifier()
+            .verify_spki_hash(&processed.sender, get_current_timestamp())
+            .await?;
+        match (&group_id, sender.certificate_type()) {
+            (SvalinGroupId::GlobalGroup, CertificateType::Root) => Ok(
+                AgentMessageContent::Instruction(processed.sender, instruction),
+            ),
+            (SvalinGroupId::GlobalGroup, certificate_type) => {
+                anyhow::bail!("{certificate_type} certificates can't broadcast instructions")
+            }
+            (_, CertificateType::Root | CertificateType::User | CertificateType::UserSession) => {
+                Ok(AgentMessageContent::Instruction(
+                    processed.sender,
+                    instruction,
+                ))
+            }
+            (_, certificate_type) => {
+                anyhow::bail!("{certificate_type} certificates can't send instructions")
             }
-            MessageToMember::AddToGroup(message) => self.handle_add_to_group(message).await,
         }
     }
 
@@ -105,11 +192,13 @@ where
             .map_err(|err| anyhow!(err))?;
         let group_id = processed.group_id()?;
 
-        let ProcessedContent::Commit(commit) = processed.content else {
-            anyhow::bail!("Expected a commit message, got {:?}", processed.content)
+        let commit = match processed.content {
+            ProcessedContent::Commit(commit) => commit,
+            ProcessedContent::OwnCommit => return Ok(()),
+            content => anyhow::bail!("Expected a commit message, got {content:?}"),
         };
 
-        if group_id != self.my_device_group {
+        if group_id != self.my_device_group && group_id != SvalinGroupId::GlobalGroup {
             anyhow::bail!("received message for unexpected group: {:?}", group_id)
         }
 
@@ -120,58 +209,108 @@ where
         Ok(())
     }
 
-    // async fn handle_welcome(
-    //     &self,
-    //     welcome: Welcome,
-    // ) -> Result<(), HandleWelcomeError<KeyRetriever::Error>> {
-    //     let staged = self.harness.processor().stage_join(welcome).await?;
-    //     let id = SvalinGroupId::from_group_id(staged.group_context().group_id())?;
-
-    //     match &id {
-    //         SvalinGroupId::DeviceGroup(device) => {
-    //             if device != self.me.spki_hash() {
-    //                 return Err(HandleWelcomeError::UnwantedGroup);
-    //             }
-    //         }
-    //     }
-
-    //     let required_members = self
-    //         .harness.key_retriever()    //         .get_required_group_members(&id)
-    //         .await
-    //         .map_err(HandleWelcomeError::RetrieverError)?
-    //         .into_iter()
-    //         .collect::<HashSet<_>>();
-
-    //     let members = staged
-    //         .members()
-    //         .map(|m| m.credential.deserialized())
-    //         .collect::<Result<HashSet<SpkiHash>, tls_codec::Error>>()?;
-
-    //     if members != required_members {
-    //         return Err(HandleWelcomeError::IncorrectMembers);
-    //     }
-
-    //     self.harness.processor().join_group(staged).await?;
-
-    //     Ok(())
-    // }
+    /// Joins the global group. The device group is created by the agent
+    /// itself, so it's the only group an agent joins.
+    async fn handle_welcome(
+        &self,
+        welcome: Welcome,
+    ) -> Result<(), HandleWelcomeError<KeyRetriever::Error>> {
+        let staged = self.harness.processor().stage_join(welcome).await?;
+        let id = SvalinGroupId::from_group_id(staged.group_context().group_id())?;
+
+        if id != SvalinGroupId::GlobalGroup {
+            return Err(HandleWelcomeError::UnwantedGroup);
+        }
+
+        let required_members = self
+            .harness
+            .key_retriever()
+            .get_required_group_members(&id)
+            .await
+            .map_err(HandleWelcomeError::RetrieverError)?;
+
+        let members = staged
+            .members()
+            .map(|m| m.credential.deserialized())
+            .collect::<Result<HashSet<SpkiHash>, tls_codec::Error>>()?;
+
+        for required in required_members {
+            if !members.contains(&required) {
+                return Err(HandleWelcomeError::IncorrectMembers);
+            }
+        }
+
+        self.harness.processor().join_group(staged).await?;
+
+        tracing::trace!("joined group {id:?}");
+
+        Ok(())
+    }
+
+    /// Whether this agent was already added to the global group.
+    pub async fn joined_global_group(&self) -> Result<bool, GroupExistsError> {
+        self.harness
+            .processor()
+            .group_exists(SvalinGroupId::GlobalGroup.to_group_id())
+            .await
+    }
 
     pub async fn send_report(
         &self,
         report: Types::Report,
     ) -> Result<MessageToServerTransport, SendDeviceMessageError> {
-        let group_id = SvalinGroupId::DeviceGroup(self.me.spki_hash().clone()).to_group_id();
-        let message = SvalinMessage::<Types>::Report(report);
-        let encoded = postcard::to_stdvec(&message)?;
+        self.send_message(&report).await
+    }
+
+    /// Encrypts a message of any payload type for the device group.
+    pub async fn send_message<P: MessagePayload>(
+        &self,
+        payload: &P,
+    ) -> Result<MessageToServerTransport, SendDeviceMessageError> {
+        let encoded = SvalinMessage::new(payload)?.encode()?;
         let to_server = self
             .harness
             .processor()
-            .create_message(group_id, encoded)
+            .create_message(self.my_device_group.to_group_id(), encoded)
             .await?;
 
         Ok(to_server)
     }
 
+    /// Sends the signed trust store position of this agent to the users of its
+    /// device group, so they can compare it with their own.
+    pub async fn send_chain_position(
+        &self,
+        position: Checkpoint,
+    ) -> Result<MessageToServerTransport, SendDeviceMessageError> {
+        self.send_message(&position).await
+    }
+
+    /// Commits a new key for this agent in its device group, so a leaked
+    /// epoch secret only reveals the reports sent until then.
+    pub async fn update_key(&self) -> Result<Option<MessageToServerTransport>, anyhow::Error> {
+        self.harness.update_own_key(&self.my_device_group).await
+    }
+
+    /// Drops the pending commit of this agent after the server refused it,
+    /// so the next one is created on top of the current epoch.
+    pub async fn clear_pending_commit(&self) -> Result<(), anyhow::Error> {
+        self.harness
+            .processor()
+            .clear_pending_commit(self.my_device_group.to_group_id())
+            .await
+    }
+
+    /// Removes users and sessions whose certificates were dropped from the
+    /// trust store from the device group.
+    pub async fn remove_revoked_members(
+        &self,
+    ) -> Result<Option<MessageToServerTransport>, anyhow::Error> {
+        self.harness
+            .remove_revoked_members(&self.my_device_group, self.me.spki_hash())
+            .await
+    }
+
     pub async fn create_device_group_if_missing(
         &self,
     ) -> Result<Option<MessageToServerTransport>, CreateSvalinGroupError<KeyRetriever::Error>> {
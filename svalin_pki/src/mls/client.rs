@@ -18,10 +18,11 @@ use crate::{
         },
         provider::SvalinStorage,
         transport_types::{
-            MessageToMember, MessageToMemberTransport, MessageToServerTransport, MessageTypes,
-            SvalinMessage,
+            MessagePayload, MessageToMember, MessageToMemberTransport, MessageToServerTransport,
+            MessageTypes, SvalinMessage,
         },
     },
+    secure_chain::Checkpoint,
 };
 
 pub struct MlsClient<Types: MessageTypes, KeyRetriever, Verifier> {
@@ -38,6 +39,11 @@ pub struct MessageData<Types: MessageTypes> {
 pub enum MessageDataContent<Types: MessageTypes> {
     Report(SpkiHash, Types::Report),
     MetaInfo(SpkiHash, Types::MetaInfo),
+    ChainPosition(SpkiHash, Checkpoint),
+    Instruction(SpkiHash, Types::Instruction),
+    /// A payload type this crate doesn't check, with its sender. Whoever
+    /// knows the type decides whether to trust it, everyone else stores it.
+    Other(SpkiHash, SvalinMessage),
     Internal,
 }
 
@@ -116,38 +122,38 @@ where
                 let group_id = SvalinGroupId::from_group_id(&processed.group_id)
                     .context("error parsing group id")?;
                 tracing::trace!("group id parsed successfully");
-                let ProcessedContent::Message(decrypted) = processed.content else {
-                    anyhow::bail!("expected data message, got something else instead.")
-                };
-                let decoded: SvalinMessage<Types> =
-                    postcard::from_bytes(&decrypted).context("postcard error")?;
-
-                match decoded {
-                    SvalinMessage::Report(report) => match group_id.clone() {
-                        SvalinGroupId::DeviceGroup(device) => {
-                            if device != processed.sender {
-                                anyhow::bail!(
-                                    "only the device itself can send reports to its group"
-                                )
-                            } else {
-                                Ok(MessageData {
-                                    group: group_id,
-                                    content: MessageDataContent::Report(device, report),
-                                })
-                            }
+                let decrypted = match processed.content {
+                    ProcessedContent::Message(decrypted) => decrypted,
+                    ProcessedContent::Commit(commit) => {
+                        if commit.add_proposals().count() != 0 {
+                            anyhow::bail!("members can only be added with a welcome")
                         }
-                        #[allow(unreachable_patterns)]
-                        _ => anyhow::bail!("unallowed message type"),
-                    },
-                    SvalinMessage::MetaInfo(meta_info) => match group_id.clone() {
-                        SvalinGroupId::DeviceMetaGroup(device) => Ok(MessageData {
+                        self.harness.check_commit(&group_id, &commit).await?;
+                        self.harness.processor().commit(commit).await?;
+
+                        return Ok(MessageData {
                             group: group_id,
-                            content: MessageDataContent::MetaInfo(device, meta_info),
-                        }),
-                        #[allow(unreachable_patterns)]
-                        _ => anyhow::bail!("unallowed message type"),
-                    },
-                }
+                            content: MessageDataContent::Internal,
+                        });
+                    }
+                    ProcessedContent::OwnCommit => {
+                        return Ok(MessageData {
+                            group: group_id,
+                            content: MessageDataContent::Internal,
+                        });
+                    }
+                };
+                let decoded =
+                    SvalinMessage::decode(&decrypted).context("error decoding message")?;
+
+                let content = self
+                    .check_content(&group_id, processed.sender, decoded)
+                    .await?;
+
+                Ok(MessageData {
+                    group: group_id,
+                    content,
+                })
             }
             MessageToMember::AddToGroup(message) => self
                 .handle_add_to_group(message)
@@ -156,6 +162,71 @@ where
         }
     }
 
+    /// Decodes the payload types which are checked by this crate, every
+    /// other type is passed on as is.
+    async fn check_content(
+        &self,
+        group_id: &SvalinGroupId,
+        sender: SpkiHash,
+        message: SvalinMessage,
+    ) -> anyhow::Result<MessageDataContent<Types>> {
+        if message.is::<Types::Report>() {
+            let report = message.payload::<Types::Report>()?;
+            match group_id {
+                SvalinGroupId::DeviceGroup(device) if device == &sender => {
+                    Ok(MessageDataContent::Report(sender, report))
+                }
+                SvalinGroupId::DeviceGroup(_) => {
+                    anyhow::bail!("only the device itself can send reports to its group")
+                }
+                _ => anyhow::bail!("unallowed message type"),
+            }
+        } else if message.is::<Types::MetaInfo>() {
+            let meta_info = message.payload::<Types::MetaInfo>()?;
+            match group_id {
+                SvalinGroupId::DeviceMetaGroup(device) => {
+                    Ok(MessageDataContent::MetaInfo(device.clone(), meta_info))
+                }
+                _ => anyhow::bail!("unallowed message type"),
+            }
+        } else if message.is::<Checkpoint>() {
+            let position = message.payload::<Checkpoint>()?;
+            if position.signer() != &sender {
+                anyhow::bail!("chain position was not signed by its sender")
+            }
+            Ok(MessageDataContent::ChainPosition(sender, position))
+        } else if message.is::<Types::Instruction>() {
+            let instruction = message.payload::<Types::Instruction>()?;
+            match group_id {
+                SvalinGroupId::DeviceGroup(device) => {
+                    if device == &sender {
+                        anyhow::bail!("devices can't send instructions to themselves")
+                    }
+                    Ok(MessageDataContent::Instruction(sender, instruction))
+                }
+                SvalinGroupId::GlobalGroup => {
+                    let certificate = self
+                        .harness
+                        .verifier()
+                        .verify_spki_hash(&sender, get_current_timestamp())
+                        .await?;
+                    if certificate.certificate_type() != CertificateType::Root {
+                        anyhow::bail!("only root can send to the global group")
+                    }
+                    Ok(MessageDataContent::Instruction(sender, instruction))
+                }
+                _ => anyhow::bail!("unallowed message type"),
+            }
+        } else {
+            tracing::debug!(
+                "passing on message of type {} in version {}",
+                message.tag(),
+                message.version()
+            );
+            Ok(MessageDataContent::Other(sender, message))
+        }
+    }
+
     async fn handle_welcome(
         &self,
         welcome: Welcome,
@@ -229,8 +300,15 @@ where
             .map_err(|err| anyhow!(err))?;
         let group_id = processed.group_id()?;
 
-        let ProcessedContent::Commit(commit) = processed.content else {
-            anyhow::bail!("Expected a commit message, got {:?}", processed.content)
+        let commit = match processed.content {
+            ProcessedContent::Commit(commit) => commit,
+            ProcessedContent::OwnCommit => {
+                return Ok(MessageData {
+                    group: group_id,
+                    content: MessageDataContent::Internal,
+                });
+            }
+            content => anyhow::bail!("Expected a commit message, got {content:?}"),
         };
 
         self.harness.check_commit(&group_id, &commit).await?;
@@ -247,6 +325,17 @@ where
         self.harness.processor().create_key_package().await
     }
 
+    /// Creates a key package the server keeps handing out once all regular
+    /// key packages are used up.
+    pub async fn create_last_resort_key_package(
+        &self,
+    ) -> Result<KeyPackage, CreateKeyPackageError> {
+        self.harness
+            .processor()
+            .create_last_resort_key_package()
+            .await
+    }
+
     pub async fn is_member(
         &self,
         group_id: &SvalinGroupId,
@@ -272,6 +361,53 @@ where
         Ok(message_to_server)
     }
 
+    /// Removes the members of a group whose certificates were dropped from
+    /// the trust store. Does nothing if this client isn't part of the group.
+    pub async fn remove_revoked_members(
+        &self,
+        group: &SvalinGroupId,
+    ) -> anyhow::Result<Option<MessageToServerTransport>> {
+        self.harness.remove_revoked_members(group, &self.me).await
+    }
+
+    /// Commits a new key for this client in the group, so a leaked epoch
+    /// secret only reveals the messages sent until then. Does nothing if this
+    /// client isn't part of the group.
+    pub async fn update_key(
+        &self,
+        group: &SvalinGroupId,
+    ) -> anyhow::Result<Option<MessageToServerTransport>> {
+        self.harness.update_own_key(group).await
+    }
+
+    /// Drops the groups of a device key which was replaced by a rotation.
+    ///
+    /// The previous key left the trust store, so its groups can't be
+    /// continued. The agent creates the device group of its new key with the
+    /// same required members, the meta group is created again by a user.
+    pub async fn forget_rotated_device(&self, previous: &SpkiHash) -> anyhow::Result<()> {
+        for group in [
+            SvalinGroupId::DeviceGroup(previous.clone()),
+            SvalinGroupId::DeviceMetaGroup(previous.clone()),
+        ] {
+            self.harness
+                .processor()
+                .delete_group(group.to_group_id())
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether this client is part of the group.
+    pub async fn group_exists(&self, group: &SvalinGroupId) -> anyhow::Result<bool> {
+        Ok(self
+            .harness
+            .processor()
+            .group_exists(group.to_group_id())
+            .await?)
+    }
+
     pub async fn create_meta_group_if_missing(
         &self,
         spki_hash: SpkiHash,
@@ -299,8 +435,150 @@ where
         metainfo: Types::MetaInfo,
     ) -> anyhow::Result<MessageToServerTransport> {
         let group_id = SvalinGroupId::DeviceMetaGroup(spki_hash).to_group_id();
-        let message = SvalinMessage::<Types>::MetaInfo(metainfo);
-        let encoded = postcard::to_stdvec(&message)?;
+        let encoded = SvalinMessage::new(&metainfo)?.encode()?;
+        let to_server = self
+            .harness
+            .processor()
+            .create_message(group_id, encoded)
+            .await?;
+
+        Ok(to_server)
+    }
+
+    /// Encrypts a message of any payload type for the given group.
+    pub async fn send_message<P: MessagePayload>(
+        &self,
+        group: &SvalinGroupId,
+        payload: &P,
+    ) -> anyhow::Result<MessageToServerTransport> {
+        let encoded = SvalinMessage::new(payload)?.encode()?;
+        let to_server = self
+            .harness
+            .processor()
+            .create_message(group.to_group_id(), encoded)
+            .await?;
+
+        Ok(to_server)
+    }
+
+    /// Creates the global group, which only root is allowed to do.
+    pub async fn create_global_group_if_missing(
+        &self,
+    ) -> anyhow::Result<Option<MessageToServerTransport>> {
+        let certificate = self
+            .harness
+            .verifier()
+            .verify_spki_hash(&self.me, get_current_timestamp())
+            .await?;
+        if certificate.certificate_type() != CertificateType::Root {
+            anyhow::bail!("only root can create the global group");
+        }
+
+        Ok(self
+            .harness
+            .create_group_if_not_exists(&SvalinGroupId::GlobalGroup, &self.me)
+            .await
+            .map_err(|err| anyhow!(err))?)
+    }
+
+    /// Brings the members of the global group in line with the given users
+    /// and agents with a single commit.
+    ///
+    /// Members which aren't listed anymore are removed first, missing ones are
+    /// added by the next sync once that commit was delivered. Members without
+    /// an available key package are skipped, they are added by a later sync.
+    pub async fn sync_global_group(
+        &self,
+        members: &[SpkiHash],
+    ) -> anyhow::Result<Option<MessageToServerTransport>> {
+        let group = SvalinGroupId::GlobalGroup;
+
+        let removed = self
+            .harness
+            .processor()
+            .members(group.to_group_id())
+            .await?
+            .into_iter()
+            .filter(|member| member != &self.me && !members.contains(member))
+            .collect::<Vec<_>>();
+        if !removed.is_empty() {
+            let message = self
+                .harness
+                .processor()
+                .remove_members(group.to_group_id(), removed)
+                .await?;
+
+            return Ok(Some(message));
+        }
+
+        let mut key_packages = Vec::new();
+
+        for member in members {
+            if member == &self.me || self.is_member(&group, member.clone()).await? {
+                continue;
+            }
+
+            let key_package = match self
+                .harness
+                .key_retriever()
+                .get_key_packages(std::slice::from_ref(member))
+                .await
+            {
+                Ok(mut key_packages) if !key_packages.is_empty() => key_packages.remove(0),
+                Ok(_) => {
+                    tracing::debug!("no key package available for {member}");
+                    continue;
+                }
+                Err(err) => {
+                    tracing::debug!("no key package available for {member}: {err}");
+                    continue;
+                }
+            };
+            let key_package = self.harness.verify_key_package(key_package).await?;
+            if key_package.spki_hash() != member {
+                anyhow::bail!("received key package for the wrong member");
+            }
+
+            key_packages.push(key_package);
+        }
+
+        if key_packages.is_empty() {
+            return Ok(None);
+        }
+
+        let message = self
+            .harness
+            .processor()
+            .add_members(group.to_group_id(), key_packages)
+            .await?;
+
+        Ok(Some(message))
+    }
+
+    /// Encrypts an instruction for all members of the global group.
+    pub async fn broadcast_instruction(
+        &self,
+        instruction: Types::Instruction,
+    ) -> anyhow::Result<MessageToServerTransport> {
+        let group_id = SvalinGroupId::GlobalGroup.to_group_id();
+        let encoded = SvalinMessage::new(&instruction)?.encode()?;
+        let to_server = self
+            .harness
+            .processor()
+            .create_message(group_id, encoded)
+            .await?;
+
+        Ok(to_server)
+    }
+
+    /// Encrypts an instruction for the device group of an agent.
+    pub async fn send_instruction(
+        &self,
+        device: SpkiHash,
+        instruction: Types::Instruction,
+    ) -> anyhow::Result<MessageToServerTransport> {
+        let group_id = SvalinGroupId::DeviceGroup(device).to_group_id();
+        let encoded = SvalinMessage::new(&instruction)?.encode()?;
         let to_server = self
             .harness
             .processor()
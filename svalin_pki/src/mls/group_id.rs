@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 use openmls::group::GroupId;
 
 use crate::SpkiHash;
@@ -59,6 +61,17 @@ impl SvalinGroupId {
     }
 }
 
+/// Formats the group like its MLS group id.
+impl Display for SvalinGroupId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SvalinGroupId::GlobalGroup => write!(f, "global"),
+            SvalinGroupId::DeviceGroup(spki_hash) => write!(f, "device/{}", spki_hash.to_hex()),
+            SvalinGroupId::DeviceMetaGroup(spki_hash) => write!(f, "meta/{}", spki_hash.to_hex()),
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ParseGroupIdError {
     #[error("wrong group id length")]
@@ -88,5 +101,6 @@ mod tests {
         let encoded = group_id.to_group_id();
         let decoded = SvalinGroupId::from_group_id(&encoded).unwrap();
         assert_eq!(group_id, decoded);
+        assert_eq!(group_id.to_string().as_bytes(), encoded.as_slice());
     }
 }
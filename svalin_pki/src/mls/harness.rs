@@ -102,13 +102,28 @@ where
                 .verifier()
                 .verify_spki_hash(&sender, get_current_timestamp())
                 .await?;
-            check_edit_allowed(group_id, sender.certificate_type())?;
-
             let index = proposal.remove_proposal().removed();
             let spki_hash = self
                 .processor
                 .get_member(group_id.to_group_id(), index)
                 .await?;
+
+            match group_id {
+                // agents remove revoked members from their own device group,
+                // but nobody who is still valid
+                SvalinGroupId::DeviceGroup(device) if device == sender.spki_hash() => {
+                    if self
+                        .verifier()
+                        .verify_spki_hash(&spki_hash, get_current_timestamp())
+                        .await
+                        .is_ok()
+                    {
+                        anyhow::bail!("Agents can only remove revoked members: {spki_hash:?}")
+                    }
+                }
+                _ => check_edit_allowed(group_id, sender.certificate_type())?,
+            }
+
             if required_members.contains(&spki_hash) {
                 anyhow::bail!("Cannot remove required member: {spki_hash:?}")
             }
@@ -133,10 +148,15 @@ where
             let key_package = key_package
                 .verify(&self.crypto, self.protocol_version, &self.verifier)
                 .await?;
-            match key_package.certificate().certificate_type() {
-                CertificateType::User => (),
-                CertificateType::UserSession => (),
-                certificate_type => {
+            match (group_id, key_package.certificate().certificate_type()) {
+                (SvalinGroupId::GlobalGroup, CertificateType::User) => (),
+                (SvalinGroupId::GlobalGroup, CertificateType::Agent) => (),
+                (SvalinGroupId::GlobalGroup, _) => {
+                    anyhow::bail!("only users and agents can join the global group")
+                }
+                (_, CertificateType::User) => (),
+                (_, CertificateType::UserSession) => (),
+                (_, certificate_type) => {
                     anyhow::bail!(
                         "Unexpected certificate type in key package: {certificate_type:?}"
                     )
@@ -186,6 +206,79 @@ where
         Ok(Some(new_group))
     }
 
+    /// Commits a new leaf key for this member, which moves the group to a new
+    /// epoch. Secrets of earlier epochs don't reveal messages sent afterwards.
+    pub(crate) async fn update_own_key(
+        &self,
+        group_id: &SvalinGroupId,
+    ) -> anyhow::Result<Option<MessageToServerTransport>> {
+        if !self
+            .processor()
+            .group_exists(group_id.to_group_id())
+            .await?
+        {
+            return Ok(None);
+        }
+
+        let message = self.processor().self_update(group_id.to_group_id()).await?;
+
+        Ok(Some(message))
+    }
+
+    /// Removes the members whose certificates can't be verified anymore,
+    /// because they were dropped from the trust store or expired.
+    pub(crate) async fn remove_revoked_members(
+        &self,
+        group_id: &SvalinGroupId,
+        me: &SpkiHash,
+    ) -> anyhow::Result<Option<MessageToServerTransport>> {
+        if !self
+            .processor()
+            .group_exists(group_id.to_group_id())
+            .await?
+        {
+            return Ok(None);
+        }
+
+        let mut revoked = Vec::new();
+        for member in self.processor().members(group_id.to_group_id()).await? {
+            if &member == me {
+                continue;
+            }
+            if let Err(err) = self
+                .verifier()
+                .verify_spki_hash(&member, get_current_timestamp())
+                .await
+            {
+                tracing::debug!("member {member} of group {group_id:?} is revoked: {err}");
+                revoked.push(member);
+            }
+        }
+
+        if revoked.is_empty() {
+            return Ok(None);
+        }
+
+        // required members can't be removed, even if they are revoked
+        let required_members = self
+            .key_retriever()
+            .get_required_group_members(group_id)
+            .await
+            .map_err(|err| anyhow!(err))?;
+        revoked.retain(|member| !required_members.contains(member));
+
+        if revoked.is_empty() {
+            return Ok(None);
+        }
+
+        let message = self
+            .processor()
+            .remove_members(group_id.to_group_id(), revoked)
+            .await?;
+
+        Ok(Some(message))
+    }
+
     async fn get_required_key_packages(
         &self,
         group_id: &SvalinGroupId,
@@ -233,6 +326,11 @@ fn check_edit_allowed(
 ) -> anyhow::Result<()> {
     match certificate_type {
         CertificateType::Root => Ok(()),
+        // the global group carries organization wide policy, so only root
+        // decides who receives it
+        _ if group_id == &SvalinGroupId::GlobalGroup => {
+            anyhow::bail!("Only root can edit the global group")
+        }
         CertificateType::User => Ok(()),
         CertificateType::UserSession => Ok(()),
         _ => anyhow::bail!(
@@ -116,6 +116,17 @@ impl KeyPackage {
         &self.certificate
     }
 
+    /// Unix timestamp after which the key package must not be used anymore.
+    pub fn not_after(&self) -> u64 {
+        self.key_package.life_time().not_after()
+    }
+
+    /// Last resort key packages may be used for several groups, so they are
+    /// kept when handed out.
+    pub fn is_last_resort(&self) -> bool {
+        self.key_package.last_resort()
+    }
+
     pub(crate) fn unpack(self) -> openmls::prelude::KeyPackage {
         self.key_package
     }
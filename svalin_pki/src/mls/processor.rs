@@ -21,8 +21,8 @@ use openmls::{
         StagedWelcome, WelcomeError,
     },
     prelude::{
-        CredentialWithKey, KeyPackageNewError, LeafNodeIndex, MlsMessageBodyOut, ProtocolMessage,
-        Sender, SenderRatchetConfiguration, Welcome, tls_codec,
+        ContentType, CredentialWithKey, KeyPackageNewError, LeafNodeIndex, MlsMessageBodyOut,
+        ProtocolMessage, Sender, SenderRatchetConfiguration, Welcome, tls_codec,
     },
 };
 use openmls_traits::OpenMlsProvider;
@@ -56,8 +56,11 @@ impl MlsProcessorHandle {
             let mut client = client;
             while let Some(request) = recv.blocking_recv() {
                 match request {
-                    MlsProcessorRequest::CreateKeyPackage { response } => {
-                        let result = client.create_key_package();
+                    MlsProcessorRequest::CreateKeyPackage {
+                        last_resort,
+                        response,
+                    } => {
+                        let result = client.create_key_package(last_resort);
                         let _ = response.send(result);
                     }
                     MlsProcessorRequest::CreateGroup {
@@ -108,18 +111,42 @@ impl MlsProcessorHandle {
                         let result = client.get_member(group_id, index);
                         let _ = response.send(result);
                     }
-                    MlsProcessorRequest::AddMember {
+                    MlsProcessorRequest::AddMembers {
                         group_id,
-                        key_package,
+                        key_packages,
                         response,
                     } => {
-                        let result = client.add_member(group_id, key_package);
+                        let result = client.add_members(group_id, key_packages);
+                        let _ = response.send(result);
+                    }
+                    MlsProcessorRequest::Members { group_id, response } => {
+                        let result = client.members(group_id);
+                        let _ = response.send(result);
+                    }
+                    MlsProcessorRequest::RemoveMembers {
+                        group_id,
+                        members,
+                        response,
+                    } => {
+                        let result = client.remove_members(group_id, members);
+                        let _ = response.send(result);
+                    }
+                    MlsProcessorRequest::SelfUpdate { group_id, response } => {
+                        let result = client.self_update(group_id);
                         let _ = response.send(result);
                     }
                     MlsProcessorRequest::Commit { commit, response } => {
                         let result = client.commit(commit);
                         let _ = response.send(result);
                     }
+                    MlsProcessorRequest::ClearPendingCommit { group_id, response } => {
+                        let result = client.clear_pending_commit(group_id);
+                        let _ = response.send(result);
+                    }
+                    MlsProcessorRequest::DeleteGroup { group_id, response } => {
+                        let result = client.delete_group(group_id);
+                        let _ = response.send(result);
+                    }
                 }
             }
         });
@@ -128,11 +155,29 @@ impl MlsProcessorHandle {
     }
 
     pub async fn create_key_package(&self) -> Result<KeyPackage, CreateKeyPackageError> {
+        self.request_key_package(false).await
+    }
+
+    /// Creates a key package which may be used for several groups, so the
+    /// server can hand it out once all regular packages are used up.
+    pub async fn create_last_resort_key_package(
+        &self,
+    ) -> Result<KeyPackage, CreateKeyPackageError> {
+        self.request_key_package(true).await
+    }
+
+    async fn request_key_package(
+        &self,
+        last_resort: bool,
+    ) -> Result<KeyPackage, CreateKeyPackageError> {
         let (send, recv) = oneshot::channel();
 
         let _ = self
             .channel
-            .send(MlsProcessorRequest::CreateKeyPackage { response: send })
+            .send(MlsProcessorRequest::CreateKeyPackage {
+                last_resort,
+                response: send,
+            })
             .await;
 
         recv.await?
@@ -259,14 +304,74 @@ impl MlsProcessorHandle {
         &self,
         group_id: GroupId,
         key_package: KeyPackage,
+    ) -> Result<MessageToServerTransport, anyhow::Error> {
+        self.add_members(group_id, vec![key_package]).await
+    }
+
+    /// Adds all members with a single commit, a member can't create another
+    /// commit until its pending one was distributed.
+    pub(crate) async fn add_members(
+        &self,
+        group_id: GroupId,
+        key_packages: Vec<KeyPackage>,
+    ) -> Result<MessageToServerTransport, anyhow::Error> {
+        let (send, recv) = oneshot::channel();
+
+        let _ = self
+            .channel
+            .send(MlsProcessorRequest::AddMembers {
+                group_id,
+                key_packages,
+                response: send,
+            })
+            .await;
+
+        Ok(recv.await??)
+    }
+
+    pub(crate) async fn members(&self, group_id: GroupId) -> Result<Vec<SpkiHash>, anyhow::Error> {
+        let (send, recv) = oneshot::channel();
+
+        let _ = self
+            .channel
+            .send(MlsProcessorRequest::Members {
+                group_id,
+                response: send,
+            })
+            .await;
+
+        Ok(recv.await??)
+    }
+
+    pub(crate) async fn remove_members(
+        &self,
+        group_id: GroupId,
+        members: Vec<SpkiHash>,
     ) -> Result<MessageToServerTransport, anyhow::Error> {
         let (send, recv) = oneshot::channel();
 
         let _ = self
             .channel
-            .send(MlsProcessorRequest::AddMember {
+            .send(MlsProcessorRequest::RemoveMembers {
+                group_id,
+                members,
+                response: send,
+            })
+            .await;
+
+        Ok(recv.await??)
+    }
+
+    pub(crate) async fn self_update(
+        &self,
+        group_id: GroupId,
+    ) -> Result<MessageToServerTransport, anyhow::Error> {
+        let (send, recv) = oneshot::channel();
+
+        let _ = self
+            .channel
+            .send(MlsProcessorRequest::SelfUpdate {
                 group_id,
-                key_package,
                 response: send,
             })
             .await;
@@ -287,6 +392,41 @@ impl MlsProcessorHandle {
 
         Ok(recv.await??)
     }
+
+    /// Drops the pending commit of this member, after the server refused to
+    /// distribute it. The commit has to be created again for the next epoch.
+    pub(crate) async fn clear_pending_commit(
+        &self,
+        group_id: GroupId,
+    ) -> Result<(), anyhow::Error> {
+        let (send, recv) = oneshot::channel();
+
+        let _ = self
+            .channel
+            .send(MlsProcessorRequest::ClearPendingCommit {
+                group_id,
+                response: send,
+            })
+            .await;
+
+        Ok(recv.await??)
+    }
+
+    /// Forgets the local state of a group, nothing is sent to the other
+    /// members. Does nothing if the group doesn't exist.
+    pub(crate) async fn delete_group(&self, group_id: GroupId) -> Result<(), anyhow::Error> {
+        let (send, recv) = oneshot::channel();
+
+        let _ = self
+            .channel
+            .send(MlsProcessorRequest::DeleteGroup {
+                group_id,
+                response: send,
+            })
+            .await;
+
+        Ok(recv.await??)
+    }
 }
 
 impl AnyMlsProcessor for MlsProcessorHandle {
@@ -312,6 +452,7 @@ impl AnyMlsProcessor for MlsProcessorHandle {
 
 enum MlsProcessorRequest {
     CreateKeyPackage {
+        last_resort: bool,
         response: oneshot::Sender<Result<KeyPackage, CreateKeyPackageError>>,
     },
     CreateGroup {
@@ -345,9 +486,9 @@ enum MlsProcessorRequest {
         spki_hash: SpkiHash,
         response: oneshot::Sender<Result<bool, anyhow::Error>>,
     },
-    AddMember {
+    AddMembers {
         group_id: GroupId,
-        key_package: KeyPackage,
+        key_packages: Vec<KeyPackage>,
         response: oneshot::Sender<Result<MessageToServerTransport, anyhow::Error>>,
     },
     GetMember {
@@ -355,10 +496,31 @@ enum MlsProcessorRequest {
         index: LeafNodeIndex,
         response: oneshot::Sender<Result<SpkiHash, anyhow::Error>>,
     },
+    Members {
+        group_id: GroupId,
+        response: oneshot::Sender<Result<Vec<SpkiHash>, anyhow::Error>>,
+    },
+    RemoveMembers {
+        group_id: GroupId,
+        members: Vec<SpkiHash>,
+        response: oneshot::Sender<Result<MessageToServerTransport, anyhow::Error>>,
+    },
+    SelfUpdate {
+        group_id: GroupId,
+        response: oneshot::Sender<Result<MessageToServerTransport, anyhow::Error>>,
+    },
     Commit {
         commit: Box<StagedCommit>,
         response: oneshot::Sender<Result<(), anyhow::Error>>,
     },
+    ClearPendingCommit {
+        group_id: GroupId,
+        response: oneshot::Sender<Result<(), anyhow::Error>>,
+    },
+    DeleteGroup {
+        group_id: GroupId,
+        response: oneshot::Sender<Result<(), anyhow::Error>>,
+    },
 }
 
 pub(crate) struct ProcessedMessage {
@@ -376,6 +538,9 @@ impl ProcessedMessage {
 pub(crate) enum ProcessedContent {
     Message(Vec<u8>),
     Commit(Box<openmls::prelude::StagedCommit>),
+    /// The server distributed the pending commit of this member, which is
+    /// merged now.
+    OwnCommit,
 }
 
 impl Debug for ProcessedContent {
@@ -383,6 +548,7 @@ impl Debug for ProcessedContent {
         match self {
             ProcessedContent::Message(_) => f.debug_tuple("Message").finish(),
             ProcessedContent::Commit(_) => f.debug_tuple("Commit").finish(),
+            ProcessedContent::OwnCommit => f.debug_tuple("OwnCommit").finish(),
         }
     }
 }
@@ -395,8 +561,13 @@ struct MlsProcessor {
 }
 
 impl MlsProcessor {
-    fn create_key_package(&self) -> Result<KeyPackage, CreateKeyPackageError> {
-        let mls_key_package = openmls::prelude::KeyPackage::builder()
+    fn create_key_package(&self, last_resort: bool) -> Result<KeyPackage, CreateKeyPackageError> {
+        let mut builder = openmls::prelude::KeyPackage::builder();
+        if last_resort {
+            builder = builder.mark_as_last_resort();
+        }
+
+        let mls_key_package = builder
             .build(
                 self.provider.ciphersuite(),
                 &self.provider,
@@ -523,6 +694,21 @@ impl MlsProcessor {
         Ok(group)
     }
 
+    /// Loads a group to create a commit for. The commit stays pending until
+    /// the server distributed it, so only one commit can be in flight.
+    fn get_commit_group<'a>(
+        cache: &'a mut HashMap<GroupId, MlsGroup>,
+        provider: &SvalinProvider,
+        group_id: GroupId,
+    ) -> Result<&'a mut MlsGroup, anyhow::Error> {
+        let group = Self::get_group(cache, provider.storage(), group_id)?;
+        if group.pending_commit().is_some() {
+            anyhow::bail!("the last commit of this member wasn't distributed yet");
+        }
+
+        Ok(group)
+    }
+
     fn create_message(
         &mut self,
         group_id: GroupId,
@@ -554,6 +740,38 @@ impl MlsProcessor {
         )?;
 
         tracing::trace!("found group");
+
+        // Own commits are only merged once the server distributed them, so
+        // members committing at the same time can't fork the group.
+        if group.pending_commit().is_some()
+            && message.epoch() == group.epoch()
+            && message.content_type() == ContentType::Commit
+        {
+            let own_commit = match &message {
+                ProtocolMessage::PublicMessage(public) => {
+                    public.sender() == &Sender::Member(group.own_leaf_index())
+                }
+                ProtocolMessage::PrivateMessage(_) => false,
+            };
+
+            if own_commit {
+                group.merge_pending_commit(&self.provider)?;
+                tracing::trace!("merged own commit");
+                return Ok(ProcessedMessage {
+                    group_id,
+                    sender: self.svalin_credential.certificate().spki_hash().clone(),
+                    content: ProcessedContent::OwnCommit,
+                });
+            }
+
+            // The server distributed another commit first and refused ours,
+            // it has to be created again on top of the new epoch
+            tracing::debug!("dropping own commit, another one was distributed first");
+            group
+                .clear_pending_commit(self.provider.storage())
+                .map_err(ProcessMessageError::StorageError)?;
+        }
+
         let processed = group.process_message(&self.provider, message)?;
         tracing::trace!("message processed");
         let Sender::Member(sender) = processed.sender() else {
@@ -612,22 +830,28 @@ impl MlsProcessor {
         Ok(is_member)
     }
 
-    fn add_member(
+    fn add_members(
         &mut self,
         group_id: GroupId,
-        key_package: KeyPackage,
+        key_packages: Vec<KeyPackage>,
     ) -> Result<MessageToServerTransport, anyhow::Error> {
         tracing::trace!(
-            "Adding member {} to group {}",
-            key_package.spki_hash(),
+            "Adding members {:?} to group {}",
+            key_packages
+                .iter()
+                .map(KeyPackage::spki_hash)
+                .collect::<Vec<_>>(),
             String::from_utf8_lossy(group_id.as_slice())
         );
-        let group = Self::get_group(&mut self.group_cache, &self.provider.storage(), group_id)?;
-        let key_packages = [key_package.unpack()];
+        let group = Self::get_commit_group(&mut self.group_cache, &self.provider, group_id)?;
+        let key_packages = key_packages
+            .into_iter()
+            .map(KeyPackage::unpack)
+            .collect::<Vec<_>>();
 
+        // merged once the server distributed it
         let (commit, welcome, _) =
             group.add_members(&self.provider, &self.svalin_credential, &key_packages)?;
-        group.merge_pending_commit(&self.provider)?;
 
         Ok(MessageToServerTransport::add_to_group(commit, welcome)?)
         // Ok(MessageToServerTransport::AddToGroup(AddToGroupTransport {
@@ -636,6 +860,72 @@ impl MlsProcessor {
         // }))
     }
 
+    fn members(&mut self, group_id: GroupId) -> Result<Vec<SpkiHash>, anyhow::Error> {
+        let group = Self::get_group(&mut self.group_cache, &self.provider.storage(), group_id)?;
+
+        Ok(group
+            .members()
+            .map(|member| member.credential.deserialized())
+            .collect::<Result<_, _>>()?)
+    }
+
+    fn remove_members(
+        &mut self,
+        group_id: GroupId,
+        members: Vec<SpkiHash>,
+    ) -> Result<MessageToServerTransport, anyhow::Error> {
+        tracing::trace!(
+            "Removing members {members:?} from group {}",
+            String::from_utf8_lossy(group_id.as_slice())
+        );
+        let group = Self::get_commit_group(&mut self.group_cache, &self.provider, group_id)?;
+
+        let mut indices = Vec::with_capacity(members.len());
+        for member in group.members() {
+            let spki_hash: SpkiHash = member.credential.deserialized()?;
+            if members.contains(&spki_hash) {
+                indices.push(member.index);
+            }
+        }
+        if indices.len() != members.len() {
+            return Err(anyhow!("not all members to remove are part of the group"));
+        }
+
+        // The commit contains a path update, so the removed members can't
+        // derive the secrets of the new epoch.
+        let (commit, _welcome, _group_info) =
+            group.remove_members(&self.provider, &self.svalin_credential, &indices)?;
+
+        Ok(MessageToServerTransport::commit(commit)?)
+    }
+
+    fn self_update(
+        &mut self,
+        group_id: GroupId,
+    ) -> Result<MessageToServerTransport, anyhow::Error> {
+        tracing::trace!(
+            "Updating own key in group {}",
+            String::from_utf8_lossy(group_id.as_slice())
+        );
+        let group = Self::get_commit_group(&mut self.group_cache, &self.provider, group_id)?;
+
+        let bundle = group
+            .commit_builder()
+            .force_self_update(true)
+            .load_psks(self.provider.storage())?
+            .build(
+                self.provider.rand(),
+                self.provider.crypto(),
+                &self.svalin_credential,
+                |_| true,
+            )?
+            .stage_commit(&self.provider)?;
+
+        let (commit, _welcome, _group_info) = bundle.into_contents();
+
+        Ok(MessageToServerTransport::commit(commit)?)
+    }
+
     fn get_member(
         &mut self,
         group_id: GroupId,
@@ -656,6 +946,35 @@ impl MlsProcessor {
 
         Ok(())
     }
+
+    fn clear_pending_commit(&mut self, group_id: GroupId) -> Result<(), anyhow::Error> {
+        let group = Self::get_group(&mut self.group_cache, &self.provider.storage(), group_id)?;
+        group
+            .clear_pending_commit(self.provider.storage())
+            .map_err(|err| anyhow!("error clearing pending commit: {err}"))?;
+
+        Ok(())
+    }
+
+    fn delete_group(&mut self, group_id: GroupId) -> Result<(), anyhow::Error> {
+        let mut group = match Self::get_group(
+            &mut self.group_cache,
+            &self.provider.storage(),
+            group_id.clone(),
+        ) {
+            Ok(_) => self
+                .group_cache
+                .remove(&group_id)
+                .expect("group was just loaded"),
+            Err(GetGroupError::UnknownGroup) => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        group
+            .delete(self.provider.storage())
+            .map_err(|err| anyhow!("error deleting group: {err}"))?;
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -746,6 +1065,15 @@ pub enum ProcessMessageError {
     ForbiddenMessageType,
     #[error("invalid sender")]
     InvalidSender,
+    #[error("error merging own commit: {0}")]
+    MergePendingCommitError(
+        #[from]
+        MergePendingCommitError<
+            <SvalinProvider as openmls::storage::OpenMlsProvider>::StorageError,
+        >,
+    ),
+    #[error("storage error: {0}")]
+    StorageError(#[source] <SvalinProvider as openmls::storage::OpenMlsProvider>::StorageError),
     #[error("error receiving from mlsprocessor")]
     RecvError(#[from] oneshot::error::RecvError),
 }
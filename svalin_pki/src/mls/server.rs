@@ -194,9 +194,8 @@ where
             SvalinGroupId::DeviceMetaGroup(_spki_hash) => {
                 // maybe verify the spki hash? Unsure to be honest
             }
-            #[allow(unreachable_patterns)]
-            _ => {
-                return Err(AddDeviceGroupError::UnexpectedGroup);
+            SvalinGroupId::GlobalGroup => {
+                // root is the only required member, so only root can create it
             }
         }
 
@@ -1,15 +1,15 @@
 use std::fmt::Debug;
 
 use openmls::prelude::{
-    MlsMessageBodyIn, MlsMessageBodyOut, MlsMessageIn, MlsMessageOut, PrivateMessageIn,
-    ProtocolMessage, PublicMessageIn, Welcome,
+    MlsMessageBodyIn, MlsMessageBodyOut, MlsMessageIn, MlsMessageOut, ProtocolMessage,
+    PublicMessageIn, Welcome,
     group_info::{GroupInfo, VerifiableGroupInfo},
     tls_codec,
 };
 use serde::de::DeserializeOwned;
 use tls_codec::{DeserializeBytes, Serialize};
 
-use crate::SpkiHash;
+use crate::{SpkiHash, secure_chain::Checkpoint};
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub enum MessageToServerTransport {
@@ -66,6 +66,19 @@ impl MessageToServerTransport {
         Ok(Self::GroupMessage(mls_message.tls_serialize_detached()?))
     }
 
+    /// A commit without new members, like a removal. It's sent like an
+    /// application message, but as a public message so the server can track
+    /// the group.
+    pub(crate) fn commit(mls_message: MlsMessageOut) -> Result<Self, tls_codec::Error> {
+        let MlsMessageBodyOut::PublicMessage(_) = mls_message.body() else {
+            return Err(tls_codec::Error::DecodingError(
+                "Expected a Public MLS message, but got something else".into(),
+            ));
+        };
+
+        Ok(Self::GroupMessage(mls_message.tls_serialize_detached()?))
+    }
+
     pub fn new_device_group(
         group_info: &GroupInfo,
         welcome: Option<&Welcome>,
@@ -130,6 +143,15 @@ impl MessageToSend {
     pub fn remove_receiver(&mut self, receiver: &SpkiHash) {
         self.receivers.retain(|r| r != receiver);
     }
+
+    /// Removes the sender from the receivers, except for commits. The sender
+    /// only merges its own commit once it is delivered back, which makes the
+    /// server the one deciding the order of concurrent commits.
+    pub fn remove_sender(&mut self, sender: &SpkiHash) {
+        if !self.message.is_commit() {
+            self.remove_receiver(sender);
+        }
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -150,6 +172,21 @@ impl Debug for MessageToMemberTransport {
 }
 
 impl MessageToMemberTransport {
+    pub fn is_commit(&self) -> bool {
+        match self {
+            MessageToMemberTransport::Welcome(_) => false,
+            MessageToMemberTransport::AddToGroup(_) => true,
+            // application messages are always encrypted, everything in
+            // plaintext is a commit
+            MessageToMemberTransport::GroupMessage(_) => matches!(
+                self.unpack(),
+                Ok(MessageToMember::GroupMessage(
+                    ProtocolMessage::PublicMessage(_)
+                ))
+            ),
+        }
+    }
+
     pub(crate) fn unpack(&self) -> Result<MessageToMember, tls_codec::Error> {
         let unpacked = match self {
             MessageToMemberTransport::Welcome(welcome) => {
@@ -164,12 +201,17 @@ impl MessageToMemberTransport {
             }
             MessageToMemberTransport::GroupMessage(message) => {
                 let message = MlsMessageIn::tls_deserialize_exact_bytes(&message)?;
-                let MlsMessageBodyIn::PrivateMessage(private_message) = message.extract() else {
-                    return Err(tls_codec::Error::DecodingError(
-                        "Expected a Private MLS message, but got something else".into(),
-                    ));
+                let message = match message.extract() {
+                    MlsMessageBodyIn::PrivateMessage(private_message) => private_message.into(),
+                    MlsMessageBodyIn::PublicMessage(public_message) => public_message.into(),
+                    _ => {
+                        return Err(tls_codec::Error::DecodingError(
+                            "Expected a Private or Public MLS message, but got something else"
+                                .into(),
+                        ));
+                    }
                 };
-                MessageToMember::GroupMessage(private_message)
+                MessageToMember::GroupMessage(message)
             }
             MessageToMemberTransport::AddToGroup(commit) => {
                 let commit = MlsMessageIn::tls_deserialize_exact_bytes(&commit)?;
@@ -188,7 +230,8 @@ impl MessageToMemberTransport {
 
 pub(crate) enum MessageToMember {
     Welcome(Welcome),
-    GroupMessage(PrivateMessageIn),
+    /// An application message or a commit without new members.
+    GroupMessage(ProtocolMessage),
     AddToGroup(PublicMessageIn),
 }
 
@@ -229,10 +272,124 @@ pub struct NewGroup {
     pub(crate) welcome: Option<Vec<u8>>,
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
-pub enum SvalinMessage<Types: MessageTypes> {
-    Report(Types::Report),
-    MetaInfo(Types::MetaInfo),
+/// Layout version of [`SvalinMessage`], only changes if the envelope itself
+/// changes.
+pub const MESSAGE_ENVELOPE_VERSION: u16 = 1;
+
+/// A type which can be sent encrypted to a group inside a [`SvalinMessage`].
+pub trait MessagePayload: serde::Serialize + DeserializeOwned {
+    /// Identifies the type inside the envelope, has to be unique.
+    const TAG: &'static str;
+    /// Layout version of the type. Receivers treat versions they don't know
+    /// like unknown types.
+    const VERSION: u16;
+}
+
+/// Envelope of every application message sent to a group.
+///
+/// The payload is tagged with its type, so new types can be added without
+/// touching this crate, and members which don't know a type can pass it on
+/// instead of failing to decode it.
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct SvalinMessage {
+    // has to stay the first field, so it can be read before the rest
+    envelope_version: u16,
+    tag: String,
+    version: u16,
+    payload: Vec<u8>,
+}
+
+impl Debug for SvalinMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SvalinMessage")
+            .field("tag", &self.tag)
+            .field("version", &self.version)
+            .finish()
+    }
+}
+
+impl SvalinMessage {
+    pub fn new<P: MessagePayload>(payload: &P) -> Result<Self, postcard::Error> {
+        Ok(Self {
+            envelope_version: MESSAGE_ENVELOPE_VERSION,
+            tag: P::TAG.to_string(),
+            version: P::VERSION,
+            payload: postcard::to_stdvec(payload)?,
+        })
+    }
+
+    /// Reassembles a message, e.g. one which was stored before.
+    pub fn from_parts(tag: String, version: u16, payload: Vec<u8>) -> Self {
+        Self {
+            envelope_version: MESSAGE_ENVELOPE_VERSION,
+            tag,
+            version,
+            payload,
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, postcard::Error> {
+        postcard::to_stdvec(self)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeMessageError> {
+        let (envelope_version, _) = postcard::take_from_bytes::<u16>(data)?;
+        if envelope_version != MESSAGE_ENVELOPE_VERSION {
+            return Err(DecodeMessageError::UnsupportedEnvelope(envelope_version));
+        }
+
+        Ok(postcard::from_bytes(data)?)
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// The encoded payload, only needed for types without a [`MessagePayload`].
+    pub fn raw_payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Whether the payload is of the given type and version.
+    pub fn is<P: MessagePayload>(&self) -> bool {
+        self.tag == P::TAG && self.version == P::VERSION
+    }
+
+    pub fn payload<P: MessagePayload>(&self) -> Result<P, DecodeMessageError> {
+        if !self.is::<P>() {
+            return Err(DecodeMessageError::UnexpectedType {
+                expected: P::TAG,
+                tag: self.tag.clone(),
+                version: self.version,
+            });
+        }
+
+        Ok(postcard::from_bytes(&self.payload)?)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeMessageError {
+    #[error("postcard error: {0}")]
+    PostcardError(#[from] postcard::Error),
+    #[error("unsupported message envelope version {0}")]
+    UnsupportedEnvelope(u16),
+    #[error("expected a {expected} message, got {tag} in version {version}")]
+    UnexpectedType {
+        expected: &'static str,
+        tag: String,
+        version: u16,
+    },
+}
+
+/// The trust store position of the sender, used to detect split views.
+impl MessagePayload for Checkpoint {
+    const TAG: &'static str = "chain_position";
+    const VERSION: u16 = 1;
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -264,8 +421,14 @@ pub struct AddToGroup {
     pub(crate) welcome: Vec<u8>,
 }
 
+/// The payload types this crate checks itself before passing them on, every
+/// other type is passed on as a [`SvalinMessage`].
 // Just need the deserialize on the main type to get the derive macro to work
 pub trait MessageTypes: DeserializeOwned {
-    type Report: serde::Serialize + DeserializeOwned;
-    type MetaInfo: serde::Serialize + DeserializeOwned;
+    /// Sent by an agent to its device group.
+    type Report: MessagePayload;
+    /// Sent by users to the meta group of a device.
+    type MetaInfo: MessagePayload;
+    /// Sent by users to the device group, executed by the agent.
+    type Instruction: MessagePayload;
 }
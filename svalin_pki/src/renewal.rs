@@ -0,0 +1,182 @@
+use ring::signature::{ED25519, VerificationAlgorithm};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+
+use crate::{
+    Certificate, Credential, KeyPair, SpkiHash, UnverifiedCertificate, get_current_timestamp,
+    keypair::ExportedPublicKey, secure_chain::encoding::CanonicalDigest,
+};
+
+/// A request of a certificate holder to get a new certificate before the
+/// current one expires, either for the same key or for a rotated one.
+///
+/// The request is signed with the current key. If the key is rotated, it is
+/// additionally signed with the new key to prove its possession.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenewalRequest {
+    certificate: UnverifiedCertificate,
+    public_key: ExportedPublicKey,
+    time: u64,
+    signature: Vec<u8>,
+    new_key_signature: Option<Vec<u8>>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RenewalRequestError {
+    #[error("certificate of the request does not match the known certificate")]
+    CertificateDoesNotMatch,
+    #[error("request was created in the future")]
+    TimeInFuture,
+    #[error("signature of the current key is invalid")]
+    InvalidSignature,
+    #[error("signature of the new key is invalid or missing")]
+    InvalidNewKeySignature,
+}
+
+impl RenewalRequest {
+    /// Requests a renewed certificate for the key of the given credential.
+    pub fn create(credential: &Credential) -> Self {
+        let certificate = credential.certificate().clone().to_unverified();
+        let public_key = ExportedPublicKey::from_certificate(&certificate);
+        let time = get_current_timestamp();
+
+        let digest = Self::digest(&certificate, &public_key, time);
+        let signature = credential
+            .keypair()
+            .signing_keypair()
+            .sign(&digest)
+            .as_ref()
+            .to_vec();
+
+        Self {
+            certificate,
+            public_key,
+            time,
+            signature,
+            new_key_signature: None,
+        }
+    }
+
+    /// Requests a certificate for `new_key` which replaces the certificate of
+    /// the given credential.
+    pub fn create_rotated(credential: &Credential, new_key: &KeyPair) -> Self {
+        let certificate = credential.certificate().clone().to_unverified();
+        let public_key = new_key.export_public_key();
+        let time = get_current_timestamp();
+
+        let digest = Self::digest(&certificate, &public_key, time);
+        let signature = credential
+            .keypair()
+            .signing_keypair()
+            .sign(&digest)
+            .as_ref()
+            .to_vec();
+        let new_key_signature = new_key.signing_keypair().sign(&digest).as_ref().to_vec();
+
+        Self {
+            certificate,
+            public_key,
+            time,
+            signature,
+            new_key_signature: Some(new_key_signature),
+        }
+    }
+
+    fn digest(
+        certificate: &UnverifiedCertificate,
+        public_key: &ExportedPublicKey,
+        time: u64,
+    ) -> Vec<u8> {
+        Sha512::new()
+            .chain_update(b"svalin_renewal_request")
+            .chain_update(certificate.as_der())
+            .chain_update(rcgen::PublicKeyData::der_bytes(public_key))
+            .chain_update(time.to_be_bytes())
+            .finalize()
+            .to_vec()
+    }
+
+    /// Adds the complete request to the digest of a trust store transaction.
+    pub(crate) fn update_digest(&self, digest: &mut impl Digest) {
+        digest.update(self.certificate.as_der());
+        digest.update(rcgen::PublicKeyData::der_bytes(&self.public_key));
+        digest.update(self.time.to_be_bytes());
+        digest.update(&self.signature);
+        if let Some(new_key_signature) = &self.new_key_signature {
+            digest.update(new_key_signature);
+        }
+    }
+
+    /// Adds the complete request to the canonical digest of a trust store
+    /// transaction.
+    pub(crate) fn encode_canonical(&self, digest: &mut impl Digest) {
+        digest.field(self.certificate.as_der());
+        digest.field(rcgen::PublicKeyData::der_bytes(&self.public_key));
+        digest.number(self.time);
+        digest.field(&self.signature);
+        digest.optional_field(self.new_key_signature.as_deref());
+    }
+
+    /// Verifies the request against the certificate currently known for the
+    /// holder, e.g. the one found in the trust store.
+    pub fn verify(&self, current: &Certificate) -> Result<(), RenewalRequestError> {
+        if &self.certificate != current {
+            return Err(RenewalRequestError::CertificateDoesNotMatch);
+        }
+
+        // allow for some clock skew between holder and verifier
+        if self.time > get_current_timestamp() + 60 {
+            return Err(RenewalRequestError::TimeInFuture);
+        }
+
+        let digest = Self::digest(&self.certificate, &self.public_key, self.time);
+
+        ED25519
+            .verify(
+                current.public_key().into(),
+                digest.as_slice().into(),
+                self.signature.as_slice().into(),
+            )
+            .map_err(|_| RenewalRequestError::InvalidSignature)?;
+
+        if self.is_rotation() {
+            let Some(new_key_signature) = &self.new_key_signature else {
+                return Err(RenewalRequestError::InvalidNewKeySignature);
+            };
+            ED25519
+                .verify(
+                    rcgen::PublicKeyData::der_bytes(&self.public_key).into(),
+                    digest.as_slice().into(),
+                    new_key_signature.as_slice().into(),
+                )
+                .map_err(|_| RenewalRequestError::InvalidNewKeySignature)?;
+        }
+
+        Ok(())
+    }
+
+    /// The certificate which should be renewed.
+    pub fn certificate(&self) -> &UnverifiedCertificate {
+        &self.certificate
+    }
+
+    /// The key the renewed certificate should be issued for.
+    pub fn public_key(&self) -> &ExportedPublicKey {
+        &self.public_key
+    }
+
+    /// The spki hash the renewed certificate will have.
+    pub fn renewed_spki_hash(&self) -> SpkiHash {
+        UnverifiedCertificate::compute_spki_hash(&rcgen::PublicKeyData::subject_public_key_info(
+            &self.public_key,
+        ))
+    }
+
+    pub fn is_rotation(&self) -> bool {
+        &self.renewed_spki_hash() != self.certificate.spki_hash()
+    }
+
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+}
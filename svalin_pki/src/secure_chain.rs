@@ -1,4 +1,4 @@
-use std::ops::Deref;
+use std::{cmp::Ordering, ops::Deref};
 
 use ring::signature::{ED25519, VerificationAlgorithm};
 use serde::{Deserialize, Serialize};
@@ -6,6 +6,14 @@ use sha2::{Digest, Sha512};
 
 use crate::{Certificate, Credential, SpkiHash, get_current_timestamp};
 
+pub mod encoding;
+pub mod legacy;
+pub mod merkle;
+pub mod store;
+
+use encoding::{CanonicalDigest, ProtocolVersion};
+use merkle::{MerkleHash, MerkleIndex};
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 struct InnerDigest([u8; 64]);
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -37,13 +45,17 @@ pub trait ChainState: Sized {
     ) -> Result<(), Self::Error>;
     fn apply(&mut self, signer: &SpkiHash, time: u64, transaction: &Self::Transaction);
     fn revert(&mut self, transaction: &Self::Transaction);
-    fn digest(&self, digest: &mut impl sha2::Digest);
+    /// Feeds the state into the digest, using the encoding of the given
+    /// version.
+    fn digest(&self, version: ProtocolVersion, digest: &mut impl sha2::Digest);
     fn export(&self) -> Self::Exported;
     fn import(exported: Self::Exported) -> Result<Self, Self::ImportError>;
 }
 
 pub trait Transaction {
-    fn digest(&self, digest: &mut impl sha2::Digest);
+    /// Feeds the transaction into the digest, using the encoding of the given
+    /// version.
+    fn digest(&self, version: ProtocolVersion, digest: &mut impl sha2::Digest);
 }
 
 pub struct Chain<State: ChainState> {
@@ -63,10 +75,23 @@ impl<State: ChainState> Chain<State> {
         &self.state
     }
 
+    pub fn last_block(&self) -> Option<&UncheckedBlock<State::Transaction>> {
+        self.last_block.as_ref().map(CheckedBlock::as_unchecked)
+    }
+
     pub fn package(
         &mut self,
         transaction: State::Transaction,
         credential: &Credential,
+    ) -> Result<CheckedBlock<State::Transaction>, CreateBlockError<State::Error>> {
+        self.package_with_version(transaction, credential, ProtocolVersion::CURRENT)
+    }
+
+    pub(crate) fn package_with_version(
+        &mut self,
+        transaction: State::Transaction,
+        credential: &Credential,
+        version: ProtocolVersion,
     ) -> Result<CheckedBlock<State::Transaction>, CreateBlockError<State::Error>> {
         let timestamp = get_current_timestamp();
         if let Err(reason) = self.state.check(
@@ -82,9 +107,7 @@ impl<State: ChainState> Chain<State> {
             timestamp,
             &transaction,
         );
-        let mut hasher = Sha512::new();
-        self.state.digest(&mut hasher);
-        let new_digest = hasher.finalize().into();
+        let new_digest = state_digest(&self.state, version);
         self.state.revert(&transaction);
 
         let mut block = if let Some(last) = &self.last_block {
@@ -96,6 +119,7 @@ impl<State: ChainState> Chain<State> {
                 signer: credential.certificate().spki_hash().clone(),
                 transaction,
                 signature: Vec::new(),
+                version,
             }
         } else {
             UncheckedBlock {
@@ -107,6 +131,7 @@ impl<State: ChainState> Chain<State> {
                 signer: credential.certificate().spki_hash().clone(),
                 transaction,
                 signature: Vec::new(),
+                version,
             }
         };
 
@@ -127,7 +152,18 @@ impl<State: ChainState> Chain<State> {
         block: UncheckedBlock<State::Transaction>,
         certificate: &Certificate,
     ) -> Result<CheckedBlock<State::Transaction>, CheckBlockError<State::Error>> {
+        if !block.version.is_supported() {
+            return Err(CheckBlockError::UnsupportedVersion(block.version));
+        }
+
         if let Some(last) = &self.last_block {
+            // chains only move to newer encodings
+            if block.version < last.0.version {
+                return Err(CheckBlockError::VersionDowngrade(
+                    block.version,
+                    last.0.version,
+                ));
+            }
             if block.sequence != last.0.sequence + 1 {
                 return Err(CheckBlockError::SequenceMismatch);
             }
@@ -172,9 +208,7 @@ impl<State: ChainState> Chain<State> {
         self.state
             .apply(&block.signer, block.time, &block.transaction);
 
-        let mut hasher = Sha512::new();
-        self.state.digest(&mut hasher);
-        let new_digest = hasher.finalize().into();
+        let new_digest = state_digest(&self.state, block.version);
         self.state.revert(&block.transaction);
 
         if block.resulting_state != new_digest {
@@ -188,9 +222,7 @@ impl<State: ChainState> Chain<State> {
         self.state
             .apply(&block.0.signer, block.0.time, &block.0.transaction);
 
-        let mut hasher = Sha512::new();
-        self.state.digest(&mut hasher);
-        let new_digest = hasher.finalize().into();
+        let new_digest = state_digest(&self.state, block.0.version);
 
         if block.0.resulting_state != new_digest {
             panic!("resulting state mismatch should have already been checked")
@@ -200,13 +232,16 @@ impl<State: ChainState> Chain<State> {
     }
 
     pub(crate) fn digest(&self) -> ChainDigest {
-        let block_digest = self
-            .last_block
-            .as_ref()
-            .map(|block| block.0.digest())
-            .unwrap_or_else(|| BlockDigest::empty());
-        let mut digest = Sha512::new().chain_update(block_digest.0.0);
-        self.state.digest(&mut digest);
+        let version = self.version();
+        let mut digest = Sha512::new();
+        if version == ProtocolVersion::LEGACY {
+            digest.update(self.last_block_hash().0.0);
+            self.state.digest(version, &mut digest);
+        } else {
+            digest.domain("svalin/secure_chain/chain", version);
+            digest.field(self.last_block_hash().as_ref());
+            digest.field(self.state_digest().as_ref());
+        }
         digest.finalize().into()
     }
 
@@ -223,10 +258,10 @@ impl<State: ChainState> Chain<State> {
     pub fn import(exported: ExportedChain<State>) -> Result<Self, ImportError<State::ImportError>> {
         let state = State::import(exported.state)?;
         if let Some(last_block) = exported.last_block {
-            let mut digest = Sha512::new();
-            state.digest(&mut digest);
-            let digest = digest.finalize().into();
-            if last_block.resulting_state != digest {
+            if !last_block.version.is_supported() {
+                return Err(ImportError::UnsupportedVersion(last_block.version));
+            }
+            if last_block.resulting_state != state_digest(&state, last_block.version) {
                 return Err(ImportError::IncorrectStateDigest);
             }
             Ok(Self {
@@ -241,11 +276,127 @@ impl<State: ChainState> Chain<State> {
         }
     }
 
+    /// Creates a signed checkpoint of the current state, which allows others
+    /// to start from it instead of replaying all blocks.
+    pub fn checkpoint(&self, credential: &Credential) -> Checkpoint {
+        self.sign_checkpoint(credential, None)
+    }
+
+    /// Creates a signed checkpoint which also commits to the root of the
+    /// Merkle index over all blocks up to the current one, so members can
+    /// check that it extends their history. `None` if the index doesn't
+    /// contain the current block.
+    pub fn checkpoint_with_history(
+        &self,
+        credential: &Credential,
+        history: &MerkleIndex,
+    ) -> Option<Checkpoint> {
+        if let Some(block) = &self.last_block {
+            let leaf = history.leaves().get(block.sequence() as usize - 1)?;
+            if *leaf != MerkleHash::leaf(block.as_unchecked()) {
+                return None;
+            }
+        }
+        let root = history.root_at(self.sequence())?;
+        Some(self.sign_checkpoint(credential, Some(root)))
+    }
+
+    fn sign_checkpoint(
+        &self,
+        credential: &Credential,
+        history_root: Option<MerkleHash>,
+    ) -> Checkpoint {
+        let mut checkpoint = Checkpoint {
+            sequence: self.sequence(),
+            time: get_current_timestamp(),
+            last_block_hash: self.last_block_hash(),
+            state_digest: self.state_digest(),
+            signer: credential.certificate().spki_hash().clone(),
+            signature: Vec::new(),
+            version: ProtocolVersion::CURRENT,
+            history_root,
+        };
+
+        let digest = checkpoint.digest();
+        checkpoint.signature = credential
+            .keypair()
+            .signing_keypair()
+            .sign(&digest)
+            .as_ref()
+            .to_vec();
+
+        checkpoint
+    }
+
+    /// Imports an exported chain which matches the given checkpoint. The
+    /// signature of the checkpoint has to be verified by the caller.
+    pub fn import_checkpoint(
+        exported: ExportedChain<State>,
+        checkpoint: &Checkpoint,
+    ) -> Result<Self, ImportCheckpointError<State::ImportError>> {
+        let chain = Self::import(exported)?;
+
+        if chain.sequence() != checkpoint.sequence {
+            return Err(ImportCheckpointError::SequenceMismatch);
+        }
+
+        if chain.last_block_hash() != checkpoint.last_block_hash {
+            return Err(ImportCheckpointError::LastBlockMismatch);
+        }
+
+        if chain.state_digest() != checkpoint.state_digest {
+            return Err(ImportCheckpointError::StateMismatch);
+        }
+
+        Ok(chain)
+    }
+
+    /// Compares the chain with the position of another member.
+    pub fn compare_position(&self, position: &Checkpoint) -> PositionComparison {
+        match position.sequence.cmp(&self.sequence()) {
+            Ordering::Greater => PositionComparison::PeerAhead,
+            Ordering::Less => PositionComparison::PeerBehind,
+            Ordering::Equal => {
+                if position.last_block_hash == self.last_block_hash()
+                    && position.state_digest == self.state_digest()
+                {
+                    PositionComparison::Equal
+                } else {
+                    PositionComparison::Conflict
+                }
+            }
+        }
+    }
+
     pub(crate) fn sequence(&self) -> u64 {
         self.last_block
             .as_ref()
             .map_or_else(|| 0, |block| block.sequence())
     }
+
+    fn last_block_hash(&self) -> BlockDigest {
+        self.last_block
+            .as_ref()
+            .map(|block| block.0.digest())
+            .unwrap_or_else(|| BlockDigest::empty())
+    }
+
+    fn state_digest(&self) -> StateDigest {
+        state_digest(&self.state, self.version())
+    }
+
+    /// The encoding version of the last block, new chains use the current one.
+    pub fn version(&self) -> ProtocolVersion {
+        self.last_block
+            .as_ref()
+            .map_or(ProtocolVersion::CURRENT, |block| block.0.version)
+    }
+}
+
+fn state_digest(state: &impl ChainState, version: ProtocolVersion) -> StateDigest {
+    let mut hasher = Sha512::new();
+    state.digest(version, &mut hasher);
+    hasher.finalize().into()
 }
 
 #[derive(Serialize, Deserialize)]
@@ -284,6 +435,10 @@ pub enum CheckBlockError<Inner> {
     ResultingStateMismatch,
     #[error("block time is in the future")]
     TimeInFuture,
+    #[error("protocol version {0} is not supported")]
+    UnsupportedVersion(ProtocolVersion),
+    #[error("block uses protocol version {0}, but the chain already uses {1}")]
+    VersionDowngrade(ProtocolVersion, ProtocolVersion),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -304,6 +459,147 @@ pub enum ImportError<Inner> {
     ImportError(#[from] Inner),
     #[error("incorrect state digest")]
     IncorrectStateDigest,
+    #[error("protocol version {0} is not supported")]
+    UnsupportedVersion(ProtocolVersion),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportCheckpointError<Inner> {
+    #[error("import error: {0}")]
+    ImportError(#[from] ImportError<Inner>),
+    #[error("sequence does not match the checkpoint")]
+    SequenceMismatch,
+    #[error("last block does not match the checkpoint")]
+    LastBlockMismatch,
+    #[error("state does not match the checkpoint")]
+    StateMismatch,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum VerifyCheckpointError {
+    #[error("incorrect certificate given")]
+    IncorrectCertificate,
+    #[error("signature verification failed")]
+    SignatureVerificationFailed(ring::error::Unspecified),
+}
+
+/// A signed record of the chain state at a given sequence.
+///
+/// Besides bootstrapping new members, it is used by members to compare their
+/// chain positions with each other.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    sequence: u64,
+    time: u64,
+    last_block_hash: BlockDigest,
+    state_digest: StateDigest,
+    signer: SpkiHash,
+    /// Not part of the digest
+    signature: Vec<u8>,
+    version: ProtocolVersion,
+    /// Root of the Merkle index over all blocks up to the sequence, which
+    /// allows to prove that the checkpoint extends a known history.
+    history_root: Option<MerkleHash>,
+}
+
+impl Checkpoint {
+    pub(crate) fn digest(&self) -> [u8; 64] {
+        let mut digest = Sha512::new();
+        if self.version == ProtocolVersion::LEGACY {
+            digest.update(b"svalin_checkpoint");
+            digest.update(self.sequence.to_le_bytes());
+            digest.update(self.time.to_le_bytes());
+            digest.update(&self.last_block_hash);
+            digest.update(&self.state_digest);
+            digest.update(self.signer.as_slice());
+        } else {
+            digest.domain("svalin/secure_chain/checkpoint", self.version);
+            digest.number(self.sequence);
+            digest.number(self.time);
+            digest.field(self.last_block_hash.as_ref());
+            digest.field(self.state_digest.as_ref());
+            digest.field(self.signer.as_slice());
+            digest.optional_field(self.history_root.as_ref().map(AsRef::as_ref));
+        }
+        digest.finalize().into()
+    }
+
+    /// Verifies that the checkpoint was signed by the given certificate.
+    pub fn verify(&self, certificate: &Certificate) -> Result<(), VerifyCheckpointError> {
+        if &self.signer != certificate.spki_hash() {
+            return Err(VerifyCheckpointError::IncorrectCertificate);
+        }
+
+        ED25519
+            .verify(
+                certificate.public_key().into(),
+                self.digest().as_slice().into(),
+                self.signature.as_slice().into(),
+            )
+            .map_err(VerifyCheckpointError::SignatureVerificationFailed)
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    pub fn signer(&self) -> &SpkiHash {
+        &self.signer
+    }
+
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    pub fn history_root(&self) -> Option<&MerkleHash> {
+        self.history_root.as_ref()
+    }
+
+    /// Whether the Merkle index covers exactly the history this checkpoint
+    /// commits to, ending at its last block.
+    pub fn matches_history(&self, history: &MerkleIndex) -> bool {
+        let last_leaf = match history.leaves().last() {
+            Some(leaf) => *leaf == MerkleHash::from_block_digest(&self.last_block_hash),
+            None => self.sequence == 0,
+        };
+        history.size() == self.sequence
+            && self.history_root.as_ref() == Some(&history.root())
+            && last_leaf
+    }
+
+    /// Whether both checkpoints describe the same position, regardless of
+    /// who signed them.
+    pub fn matches_position(&self, other: &Checkpoint) -> bool {
+        self.sequence == other.sequence
+            && self.last_block_hash == other.last_block_hash
+            && self.state_digest == other.state_digest
+    }
+
+    /// Whether the given block leads to this position, used to compare with
+    /// a member which is behind.
+    pub fn matches_block<T: Transaction>(&self, block: &UncheckedBlock<T>) -> bool {
+        block.sequence == self.sequence
+            && block.digest() == self.last_block_hash
+            && block.resulting_state == self.state_digest
+    }
+}
+
+/// Result of comparing the own chain with the position of another member.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionComparison {
+    Equal,
+    /// The other member is further ahead, so the positions can only be
+    /// compared once this chain caught up.
+    PeerAhead,
+    /// The other member is behind, so its position has to be compared with
+    /// the stored block at its sequence using [`Checkpoint::matches_block`].
+    PeerBehind,
+    /// The members accepted different histories at the same sequence.
+    Conflict,
 }
 
 #[derive(Debug, Clone)]
@@ -337,6 +633,11 @@ pub struct UncheckedBlock<T> {
     transaction: T,
     /// Not part of the BlockDigest
     signature: Vec<u8>,
+    /// Encoding of the digests, blocks serialized without it are legacy
+    /// blocks. It is the last field, see [`legacy`] for reading blocks
+    /// stored with postcard before it was added.
+    #[serde(default)]
+    version: ProtocolVersion,
 }
 
 impl<T: Transaction> PartialEq for UncheckedBlock<T> {
@@ -351,19 +652,42 @@ impl<T: Transaction> UncheckedBlock<T> {
     }
 
     fn digest(&self) -> BlockDigest {
-        let mut hasher = Sha512::new()
-            .chain_update(self.sequence.to_le_bytes())
-            .chain_update(self.time.to_le_bytes())
-            .chain_update(&self.previous_block_hash)
-            .chain_update(&self.resulting_state)
-            .chain_update(self.signer.as_slice());
-        self.transaction.digest(&mut hasher);
+        if self.version == ProtocolVersion::LEGACY {
+            let mut hasher = Sha512::new()
+                .chain_update(self.sequence.to_le_bytes())
+                .chain_update(self.time.to_le_bytes())
+                .chain_update(&self.previous_block_hash)
+                .chain_update(&self.resulting_state)
+                .chain_update(self.signer.as_slice());
+            self.transaction.digest(self.version, &mut hasher);
+            return hasher.finalize().into();
+        }
+
+        let mut transaction = Sha512::new();
+        self.transaction.digest(self.version, &mut transaction);
+
+        let mut hasher = Sha512::new();
+        hasher.domain("svalin/secure_chain/block", self.version);
+        hasher.number(self.sequence);
+        hasher.number(self.time);
+        hasher.field(self.previous_block_hash.as_ref());
+        hasher.field(self.resulting_state.as_ref());
+        hasher.field(self.signer.as_slice());
+        hasher.field(&transaction.finalize());
         hasher.finalize().into()
     }
 
     pub fn sequence(&self) -> u64 {
         self.sequence
     }
+
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    pub fn transaction(&self) -> &T {
+        &self.transaction
+    }
 }
 
 use serde::de::{DeserializeOwned, Error};
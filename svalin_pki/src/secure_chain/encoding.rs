@@ -0,0 +1,65 @@
+//! Canonical encoding of digest inputs from [`ProtocolVersion::V1`] on.
+//!
+//! Every digest starts with a domain label naming the hashed structure,
+//! followed by the protocol version, so digests of different structures or
+//! versions can never collide. Integers are written as 8 byte big endian and
+//! variable length fields are prefixed with their length, which makes the
+//! encoding unambiguous.
+
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+
+/// Version of the encoding used for the digests and signatures of a block.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ProtocolVersion(u16);
+
+impl ProtocolVersion {
+    /// Raw concatenation without domain separation, used by blocks created
+    /// before the version was introduced.
+    pub const LEGACY: Self = Self(0);
+    /// Canonical encoding with domain separation.
+    pub const V1: Self = Self(1);
+    /// Version used for new blocks.
+    pub const CURRENT: Self = Self::V1;
+
+    pub fn is_supported(self) -> bool {
+        self <= Self::CURRENT
+    }
+}
+
+impl std::fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+/// Writes canonically encoded values into a digest.
+pub trait CanonicalDigest: Digest {
+    /// Starts the digest of a structure, has to be the first call.
+    fn domain(&mut self, label: &str, version: ProtocolVersion) {
+        self.field(label.as_bytes());
+        self.number(version.0.into());
+    }
+
+    fn number(&mut self, value: u64) {
+        self.update(value.to_be_bytes());
+    }
+
+    fn field(&mut self, bytes: &[u8]) {
+        self.number(bytes.len() as u64);
+        self.update(bytes);
+    }
+
+    fn optional_field(&mut self, bytes: Option<&[u8]>) {
+        match bytes {
+            Some(bytes) => {
+                self.number(1);
+                self.field(bytes);
+            }
+            None => self.number(0),
+        }
+    }
+}
+
+impl<D: Digest> CanonicalDigest for D {}
@@ -0,0 +1,88 @@
+//! Serialized layouts from before blocks and checkpoints carried a
+//! [`ProtocolVersion`].
+//!
+//! Self describing formats like JSON fill in the missing version, but
+//! postcard data stored by older releases has to be read with these types and
+//! converted. Stores should try the current layout first, data in the legacy
+//! layout always fails it because the trailing version is missing.
+
+use serde::Deserialize;
+
+use crate::SpkiHash;
+
+use super::{
+    BlockDigest, ChainState, Checkpoint, ExportedChain, ProtocolVersion, StateDigest,
+    UncheckedBlock,
+};
+
+#[derive(Deserialize)]
+pub struct LegacyUncheckedBlock<T> {
+    sequence: u64,
+    time: u64,
+    previous_block_hash: BlockDigest,
+    resulting_state: StateDigest,
+    signer: SpkiHash,
+    transaction: T,
+    signature: Vec<u8>,
+}
+
+impl<T> From<LegacyUncheckedBlock<T>> for UncheckedBlock<T> {
+    fn from(block: LegacyUncheckedBlock<T>) -> Self {
+        Self {
+            sequence: block.sequence,
+            time: block.time,
+            previous_block_hash: block.previous_block_hash,
+            resulting_state: block.resulting_state,
+            signer: block.signer,
+            transaction: block.transaction,
+            signature: block.signature,
+            version: ProtocolVersion::LEGACY,
+        }
+    }
+}
+
+/// The exported state can be replaced by an older layout of it, as long as it
+/// converts into the current one.
+#[derive(Deserialize)]
+pub struct LegacyExportedChain<State: ChainState, Exported = <State as ChainState>::Exported> {
+    state: Exported,
+    last_block: Option<LegacyUncheckedBlock<State::Transaction>>,
+}
+
+impl<State, Exported> From<LegacyExportedChain<State, Exported>> for ExportedChain<State>
+where
+    State: ChainState,
+    Exported: Into<State::Exported>,
+{
+    fn from(exported: LegacyExportedChain<State, Exported>) -> Self {
+        Self {
+            state: exported.state.into(),
+            last_block: exported.last_block.map(Into::into),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LegacyCheckpoint {
+    sequence: u64,
+    time: u64,
+    last_block_hash: BlockDigest,
+    state_digest: StateDigest,
+    signer: SpkiHash,
+    signature: Vec<u8>,
+}
+
+impl From<LegacyCheckpoint> for Checkpoint {
+    fn from(checkpoint: LegacyCheckpoint) -> Self {
+        Self {
+            sequence: checkpoint.sequence,
+            time: checkpoint.time,
+            last_block_hash: checkpoint.last_block_hash,
+            state_digest: checkpoint.state_digest,
+            signer: checkpoint.signer,
+            signature: checkpoint.signature,
+            version: ProtocolVersion::LEGACY,
+            history_root: None,
+        }
+    }
+}
@@ -0,0 +1,413 @@
+//! A Merkle tree over the block digests of a chain.
+//!
+//! The index is derived data, it can always be rebuilt from the stored
+//! blocks. It allows proving that a block is part of a history, that a
+//! history extends an older one, and locating the first block where two
+//! histories differ without exchanging all blocks.
+//!
+//! The tree layout and proofs follow RFC 9162 (Certificate Transparency),
+//! using SHA-512 with separate prefixes for leaves and inner nodes.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+
+use super::{BlockDigest, InnerDigest, Transaction, UncheckedBlock};
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MerkleHash(InnerDigest);
+
+impl MerkleHash {
+    fn empty() -> Self {
+        Self(InnerDigest(Sha512::digest(b"").into()))
+    }
+
+    /// The leaf of a block, which is all the index needs to know about it.
+    pub fn leaf<T: Transaction>(block: &UncheckedBlock<T>) -> Self {
+        Self::from_block_digest(&block.digest())
+    }
+
+    pub(super) fn from_block_digest(digest: &BlockDigest) -> Self {
+        let hash = Sha512::new()
+            .chain_update([LEAF_PREFIX])
+            .chain_update(digest)
+            .finalize();
+        Self(InnerDigest(hash.into()))
+    }
+
+    fn node(left: &Self, right: &Self) -> Self {
+        let hash = Sha512::new()
+            .chain_update([NODE_PREFIX])
+            .chain_update(left.0.0)
+            .chain_update(right.0.0)
+            .finalize();
+        Self(InnerDigest(hash.into()))
+    }
+}
+
+impl AsRef<[u8]> for MerkleHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0.0
+    }
+}
+
+/// Merkle index over the blocks of a chain, the leaf at index `i` belongs to
+/// the block with sequence `i + 1`.
+#[derive(Clone, Debug, Default)]
+pub struct MerkleIndex {
+    leaves: Vec<MerkleHash>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MerkleError {
+    #[error("expected block {expected}, got {actual}")]
+    SequenceMismatch { expected: u64, actual: u64 },
+}
+
+impl MerkleIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the index from all blocks of a chain, starting at sequence 1.
+    pub fn rebuild<'a, T: Transaction + 'a>(
+        blocks: impl IntoIterator<Item = &'a UncheckedBlock<T>>,
+    ) -> Result<Self, MerkleError> {
+        let mut index = Self::new();
+        for block in blocks {
+            index.push(block)?;
+        }
+        Ok(index)
+    }
+
+    /// Builds the index from the leaves of all blocks, starting at sequence
+    /// 1.
+    ///
+    /// Stores keep the leaves when they prune blocks, so the index still
+    /// covers the whole history afterwards.
+    pub fn from_leaves(leaves: Vec<MerkleHash>) -> Self {
+        Self { leaves }
+    }
+
+    pub fn leaves(&self) -> &[MerkleHash] {
+        &self.leaves
+    }
+
+    /// Appends the next block of the chain.
+    pub fn push<T: Transaction>(&mut self, block: &UncheckedBlock<T>) -> Result<(), MerkleError> {
+        let expected = self.size() + 1;
+        if block.sequence() != expected {
+            return Err(MerkleError::SequenceMismatch {
+                expected,
+                actual: block.sequence(),
+            });
+        }
+
+        self.leaves.push(MerkleHash::leaf(block));
+        Ok(())
+    }
+
+    /// Number of indexed blocks, which equals the sequence of the last one.
+    pub fn size(&self) -> u64 {
+        self.leaves.len() as u64
+    }
+
+    pub fn root(&self) -> MerkleHash {
+        subtree_root(&self.leaves)
+    }
+
+    /// The root of the history up to and including the given sequence.
+    pub fn root_at(&self, size: u64) -> Option<MerkleHash> {
+        self.prefix(size).map(subtree_root)
+    }
+
+    /// Proves that the block with the given sequence is part of the history
+    /// of the given size.
+    pub fn inclusion_proof(&self, sequence: u64, size: u64) -> Option<InclusionProof> {
+        let leaves = self.prefix(size)?;
+        if sequence == 0 || sequence > size {
+            return None;
+        }
+
+        let mut path = Vec::new();
+        inclusion_path((sequence - 1) as usize, leaves, &mut path);
+
+        Some(InclusionProof {
+            sequence,
+            size,
+            path,
+        })
+    }
+
+    /// Proves that the history of `new_size` extends the one of `old_size`.
+    pub fn consistency_proof(&self, old_size: u64, new_size: u64) -> Option<ConsistencyProof> {
+        let leaves = self.prefix(new_size)?;
+        if old_size == 0 || old_size > new_size {
+            return None;
+        }
+
+        let mut path = Vec::new();
+        consistency_path(old_size as usize, leaves, true, &mut path);
+
+        Some(ConsistencyProof {
+            old_size,
+            new_size,
+            path,
+        })
+    }
+
+    /// Locates the first sequence at which the two histories differ, `None`
+    /// if one is a prefix of the other.
+    pub fn first_difference(&self, other: &MerkleIndex) -> Option<u64> {
+        let mut bisection = Bisection::new(self.size(), other.size());
+        while let Some(size) = bisection.next_size() {
+            let equal = self.root_at(size) == other.root_at(size);
+            bisection.record(size, equal);
+        }
+        bisection.first_difference()
+    }
+
+    fn prefix(&self, size: u64) -> Option<&[MerkleHash]> {
+        self.leaves.get(..usize::try_from(size).ok()?)
+    }
+}
+
+/// Largest power of two smaller than `n`, for `n > 1`.
+fn split(n: usize) -> usize {
+    1 << (usize::BITS - 1 - (n - 1).leading_zeros())
+}
+
+fn subtree_root(leaves: &[MerkleHash]) -> MerkleHash {
+    match leaves {
+        [] => MerkleHash::empty(),
+        [leaf] => leaf.clone(),
+        _ => {
+            let k = split(leaves.len());
+            MerkleHash::node(&subtree_root(&leaves[..k]), &subtree_root(&leaves[k..]))
+        }
+    }
+}
+
+fn inclusion_path(index: usize, leaves: &[MerkleHash], path: &mut Vec<MerkleHash>) {
+    if leaves.len() <= 1 {
+        return;
+    }
+
+    let k = split(leaves.len());
+    if index < k {
+        inclusion_path(index, &leaves[..k], path);
+        path.push(subtree_root(&leaves[k..]));
+    } else {
+        inclusion_path(index - k, &leaves[k..], path);
+        path.push(subtree_root(&leaves[..k]));
+    }
+}
+
+fn consistency_path(
+    old_size: usize,
+    leaves: &[MerkleHash],
+    complete: bool,
+    path: &mut Vec<MerkleHash>,
+) {
+    if old_size == leaves.len() {
+        if !complete {
+            path.push(subtree_root(leaves));
+        }
+        return;
+    }
+
+    let k = split(leaves.len());
+    if old_size <= k {
+        consistency_path(old_size, &leaves[..k], complete, path);
+        path.push(subtree_root(&leaves[k..]));
+    } else {
+        consistency_path(old_size - k, &leaves[k..], false, path);
+        path.push(subtree_root(&leaves[..k]));
+    }
+}
+
+/// Proof that a block is part of the history with the given root.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InclusionProof {
+    sequence: u64,
+    size: u64,
+    path: Vec<MerkleHash>,
+}
+
+impl InclusionProof {
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn verify<T: Transaction>(&self, block: &UncheckedBlock<T>, root: &MerkleHash) -> bool {
+        if block.sequence() != self.sequence || self.sequence == 0 || self.sequence > self.size {
+            return false;
+        }
+
+        let mut index = self.sequence - 1;
+        let mut last = self.size - 1;
+        let mut hash = MerkleHash::leaf(block);
+
+        for sibling in &self.path {
+            if last == 0 {
+                return false;
+            }
+            if index & 1 == 1 || index == last {
+                hash = MerkleHash::node(sibling, &hash);
+                while index & 1 == 0 && index != 0 {
+                    index >>= 1;
+                    last >>= 1;
+                }
+            } else {
+                hash = MerkleHash::node(&hash, sibling);
+            }
+            index >>= 1;
+            last >>= 1;
+        }
+
+        last == 0 && &hash == root
+    }
+}
+
+/// Proof that a history is an extension of an older one.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConsistencyProof {
+    old_size: u64,
+    new_size: u64,
+    path: Vec<MerkleHash>,
+}
+
+impl ConsistencyProof {
+    pub fn old_size(&self) -> u64 {
+        self.old_size
+    }
+
+    pub fn new_size(&self) -> u64 {
+        self.new_size
+    }
+
+    pub fn verify(&self, old_root: &MerkleHash, new_root: &MerkleHash) -> bool {
+        if self.old_size == 0 || self.old_size > self.new_size {
+            return false;
+        }
+        if self.old_size == self.new_size {
+            return self.path.is_empty() && old_root == new_root;
+        }
+
+        let mut path = self.path.iter();
+        let first = if self.old_size.is_power_of_two() {
+            old_root
+        } else {
+            let Some(first) = path.next() else {
+                return false;
+            };
+            first
+        };
+
+        let mut index = self.old_size - 1;
+        let mut last = self.new_size - 1;
+        while index & 1 == 1 {
+            index >>= 1;
+            last >>= 1;
+        }
+
+        let mut old_hash = first.clone();
+        let mut new_hash = first.clone();
+        for sibling in path {
+            if last == 0 {
+                return false;
+            }
+            if index & 1 == 1 || index == last {
+                old_hash = MerkleHash::node(sibling, &old_hash);
+                new_hash = MerkleHash::node(sibling, &new_hash);
+                while index & 1 == 0 && index != 0 {
+                    index >>= 1;
+                    last >>= 1;
+                }
+            } else {
+                new_hash = MerkleHash::node(&new_hash, sibling);
+            }
+            index >>= 1;
+            last >>= 1;
+        }
+
+        last == 0 && &old_hash == old_root && &new_hash == new_root
+    }
+}
+
+/// Binary search for the first block at which two histories differ.
+///
+/// Only the roots of the other member at the requested sizes are needed, so
+/// it can be driven over the network: ask for the root at
+/// [`Bisection::next_size`], compare it with the local one and
+/// [`record`](Bisection::record) the result until no further size is
+/// requested.
+#[derive(Clone, Debug)]
+pub struct Bisection {
+    /// Largest size at which both histories are known to be equal.
+    equal: u64,
+    /// Smallest size at which the histories are known to differ, or the size
+    /// of the common range as long as it wasn't compared yet.
+    different: u64,
+    compared_common: bool,
+}
+
+impl Bisection {
+    pub fn new(local_size: u64, remote_size: u64) -> Self {
+        Self {
+            equal: 0,
+            different: local_size.min(remote_size),
+            compared_common: false,
+        }
+    }
+
+    /// The size at which the roots have to be compared next.
+    pub fn next_size(&self) -> Option<u64> {
+        if !self.compared_common {
+            return (self.different > 0).then_some(self.different);
+        }
+        if self.different - self.equal > 1 {
+            return Some(self.equal + (self.different - self.equal) / 2);
+        }
+        None
+    }
+
+    /// Records whether the roots at the given size are equal.
+    pub fn record(&mut self, size: u64, equal: bool) {
+        if !self.compared_common {
+            if size != self.different {
+                return;
+            }
+            self.compared_common = true;
+            if equal {
+                self.equal = size;
+            }
+            return;
+        }
+
+        if size <= self.equal || size >= self.different {
+            return;
+        }
+        if equal {
+            self.equal = size;
+        } else {
+            self.different = size;
+        }
+    }
+
+    /// The first sequence at which the histories differ, once the search is
+    /// complete. `None` if the shorter history is a prefix of the longer one.
+    pub fn first_difference(&self) -> Option<u64> {
+        if self.next_size().is_some() || self.equal == self.different {
+            return None;
+        }
+        Some(self.different)
+    }
+}
@@ -0,0 +1,168 @@
+//! Persistent storage for the blocks of a [`Chain`].
+
+use std::ops::RangeInclusive;
+
+use crate::{Certificate, Credential, SpkiHash};
+
+use super::{Chain, ChainState, CheckBlockError, CheckedBlock, CreateBlockError, UncheckedBlock};
+
+/// Number of blocks loaded at once while verifying a stored chain.
+const OPEN_BATCH_SIZE: u64 = 256;
+
+/// Append only storage of the blocks of a single chain.
+pub trait BlockStore<T> {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Sequence of the last stored block, 0 if the store is empty.
+    fn last_sequence(&self) -> impl Future<Output = Result<u64, Self::Error>> + Send;
+
+    /// Stores a block, which has to directly follow the last stored block.
+    fn append(
+        &self,
+        block: &CheckedBlock<T>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Loads the stored blocks within the range of sequences, ordered by
+    /// sequence.
+    fn load_range(
+        &self,
+        range: RangeInclusive<u64>,
+    ) -> impl Future<Output = Result<Vec<UncheckedBlock<T>>, Self::Error>> + Send;
+}
+
+type ResolveSigner<State> = dyn Fn(&State, &SpkiHash) -> Option<Certificate> + Send + Sync;
+
+/// A [`Chain`] whose blocks are written to a [`BlockStore`] before they are
+/// applied.
+pub struct StoredChain<State: ChainState, Store> {
+    chain: Chain<State>,
+    store: Store,
+    resolve_signer: Box<ResolveSigner<State>>,
+}
+
+impl<State, Store> StoredChain<State, Store>
+where
+    State: ChainState,
+    Store: BlockStore<State::Transaction>,
+{
+    /// Replays all stored blocks on top of the initial state.
+    ///
+    /// Every block is checked again like a block received from another
+    /// member, so a store which was modified outside of the chain is
+    /// detected. `resolve_signer` looks up the certificate of a block signer
+    /// in the state before the block.
+    pub async fn open(
+        initial: State,
+        store: Store,
+        resolve_signer: impl Fn(&State, &SpkiHash) -> Option<Certificate> + Send + Sync + 'static,
+    ) -> Result<Self, OpenError<State::Error, Store::Error>> {
+        let mut chain = Chain::initialize(initial);
+        let last_sequence = store.last_sequence().await.map_err(OpenError::Store)?;
+
+        let mut next = 1;
+        while next <= last_sequence {
+            let until = (next + OPEN_BATCH_SIZE - 1).min(last_sequence);
+            let blocks = store
+                .load_range(next..=until)
+                .await
+                .map_err(OpenError::Store)?;
+
+            for block in blocks {
+                let sequence = block.sequence();
+                if sequence != next {
+                    return Err(OpenError::MissingBlock(next));
+                }
+                let certificate = resolve_signer(chain.state(), block.signer())
+                    .ok_or(OpenError::UnknownSigner(sequence))?;
+                let block = chain
+                    .check(block, &certificate)
+                    .map_err(|err| OpenError::InvalidBlock(sequence, err))?;
+                chain.apply(block);
+                next += 1;
+            }
+
+            if next <= until {
+                return Err(OpenError::MissingBlock(next));
+            }
+        }
+
+        Ok(Self {
+            chain,
+            store,
+            resolve_signer: Box::new(resolve_signer),
+        })
+    }
+
+    pub fn chain(&self) -> &Chain<State> {
+        &self.chain
+    }
+
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Checks a block received from another member, stores and applies it.
+    pub async fn receive(
+        &mut self,
+        block: UncheckedBlock<State::Transaction>,
+    ) -> Result<(), AppendError<State::Error, Store::Error>> {
+        let sequence = block.sequence();
+        let certificate = (self.resolve_signer)(self.chain.state(), block.signer())
+            .ok_or(AppendError::UnknownSigner(sequence))?;
+        let block = self.chain.check(block, &certificate)?;
+
+        self.append(block).await
+    }
+
+    /// Creates a new block, stores and applies it.
+    pub async fn package(
+        &mut self,
+        transaction: State::Transaction,
+        credential: &Credential,
+    ) -> Result<CheckedBlock<State::Transaction>, AppendError<State::Error, Store::Error>>
+    where
+        State::Transaction: Clone,
+    {
+        let block = self.chain.package(transaction, credential)?;
+        self.append(block.clone()).await?;
+
+        Ok(block)
+    }
+
+    async fn append(
+        &mut self,
+        block: CheckedBlock<State::Transaction>,
+    ) -> Result<(), AppendError<State::Error, Store::Error>> {
+        self.store
+            .append(&block)
+            .await
+            .map_err(AppendError::Store)?;
+        self.chain.apply(block);
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum OpenError<Inner, StoreError> {
+    #[error("store error: {0}")]
+    Store(StoreError),
+    #[error("block {0} is missing from the store")]
+    MissingBlock(u64),
+    #[error("signer of block {0} is unknown")]
+    UnknownSigner(u64),
+    #[error("stored block {0} is invalid: {1}")]
+    InvalidBlock(u64, CheckBlockError<Inner>),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AppendError<Inner, StoreError> {
+    #[error("store error: {0}")]
+    Store(StoreError),
+    #[error("signer of block {0} is unknown")]
+    UnknownSigner(u64),
+    #[error("invalid block: {0}")]
+    InvalidBlock(#[from] CheckBlockError<Inner>),
+    #[error("failed to create block: {0}")]
+    CreateBlock(#[from] CreateBlockError<Inner>),
+}
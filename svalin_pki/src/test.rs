@@ -1,5 +1,30 @@
 mod central_trust_store;
 mod certificate;
+mod checkpoint;
 mod experiments;
+mod expiry;
+mod merkle;
 mod mls;
+mod renewal;
 mod secure_chain;
+
+use crate::{
+    Credential, KeyPair,
+    secure_chain::CheckedBlock,
+    trust_store::{Transaction, TrustStore},
+};
+
+/// Adds a new agent to the trust store. Returns its credential and the block
+/// which added it.
+pub(crate) fn add_agent(
+    store: &mut TrustStore,
+    root: &Credential,
+) -> (Credential, CheckedBlock<Transaction>) {
+    let key = KeyPair::generate();
+    let cert = root
+        .create_agent_certificate_for_key(&key.export_public_key())
+        .unwrap();
+    let block = store.add(cert.clone(), root).unwrap();
+    store.apply(block.clone());
+    (key.upgrade(cert.to_unverified()).unwrap(), block)
+}
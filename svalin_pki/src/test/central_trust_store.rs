@@ -1,4 +1,10 @@
-use crate::{Credential, KeyPair, trust_store::TrustStore};
+use sha2::{Digest, Sha512};
+
+use crate::{
+    Credential, KeyPair, UnverifiedCertificate,
+    secure_chain::{ChainState, Transaction as _, encoding::ProtocolVersion},
+    trust_store::{State, Transaction, TrustStore},
+};
 
 #[test]
 fn test_trust_store() {
@@ -44,3 +50,48 @@ fn test_trust_store() {
 
     let _agent2_store = TrustStore::import(exported.clone()).unwrap();
 }
+
+/// Self signed root certificate with fixed key and validity, so the digests
+/// over it are stable.
+const ROOT_DER: [&str; 13] = [
+    "3082018630820138a003020102020101300506032b6570305a310d300b060355",
+    "040b0c04726f6f743149304706035504030c4039443731343332333134413837",
+    "3033454444464639394135454533323431373136334443453736384336353739",
+    "35463933324135333931354530323532303143301e170d323331313134323231",
+    "3332305a170d3333313131313232313332305a305a310d300b060355040b0c04",
+    "726f6f743149304706035504030c403944373134333233313441383730334544",
+    "4446463939413545453332343137313633444345373638433635373935463933",
+    "324135333931354530323532303143302a300506032b6570032100ea4a6c63e2",
+    "9c520abef5507b132ec5f9954776aebebe7b92421eea691446d22ca323302130",
+    "0f0603551d130101ff040530030101ff300e0603551d0f0101ff040403020284",
+    "300506032b6570034100dd47c8ef9312b7459f7213ce96ad3969a6f05249136f",
+    "67e8e8f1ca243fb0b7dfe0d5acfd3056d548bbfed74660fc30fdf5c9dfdecc93",
+    "3cfc740b5811fefcef0e",
+];
+
+#[test]
+fn test_trust_store_vectors() {
+    let root = UnverifiedCertificate::from_der(hex::decode(ROOT_DER.concat()).unwrap())
+        .unwrap()
+        .use_as_root()
+        .unwrap();
+
+    let mut digest = Sha512::new();
+    Transaction::Add(root.clone().to_unverified()).digest(ProtocolVersion::V1, &mut digest);
+    assert_eq!(
+        hex::encode(digest.finalize()),
+        "4d1e8a3be18f7c4cfc0c69c6ae5311a903c8a605efb1e76a52d765a163f9d46048f98ee563a49004658b0f64084909949bd6e1592a5880fd608a674e10a40c0d"
+    );
+
+    let mut digest = Sha512::new();
+    State::initialize(root.clone()).digest(ProtocolVersion::V1, &mut digest);
+    assert_eq!(
+        hex::encode(digest.finalize()),
+        "2981c1640429ee01905f8aeeb4abffbb2f5cd468cee6ec5086c4676f27ee2fd9aec7183ab4745aeddde08876ca9151da1293f1a781244a524150929d9e9fab67"
+    );
+
+    assert_eq!(
+        hex::encode(TrustStore::initialize(root).digest()),
+        "348cfb87d6e07f898df060f00442e31ba4d3f073fb290a2b3e6b315b64c06f9bbed244717b46cd8724b2c87402f450a886c116a79ed9f7538628e5b68d63529d"
+    );
+}
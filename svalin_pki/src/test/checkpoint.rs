@@ -0,0 +1,300 @@
+use std::sync::{Arc, RwLock};
+
+use crate::{
+    Credential, KeyPair, RenewalRequest, TrustStoreVerifier, Verifier, VerifyError,
+    get_current_timestamp,
+    secure_chain::{PositionComparison, merkle::MerkleIndex},
+    test::add_agent,
+    trust_store::TrustStore,
+};
+
+#[test]
+fn test_checkpoint() {
+    let root_credential = Credential::generate_root().unwrap();
+    let root = root_credential
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+
+    let mut server_store = TrustStore::initialize(root.clone());
+
+    let server = KeyPair::generate();
+    let cert = root_credential
+        .create_server_certificate_for_key(&server.export_public_key())
+        .unwrap();
+    let block = server_store.add(cert.clone(), &root_credential).unwrap();
+    server_store.apply(block);
+    let server = server.upgrade(cert.to_unverified()).unwrap();
+    let server_certificate = server.certificate().clone().to_unverified();
+
+    let agent = KeyPair::generate();
+    let cert = root_credential
+        .create_agent_certificate_for_key(&agent.export_public_key())
+        .unwrap();
+    let block = server_store.add(cert.clone(), &root_credential).unwrap();
+    server_store.apply(block);
+    let agent = agent.upgrade(cert.to_unverified()).unwrap();
+
+    let checkpoint = server_store.checkpoint(&server);
+    assert_eq!(checkpoint.sequence(), server_store.sequence());
+    let exported = server_store.export();
+
+    // the signer isn't taken from the imported state
+    TrustStore::import_checkpoint(exported.clone(), &checkpoint, &root, None).unwrap_err();
+    let mut member_store = TrustStore::import_checkpoint(
+        exported.clone(),
+        &checkpoint,
+        &root,
+        Some(&server_certificate),
+    )
+    .unwrap();
+    assert_eq!(member_store.digest(), server_store.digest());
+
+    // blocks after the checkpoint can be applied on top of it
+    let other = KeyPair::generate();
+    let cert = root_credential
+        .create_agent_certificate_for_key(&other.export_public_key())
+        .unwrap();
+    let block = server_store.add(cert, &root_credential).unwrap();
+    let unchecked = block.as_unchecked().clone();
+    server_store.apply(block);
+    let block = member_store.check(unchecked).unwrap();
+    member_store.apply(block);
+    assert_eq!(member_store.digest(), server_store.digest());
+
+    // the checkpoint doesn't match a newer state
+    TrustStore::import_checkpoint(
+        server_store.export(),
+        &checkpoint,
+        &root,
+        Some(&server_certificate),
+    )
+    .unwrap_err();
+
+    // only servers may sign checkpoints
+    let agent_checkpoint = TrustStore::import(exported.clone())
+        .unwrap()
+        .checkpoint(&agent);
+    TrustStore::import_checkpoint(
+        exported.clone(),
+        &agent_checkpoint,
+        &root,
+        Some(&agent.certificate().clone().to_unverified()),
+    )
+    .unwrap_err();
+
+    // a checkpoint of another root is rejected
+    let other_root = Credential::generate_root()
+        .unwrap()
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+    TrustStore::import_checkpoint(
+        exported,
+        &checkpoint,
+        &other_root,
+        Some(&server_certificate),
+    )
+    .unwrap_err();
+}
+
+#[test]
+fn test_accept_checkpoint() {
+    let root_credential = Credential::generate_root().unwrap();
+    let root = root_credential
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+
+    let mut server_store = TrustStore::initialize(root.clone());
+    let mut history = MerkleIndex::new();
+    let server = KeyPair::generate();
+    let cert = root_credential
+        .create_server_certificate_for_key(&server.export_public_key())
+        .unwrap();
+    let block = server_store.add(cert.clone(), &root_credential).unwrap();
+    history.push(block.as_unchecked()).unwrap();
+    server_store.apply(block);
+    let server = server.upgrade(cert.to_unverified()).unwrap();
+    let server_certificate = server.certificate().clone().to_unverified();
+
+    let (agent, block) = add_agent(&mut server_store, &root_credential);
+    history.push(block.as_unchecked()).unwrap();
+    let member_store = TrustStore::import(server_store.export()).unwrap();
+    let before_rotation = TrustStore::import(server_store.export()).unwrap();
+    let before_rotation_history = history.clone();
+
+    // the agent rotates its key, which revokes the previous one
+    let request = RenewalRequest::create_rotated(&agent, &KeyPair::generate());
+    let rotated = root_credential
+        .create_renewed_certificate(&request)
+        .unwrap();
+    let block = server_store
+        .rotate(&request, rotated, &root_credential)
+        .unwrap();
+    history.push(block.as_unchecked()).unwrap();
+    server_store.apply(block);
+    let rotated_store = TrustStore::import(server_store.export()).unwrap();
+    let (_, block) = add_agent(&mut server_store, &root_credential);
+    history.push(block.as_unchecked()).unwrap();
+
+    let checkpoint = server_store
+        .checkpoint_with_history(&server, &history)
+        .unwrap();
+    let exported = server_store.export();
+
+    // members with a history need the proof that the checkpoint extends it
+    member_store
+        .accept_checkpoint(exported.clone(), &checkpoint, None, None)
+        .unwrap_err();
+    let accepted = member_store
+        .accept_checkpoint(exported.clone(), &checkpoint, None, Some(&history))
+        .unwrap();
+    assert_eq!(accepted.digest(), server_store.digest());
+
+    // the history has to be the one the checkpoint commits to
+    let mut leaves = history.leaves().to_vec();
+    leaves.pop();
+    member_store
+        .accept_checkpoint(
+            exported.clone(),
+            &checkpoint,
+            None,
+            Some(&MerkleIndex::from_leaves(leaves)),
+        )
+        .unwrap_err();
+
+    // and it has to contain the current block of the member
+    let mut leaves = history.leaves().to_vec();
+    leaves[1] = leaves[0].clone();
+    let other_history = MerkleIndex::from_leaves(leaves);
+    let other_checkpoint = server_store
+        .checkpoint_with_history(&server, &other_history)
+        .unwrap();
+    member_store
+        .accept_checkpoint(
+            exported.clone(),
+            &other_checkpoint,
+            None,
+            Some(&other_history),
+        )
+        .unwrap_err();
+
+    // checkpoints have to be newer
+    accepted
+        .accept_checkpoint(exported.clone(), &checkpoint, None, Some(&history))
+        .unwrap_err();
+
+    // only empty members bootstrap, with the server certificate they know
+    let empty = TrustStore::initialize(root.clone());
+    empty
+        .accept_checkpoint(exported.clone(), &checkpoint, None, None)
+        .unwrap_err();
+    let bootstrapped = empty
+        .accept_checkpoint(exported, &checkpoint, Some(&server_certificate), None)
+        .unwrap();
+    assert_eq!(bootstrapped.digest(), server_store.digest());
+
+    // a checkpoint can't bring back a rotated key, even if its history
+    // contains the rotation
+    let mut forked = before_rotation;
+    let mut forked_history = before_rotation_history;
+    for _ in 0..2 {
+        let (_, block) = add_agent(&mut forked, &root_credential);
+        forked_history.push(block.as_unchecked()).unwrap();
+    }
+    let mut leaves = history.leaves()[..3].to_vec();
+    leaves.push(forked_history.leaves()[3].clone());
+    let forged_history = MerkleIndex::from_leaves(leaves);
+    let forged = forked
+        .checkpoint_with_history(&server, &forged_history)
+        .unwrap();
+    rotated_store
+        .accept_checkpoint(forked.export(), &forged, None, Some(&forged_history))
+        .unwrap_err();
+}
+
+#[tokio::test]
+async fn test_compare_positions() {
+    let root_credential = Credential::generate_root().unwrap();
+    let root = root_credential
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+
+    let mut store = TrustStore::initialize(root.clone());
+    let mut forked = TrustStore::initialize(root.clone());
+
+    let agent = KeyPair::generate();
+    let cert = root_credential
+        .create_agent_certificate_for_key(&agent.export_public_key())
+        .unwrap();
+    let block = store.add(cert.clone(), &root_credential).unwrap();
+    let first_block = block.as_unchecked().clone();
+    store.apply(block);
+    let agent = agent.upgrade(cert.to_unverified()).unwrap();
+
+    // the fork accepted a different block at the same sequence
+    let other = KeyPair::generate();
+    let cert = root_credential
+        .create_agent_certificate_for_key(&other.export_public_key())
+        .unwrap();
+    let block = forked.add(cert, &root_credential).unwrap();
+    forked.apply(block);
+
+    let position = store.checkpoint(&agent);
+    assert_eq!(store.compare_position(&position), PositionComparison::Equal);
+    assert_eq!(
+        forked.compare_position(&position),
+        PositionComparison::Conflict
+    );
+    let forked_position = forked.checkpoint(&root_credential);
+    assert!(position.matches_block(&first_block));
+    assert!(!forked_position.matches_block(&first_block));
+
+    // once the store moves on, older positions have to be compared with the
+    // stored blocks
+    let third = KeyPair::generate();
+    let cert = root_credential
+        .create_agent_certificate_for_key(&third.export_public_key())
+        .unwrap();
+    let block = store.add(cert.clone(), &root_credential).unwrap();
+    store.apply(block);
+    let third = third.upgrade(cert.to_unverified()).unwrap();
+    assert_eq!(
+        store.compare_position(&position),
+        PositionComparison::PeerBehind
+    );
+    assert_eq!(
+        forked.compare_position(&store.checkpoint(&agent)),
+        PositionComparison::PeerAhead
+    );
+
+    // a single member can't make the trust store unusable
+    assert!(!forked.report_conflict(position.clone()));
+    assert!(!forked.report_conflict(position));
+    assert!(forked.conflict().is_none());
+
+    // reports of enough members make the trust store unusable until reset
+    assert!(forked.report_conflict(store.checkpoint(&third)));
+    assert_eq!(forked.conflict_reports().count(), 2);
+    forked.reset_conflict();
+    assert!(forked.conflict().is_none());
+    assert_eq!(forked.conflict_reports().count(), 0);
+
+    // root is trusted on its own
+    assert!(forked.report_conflict(store.checkpoint(&root_credential)));
+    let verifier = TrustStoreVerifier::new(Arc::new(RwLock::new(forked)));
+    let result = verifier
+        .verify_spki_hash(root.spki_hash(), get_current_timestamp())
+        .await;
+    assert!(matches!(result, Err(VerifyError::ChainConflict)));
+}
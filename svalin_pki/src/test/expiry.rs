@@ -0,0 +1,86 @@
+use crate::{
+    CertificateType, Credential, KeyPair, get_current_timestamp,
+    secure_chain::{ChainState, CreateBlockError},
+    trust_store::{Error, State, Transaction, TrustStore},
+};
+
+#[test]
+fn test_expiring_certificates() {
+    let root_credential = Credential::generate_root().unwrap();
+    let root = root_credential
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+    let mut trust_store = TrustStore::initialize(root);
+
+    let keypair = KeyPair::generate();
+    let agent = root_credential
+        .create_agent_certificate_for_key(&keypair.export_public_key())
+        .unwrap();
+    let block = trust_store.add(agent.clone(), &root_credential).unwrap();
+    trust_store.apply(block);
+
+    let now = get_current_timestamp();
+    assert!(trust_store.expiring_within(0, now).is_empty());
+    assert!(trust_store.expired(now).is_empty());
+
+    let window = agent.not_after() - now + 1;
+    let expiring = trust_store.expiring_within(window, now);
+    assert_eq!(expiring.len(), 1);
+    assert_eq!(expiring[&CertificateType::Agent], vec![&agent]);
+
+    let later = root_credential.certificate().not_after() + 1;
+    let expiring = trust_store.expiring_within(0, later);
+    assert_eq!(expiring[&CertificateType::Agent], vec![&agent]);
+    assert_eq!(
+        expiring[&CertificateType::Root],
+        vec![root_credential.certificate()]
+    );
+
+    // the root can never be removed, even once it expired
+    assert_eq!(trust_store.expired(later), vec![&agent]);
+
+    // the certificate is still valid, so it can't be removed yet
+    trust_store
+        .remove_expired(&agent, &root_credential)
+        .unwrap_err();
+
+    // the root issued the agent, so it is never removed
+    assert!(matches!(
+        trust_store.remove_expired(root_credential.certificate(), &root_credential),
+        Err(CreateBlockError::InvalidTransaction(
+            Error::CertificateIsIssuer
+        ))
+    ));
+}
+
+#[test]
+fn test_replay_issuer_removal() {
+    let root_credential = Credential::generate_root().unwrap();
+    let root = root_credential
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+    let signer = root_credential.certificate().spki_hash();
+
+    let keypair = KeyPair::generate();
+    let agent = root_credential
+        .create_agent_certificate_for_key(&keypair.export_public_key())
+        .unwrap();
+
+    let mut state = State::initialize(root);
+    let add = Transaction::Add(agent.to_unverified());
+    let now = get_current_timestamp();
+    state.check(signer, now, &add).unwrap();
+    state.apply(signer, now, &add);
+
+    // chains created before issuers were kept may remove them, their blocks
+    // still have to be accepted
+    let removal = Transaction::RemoveExpired(root_credential.certificate().clone().to_unverified());
+    let later = root_credential.certificate().not_after() + 1;
+    state.check(signer, later, &removal).unwrap();
+}
@@ -0,0 +1,119 @@
+use crate::{
+    Credential,
+    secure_chain::merkle::{Bisection, MerkleIndex},
+    test::add_agent,
+    trust_store::TrustStore,
+};
+
+#[test]
+fn test_merkle_proofs() {
+    let root_credential = Credential::generate_root().unwrap();
+    let root = root_credential
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+
+    let mut store = TrustStore::initialize(root);
+    let blocks: Vec<_> = (0..11)
+        .map(|_| add_agent(&mut store, &root_credential).1.to_unchecked())
+        .collect();
+
+    let index = MerkleIndex::rebuild(&blocks).unwrap();
+    assert_eq!(index.size(), 11);
+
+    // blocks have to be indexed in order
+    let mut partial = MerkleIndex::new();
+    partial.push(&blocks[1]).unwrap_err();
+    partial.push(&blocks[0]).unwrap();
+    assert_eq!(partial.root(), index.root_at(1).unwrap());
+
+    // the leaves are enough to restore the index without the blocks
+    let restored = MerkleIndex::from_leaves(index.leaves().to_vec());
+    assert_eq!(restored.root(), index.root());
+    let proof = restored.consistency_proof(3, 11).unwrap();
+    assert!(proof.verify(&index.root_at(3).unwrap(), &index.root()));
+
+    for size in 1..=index.size() {
+        let root = index.root_at(size).unwrap();
+
+        for block in &blocks[..size as usize] {
+            let proof = index.inclusion_proof(block.sequence(), size).unwrap();
+            assert!(proof.verify(block, &root));
+
+            let other = &blocks[(block.sequence() % size) as usize];
+            if other.sequence() != block.sequence() {
+                assert!(!proof.verify(other, &root));
+            }
+        }
+
+        for old_size in 1..=size {
+            let old_root = index.root_at(old_size).unwrap();
+            let proof = index.consistency_proof(old_size, size).unwrap();
+            assert!(proof.verify(&old_root, &root));
+
+            if old_size < size {
+                let wrong_root = index.root_at(old_size + 1).unwrap();
+                assert!(!proof.verify(&wrong_root, &root));
+            }
+        }
+    }
+
+    assert!(index.inclusion_proof(12, 11).is_none());
+    assert!(index.inclusion_proof(3, 12).is_none());
+    assert!(index.consistency_proof(0, 5).is_none());
+    assert!(index.consistency_proof(6, 5).is_none());
+}
+
+#[test]
+fn test_merkle_bisection() {
+    let root_credential = Credential::generate_root().unwrap();
+    let root = root_credential
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+
+    let mut store = TrustStore::initialize(root.clone());
+    let mut forked = TrustStore::initialize(root);
+
+    let mut blocks = Vec::new();
+    for _ in 0..5 {
+        let block = add_agent(&mut store, &root_credential).1.to_unchecked();
+        let checked = forked.check(block.clone()).unwrap();
+        forked.apply(checked);
+        blocks.push(block);
+    }
+    let mut forked_blocks = blocks.clone();
+
+    for _ in 0..4 {
+        blocks.push(add_agent(&mut store, &root_credential).1.to_unchecked());
+    }
+    for _ in 0..7 {
+        forked_blocks.push(add_agent(&mut forked, &root_credential).1.to_unchecked());
+    }
+
+    let index = MerkleIndex::rebuild(&blocks).unwrap();
+    let forked_index = MerkleIndex::rebuild(&forked_blocks).unwrap();
+
+    assert_eq!(index.first_difference(&forked_index), Some(6));
+    assert_eq!(forked_index.first_difference(&index), Some(6));
+
+    // a prefix of the history is no difference
+    let prefix = MerkleIndex::rebuild(&blocks[..3]).unwrap();
+    assert_eq!(index.first_difference(&prefix), None);
+    assert_eq!(index.first_difference(&index), None);
+    assert_eq!(index.first_difference(&MerkleIndex::new()), None);
+
+    // the bisection only needs the roots of the other side
+    let mut bisection = Bisection::new(index.size(), forked_index.size());
+    let mut requests = 0;
+    while let Some(size) = bisection.next_size() {
+        requests += 1;
+        bisection.record(size, index.root_at(size) == forked_index.root_at(size));
+    }
+    assert_eq!(bisection.first_difference(), Some(6));
+    assert!(requests <= 5);
+}
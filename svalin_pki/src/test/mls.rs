@@ -1,14 +1,20 @@
-use std::{cell::RefCell, collections::HashMap, fmt::Display, sync::Arc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    sync::{Arc, Mutex},
+};
 
 use openmls::group::GroupId;
 use openmls_sqlx_storage::SqliteStorageProvider;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 
 use crate::{
     Certificate, Credential, KeyPair, SpkiHash, Verifier, VerifyError,
     mls::{
-        agent::MlsAgent,
+        SvalinGroupId,
+        agent::{AgentMessageContent, MlsAgent},
         client::{MessageDataContent, MlsClient},
         key_package::{KeyPackage, UnverifiedKeyPackage},
         key_retriever::KeyRetriever,
@@ -17,7 +23,8 @@ use crate::{
         public_processor::PublicProcessorHandle,
         server::MlsServer,
         transport_types::{
-            MessageToMember, MessageToMemberTransport, MessageToServer, MessageTypes,
+            MessagePayload, MessageToMember, MessageToMemberTransport, MessageToSend,
+            MessageToServer, MessageToServerTransport, MessageTypes, SvalinMessage,
         },
     },
 };
@@ -46,12 +53,51 @@ async fn test_key_package_creation() {
     let _key_package = processor.create_key_package().await.unwrap();
 }
 
+#[tokio::test]
+async fn test_last_resort_key_package() {
+    let credential = Credential::generate_root().unwrap();
+    let processor = create_processor(credential).await;
+
+    let key_package = processor.create_key_package().await.unwrap();
+    assert!(!key_package.is_last_resort());
+    assert!(key_package.not_after() > crate::get_current_timestamp());
+
+    let last_resort = processor.create_last_resort_key_package().await.unwrap();
+    assert!(last_resort.is_last_resort());
+    assert!(last_resort.not_after() > crate::get_current_timestamp());
+}
+
 #[derive(Deserialize)]
 struct Types {}
 
 impl MessageTypes for Types {
-    type Report = String;
-    type MetaInfo = String;
+    type Report = Report;
+    type MetaInfo = MetaInfo;
+    type Instruction = Instruction;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Report(String);
+
+impl MessagePayload for Report {
+    const TAG: &'static str = "report";
+    const VERSION: u16 = 1;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct MetaInfo(String);
+
+impl MessagePayload for MetaInfo {
+    const TAG: &'static str = "meta_info";
+    const VERSION: u16 = 1;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Instruction(String);
+
+impl MessagePayload for Instruction {
+    const TAG: &'static str = "instruction";
+    const VERSION: u16 = 1;
 }
 
 #[tokio::test]
@@ -170,10 +216,14 @@ async fn test_public_processor() {
     };
 
     let processed = member1.process_message(commit.clone()).await.unwrap();
-    let ProcessedContent::Commit(commit) = processed.content else {
+    let ProcessedContent::Commit(staged) = processed.content else {
         panic!("wrong message type")
     };
-    member1.commit(commit).await.unwrap();
+    member1.commit(staged).await.unwrap();
+
+    // the sender merges its commit once the server delivered it back
+    let processed = member2.process_message(commit).await.unwrap();
+    assert!(matches!(processed.content, ProcessedContent::OwnCommit));
 
     let MessageToMember::Welcome(welcome) = MessageToMemberTransport::Welcome(add_member.welcome)
         .unpack()
@@ -231,15 +281,23 @@ async fn test_public_processor() {
 #[derive(Clone)]
 struct TestRetriever {
     key_packages: Arc<RefCell<HashMap<SpkiHash, UnverifiedKeyPackage>>>,
+    required_members: Arc<RefCell<HashMap<SvalinGroupId, Vec<SpkiHash>>>>,
 }
 
 impl TestRetriever {
     fn new() -> Self {
         Self {
             key_packages: Arc::new(RefCell::new(HashMap::new())),
+            required_members: Arc::new(RefCell::new(HashMap::new())),
         }
     }
 
+    /// Overrides the required members of a group, which are all members with
+    /// a key package by default.
+    fn set_required_members(&self, group: SvalinGroupId, members: Vec<SpkiHash>) {
+        self.required_members.borrow_mut().insert(group, members);
+    }
+
     fn add(&self, key_package: KeyPackage) {
         self.key_packages
             .borrow_mut()
@@ -261,8 +319,11 @@ impl KeyRetriever for TestRetriever {
 
     async fn get_required_group_members(
         &self,
-        _id: &crate::mls::SvalinGroupId,
+        id: &crate::mls::SvalinGroupId,
     ) -> Result<Vec<crate::SpkiHash>, Self::Error> {
+        if let Some(required) = self.required_members.borrow().get(id) {
+            return Ok(required.clone());
+        }
         Ok(self.key_packages.borrow().keys().cloned().collect())
     }
 
@@ -280,18 +341,39 @@ impl KeyRetriever for TestRetriever {
 
 #[derive(Debug, Clone)]
 struct TestVerifier {
-    known: HashMap<SpkiHash, Certificate>,
+    /// Shared with all clones, so certificates issued later are known to
+    /// every member.
+    known: Arc<Mutex<HashMap<SpkiHash, Certificate>>>,
+    revoked: Arc<Mutex<HashSet<SpkiHash>>>,
 }
 
 impl TestVerifier {
     fn new() -> Self {
         Self {
-            known: HashMap::new(),
+            known: Arc::new(Mutex::new(HashMap::new())),
+            revoked: Arc::new(Mutex::new(HashSet::new())),
         }
     }
 
     fn push(&mut self, cert: Certificate) {
-        self.known.insert(cert.spki_hash().clone(), cert);
+        self.known
+            .lock()
+            .unwrap()
+            .insert(cert.spki_hash().clone(), cert);
+    }
+
+    /// Drops the certificate for this verifier and all its clones.
+    fn revoke(&self, spki_hash: &SpkiHash) {
+        self.revoked.lock().unwrap().insert(spki_hash.clone());
+    }
+
+    /// A copy with its own revocations, for a member whose view of the trust
+    /// store differs from everyone else's.
+    fn detached(&self) -> Self {
+        Self {
+            known: self.known.clone(),
+            revoked: Arc::new(Mutex::new(self.revoked.lock().unwrap().clone())),
+        }
     }
 }
 
@@ -301,14 +383,21 @@ impl Verifier for TestVerifier {
         spki_hash: &SpkiHash,
         time: u64,
     ) -> Result<Certificate, crate::VerifyError> {
+        if self.revoked.lock().unwrap().contains(spki_hash) {
+            return Err(VerifyError::UnknownCertificate);
+        }
+
         let cert = self
             .known
+            .lock()
+            .unwrap()
             .get(spki_hash)
+            .cloned()
             .ok_or(VerifyError::UnknownCertificate)?;
 
         cert.check_validity_at(time)?;
 
-        Ok(cert.clone())
+        Ok(cert)
     }
 }
 
@@ -371,7 +460,7 @@ async fn test_device_group() {
 
     client.handle_message(&welcome[0].message).await.unwrap();
 
-    let report = "Test Data".to_string();
+    let report = Report("Test Data".to_string());
     let to_server = agent.send_report(report.clone()).await.unwrap();
 
     let to_send = server.process_message(to_server).await.unwrap();
@@ -384,4 +473,739 @@ async fn test_device_group() {
 
     assert_eq!(&sender, agent_credential.certificate().spki_hash());
     assert_eq!(&received_report, &report);
+
+    let instruction = Instruction("Test Instruction".to_string());
+    let to_server = client
+        .send_instruction(
+            agent_credential.certificate().spki_hash().clone(),
+            instruction.clone(),
+        )
+        .await
+        .unwrap();
+
+    let to_send = server.process_message(to_server).await.unwrap();
+
+    let AgentMessageContent::Instruction(sender, received_instruction) =
+        agent.handle_message(&to_send[0].message).await.unwrap()
+    else {
+        panic!("wrong message type")
+    };
+
+    assert_eq!(&sender, client_credential.certificate().spki_hash());
+    assert_eq!(&received_instruction, &instruction);
+
+    // the only group agents join is the global group
+    assert!(agent.handle_message(&welcome[0].message).await.is_err());
+}
+
+fn create_agent_credential(root: &Credential, verifier: &mut TestVerifier) -> Credential {
+    let keypair = KeyPair::generate();
+    let cert = root
+        .create_agent_certificate_for_key(&keypair.export_public_key())
+        .unwrap();
+    let credential = keypair.upgrade(cert.to_unverified()).unwrap();
+    verifier.push(credential.certificate().clone());
+    credential
+}
+
+async fn create_agent(
+    credential: &Credential,
+    verifier: &TestVerifier,
+    retriever: &TestRetriever,
+) -> MlsAgent<Types, TestRetriever, TestVerifier> {
+    let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    let storage = SqliteStorageProvider::<PostcardCodec>::new(pool);
+    storage.run_migrations().await.unwrap();
+
+    MlsAgent::<Types, _, _>::new(
+        credential.clone(),
+        storage,
+        retriever.clone(),
+        verifier.clone(),
+    )
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_global_group() {
+    let mut verifier = TestVerifier::new();
+    let retriever = TestRetriever::new();
+
+    let root_credential = Credential::generate_root().unwrap();
+    verifier.push(root_credential.certificate().clone());
+    let root_hash = root_credential.certificate().spki_hash().clone();
+    retriever.set_required_members(SvalinGroupId::GlobalGroup, vec![root_hash.clone()]);
+
+    let agent1_credential = create_agent_credential(&root_credential, &mut verifier);
+    let agent2_credential = create_agent_credential(&root_credential, &mut verifier);
+    let session_credential = root_credential.create_user_device_credential().unwrap();
+    verifier.push(session_credential.certificate().clone());
+
+    let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    let root_storage = SqliteStorageProvider::<PostcardCodec>::new(pool);
+    root_storage.run_migrations().await.unwrap();
+    let root = MlsClient::<Types, _, _>::new(
+        root_credential.clone(),
+        root_storage.into(),
+        retriever.clone(),
+        verifier.clone(),
+    )
+    .unwrap();
+
+    let agent1 = create_agent(&agent1_credential, &verifier, &retriever).await;
+    let agent2 = create_agent(&agent2_credential, &verifier, &retriever).await;
+    retriever.add(agent1.create_key_package().await.unwrap());
+    retriever.add(agent2.create_key_package().await.unwrap());
+    let agent1_hash = agent1_credential.certificate().spki_hash().clone();
+    let agent2_hash = agent2_credential.certificate().spki_hash().clone();
+
+    // only root can create the global group
+    let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    let session_storage = SqliteStorageProvider::<PostcardCodec>::new(pool);
+    session_storage.run_migrations().await.unwrap();
+    let session = MlsClient::<Types, _, _>::new(
+        session_credential,
+        session_storage.into(),
+        retriever.clone(),
+        verifier.clone(),
+    )
+    .unwrap();
+    assert!(session.create_global_group_if_missing().await.is_err());
+
+    let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    let storage = SqliteStorageProvider::<PostcardCodec>::new(pool);
+    storage.run_migrations().await.unwrap();
+    let server = MlsServer::new(storage, verifier.clone(), retriever.clone());
+
+    let new_group = root
+        .create_global_group_if_missing()
+        .await
+        .unwrap()
+        .unwrap();
+    server.process_message(new_group).await.unwrap();
+    assert!(
+        root.create_global_group_if_missing()
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // all missing members are added with one commit
+    let expected = vec![root_hash.clone(), agent1_hash.clone(), agent2_hash.clone()];
+    let add = root.sync_global_group(&expected).await.unwrap().unwrap();
+    for to_send in server.process_message(add).await.unwrap() {
+        if to_send.receivers.contains(&root_hash) {
+            root.handle_message(&to_send.message).await.unwrap();
+        }
+        for (hash, agent) in [(&agent1_hash, &agent1), (&agent2_hash, &agent2)] {
+            if to_send.receivers.contains(hash) {
+                agent.handle_message(&to_send.message).await.unwrap();
+            }
+        }
+    }
+    assert!(agent1.joined_global_group().await.unwrap());
+    assert!(agent2.joined_global_group().await.unwrap());
+
+    // everyone is a member now
+    assert!(root.sync_global_group(&expected).await.unwrap().is_none());
+
+    let instruction = Instruction("Update channel is now beta".to_string());
+    let to_server = root
+        .broadcast_instruction(instruction.clone())
+        .await
+        .unwrap();
+    let to_send = server.process_message(to_server).await.unwrap();
+    assert!(to_send[0].receivers.contains(&agent1_hash));
+    assert!(to_send[0].receivers.contains(&agent2_hash));
+
+    for agent in [&agent1, &agent2] {
+        let AgentMessageContent::Instruction(sender, received) =
+            agent.handle_message(&to_send[0].message).await.unwrap()
+        else {
+            panic!("wrong message type")
+        };
+        assert_eq!(sender, root_hash);
+        assert_eq!(received, instruction);
+    }
+
+    // members which aren't listed anymore are removed
+    let expected = vec![root_hash.clone(), agent1_hash.clone()];
+    let remove = root.sync_global_group(&expected).await.unwrap().unwrap();
+    for to_send in server.process_message(remove).await.unwrap() {
+        if to_send.receivers.contains(&root_hash) {
+            root.handle_message(&to_send.message).await.unwrap();
+        }
+    }
+    assert!(
+        !root
+            .is_member(&SvalinGroupId::GlobalGroup, agent2_hash.clone())
+            .await
+            .unwrap()
+    );
+    assert!(
+        root.is_member(&SvalinGroupId::GlobalGroup, agent1_hash.clone())
+            .await
+            .unwrap()
+    );
+    assert!(root.sync_global_group(&expected).await.unwrap().is_none());
+}
+
+async fn create_client(
+    credential: &Credential,
+    verifier: &TestVerifier,
+    retriever: &TestRetriever,
+) -> MlsClient<Types, TestRetriever, TestVerifier> {
+    let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    let storage = SqliteStorageProvider::<PostcardCodec>::new(pool);
+    storage.run_migrations().await.unwrap();
+
+    MlsClient::<Types, _, _>::new(
+        credential.clone(),
+        storage.into(),
+        retriever.clone(),
+        verifier.clone(),
+    )
+    .unwrap()
+}
+
+/// A device group created by its agent, with root as required member and
+/// sessions of root added afterwards.
+struct DeviceGroup {
+    verifier: TestVerifier,
+    /// Only used by the agent, see [`DeviceGroup::revoke`].
+    agent_verifier: TestVerifier,
+    retriever: TestRetriever,
+    server: MlsServer<TestRetriever, TestVerifier>,
+    group: SvalinGroupId,
+    root_credential: Credential,
+    root_hash: SpkiHash,
+    root: MlsClient<Types, TestRetriever, TestVerifier>,
+    agent_hash: SpkiHash,
+    agent: MlsAgent<Types, TestRetriever, TestVerifier>,
+    sessions: Vec<(SpkiHash, MlsClient<Types, TestRetriever, TestVerifier>)>,
+}
+
+impl DeviceGroup {
+    async fn new(session_count: usize) -> Self {
+        let mut verifier = TestVerifier::new();
+        let retriever = TestRetriever::new();
+
+        let root_credential = Credential::generate_root().unwrap();
+        verifier.push(root_credential.certificate().clone());
+        let session_credentials = (0..session_count)
+            .map(|_| {
+                let credential = root_credential.create_user_device_credential().unwrap();
+                verifier.push(credential.certificate().clone());
+                credential
+            })
+            .collect::<Vec<_>>();
+        let agent_credential = create_agent_credential(&root_credential, &mut verifier);
+
+        let root_hash = root_credential.certificate().spki_hash().clone();
+        let agent_hash = agent_credential.certificate().spki_hash().clone();
+
+        let agent_verifier = verifier.detached();
+        let root = create_client(&root_credential, &verifier, &retriever).await;
+        let agent = create_agent(&agent_credential, &agent_verifier, &retriever).await;
+        let mut sessions = Vec::new();
+        for credential in &session_credentials {
+            let session = create_client(credential, &verifier, &retriever).await;
+            sessions.push((credential.certificate().spki_hash().clone(), session));
+        }
+
+        let group = SvalinGroupId::DeviceGroup(agent_hash.clone());
+        retriever.set_required_members(group.clone(), vec![root_hash.clone(), agent_hash.clone()]);
+        retriever.add(root.create_key_package().await.unwrap());
+
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let storage = SqliteStorageProvider::<PostcardCodec>::new(pool);
+        storage.run_migrations().await.unwrap();
+        let server = MlsServer::new(storage, verifier.clone(), retriever.clone());
+
+        let new_group = agent
+            .create_device_group_if_missing()
+            .await
+            .unwrap()
+            .unwrap();
+        let welcome = server.process_message(new_group).await.unwrap();
+        root.handle_message(&welcome[0].message).await.unwrap();
+
+        let device_group = Self {
+            verifier,
+            agent_verifier,
+            retriever,
+            server,
+            group,
+            root_credential,
+            root_hash,
+            root,
+            agent_hash,
+            agent,
+            sessions,
+        };
+
+        for (_, session) in &device_group.sessions {
+            let key_package = session.create_key_package().await.unwrap();
+            let add = device_group
+                .root
+                .add_member(&device_group.group, key_package)
+                .await
+                .unwrap();
+            device_group.send(add).await;
+        }
+
+        device_group
+    }
+
+    /// Revokes the certificate for all members, including the agent.
+    fn revoke(&self, spki_hash: &SpkiHash) {
+        self.verifier.revoke(spki_hash);
+        self.agent_verifier.revoke(spki_hash);
+    }
+
+    /// Processes the message on the server and delivers the result to all
+    /// receivers, including the sender of a commit.
+    async fn send(&self, message: MessageToServerTransport) -> Vec<MessageToSend> {
+        let to_send = self.server.process_message(message).await.unwrap();
+        for message in &to_send {
+            self.deliver(message).await;
+        }
+        to_send
+    }
+
+    async fn deliver(&self, to_send: &MessageToSend) {
+        if to_send.receivers.contains(&self.agent_hash) {
+            self.agent.handle_message(&to_send.message).await.unwrap();
+        }
+        if to_send.receivers.contains(&self.root_hash) {
+            self.root.handle_message(&to_send.message).await.unwrap();
+        }
+        for (hash, session) in &self.sessions {
+            if to_send.receivers.contains(hash) {
+                session.handle_message(&to_send.message).await.unwrap();
+            }
+        }
+    }
+
+    /// Sends a report of the agent and returns what the given member
+    /// decrypted.
+    async fn receive_report(
+        &self,
+        member: &MlsClient<Types, TestRetriever, TestVerifier>,
+        text: &str,
+    ) -> anyhow::Result<Report> {
+        let report = Report(text.to_string());
+        let to_server = self.agent.send_report(report).await.unwrap();
+        let to_send = self.server.process_message(to_server).await.unwrap();
+        let MessageDataContent::Report(_, received) =
+            member.handle_message(&to_send[0].message).await?.content
+        else {
+            panic!("wrong message type")
+        };
+        Ok(received)
+    }
+}
+
+#[tokio::test]
+async fn test_member_removal() {
+    let device_group = DeviceGroup::new(2).await;
+    let group = &device_group.group;
+    let (session1_hash, session1) = &device_group.sessions[0];
+    let (session2_hash, session2) = &device_group.sessions[1];
+    let root = &device_group.root;
+    let agent = &device_group.agent;
+
+    assert!(root.remove_revoked_members(group).await.unwrap().is_none());
+
+    // root removes the revoked session, which moves the group to a new epoch
+    device_group.revoke(session1_hash);
+    let removal = root.remove_revoked_members(group).await.unwrap().unwrap();
+    let to_send = device_group.server.process_message(removal).await.unwrap();
+    for mut to_send in to_send {
+        to_send.remove_receiver(session1_hash);
+        device_group.deliver(&to_send).await;
+    }
+    assert!(!root.is_member(group, session1_hash.clone()).await.unwrap());
+
+    let report = Report("After removal".to_string());
+    let to_server = agent.send_report(report.clone()).await.unwrap();
+    let to_send = device_group
+        .server
+        .process_message(to_server)
+        .await
+        .unwrap();
+    assert!(!to_send[0].receivers.contains(session1_hash));
+    assert!(session1.handle_message(&to_send[0].message).await.is_err());
+    for member in [root, session2] {
+        let MessageDataContent::Report(_, received) = member
+            .handle_message(&to_send[0].message)
+            .await
+            .unwrap()
+            .content
+        else {
+            panic!("wrong message type")
+        };
+        assert_eq!(received, report);
+    }
+
+    // the agent removes revoked members from its own device group
+    device_group.revoke(session2_hash);
+    let removal = agent.remove_revoked_members().await.unwrap().unwrap();
+    let to_send = device_group.server.process_message(removal).await.unwrap();
+    for mut to_send in to_send {
+        to_send.remove_receiver(session2_hash);
+        device_group.deliver(&to_send).await;
+    }
+    assert!(!root.is_member(group, session2_hash.clone()).await.unwrap());
+
+    // required members stay, even if they can't be verified anymore
+    device_group.revoke(&device_group.root_hash);
+    assert!(agent.remove_revoked_members().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_agent_removes_only_revoked_members() {
+    let device_group = DeviceGroup::new(1).await;
+    let (session_hash, session) = &device_group.sessions[0];
+
+    // the agent considers a session revoked which everyone else still trusts
+    device_group.agent_verifier.revoke(session_hash);
+    let removal = device_group
+        .agent
+        .remove_revoked_members()
+        .await
+        .unwrap()
+        .unwrap();
+    let to_send = device_group.server.process_message(removal).await.unwrap();
+
+    for member in [&device_group.root, session] {
+        assert!(member.handle_message(&to_send[0].message).await.is_err());
+        assert!(
+            member
+                .is_member(&device_group.group, session_hash.clone())
+                .await
+                .unwrap()
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_key_update() {
+    let device_group = DeviceGroup::new(1).await;
+    let group = &device_group.group;
+    let (_, session) = &device_group.sessions[0];
+    let root = &device_group.root;
+    let agent = &device_group.agent;
+
+    // the agent updates its key, all members follow
+    let update = agent.update_key().await.unwrap().unwrap();
+    // only one commit can be pending at a time
+    assert!(agent.update_key().await.is_err());
+    let to_send = device_group.send(update).await;
+    assert!(to_send[0].receivers.contains(&device_group.root_hash));
+    assert!(to_send[0].receivers.contains(&device_group.agent_hash));
+
+    for member in [root, session] {
+        let received = device_group.receive_report(member, "New epoch").await;
+        assert_eq!(received.unwrap(), Report("New epoch".to_string()));
+    }
+
+    // clients update their keys in the same way
+    let update = root.update_key(group).await.unwrap().unwrap();
+    device_group.send(update).await;
+
+    for member in [root, session] {
+        let received = device_group.receive_report(member, "Newer epoch").await;
+        assert_eq!(received.unwrap(), Report("Newer epoch".to_string()));
+    }
+}
+
+#[tokio::test]
+async fn test_concurrent_key_updates() {
+    let device_group = DeviceGroup::new(1).await;
+    let group = &device_group.group;
+    let (_, session) = &device_group.sessions[0];
+    let root = &device_group.root;
+    let agent = &device_group.agent;
+
+    // both commit on the same epoch, the server accepts the first one
+    let agent_update = agent.update_key().await.unwrap().unwrap();
+    let root_update = root.update_key(group).await.unwrap().unwrap();
+
+    device_group.send(agent_update).await;
+    assert!(
+        device_group
+            .server
+            .process_message(root_update)
+            .await
+            .is_err()
+    );
+
+    // root dropped its refused commit and follows the agent
+    for member in [root, session] {
+        let received = device_group.receive_report(member, "Agent won").await;
+        assert_eq!(received.unwrap(), Report("Agent won".to_string()));
+    }
+
+    // and commits again on top of the new epoch
+    let root_update = root.update_key(group).await.unwrap().unwrap();
+    device_group.send(root_update).await;
+    for member in [root, session] {
+        let received = device_group.receive_report(member, "Root retried").await;
+        assert_eq!(received.unwrap(), Report("Root retried".to_string()));
+    }
+
+    // the agent drops a refused commit right away, before the winning one
+    // arrives
+    let agent_update = agent.update_key().await.unwrap().unwrap();
+    let root_update = root.update_key(group).await.unwrap().unwrap();
+    let to_send = device_group
+        .server
+        .process_message(root_update)
+        .await
+        .unwrap();
+    assert!(
+        device_group
+            .server
+            .process_message(agent_update)
+            .await
+            .is_err()
+    );
+    agent.clear_pending_commit().await.unwrap();
+    for to_send in &to_send {
+        device_group.deliver(to_send).await;
+    }
+    let agent_update = agent.update_key().await.unwrap().unwrap();
+    device_group.send(agent_update).await;
+
+    for member in [root, session] {
+        let received = device_group.receive_report(member, "Agent retried").await;
+        assert_eq!(received.unwrap(), Report("Agent retried".to_string()));
+    }
+}
+
+#[tokio::test]
+async fn test_agent_rotation() {
+    let device_group = DeviceGroup::new(0).await;
+    let root = &device_group.root;
+    let retriever = &device_group.retriever;
+
+    // root keeps the meta information of the device in its meta group
+    let meta_group = SvalinGroupId::DeviceMetaGroup(device_group.agent_hash.clone());
+    retriever.set_required_members(meta_group.clone(), vec![device_group.root_hash.clone()]);
+    let created = root
+        .create_meta_group_if_missing(device_group.agent_hash.clone())
+        .await
+        .unwrap()
+        .unwrap();
+    device_group.server.process_message(created).await.unwrap();
+
+    // the agent rotates its key, the previous one leaves the trust store
+    let rotated = create_agent_credential(
+        &device_group.root_credential,
+        &mut device_group.verifier.clone(),
+    );
+    let rotated_hash = rotated.certificate().spki_hash().clone();
+    device_group.revoke(&device_group.agent_hash);
+
+    // on start the agent creates the device group of its new key, which
+    // welcomes the same required members
+    let group = SvalinGroupId::DeviceGroup(rotated_hash.clone());
+    retriever.set_required_members(
+        group.clone(),
+        vec![device_group.root_hash.clone(), rotated_hash.clone()],
+    );
+    retriever.add(root.create_key_package().await.unwrap());
+    let agent = create_agent(&rotated, &device_group.verifier, retriever).await;
+    let new_group = agent
+        .create_device_group_if_missing()
+        .await
+        .unwrap()
+        .unwrap();
+    let welcome = device_group
+        .server
+        .process_message(new_group)
+        .await
+        .unwrap();
+    root.handle_message(&welcome[0].message).await.unwrap();
+    assert!(root.is_member(&group, rotated_hash.clone()).await.unwrap());
+
+    // the old leaf can't be verified anymore, its groups are dropped
+    root.forget_rotated_device(&device_group.agent_hash)
+        .await
+        .unwrap();
+    assert!(!root.group_exists(&device_group.group).await.unwrap());
+    assert!(!root.group_exists(&meta_group).await.unwrap());
+
+    // reports of the new key arrive in the new group
+    let report = Report("Rotated".to_string());
+    let to_server = agent.send_report(report.clone()).await.unwrap();
+    let to_send = device_group
+        .server
+        .process_message(to_server)
+        .await
+        .unwrap();
+    let MessageDataContent::Report(sender, received) = root
+        .handle_message(&to_send[0].message)
+        .await
+        .unwrap()
+        .content
+    else {
+        panic!("wrong message type")
+    };
+    assert_eq!(sender, rotated_hash);
+    assert_eq!(received, report);
+
+    // the meta group moves to the new key as well
+    retriever.set_required_members(
+        SvalinGroupId::DeviceMetaGroup(rotated_hash.clone()),
+        vec![device_group.root_hash.clone()],
+    );
+    let created = root
+        .create_meta_group_if_missing(rotated_hash.clone())
+        .await
+        .unwrap()
+        .unwrap();
+    device_group.server.process_message(created).await.unwrap();
+    let meta_info = root
+        .send_meta_info(rotated_hash, MetaInfo("Moved".to_string()))
+        .await
+        .unwrap();
+    device_group
+        .server
+        .process_message(meta_info)
+        .await
+        .unwrap();
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Alert {
+    severity: u8,
+    text: String,
+}
+
+impl MessagePayload for Alert {
+    const TAG: &'static str = "alert";
+    const VERSION: u16 = 1;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct ReportV2 {
+    text: String,
+    generated_at: u64,
+}
+
+impl MessagePayload for ReportV2 {
+    const TAG: &'static str = "report";
+    const VERSION: u16 = 2;
+}
+
+#[test]
+fn test_message_envelope() {
+    let alert = Alert {
+        severity: 3,
+        text: "disk almost full".to_string(),
+    };
+    let message = SvalinMessage::new(&alert).unwrap();
+    let decoded = SvalinMessage::decode(&message.encode().unwrap()).unwrap();
+    assert_eq!(decoded, message);
+    assert_eq!(decoded.tag(), "alert");
+    assert!(decoded.is::<Alert>());
+    assert!(!decoded.is::<Report>());
+    assert_eq!(decoded.payload::<Alert>().unwrap(), alert);
+    assert!(decoded.payload::<Report>().is_err());
+
+    // the same type in another version is a different type
+    let report = SvalinMessage::new(&Report("old layout".to_string())).unwrap();
+    assert!(!report.is::<ReportV2>());
+    assert!(report.payload::<ReportV2>().is_err());
+
+    // envelopes of a later layout are rejected instead of being misread
+    let mut encoded = postcard::to_stdvec(&2u16).unwrap();
+    encoded.extend_from_slice(&message.encode().unwrap()[1..]);
+    assert!(SvalinMessage::decode(&encoded).is_err());
+}
+
+#[tokio::test]
+async fn test_unknown_message_types() {
+    let mut verifier = TestVerifier::new();
+    let retriever = TestRetriever::new();
+
+    let root_credential = Credential::generate_root().unwrap();
+    verifier.push(root_credential.certificate().clone());
+    let agent_credential = create_agent_credential(&root_credential, &mut verifier);
+
+    let root_hash = root_credential.certificate().spki_hash().clone();
+    let agent_hash = agent_credential.certificate().spki_hash().clone();
+
+    let root = create_client(&root_credential, &verifier, &retriever).await;
+    let agent = create_agent(&agent_credential, &verifier, &retriever).await;
+
+    let device_group = SvalinGroupId::DeviceGroup(agent_hash.clone());
+    retriever.set_required_members(
+        device_group.clone(),
+        vec![root_hash.clone(), agent_hash.clone()],
+    );
+    retriever.add(root.create_key_package().await.unwrap());
+
+    let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    let storage = SqliteStorageProvider::<PostcardCodec>::new(pool);
+    storage.run_migrations().await.unwrap();
+    let server = MlsServer::new(storage, verifier.clone(), retriever.clone());
+
+    let new_group = agent
+        .create_device_group_if_missing()
+        .await
+        .unwrap()
+        .unwrap();
+    let welcome = server.process_message(new_group).await.unwrap();
+    root.handle_message(&welcome[0].message).await.unwrap();
+
+    // types the client doesn't check are passed on with their sender
+    let alert = Alert {
+        severity: 5,
+        text: "service crashed".to_string(),
+    };
+    let to_server = agent.send_message(&alert).await.unwrap();
+    let to_send = server.process_message(to_server).await.unwrap();
+    let MessageDataContent::Other(sender, message) = root
+        .handle_message(&to_send[0].message)
+        .await
+        .unwrap()
+        .content
+    else {
+        panic!("wrong message type")
+    };
+    assert_eq!(sender, agent_hash);
+    assert_eq!(message.payload::<Alert>().unwrap(), alert);
+
+    // so are known types in a version the client doesn't know
+    let report = ReportV2 {
+        text: "new layout".to_string(),
+        generated_at: 1,
+    };
+    let to_server = agent.send_message(&report).await.unwrap();
+    let to_send = server.process_message(to_server).await.unwrap();
+    let MessageDataContent::Other(_, message) = root
+        .handle_message(&to_send[0].message)
+        .await
+        .unwrap()
+        .content
+    else {
+        panic!("wrong message type")
+    };
+    assert_eq!(message.tag(), "report");
+    assert_eq!(message.version(), 2);
+
+    // agents pass on everything but instructions
+    let to_server = root.send_message(&device_group, &alert).await.unwrap();
+    let to_send = server.process_message(to_server).await.unwrap();
+    let AgentMessageContent::Other(sender, message) =
+        agent.handle_message(&to_send[0].message).await.unwrap()
+    else {
+        panic!("wrong message type")
+    };
+    assert_eq!(sender, root_hash);
+    assert!(message.is::<Alert>());
 }
@@ -0,0 +1,196 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::{
+    Credential, KeyPair, RenewalRequest, UnverifiedCertificate,
+    trust_store::{Exported, LegacyExported, PreRotationExported, TrustStore},
+};
+
+fn root_and_agent() -> (Credential, Credential) {
+    let root_credential = Credential::generate_root().unwrap();
+
+    let keypair = KeyPair::generate();
+    let cert = root_credential
+        .create_agent_certificate_for_key(&keypair.export_public_key())
+        .unwrap();
+    let agent = keypair.upgrade(cert.to_unverified()).unwrap();
+
+    (root_credential, agent)
+}
+
+#[test]
+fn test_renewal_request() {
+    let (root_credential, agent) = root_and_agent();
+
+    let request = RenewalRequest::create(&agent);
+    assert!(!request.is_rotation());
+    request.verify(agent.certificate()).unwrap();
+    request.verify(root_credential.certificate()).unwrap_err();
+
+    let new_key = KeyPair::generate();
+    let rotated = RenewalRequest::create_rotated(&agent, &new_key);
+    assert!(rotated.is_rotation());
+    rotated.verify(agent.certificate()).unwrap();
+
+    let certificate = root_credential
+        .create_renewed_certificate(&rotated)
+        .unwrap();
+    assert_eq!(certificate.spki_hash(), &rotated.renewed_spki_hash());
+    assert_eq!(
+        certificate.certificate_type(),
+        agent.certificate().certificate_type()
+    );
+    new_key.upgrade(certificate.to_unverified()).unwrap();
+}
+
+#[test]
+fn test_renew_in_trust_store() {
+    let (root_credential, agent) = root_and_agent();
+    let root = root_credential
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+
+    let mut root_store = TrustStore::initialize(root);
+    let block = root_store
+        .add(agent.certificate().clone(), &root_credential)
+        .unwrap();
+    root_store.apply(block);
+    let mut agent_store = TrustStore::import(root_store.export()).unwrap();
+
+    // certificate times only have a resolution of seconds
+    std::thread::sleep(Duration::from_millis(1100));
+
+    let request = RenewalRequest::create(&agent);
+    request
+        .verify(root_store.get(agent.certificate().spki_hash()).unwrap())
+        .unwrap();
+    let renewed = root_credential
+        .create_renewed_certificate(&request)
+        .unwrap();
+    assert!(renewed.not_after() > agent.certificate().not_after());
+
+    // the agent itself is not allowed to renew its certificate
+    agent_store.renew(renewed.clone(), &agent).unwrap_err();
+
+    let block = root_store.renew(renewed.clone(), &root_credential).unwrap();
+    let unchecked = block.as_unchecked().clone();
+    root_store.apply(block);
+
+    let block = agent_store.check(unchecked).unwrap();
+    agent_store.apply(block);
+    assert_eq!(
+        agent_store.get(agent.certificate().spki_hash()).unwrap(),
+        &renewed
+    );
+
+    // renewing with a certificate which doesn't expire later is rejected
+    root_store
+        .renew(renewed.clone(), &root_credential)
+        .unwrap_err();
+
+    let agent = agent
+        .with_renewed_certificate(renewed.clone().to_unverified())
+        .unwrap();
+    assert_eq!(agent.certificate(), &renewed);
+}
+
+#[test]
+fn test_rotate_in_trust_store() {
+    let (root_credential, agent) = root_and_agent();
+    let root = root_credential
+        .certificate()
+        .clone()
+        .to_unverified()
+        .use_as_root()
+        .unwrap();
+
+    let mut root_store = TrustStore::initialize(root);
+    let block = root_store
+        .add(agent.certificate().clone(), &root_credential)
+        .unwrap();
+    root_store.apply(block);
+    let mut agent_store = TrustStore::import(root_store.export()).unwrap();
+
+    let new_key = KeyPair::generate();
+    let request = RenewalRequest::create_rotated(&agent, &new_key);
+    let rotated = root_credential
+        .create_renewed_certificate(&request)
+        .unwrap();
+
+    // a plain renewal of the same key is no valid proof
+    let plain = RenewalRequest::create(&agent);
+    root_store
+        .rotate(&plain, rotated.clone(), &root_credential)
+        .unwrap_err();
+
+    // the agent itself is not allowed to rotate its certificate
+    agent_store
+        .rotate(&request, rotated.clone(), &agent)
+        .unwrap_err();
+
+    let block = root_store
+        .rotate(&request, rotated.clone(), &root_credential)
+        .unwrap();
+    let unchecked = block.as_unchecked().clone();
+    root_store.apply(block);
+
+    let block = agent_store.check(unchecked).unwrap();
+    agent_store.apply(block);
+
+    let previous = agent.certificate().spki_hash();
+    assert!(agent_store.get(previous).is_none());
+    assert_eq!(agent_store.get(rotated.spki_hash()).unwrap(), &rotated);
+    assert_eq!(agent_store.successor(previous), Some(rotated.spki_hash()));
+    assert_eq!(agent_store.latest_successor(previous), rotated.spki_hash());
+
+    // the previous key can't be added again
+    root_store
+        .add(agent.certificate().clone(), &root_credential)
+        .unwrap_err();
+
+    let imported = TrustStore::import(agent_store.export()).unwrap();
+    assert_eq!(imported.successor(previous), Some(rotated.spki_hash()));
+    assert_eq!(imported.digest(), agent_store.digest());
+
+    new_key.upgrade(rotated.to_unverified()).unwrap();
+}
+
+/// Postcard layout of a trust store export before agent keys could be rotated.
+#[derive(Serialize)]
+struct PreRotationLayout {
+    root: UnverifiedCertificate,
+    certificates: Vec<UnverifiedCertificate>,
+    last_block: Option<()>,
+}
+
+#[test]
+fn test_import_pre_rotation_export() {
+    let root_credential = Credential::generate_root().unwrap();
+    let root = root_credential.certificate().clone().to_unverified();
+
+    let encoded = postcard::to_extend(
+        &PreRotationLayout {
+            root: root.clone(),
+            certificates: vec![root.clone()],
+            last_block: None,
+        },
+        Vec::new(),
+    )
+    .unwrap();
+
+    assert!(postcard::from_bytes::<Exported>(&encoded).is_err());
+    assert!(postcard::from_bytes::<LegacyExported>(&encoded).is_err());
+    let exported: PreRotationExported = postcard::from_bytes(&encoded).unwrap();
+
+    let imported = TrustStore::import(exported.into()).unwrap();
+    let initialized = TrustStore::initialize(root.use_as_root().unwrap());
+    assert_eq!(imported.digest(), initialized.digest());
+    assert_eq!(
+        imported.successor(root_credential.certificate().spki_hash()),
+        None
+    );
+}
@@ -1,10 +1,17 @@
-use std::convert::Infallible;
+use std::{convert::Infallible, ops::RangeInclusive};
 
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
 
 use crate::{
-    Credential,
-    secure_chain::{self, Chain},
+    Credential, SpkiHash,
+    secure_chain::{
+        self, Chain, CheckBlockError, CheckedBlock, Checkpoint, ExportedChain, ImportError,
+        UncheckedBlock,
+        encoding::{CanonicalDigest, ProtocolVersion},
+        legacy::{LegacyCheckpoint, LegacyExportedChain},
+        store::{BlockStore, OpenError, StoredChain},
+    },
 };
 
 #[derive(Debug)]
@@ -24,8 +31,13 @@ pub struct Transaction {
 }
 
 impl secure_chain::Transaction for Transaction {
-    fn digest(&self, digest: &mut impl sha2::Digest) {
-        digest.update(self.add.to_le_bytes());
+    fn digest(&self, version: ProtocolVersion, digest: &mut impl sha2::Digest) {
+        if version == ProtocolVersion::LEGACY {
+            digest.update(self.add.to_le_bytes());
+        } else {
+            digest.domain("svalin/test/transaction", version);
+            digest.number(self.add);
+        }
     }
 }
 
@@ -70,8 +82,13 @@ impl secure_chain::ChainState for State {
         self.number -= transaction.add;
     }
 
-    fn digest(&self, digest: &mut impl sha2::Digest) {
-        digest.update(self.number.to_le_bytes());
+    fn digest(&self, version: ProtocolVersion, digest: &mut impl sha2::Digest) {
+        if version == ProtocolVersion::LEGACY {
+            digest.update(self.number.to_le_bytes());
+        } else {
+            digest.domain("svalin/test/state", version);
+            digest.number(self.number);
+        }
     }
 
     fn export(&self) -> Self::Exported {
@@ -132,3 +149,321 @@ fn test_simple_chain() {
         .clone();
     chain2.check(broken, credential.certificate()).unwrap_err();
 }
+
+/// Block layout as written by postcard, used to build chains with fixed
+/// values for the golden vectors.
+#[derive(Serialize)]
+struct RawBlock {
+    sequence: u64,
+    time: u64,
+    previous_block_hash: Vec<u8>,
+    resulting_state: Vec<u8>,
+    signer: SpkiHash,
+    transaction: Transaction,
+    signature: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct RawExportedChain<Block> {
+    state: Exported,
+    last_block: Option<Block>,
+}
+
+fn raw_block(resulting_state: &str) -> RawBlock {
+    RawBlock {
+        sequence: 1,
+        time: 1_700_000_000,
+        previous_block_hash: vec![0; 64],
+        resulting_state: hex::decode(resulting_state).unwrap(),
+        signer: SpkiHash::from_hex(&[b'0', b'7'].repeat(32)).unwrap(),
+        transaction: Transaction { add: 5 },
+        signature: Vec::new(),
+    }
+}
+
+#[test]
+fn test_canonical_encoding_vector() {
+    let mut digest = Sha512::new();
+    digest.domain("svalin/test", ProtocolVersion::V1);
+    digest.number(42);
+    digest.field(b"abc");
+    digest.optional_field(None);
+    digest.optional_field(Some(b"x"));
+
+    assert_eq!(
+        hex::encode(digest.finalize()),
+        "906b0e47bae6df94992c25668f0c57931029f9eb18004cbc4a2f993c53dd208500414346d94b4b88692e842ee47e99f453bbf2e55c3bf141c914b14b07d081c4"
+    );
+}
+
+#[test]
+fn test_legacy_chain_vector() {
+    // postcard data stored before blocks carried a version
+    let raw = RawExportedChain {
+        state: Exported { number: 5 },
+        last_block: Some(raw_block(
+            "470f585f2f412ca3807f9964685633350da6f920e3ea3ba834901ae26ea2a28ebbb7a6736e58a9022f41bc5a78be0e2b9767c26dc96477c93e6a94611e8ca181",
+        )),
+    };
+    let encoded = postcard::to_extend(&raw, Vec::new()).unwrap();
+
+    assert!(postcard::from_bytes::<ExportedChain<State>>(&encoded).is_err());
+    let exported: LegacyExportedChain<State> = postcard::from_bytes(&encoded).unwrap();
+
+    let chain = Chain::import(exported.into()).unwrap();
+    assert_eq!(chain.version(), ProtocolVersion::LEGACY);
+    assert_eq!(
+        hex::encode(chain.digest()),
+        "acbfa83459fc210d71d82cf769664b4a55e257f54a51cd59aa8fa133e6e6ba1eeab03f6f5b13c1893dfa6239d81d301f0c9917fd2529c0d49910bacd4558ee70"
+    );
+}
+
+#[test]
+fn test_v1_chain_vector() {
+    let raw = RawExportedChain {
+        state: Exported { number: 5 },
+        last_block: Some((
+            raw_block(
+                "9d3775d10b05be042c8c9a49a013d886c93b958c7d34c1c90149a21c8457e04faee6a2eb6197d96a0baa559ec14136c793f5bc94cf7d1a07e33f3a27c278d3d0",
+            ),
+            ProtocolVersion::V1,
+        )),
+    };
+    let encoded = postcard::to_extend(&raw, Vec::new()).unwrap();
+    let exported: ExportedChain<State> = postcard::from_bytes(&encoded).unwrap();
+
+    let chain = Chain::import(exported).unwrap();
+    assert_eq!(chain.version(), ProtocolVersion::V1);
+    assert_eq!(
+        hex::encode(chain.digest()),
+        "e3da63f5b73d9bb34bc3ca9dd39dc4e59a721f1b51bb305f786477c016d00990c3d7bf7d9784707d705754ddd1635a84c54d6cd5dbb4852b02378b6b585f3b26"
+    );
+}
+
+/// Checkpoint layout as written by postcard, the legacy layout ends after the
+/// signature.
+#[derive(Serialize)]
+struct RawCheckpoint<Trailer> {
+    sequence: u64,
+    time: u64,
+    last_block_hash: Vec<u8>,
+    state_digest: Vec<u8>,
+    signer: SpkiHash,
+    signature: Vec<u8>,
+    trailer: Trailer,
+}
+
+fn raw_checkpoint<Trailer>(trailer: Trailer) -> Vec<u8>
+where
+    Trailer: Serialize,
+{
+    let raw = RawCheckpoint {
+        sequence: 3,
+        time: 1_700_000_000,
+        last_block_hash: vec![1; 64],
+        state_digest: vec![2; 64],
+        signer: SpkiHash::from_hex(&[b'0', b'7'].repeat(32)).unwrap(),
+        signature: Vec::new(),
+        trailer,
+    };
+    postcard::to_extend(&raw, Vec::new()).unwrap()
+}
+
+#[test]
+fn test_legacy_checkpoint_vector() {
+    let encoded = raw_checkpoint(());
+
+    assert!(postcard::from_bytes::<Checkpoint>(&encoded).is_err());
+    let checkpoint: Checkpoint = postcard::from_bytes::<LegacyCheckpoint>(&encoded)
+        .unwrap()
+        .into();
+    assert_eq!(checkpoint.version(), ProtocolVersion::LEGACY);
+    assert_eq!(
+        hex::encode(checkpoint.digest()),
+        "8bc6bb1fd805752834768310945826429b414f406a98c5978d0ba2eeb49568e7ec437567a9ff13c7b1f39e845e6ce9c1c0621dd32f3610a51c8773093c8e630b"
+    );
+}
+
+#[test]
+fn test_v1_checkpoint_vector() {
+    let checkpoint: Checkpoint =
+        postcard::from_bytes(&raw_checkpoint((ProtocolVersion::V1, None::<Vec<u8>>))).unwrap();
+    assert_eq!(checkpoint.version(), ProtocolVersion::V1);
+    assert_eq!(
+        hex::encode(checkpoint.digest()),
+        "80d9086dca623695c25b7ac09c922769731173c41d24435155fe70d443285898c44f9047bbdc72140fa3bf7af7d7944b96bbcd6109123ecbabe20d66d5a0604a"
+    );
+
+    let checkpoint: Checkpoint =
+        postcard::from_bytes(&raw_checkpoint((ProtocolVersion::V1, Some(vec![9u8; 64])))).unwrap();
+    assert!(checkpoint.history_root().is_some());
+    assert_eq!(
+        hex::encode(checkpoint.digest()),
+        "42323d306e1fefbb079b0f8f19a2bf69827ad033890545a297ab68bda2e0af91593be4bfe93162df6184387759dc10028f7ee9668caa4c0c1b2daa86d9e5c5b5"
+    );
+}
+
+#[test]
+fn test_version_migration() {
+    let credential = Credential::generate_root().unwrap();
+
+    let mut chain = Chain::initialize(State {
+        number: 0,
+        allow_wrong_transaction: false,
+    });
+    let mut chain2 = Chain::initialize(State {
+        number: 0,
+        allow_wrong_transaction: false,
+    });
+
+    let mut saved = Vec::new();
+    for add in [1, 2] {
+        let block = chain
+            .package_with_version(Transaction { add }, &credential, ProtocolVersion::LEGACY)
+            .unwrap();
+        saved.push(block.as_unchecked().clone());
+        chain.apply(block);
+    }
+    assert_eq!(chain.version(), ProtocolVersion::LEGACY);
+
+    let block = chain.package(Transaction { add: 3 }, &credential).unwrap();
+    saved.push(block.as_unchecked().clone());
+    chain.apply(block);
+    assert_eq!(chain.version(), ProtocolVersion::CURRENT);
+
+    for block in saved {
+        let checked = chain2.check(block, credential.certificate()).unwrap();
+        chain2.apply(checked);
+    }
+    assert_eq!(chain.digest(), chain2.digest());
+
+    // once upgraded, the chain doesn't accept the legacy encoding anymore
+    let downgrade = chain
+        .package_with_version(Transaction { add: 1 }, &credential, ProtocolVersion::LEGACY)
+        .unwrap()
+        .to_unchecked();
+    assert!(matches!(
+        chain2.check(downgrade, credential.certificate()),
+        Err(CheckBlockError::VersionDowngrade(..))
+    ));
+}
+
+#[test]
+fn test_unsupported_version() {
+    let raw = RawExportedChain {
+        state: Exported { number: 5 },
+        last_block: Some((
+            raw_block(
+                "9d3775d10b05be042c8c9a49a013d886c93b958c7d34c1c90149a21c8457e04faee6a2eb6197d96a0baa559ec14136c793f5bc94cf7d1a07e33f3a27c278d3d0",
+            ),
+            u16::MAX,
+        )),
+    };
+    let encoded = postcard::to_extend(&raw, Vec::new()).unwrap();
+    let exported: ExportedChain<State> = postcard::from_bytes(&encoded).unwrap();
+
+    assert!(matches!(
+        Chain::import(exported),
+        Err(ImportError::UnsupportedVersion(_))
+    ));
+}
+
+#[derive(Default)]
+struct MemoryBlockStore(std::sync::Mutex<Vec<UncheckedBlock<Transaction>>>);
+
+impl BlockStore<Transaction> for MemoryBlockStore {
+    type Error = Infallible;
+
+    async fn last_sequence(&self) -> Result<u64, Self::Error> {
+        Ok(self
+            .0
+            .lock()
+            .unwrap()
+            .last()
+            .map_or(0, |block| block.sequence()))
+    }
+
+    async fn append(&self, block: &CheckedBlock<Transaction>) -> Result<(), Self::Error> {
+        self.0.lock().unwrap().push(block.as_unchecked().clone());
+        Ok(())
+    }
+
+    async fn load_range(
+        &self,
+        range: RangeInclusive<u64>,
+    ) -> Result<Vec<UncheckedBlock<Transaction>>, Self::Error> {
+        Ok(self
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|block| range.contains(&block.sequence()))
+            .cloned()
+            .collect())
+    }
+}
+
+async fn open_stored(
+    store: MemoryBlockStore,
+    credential: &Credential,
+) -> Result<StoredChain<State, MemoryBlockStore>, OpenError<Error, Infallible>> {
+    let certificate = credential.certificate().clone();
+    StoredChain::open(
+        State {
+            number: 0,
+            allow_wrong_transaction: false,
+        },
+        store,
+        move |_, signer| (signer == certificate.spki_hash()).then(|| certificate.clone()),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn test_stored_chain() {
+    let credential = Credential::generate_root().unwrap();
+
+    let mut stored = open_stored(MemoryBlockStore::default(), &credential)
+        .await
+        .unwrap();
+    for add in 1..=3 {
+        stored
+            .package(Transaction { add }, &credential)
+            .await
+            .unwrap();
+    }
+    let digest = stored.chain().digest();
+
+    // reopening verifies and replays the stored blocks
+    let blocks = stored.store().0.lock().unwrap().clone();
+    let reopened = open_stored(MemoryBlockStore(blocks.clone().into()), &credential)
+        .await
+        .unwrap();
+    assert_eq!(reopened.chain().state().number, 6);
+    assert_eq!(reopened.chain().digest(), digest);
+
+    // a block of another chain doesn't fit in
+    let mut other = Chain::initialize(State {
+        number: 0,
+        allow_wrong_transaction: false,
+    });
+    let mut tampered = blocks.clone();
+    for (add, slot) in [4, 5].into_iter().zip(tampered.iter_mut()) {
+        let block = other.package(Transaction { add }, &credential).unwrap();
+        other.apply(block.clone());
+        *slot = block.to_unchecked();
+    }
+    tampered.truncate(2);
+    tampered.push(blocks[2].clone());
+    assert!(matches!(
+        open_stored(MemoryBlockStore(tampered.into()), &credential).await,
+        Err(OpenError::InvalidBlock(3, _))
+    ));
+
+    let mut missing = blocks;
+    missing.remove(1);
+    assert!(matches!(
+        open_stored(MemoryBlockStore(missing.into()), &credential).await,
+        Err(OpenError::MissingBlock(2))
+    ));
+}
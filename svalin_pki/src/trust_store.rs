@@ -6,16 +6,31 @@ use std::{
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    AddCertificateError, Certificate, CertificateChainBuilder, Credential, RootCertificate,
-    SignatureVerificationError, SpkiHash, UnverifiedCertificate, UnverifiedCertificateChain,
-    UseAsRootError,
-    secure_chain::{self, Chain, ChainDigest, ChainState, CheckedBlock, UncheckedBlock},
+    AddCertificateError, Certificate, CertificateChainBuilder, CertificateType, Credential,
+    RenewalRequest, RenewalRequestError, RootCertificate, SignatureVerificationError, SpkiHash,
+    UnverifiedCertificate, UnverifiedCertificateChain, UseAsRootError,
+    secure_chain::{
+        self, Chain, ChainDigest, ChainState, CheckedBlock, Checkpoint, PositionComparison,
+        UncheckedBlock, VerifyCheckpointError,
+        encoding::{CanonicalDigest, ProtocolVersion},
+        legacy::LegacyExportedChain,
+        merkle::{MerkleHash, MerkleIndex},
+    },
 };
 pub type CreateBlockError = secure_chain::CreateBlockError<Error>;
 pub type CheckBlockError = secure_chain::CheckBlockError<Error>;
 
+/// Number of members which have to report a conflicting position before the
+/// trust store is considered conflicting, unless root reports it.
+pub const CONFLICT_REPORTS: usize = 2;
+
 pub struct TrustStore {
     chain: Chain<State>,
+    /// Positions of other members which conflict with this trust store, by
+    /// their signer.
+    conflict_reports: HashMap<SpkiHash, Checkpoint>,
+    /// The report which made the trust store conflicting.
+    conflict: Option<Checkpoint>,
 }
 
 impl fmt::Debug for TrustStore {
@@ -34,6 +49,8 @@ impl TrustStore {
 
         Self {
             chain: Chain::initialize(state),
+            conflict_reports: HashMap::new(),
+            conflict: None,
         }
     }
 
@@ -65,6 +82,71 @@ impl TrustStore {
             .package(Transaction::Add(certificate.to_unverified()), credential)
     }
 
+    /// Replaces the known certificate with the same key by `renewed`.
+    pub fn renew(
+        &mut self,
+        renewed: Certificate,
+        credential: &Credential,
+    ) -> Result<CheckedBlock<Transaction>, CreateBlockError> {
+        let previous = self
+            .get(renewed.spki_hash())
+            .ok_or(CreateBlockError::InvalidTransaction(
+                Error::CertificateNotFound,
+            ))?
+            .clone()
+            .to_unverified();
+
+        self.chain.package(
+            Transaction::Renew {
+                previous,
+                renewed: renewed.to_unverified(),
+            },
+            credential,
+        )
+    }
+
+    /// Replaces the certificate of an agent by `rotated`, which was issued
+    /// for the new key of the given rotation request.
+    ///
+    /// The request is kept in the transaction as proof that the holder of the
+    /// previous key handed over to the new one.
+    pub fn rotate(
+        &mut self,
+        request: &RenewalRequest,
+        rotated: Certificate,
+        credential: &Credential,
+    ) -> Result<CheckedBlock<Transaction>, CreateBlockError> {
+        self.chain.package(
+            Transaction::Rotate {
+                proof: request.clone(),
+                rotated: rotated.to_unverified(),
+            },
+            credential,
+        )
+    }
+
+    /// Removes a certificate which has already expired. Issuers of other
+    /// certificates are kept, removing them would leave their certificates
+    /// without one.
+    ///
+    /// Blocks checked by [`TrustStore::check`] may still remove issuers,
+    /// chains created before this was refused have to stay valid.
+    pub fn remove_expired(
+        &mut self,
+        certificate: &Certificate,
+        credential: &Credential,
+    ) -> Result<CheckedBlock<Transaction>, CreateBlockError> {
+        if self.chain.state().is_issuer(certificate.spki_hash()) {
+            return Err(CreateBlockError::InvalidTransaction(
+                Error::CertificateIsIssuer,
+            ));
+        }
+        self.chain.package(
+            Transaction::RemoveExpired(certificate.clone().to_unverified()),
+            credential,
+        )
+    }
+
     pub fn export(&self) -> Exported {
         Exported {
             chain: self.chain.export(),
@@ -74,9 +156,171 @@ impl TrustStore {
     pub fn import(exported: Exported) -> Result<Self, ImportError> {
         Ok(Self {
             chain: secure_chain::Chain::import(exported.chain)?,
+            conflict_reports: HashMap::new(),
+            conflict: None,
         })
     }
 
+    /// Creates a signed checkpoint of the current state.
+    pub fn checkpoint(&self, credential: &Credential) -> Checkpoint {
+        self.chain.checkpoint(credential)
+    }
+
+    /// Creates a signed checkpoint which also commits to the Merkle history,
+    /// see [`Chain::checkpoint_with_history`].
+    pub fn checkpoint_with_history(
+        &self,
+        credential: &Credential,
+        history: &MerkleIndex,
+    ) -> Option<Checkpoint> {
+        self.chain.checkpoint_with_history(credential, history)
+    }
+
+    /// Imports a trust store matching the given checkpoint.
+    ///
+    /// The checkpoint has to be signed by the root or by the given server
+    /// certificate issued by the root, so members which only know the root
+    /// can bootstrap from it. The signer is never looked up in the imported
+    /// state, which can only be trusted once the checkpoint is verified.
+    pub fn import_checkpoint(
+        exported: Exported,
+        checkpoint: &Checkpoint,
+        root: &RootCertificate,
+        server: Option<&UnverifiedCertificate>,
+    ) -> Result<Self, ImportCheckpointError> {
+        let signer = if checkpoint.signer() == root.spki_hash() {
+            root.clone().to_certificate()
+        } else {
+            let server = server
+                .filter(|server| server.spki_hash() == checkpoint.signer())
+                .ok_or(InnerCheckpointError::SignerNotKnown)?;
+            if server.certificate_type() != CertificateType::Server {
+                return Err(InnerCheckpointError::SignerNotTrusted.into());
+            }
+            server
+                .clone()
+                .verify_signature(root, checkpoint.time())
+                .map_err(InnerCheckpointError::SignerInvalid)?
+        };
+        checkpoint
+            .verify(&signer)
+            .map_err(InnerCheckpointError::InvalidSignature)?;
+
+        Self::restore_checkpoint(exported, checkpoint, root)
+    }
+
+    /// Restores a checkpoint from the own store, which was verified before it
+    /// was stored. Only checks that the state matches the checkpoint.
+    pub fn restore_checkpoint(
+        exported: Exported,
+        checkpoint: &Checkpoint,
+        root: &RootCertificate,
+    ) -> Result<Self, ImportCheckpointError> {
+        let chain = Chain::import_checkpoint(exported.chain, checkpoint)
+            .map_err(InnerCheckpointError::Import)?;
+        let trust_store = Self {
+            chain,
+            conflict_reports: HashMap::new(),
+            conflict: None,
+        };
+
+        if trust_store.root().spki_hash() != root.spki_hash() {
+            return Err(InnerCheckpointError::RootMismatch.into());
+        }
+
+        Ok(trust_store)
+    }
+
+    /// Verifies a checkpoint the server sent instead of the blocks this trust
+    /// store is missing, and returns the trust store at the checkpoint.
+    ///
+    /// Only a trust store which knows nothing but the root is replaced
+    /// directly. Any other has to be part of the history the checkpoint
+    /// commits to, which `history` has to prove by containing the current
+    /// block. The signer is looked up in this trust store before `server` is
+    /// used, and keys which were replaced by a rotation stay revoked.
+    pub fn accept_checkpoint(
+        &self,
+        exported: Exported,
+        checkpoint: &Checkpoint,
+        server: Option<&UnverifiedCertificate>,
+        history: Option<&MerkleIndex>,
+    ) -> Result<Self, ImportCheckpointError> {
+        if checkpoint.sequence() <= self.sequence() {
+            return Err(InnerCheckpointError::Outdated.into());
+        }
+        if history.is_some_and(|history| !checkpoint.matches_history(history)) {
+            return Err(InnerCheckpointError::HistoryMismatch.into());
+        }
+        if let Some(block) = self.last_block() {
+            let history = history.ok_or(InnerCheckpointError::MissingHistory)?;
+            let leaf = history.leaves().get(block.sequence() as usize - 1);
+            if leaf != Some(&MerkleHash::leaf(block)) {
+                return Err(InnerCheckpointError::HistoryMismatch.into());
+            }
+        }
+
+        let known = self
+            .get(checkpoint.signer())
+            .map(|signer| signer.clone().to_unverified());
+        let imported =
+            Self::import_checkpoint(exported, checkpoint, self.root(), known.as_ref().or(server))?;
+
+        for (previous, successor) in &self.chain.state().successors {
+            if imported.get(previous).is_some() || imported.successor(previous) != Some(successor) {
+                return Err(InnerCheckpointError::RevocationUndone(previous.clone()).into());
+            }
+        }
+
+        Ok(imported)
+    }
+
+    /// Compares the trust store with the chain position of another member.
+    pub fn compare_position(&self, position: &Checkpoint) -> PositionComparison {
+        self.chain.compare_position(position)
+    }
+
+    /// Records that another member was shown a different history, which
+    /// means the server presents a split view. Returns whether the trust
+    /// store is conflicting now.
+    ///
+    /// A single member could forge a position to lock everyone out, so the
+    /// conflict only counts once root or [`CONFLICT_REPORTS`] members reported
+    /// one. Certificates can no longer be verified with this trust store
+    /// afterwards, until [`TrustStore::reset_conflict`] is called.
+    pub fn report_conflict(&mut self, position: Checkpoint) -> bool {
+        if self.conflict.is_some() {
+            return true;
+        }
+
+        let from_root = position.signer() == self.root().spki_hash();
+        self.conflict_reports
+            .entry(position.signer().clone())
+            .or_insert_with(|| position.clone());
+        if from_root || self.conflict_reports.len() >= CONFLICT_REPORTS {
+            self.conflict = Some(position);
+        }
+
+        self.conflict.is_some()
+    }
+
+    /// The conflicting position which made the trust store conflicting.
+    pub fn conflict(&self) -> Option<&Checkpoint> {
+        self.conflict.as_ref()
+    }
+
+    /// All reported conflicting positions, including those which didn't make
+    /// the trust store conflicting yet.
+    pub fn conflict_reports(&self) -> impl Iterator<Item = &Checkpoint> {
+        self.conflict_reports.values()
+    }
+
+    /// Forgets all reported conflicts, once an admin resolved the split view.
+    pub fn reset_conflict(&mut self) {
+        self.conflict_reports.clear();
+        self.conflict = None;
+    }
+
     pub fn get(&self, spki_hash: &SpkiHash) -> Option<&Certificate> {
         self.chain.state().certificates.get(&spki_hash)
     }
@@ -85,6 +329,70 @@ impl TrustStore {
         &self.chain.state().root
     }
 
+    /// Returns the known certificates of the given type.
+    pub fn certificates_of_type(&self, certificate_type: CertificateType) -> Vec<&Certificate> {
+        self.chain
+            .state()
+            .certificates
+            .values()
+            .filter(|certificate| certificate.certificate_type() == certificate_type)
+            .collect()
+    }
+
+    /// Returns the certificates expiring before `now + window`, including the
+    /// ones which already expired. The certificates are grouped by their type
+    /// and sorted by their expiry.
+    pub fn expiring_within(
+        &self,
+        window: u64,
+        now: u64,
+    ) -> HashMap<CertificateType, Vec<&Certificate>> {
+        let deadline = now.saturating_add(window);
+        let mut expiring = HashMap::<_, Vec<_>>::new();
+
+        for certificate in self.chain.state().certificates.values() {
+            if certificate.not_after() < deadline {
+                expiring
+                    .entry(certificate.certificate_type())
+                    .or_default()
+                    .push(certificate);
+            }
+        }
+
+        for certificates in expiring.values_mut() {
+            certificates.sort_by_key(|certificate| certificate.not_after());
+        }
+
+        expiring
+    }
+
+    /// Returns the expired certificates which can be removed by
+    /// [`TrustStore::remove_expired`].
+    pub fn expired(&self, now: u64) -> Vec<&Certificate> {
+        let state = self.chain.state();
+        state
+            .certificates
+            .values()
+            .filter(|certificate| certificate.not_after() < now)
+            .filter(|certificate| certificate.spki_hash() != state.root.spki_hash())
+            .filter(|certificate| !state.is_issuer(certificate.spki_hash()))
+            .collect()
+    }
+
+    /// Returns the spki hash of the key which replaced the given one.
+    pub fn successor(&self, spki_hash: &SpkiHash) -> Option<&SpkiHash> {
+        self.chain.state().successors.get(spki_hash)
+    }
+
+    /// Follows all rotations starting at the given spki hash and returns the
+    /// most recent key.
+    pub fn latest_successor<'a>(&'a self, mut spki_hash: &'a SpkiHash) -> &'a SpkiHash {
+        while let Some(successor) = self.successor(spki_hash) {
+            spki_hash = successor;
+        }
+        spki_hash
+    }
+
     pub fn complete_certificate_chain(
         &self,
         mut cert_chain: CertificateChainBuilder,
@@ -106,6 +414,11 @@ impl TrustStore {
         self.chain.sequence()
     }
 
+    /// The block the trust store is at, `None` for a new trust store.
+    pub fn last_block(&self) -> Option<&UncheckedBlock<Transaction>> {
+        self.chain.last_block()
+    }
+
     pub fn digest(&self) -> ChainDigest {
         self.chain.digest()
     }
@@ -116,22 +429,84 @@ pub struct Exported {
     chain: secure_chain::ExportedChain<State>,
 }
 
+/// [`Exported`] as stored with postcard before blocks carried a protocol
+/// version, see [`secure_chain::legacy`].
+#[derive(Deserialize)]
+pub struct LegacyExported {
+    chain: LegacyExportedChain<State>,
+}
+
+impl From<LegacyExported> for Exported {
+    fn from(exported: LegacyExported) -> Self {
+        Self {
+            chain: exported.chain.into(),
+        }
+    }
+}
+
+/// [`Exported`] as stored with postcard before agent keys could be rotated,
+/// the state doesn't contain the successors of rotated keys yet.
+#[derive(Deserialize)]
+pub struct PreRotationExported {
+    chain: LegacyExportedChain<State, PreRotationExportedState>,
+}
+
+impl From<PreRotationExported> for Exported {
+    fn from(exported: PreRotationExported) -> Self {
+        Self {
+            chain: exported.chain.into(),
+        }
+    }
+}
+
 impl Debug for Exported {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("trust_store::Exported").finish()
     }
 }
 
-struct State {
+pub(crate) struct State {
     root: RootCertificate,
     certificates: HashMap<SpkiHash, Certificate>,
+    /// Maps the spki hash of rotated keys to the key replacing them.
+    successors: HashMap<SpkiHash, SpkiHash>,
 }
 
 impl State {
-    fn initialize(root: RootCertificate) -> Self {
+    pub(crate) fn initialize(root: RootCertificate) -> Self {
         let mut certificates = HashMap::new();
         certificates.insert(root.spki_hash().clone(), root.clone().to_certificate());
-        Self { root, certificates }
+        Self {
+            root,
+            certificates,
+            successors: HashMap::new(),
+        }
+    }
+
+    /// Whether any other certificate was issued by the given one.
+    fn is_issuer(&self, spki_hash: &SpkiHash) -> bool {
+        self.certificates.values().any(|certificate| {
+            certificate.issuer() == spki_hash && certificate.spki_hash() != spki_hash
+        })
+    }
+
+    fn legacy_digest(&self, digest: &mut impl sha2::Digest) {
+        digest.update(self.root.as_der());
+        let mut keys = self.certificates.keys().collect::<Vec<_>>();
+        keys.sort();
+        for key in keys {
+            let certificate = self
+                .certificates
+                .get(&key)
+                .expect("keys were already taken from map");
+            digest.update(certificate.as_der());
+        }
+        let mut successors = self.successors.iter().collect::<Vec<_>>();
+        successors.sort();
+        for (previous, successor) in successors {
+            digest.update(previous.as_slice());
+            digest.update(successor.as_slice());
+        }
     }
 }
 
@@ -139,10 +514,52 @@ impl State {
 pub enum Transaction {
     Add(UnverifiedCertificate),
     RemoveExpired(UnverifiedCertificate),
+    /// Replaces a certificate by a newer one for the same key.
+    Renew {
+        previous: UnverifiedCertificate,
+        renewed: UnverifiedCertificate,
+    },
+    /// Replaces the certificate of an agent by one for a new key. The proof
+    /// is signed by both the previous and the new key.
+    Rotate {
+        proof: RenewalRequest,
+        rotated: UnverifiedCertificate,
+    },
 }
 
 impl secure_chain::Transaction for Transaction {
-    fn digest(&self, digest: &mut impl sha2::Digest) {
+    fn digest(&self, version: ProtocolVersion, digest: &mut impl sha2::Digest) {
+        if version == ProtocolVersion::LEGACY {
+            self.legacy_digest(digest);
+            return;
+        }
+
+        digest.domain("svalin/trust_store/transaction", version);
+        match self {
+            Transaction::Add(certificate) => {
+                digest.field(b"add");
+                digest.field(certificate.as_der());
+            }
+            Transaction::RemoveExpired(certificate) => {
+                digest.field(b"remove_expired");
+                digest.field(certificate.as_der());
+            }
+            Transaction::Renew { previous, renewed } => {
+                digest.field(b"renew");
+                digest.field(previous.as_der());
+                digest.field(renewed.as_der());
+            }
+            Transaction::Rotate { proof, rotated } => {
+                digest.field(b"rotate");
+                proof.encode_canonical(digest);
+                digest.field(rotated.as_der());
+            }
+        }
+    }
+}
+
+impl Transaction {
+    fn legacy_digest(&self, digest: &mut impl sha2::Digest) {
         match self {
             Transaction::Add(certificate) => {
                 digest.update(b"add");
@@ -152,6 +569,16 @@ impl secure_chain::Transaction for Transaction {
                 digest.update(b"remove_expired");
                 digest.update(certificate.as_der());
             }
+            Transaction::Renew { previous, renewed } => {
+                digest.update(b"renew");
+                digest.update(previous.as_der());
+                digest.update(renewed.as_der());
+            }
+            Transaction::Rotate { proof, rotated } => {
+                digest.update(b"rotate");
+                proof.update_digest(digest);
+                digest.update(rotated.as_der());
+            }
         }
     }
 }
@@ -170,8 +597,24 @@ pub enum Error {
     CertificateNotExpired,
     #[error("certificate not found")]
     CertificateNotFound,
+    #[error("certificate is the issuer of other certificates")]
+    CertificateIsIssuer,
     #[error("certificate does not match")]
     CertificateDoesNotMatch,
+    #[error("renewed certificate has a different key")]
+    RenewedKeyMismatch,
+    #[error("renewed certificate has a different type")]
+    RenewedTypeMismatch,
+    #[error("renewed certificate does not expire later than the previous one")]
+    RenewedNotNewer,
+    #[error("only agent certificates can be rotated")]
+    RotationNotSupported,
+    #[error("rotated certificate does not match the key of the proof")]
+    RotatedKeyMismatch,
+    #[error("key was already rotated")]
+    KeyAlreadyRotated,
+    #[error("invalid rotation proof: {0}")]
+    InvalidRotationProof(#[from] RenewalRequestError),
 }
 
 impl ChainState for State {
@@ -192,6 +635,9 @@ impl ChainState for State {
                 if self.certificates.contains_key(certificate.spki_hash()) {
                     return Err(Error::CertificateAlreadyExists);
                 }
+                if self.successors.contains_key(certificate.spki_hash()) {
+                    return Err(Error::KeyAlreadyRotated);
+                }
                 if signer.spki_hash() != certificate.issuer() {
                     return Err(Error::IssuerIsNotSigner);
                 }
@@ -208,6 +654,55 @@ impl ChainState for State {
                     return Err(Error::CertificateNotExpired);
                 }
             }
+            Transaction::Renew { previous, renewed } => {
+                let Some(found_certificate) = self.certificates.get(previous.spki_hash()) else {
+                    return Err(Error::CertificateNotFound);
+                };
+                if found_certificate != previous {
+                    return Err(Error::CertificateDoesNotMatch);
+                }
+                if renewed.spki_hash() != previous.spki_hash() {
+                    return Err(Error::RenewedKeyMismatch);
+                }
+                if renewed.certificate_type() != previous.certificate_type() {
+                    return Err(Error::RenewedTypeMismatch);
+                }
+                if renewed.not_after() <= previous.not_after() {
+                    return Err(Error::RenewedNotNewer);
+                }
+                if signer.spki_hash() != renewed.issuer() {
+                    return Err(Error::IssuerIsNotSigner);
+                }
+                renewed.clone().verify_signature(signer, time)?;
+            }
+            Transaction::Rotate { proof, rotated } => {
+                let previous = proof.certificate();
+                let Some(found_certificate) = self.certificates.get(previous.spki_hash()) else {
+                    return Err(Error::CertificateNotFound);
+                };
+                if found_certificate != previous {
+                    return Err(Error::CertificateDoesNotMatch);
+                }
+                if previous.certificate_type() != CertificateType::Agent {
+                    return Err(Error::RotationNotSupported);
+                }
+                if !proof.is_rotation() || &proof.renewed_spki_hash() != rotated.spki_hash() {
+                    return Err(Error::RotatedKeyMismatch);
+                }
+                if self.certificates.contains_key(rotated.spki_hash())
+                    || self.successors.contains_key(rotated.spki_hash())
+                {
+                    return Err(Error::CertificateAlreadyExists);
+                }
+                if rotated.certificate_type() != previous.certificate_type() {
+                    return Err(Error::RenewedTypeMismatch);
+                }
+                proof.verify(found_certificate)?;
+                if signer.spki_hash() != rotated.issuer() {
+                    return Err(Error::IssuerIsNotSigner);
+                }
+                rotated.clone().verify_signature(signer, time)?;
+            }
         }
 
         Ok(())
@@ -230,6 +725,34 @@ impl ChainState for State {
             Transaction::RemoveExpired(certificate) => {
                 self.certificates.remove(certificate.spki_hash());
             }
+            Transaction::Renew { renewed, .. } => {
+                let signer = self
+                    .certificates
+                    .get(renewed.issuer())
+                    .expect("transaction already checked");
+                let certificate = renewed
+                    .clone()
+                    .verify_signature(signer, time)
+                    .expect("transaction already checked");
+                self.certificates
+                    .insert(certificate.spki_hash().clone(), certificate);
+            }
+            Transaction::Rotate { proof, rotated } => {
+                let signer = self
+                    .certificates
+                    .get(rotated.issuer())
+                    .expect("transaction already checked");
+                let certificate = rotated
+                    .clone()
+                    .verify_signature(signer, time)
+                    .expect("transaction already checked");
+                let previous = proof.certificate().spki_hash();
+                self.certificates.remove(previous);
+                self.successors
+                    .insert(previous.clone(), certificate.spki_hash().clone());
+                self.certificates
+                    .insert(certificate.spki_hash().clone(), certificate);
+            }
         }
     }
 
@@ -244,19 +767,44 @@ impl ChainState for State {
                     certificate.clone().mark_as_trusted(),
                 );
             }
+            Transaction::Renew { previous, .. } => {
+                self.certificates.insert(
+                    previous.spki_hash().clone(),
+                    previous.clone().mark_as_trusted(),
+                );
+            }
+            Transaction::Rotate { proof, rotated } => {
+                let previous = proof.certificate();
+                self.certificates.remove(rotated.spki_hash());
+                self.successors.remove(previous.spki_hash());
+                self.certificates.insert(
+                    previous.spki_hash().clone(),
+                    previous.clone().mark_as_trusted(),
+                );
+            }
         }
     }
 
-    fn digest(&self, digest: &mut impl sha2::Digest) {
-        digest.update(self.root.as_der());
+    fn digest(&self, version: ProtocolVersion, digest: &mut impl sha2::Digest) {
+        if version == ProtocolVersion::LEGACY {
+            self.legacy_digest(digest);
+            return;
+        }
+
+        digest.domain("svalin/trust_store/state", version);
+        digest.field(self.root.as_der());
         let mut keys = self.certificates.keys().collect::<Vec<_>>();
         keys.sort();
+        digest.number(keys.len() as u64);
         for key in keys {
-            let certificate = self
-                .certificates
-                .get(&key)
-                .expect("keys were already taken from map");
-            digest.update(certificate.as_der());
+            digest.field(self.certificates[key].as_der());
+        }
+        let mut successors = self.successors.iter().collect::<Vec<_>>();
+        successors.sort();
+        digest.number(successors.len() as u64);
+        for (previous, successor) in successors {
+            digest.field(previous.as_slice());
+            digest.field(successor.as_slice());
         }
     }
 
@@ -268,6 +816,11 @@ impl ChainState for State {
                 .values()
                 .map(|c| c.clone().to_unverified())
                 .collect(),
+            successors: self
+                .successors
+                .iter()
+                .map(|(previous, successor)| (previous.clone(), successor.clone()))
+                .collect(),
         }
     }
 
@@ -297,7 +850,11 @@ impl ChainState for State {
             .map(|cert| (cert.spki_hash().clone(), cert.mark_as_trusted()))
             .collect();
 
-        Ok(Self { root, certificates })
+        Ok(Self {
+            root,
+            certificates,
+            successors: exported.successors.into_iter().collect(),
+        })
     }
 }
 
@@ -305,6 +862,26 @@ impl ChainState for State {
 struct ExportedState {
     root: UnverifiedCertificate,
     certificates: Vec<UnverifiedCertificate>,
+    /// Missing in self describing formats written before agent keys could be
+    /// rotated, postcard data needs [`PreRotationExported`].
+    #[serde(default)]
+    successors: Vec<(SpkiHash, SpkiHash)>,
+}
+
+#[derive(Deserialize)]
+struct PreRotationExportedState {
+    root: UnverifiedCertificate,
+    certificates: Vec<UnverifiedCertificate>,
+}
+
+impl From<PreRotationExportedState> for ExportedState {
+    fn from(exported: PreRotationExportedState) -> Self {
+        Self {
+            root: exported.root,
+            certificates: exported.certificates,
+            successors: Vec::new(),
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -319,6 +896,34 @@ enum InnerImportError {
     MissingIssuers(Vec<SpkiHash>),
 }
 
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct ImportCheckpointError(#[from] InnerCheckpointError);
+
+#[derive(Debug, thiserror::Error)]
+enum InnerCheckpointError {
+    #[error("error importing checkpoint: {0}")]
+    Import(secure_chain::ImportCheckpointError<InnerImportError>),
+    #[error("checkpoint has a different root")]
+    RootMismatch,
+    #[error("signer of the checkpoint is not known")]
+    SignerNotKnown,
+    #[error("signer of the checkpoint is not a server")]
+    SignerNotTrusted,
+    #[error("certificate of the signer is invalid: {0}")]
+    SignerInvalid(SignatureVerificationError),
+    #[error("invalid checkpoint signature: {0}")]
+    InvalidSignature(VerifyCheckpointError),
+    #[error("checkpoint is not newer than the trust store")]
+    Outdated,
+    #[error("checkpoint doesn't prove that it extends the local history")]
+    MissingHistory,
+    #[error("history of the checkpoint doesn't contain the local history")]
+    HistoryMismatch,
+    #[error("checkpoint restores the rotated key {0}")]
+    RevocationUndone(SpkiHash),
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum CompleteCertChainError {
     #[error("issuer with spki hash {0} not found")]
@@ -41,6 +41,8 @@ pub enum VerifyError {
     },
     #[error("The certificate type is incorrect")]
     IncorrectCertificateType,
+    #[error("The trust store conflicts with the chain of another member")]
+    ChainConflict,
     #[error("Internal Error: {0}")]
     InternalError(#[from] anyhow::Error),
 }
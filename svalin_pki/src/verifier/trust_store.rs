@@ -20,6 +20,9 @@ impl super::Verifier for TrustStoreVerifier {
         time: u64,
     ) -> Result<crate::Certificate, super::VerifyError> {
         let guard = self.trust_store.read().unwrap();
+        if guard.conflict().is_some() {
+            return Err(super::VerifyError::ChainConflict);
+        }
         let Some(cert) = guard.get(spki_hash) else {
             return Err(super::VerifyError::UnknownCertificate);
         };
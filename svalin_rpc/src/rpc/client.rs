@@ -1,17 +1,25 @@
-use std::{net::ToSocketAddrs, sync::Arc, time::Duration};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 
 use crate::{permissions::PermissionHandler, rustls};
-use anyhow::{Ok, Result, anyhow};
-use quinn::{
-    TransportConfig, VarInt, crypto::rustls::QuicClientConfig, rustls::crypto::CryptoProvider,
-};
+use anyhow::{Context, Result, anyhow};
+use quinn::{EndpointConfig, crypto::rustls::QuicClientConfig, rustls::crypto::CryptoProvider};
+use socket2::{Domain, Protocol, Socket, Type};
 use svalin_pki::Credential;
-use tokio::time::{error::Elapsed, timeout};
+use tokio::{
+    select,
+    task::JoinSet,
+    time::{error::Elapsed, sleep, timeout},
+};
 use tokio_util::{sync::CancellationToken, task::TaskTracker};
 
 use super::{
     command::handler::HandlerCollection,
     connection::{ServeableConnection, direct_connection::DirectConnection},
+    transport_config::QuicTransportConfig,
 };
 
 pub struct RpcClient {
@@ -26,12 +34,29 @@ impl RpcClient {
         identity: Option<&Credential>,
         verifier: Arc<dyn rustls::client::danger::ServerCertVerifier>,
         cancel: CancellationToken,
+    ) -> Result<RpcClient> {
+        Self::connect_with_transport(
+            address,
+            identity,
+            verifier,
+            &QuicTransportConfig::client_default(),
+            cancel,
+        )
+        .await
+    }
+
+    pub async fn connect_with_transport(
+        address: &str,
+        identity: Option<&Credential>,
+        verifier: Arc<dyn rustls::client::danger::ServerCertVerifier>,
+        transport_config: &QuicTransportConfig,
+        cancel: CancellationToken,
     ) -> Result<RpcClient> {
         if CryptoProvider::get_default().is_none() {
             let _ = quinn::rustls::crypto::ring::default_provider().install_default();
         }
 
-        let mut endpoint = quinn::Endpoint::client("0.0.0.0:0".parse()?)?;
+        let mut endpoint = Self::create_endpoint()?;
 
         let builder = rustls::ClientConfig::builder()
             .dangerous()
@@ -47,34 +72,20 @@ impl RpcClient {
             None => builder.with_no_client_auth(),
         };
 
-        // TODO: lower keepalive - needs higher server timeout
-        let mut transport_config = TransportConfig::default();
-        transport_config.max_idle_timeout(Some(VarInt::from_u32(10_000).into()));
-        transport_config.keep_alive_interval(Some(Duration::from_secs(5)));
-
         let mut client_config =
             quinn::ClientConfig::new(Arc::new(QuicClientConfig::try_from(rustls_conf)?));
-        client_config.transport_config(Arc::new(transport_config));
+        client_config.transport_config(Arc::new(transport_config.to_quinn()?));
 
         endpoint.set_default_client_config(client_config);
 
-        let split: Vec<&str> = address.split(":").collect();
-
-        let host = *split
-            .get(0)
-            .ok_or_else(|| anyhow!("missing host in endpoint"))?;
+        let (host, port) = split_host_port(address)?;
 
-        let port: u16 = split
-            .get(1)
-            .ok_or_else(|| anyhow!("missing port in endpoint"))?
-            .parse()?;
-
-        let addr = (host, port)
-            .to_socket_addrs()?
-            .find(|a| a.is_ipv4())
-            .ok_or_else(|| anyhow!("Unable to resolve Hostname, no IPv6 yet"))?;
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+            .await
+            .with_context(|| format!("failed to resolve {host}"))?
+            .collect();
 
-        let connection = endpoint.connect(addr, host)?.await?;
+        let connection = race_connections(&endpoint, sort_addresses(addrs), host).await?;
 
         let direct_connection = DirectConnection::new(connection)?;
 
@@ -85,6 +96,25 @@ impl RpcClient {
         })
     }
 
+    /// Creates a client endpoint which can reach both IPv4 and IPv6 peers.
+    ///
+    /// Falls back to an IPv4 only socket if the system has no IPv6 support.
+    fn create_endpoint() -> Result<quinn::Endpoint> {
+        let socket = match bind_dual_stack_socket() {
+            Ok(socket) => socket,
+            Err(err) => {
+                tracing::debug!("unable to create dual stack socket, falling back to IPv4: {err}");
+                std::net::UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))?
+            }
+        };
+
+        let runtime = quinn::default_runtime().ok_or_else(|| anyhow!("no async runtime found"))?;
+
+        let endpoint = quinn::Endpoint::new(EndpointConfig::default(), None, socket, runtime)?;
+
+        Ok(endpoint)
+    }
+
     pub fn upstream_connection(&self) -> DirectConnection {
         self.connection.clone()
     }
@@ -115,3 +145,139 @@ impl RpcClient {
             .await
     }
 }
+
+/// Delay before the next connection attempt is started while the previous one
+/// is still pending, as recommended by RFC 8305.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+fn bind_dual_stack_socket() -> std::io::Result<std::net::UdpSocket> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_only_v6(false)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)).into())?;
+
+    Ok(socket.into())
+}
+
+/// Splits an address into host and port.
+///
+/// IPv6 literals have to be enclosed in brackets, e.g. `[::1]:55411`.
+pub(crate) fn split_host_port(address: &str) -> Result<(&str, u16)> {
+    let (host, port) = address
+        .rsplit_once(':')
+        .ok_or_else(|| anyhow!("missing port in address {address}"))?;
+
+    let host = match host.strip_prefix('[') {
+        Some(bracketed) => {
+            let host = bracketed
+                .strip_suffix(']')
+                .ok_or_else(|| anyhow!("missing closing bracket in address {address}"))?;
+            host.parse::<Ipv6Addr>()
+                .with_context(|| format!("invalid IPv6 address in {address}"))?;
+            host
+        }
+        None => {
+            if host.contains(':') {
+                return Err(anyhow!(
+                    "IPv6 addresses need to be enclosed in brackets, e.g. [::1]:1234"
+                ));
+            }
+            host
+        }
+    };
+
+    if host.is_empty() {
+        return Err(anyhow!("missing host in address {address}"));
+    }
+
+    let port = port
+        .parse()
+        .with_context(|| format!("invalid port in address {address}"))?;
+
+    Ok((host, port))
+}
+
+/// Orders resolved addresses for connection attempts by alternating between
+/// IPv6 and IPv4, starting with IPv6.
+pub(crate) fn sort_addresses(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (mut v6, mut v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    v6.dedup();
+    v4.dedup();
+
+    let mut sorted = Vec::with_capacity(v6.len() + v4.len());
+    let mut v6 = v6.into_iter();
+    let mut v4 = v4.into_iter();
+
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break,
+            (a, b) => sorted.extend(a.into_iter().chain(b)),
+        }
+    }
+
+    sorted
+}
+
+/// Connects to the first reachable address, racing the attempts
+/// Happy-Eyeballs-style.
+///
+/// A new attempt is started every [`CONNECTION_ATTEMPT_DELAY`] or as soon as
+/// the previous one failed. The first successful connection wins, all other
+/// attempts are aborted.
+async fn race_connections(
+    endpoint: &quinn::Endpoint,
+    addrs: Vec<SocketAddr>,
+    server_name: &str,
+) -> Result<quinn::Connection> {
+    let mut remaining = addrs.into_iter();
+    let mut attempts = JoinSet::new();
+    let mut last_error = None;
+
+    loop {
+        if let Some(addr) = remaining.next() {
+            match endpoint.connect(addr, server_name) {
+                Ok(connecting) => {
+                    attempts.spawn(async move {
+                        connecting
+                            .await
+                            .with_context(|| format!("failed to connect to {addr}"))
+                    });
+                }
+                Err(err) => {
+                    last_error = Some(anyhow!(err).context(format!("unable to connect to {addr}")));
+                    continue;
+                }
+            }
+        } else if attempts.is_empty() {
+            break;
+        }
+
+        let has_remaining = !remaining.as_slice().is_empty();
+        let next_attempt = async {
+            if has_remaining {
+                sleep(CONNECTION_ATTEMPT_DELAY).await
+            } else {
+                std::future::pending().await
+            }
+        };
+
+        select! {
+            Some(result) = attempts.join_next() => {
+                match result {
+                    Ok(Ok(connection)) => {
+                        attempts.abort_all();
+                        return Ok(connection);
+                    }
+                    Ok(Err(err)) => {
+                        tracing::debug!("{err:#}");
+                        last_error = Some(err);
+                    }
+                    Err(err) => last_error = Some(err.into()),
+                }
+            }
+            _ = next_attempt => {}
+        }
+    }
+
+    Err(last_error
+        .unwrap_or_else(|| anyhow!("hostname {server_name} did not resolve to any address")))
+}
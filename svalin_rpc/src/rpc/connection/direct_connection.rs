@@ -1,3 +1,5 @@
+use std::net::SocketAddr;
+
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
 use quinn::{VarInt, rustls::pki_types::CertificateDer};
@@ -28,6 +30,10 @@ impl Connection for DirectConnection {
         &self.peer
     }
 
+    fn remote_address(&self) -> Option<SocketAddr> {
+        Some(self.conn.remote_address())
+    }
+
     async fn closed(&self) {
         // TODO: maybe return connection error from upstream?
         self.conn.closed().await;
@@ -1,4 +1,5 @@
 use std::fmt::Display;
+use std::net::SocketAddr;
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
@@ -39,6 +40,11 @@ pub trait Connection: Send + Sync + Clone {
 
     fn peer(&self) -> &Peer;
 
+    /// The address of the remote side, if the connection is not tunneled.
+    fn remote_address(&self) -> Option<SocketAddr> {
+        None
+    }
+
     async fn closed(&self);
 }
 
@@ -84,7 +90,8 @@ where
                 session = self.accept_raw_session() => {
                     match session {
                         Ok(transport) => {
-                            let session = Session::new(transport, self.peer().clone());
+                            let session = Session::new(transport, self.peer().clone())
+                                .with_remote_address(self.remote_address());
 
                             let commands2 = commands.clone();
                             open_sessions.spawn(async move {
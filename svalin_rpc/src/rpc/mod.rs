@@ -4,3 +4,4 @@ pub mod connection;
 pub mod peer;
 pub mod server;
 pub mod session;
+pub mod transport_config;
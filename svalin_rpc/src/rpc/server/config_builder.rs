@@ -5,7 +5,10 @@ use quinn::rustls::server::danger::ClientCertVerifier;
 use svalin_pki::Credential;
 use tokio_util::{sync::CancellationToken, task::TaskTracker};
 
-use crate::{permissions::PermissionHandler, rpc::command::handler::HandlerCollection};
+use crate::{
+    permissions::PermissionHandler,
+    rpc::{command::handler::HandlerCollection, transport_config::QuicTransportConfig},
+};
 
 use super::{RpcServer, RpcServerConfig, Socket};
 
@@ -15,6 +18,7 @@ pub struct RpcServerConfigBuilder<A, B, C, D, E> {
     command_builder: C,
     cancellation_token: D,
     task_tracker: E,
+    transport_config: QuicTransportConfig,
 }
 
 impl RpcServerConfigBuilder<(), (), (), (), ()> {
@@ -25,6 +29,7 @@ impl RpcServerConfigBuilder<(), (), (), (), ()> {
             command_builder: (),
             cancellation_token: (),
             task_tracker: (),
+            transport_config: QuicTransportConfig::default(),
         }
     }
 }
@@ -40,6 +45,7 @@ impl<A, B, C, D, E> RpcServerConfigBuilder<A, B, C, D, E> {
             command_builder: self.command_builder,
             cancellation_token: self.cancellation_token,
             task_tracker: self.task_tracker,
+            transport_config: self.transport_config,
         }
     }
 
@@ -53,6 +59,7 @@ impl<A, B, C, D, E> RpcServerConfigBuilder<A, B, C, D, E> {
             command_builder: self.command_builder,
             cancellation_token: self.cancellation_token,
             task_tracker: self.task_tracker,
+            transport_config: self.transport_config,
         }
     }
 
@@ -66,6 +73,7 @@ impl<A, B, C, D, E> RpcServerConfigBuilder<A, B, C, D, E> {
             command_builder,
             cancellation_token: self.cancellation_token,
             task_tracker: self.task_tracker,
+            transport_config: self.transport_config,
         }
     }
 
@@ -79,6 +87,7 @@ impl<A, B, C, D, E> RpcServerConfigBuilder<A, B, C, D, E> {
             command_builder: self.command_builder,
             cancellation_token,
             task_tracker: self.task_tracker,
+            transport_config: self.transport_config,
         }
     }
 
@@ -92,6 +101,16 @@ impl<A, B, C, D, E> RpcServerConfigBuilder<A, B, C, D, E> {
             command_builder: self.command_builder,
             cancellation_token: self.cancellation_token,
             task_tracker,
+            transport_config: self.transport_config,
+        }
+    }
+
+    /// Overrides the QUIC transport parameters, unset fields keep the quinn
+    /// defaults.
+    pub fn transport_config(self, transport_config: QuicTransportConfig) -> Self {
+        Self {
+            transport_config,
+            ..self
         }
     }
 }
@@ -108,13 +127,19 @@ where
     CB: RpcCommandBuilder,
 {
     pub async fn start_server(self, socket: Socket) -> Result<Arc<RpcServer>> {
+        self.start_server_on(vec![socket]).await
+    }
+
+    /// Starts a single server which accepts connections on all given sockets.
+    pub async fn start_server_on(self, sockets: Vec<Socket>) -> Result<Arc<RpcServer>> {
         let config = RpcServerConfig {
             credentials: self.credentials,
             client_cert_verifier: self.client_cert_verifier,
             cancellation_token: self.cancellation_token,
+            transport_config: self.transport_config,
         };
 
-        RpcServer::run(socket, config, self.command_builder, self.task_tracker).await
+        RpcServer::run(sockets, config, self.command_builder, self.task_tracker).await
     }
 }
 
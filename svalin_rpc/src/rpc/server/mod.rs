@@ -19,6 +19,7 @@ use tracing::error;
 use crate::permissions::PermissionHandler;
 use crate::rpc::connection::{Connection, ServeableConnection};
 use crate::rpc::peer::Peer;
+use crate::rpc::transport_config::QuicTransportConfig;
 use crate::rustls::{self, server::danger::ClientCertVerifier};
 
 use crate::rpc::command::handler::HandlerCollection;
@@ -32,7 +33,7 @@ use config_builder::{RpcCommandBuilder, RpcServerConfigBuilder};
 #[derive(Debug)]
 pub struct RpcServer {
     config: RpcServerConfig,
-    endpoint: quinn::Endpoint,
+    endpoints: Vec<quinn::Endpoint>,
     connection_data: Mutex<ServerConnectionData>,
     client_status_broadcast: broadcast::Sender<(Certificate, bool)>,
     tasks: TaskTracker,
@@ -43,6 +44,7 @@ struct RpcServerConfig {
     credentials: Credential,
     client_cert_verifier: Arc<dyn ClientCertVerifier>,
     cancellation_token: CancellationToken,
+    transport_config: QuicTransportConfig,
 }
 
 #[derive(Debug)]
@@ -58,8 +60,23 @@ impl RpcServer {
         RpcServerConfigBuilder::new()
     }
 
+    /// Binds the UDP socket used by the server.
+    ///
+    /// Binding to the unspecified IPv6 address (`[::]`) creates a dual stack
+    /// socket which also accepts IPv4 connections.
     pub fn create_socket(addr: SocketAddr) -> std::io::Result<Socket> {
-        let std_socket = std::net::UdpSocket::bind(addr)?;
+        let socket = socket2::Socket::new(
+            socket2::Domain::for_address(addr),
+            socket2::Type::DGRAM,
+            Some(socket2::Protocol::UDP),
+        )?;
+
+        if addr.is_ipv6() {
+            socket.set_only_v6(!addr.ip().is_unspecified())?;
+        }
+
+        socket.bind(&addr.into())?;
+        let std_socket: std::net::UdpSocket = socket.into();
         let runtime = quinn::default_runtime().ok_or_else(|| {
             std::io::Error::new(std::io::ErrorKind::Other, "no async runtime found")
         })?;
@@ -70,20 +87,23 @@ impl RpcServer {
     }
 
     async fn run(
-        socket: Socket,
+        sockets: Vec<Socket>,
         config: RpcServerConfig,
         command_builder: impl RpcCommandBuilder,
         master_tracker: TaskTracker,
     ) -> Result<Arc<Self>> {
-        let server = Self::create(socket, config, master_tracker)?;
+        let server = Self::create(sockets, config, master_tracker)?;
 
-        let serve_future = server.clone().serve(command_builder.build(&server).await?);
+        let commands = command_builder.build(&server).await?;
 
-        server.tasks.spawn(serve_future);
+        for endpoint in server.endpoints.iter() {
+            let serve_future = server.clone().serve(endpoint.clone(), commands.clone());
+            server.tasks.spawn(serve_future);
+        }
 
         let cancel_token = server.config.cancellation_token.clone();
         let tasks = server.tasks.clone();
-        let endpoint = server.endpoint.clone();
+        let endpoints = server.endpoints.clone();
 
         tokio::spawn(async move {
             // fallback in case the close method is not called
@@ -91,14 +111,16 @@ impl RpcServer {
             tasks.close();
             tasks.wait().await;
 
-            endpoint.close(0u32.into(), b"graceful shutdown, goodbye");
+            for endpoint in endpoints {
+                endpoint.close(0u32.into(), b"graceful shutdown, goodbye");
+            }
         });
 
         Ok(server)
     }
 
     fn create(
-        socket: Socket,
+        sockets: Vec<Socket>,
         config: RpcServerConfig,
         master_tracker: TaskTracker,
     ) -> Result<Arc<Self>> {
@@ -106,8 +128,17 @@ impl RpcServer {
             let _ = quinn::rustls::crypto::ring::default_provider().install_default();
         }
 
-        let endpoint =
-            RpcServer::create_endpoint(socket, &config).context("failed to create rpc endpoint")?;
+        if sockets.is_empty() {
+            return Err(anyhow!(
+                "at least one socket is required to start the server"
+            ));
+        }
+
+        let endpoints = sockets
+            .into_iter()
+            .map(|socket| RpcServer::create_endpoint(socket, &config))
+            .collect::<Result<Vec<_>>>()
+            .context("failed to create rpc endpoint")?;
 
         let (br_send, _) = broadcast::channel(10);
 
@@ -119,7 +150,7 @@ impl RpcServer {
         });
 
         Ok(Arc::new(Self {
-            endpoint,
+            endpoints,
             connection_data: Mutex::new(ServerConnectionData {
                 latest_connections: HashMap::new(),
             }),
@@ -140,9 +171,10 @@ impl RpcServer {
             .with_client_cert_verifier(config.client_cert_verifier.clone())
             .with_single_cert(cert_chain, priv_key)?;
 
-        let config = quinn::ServerConfig::with_crypto(Arc::new(
+        let mut server_config = quinn::ServerConfig::with_crypto(Arc::new(
             QuicServerConfig::try_from(crypto).map_err(|err| anyhow!(err))?,
         ));
+        server_config.transport_config(Arc::new(config.transport_config.to_quinn()?));
 
         let runtime = quinn::default_runtime().ok_or_else(|| {
             std::io::Error::new(std::io::ErrorKind::Other, "no async runtime found")
@@ -150,7 +182,7 @@ impl RpcServer {
 
         let endpoint = quinn::Endpoint::new_with_abstract_socket(
             EndpointConfig::default(),
-            Some(config),
+            Some(server_config),
             socket.0,
             runtime,
         )?;
@@ -158,7 +190,11 @@ impl RpcServer {
         Ok(endpoint)
     }
 
-    async fn serve(self: Arc<Self>, commands: HandlerCollection<impl PermissionHandler>) {
+    async fn serve(
+        self: Arc<Self>,
+        endpoint: quinn::Endpoint,
+        commands: HandlerCollection<impl PermissionHandler>,
+    ) {
         // tracing::trace!("starting server");
 
         loop {
@@ -168,7 +204,7 @@ impl RpcServer {
                     // tracing::trace!("canceling RPC main serve loop");
                     break;
                 }
-                conn_option = self.endpoint.accept() => {
+                conn_option = endpoint.accept() => {
                     match conn_option {
                         None => {
                             // tracing::trace!("no more connections");
@@ -259,8 +295,9 @@ impl RpcServer {
 
         let result = timeout(timeout_duration, self.tasks.wait()).await;
 
-        self.endpoint
-            .close(0u32.into(), b"graceful shutdown, goodbye");
+        for endpoint in self.endpoints.iter() {
+            endpoint.close(0u32.into(), b"graceful shutdown, goodbye");
+        }
 
         result
     }
@@ -1,4 +1,5 @@
 use std::fmt::Debug;
+use std::net::SocketAddr;
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
@@ -22,6 +23,7 @@ use super::{
 pub struct Session {
     transport: ObjectTransport,
     peer: Peer,
+    remote_address: Option<SocketAddr>,
 }
 
 impl Debug for Session {
@@ -77,7 +79,17 @@ impl Session {
     pub fn new(transport: Box<dyn SessionTransport>, peer: Peer) -> Self {
         let transport = ObjectTransport::new(transport);
 
-        Self { transport, peer }
+        Self {
+            transport,
+            peer,
+            remote_address: None,
+        }
+    }
+
+    /// Sets the address the underlying connection was accepted from.
+    pub fn with_remote_address(mut self, remote_address: Option<SocketAddr>) -> Self {
+        self.remote_address = remote_address;
+        self
     }
 
     pub(crate) async fn handle<P>(
@@ -151,6 +163,12 @@ impl Session {
         &self.peer
     }
 
+    /// The address of the remote side, only known for sessions accepted on a
+    /// direct connection.
+    pub fn remote_address(&self) -> Option<SocketAddr> {
+        self.remote_address
+    }
+
     pub async fn read_object<W: serde::de::DeserializeOwned>(
         &mut self,
     ) -> Result<W, SessionReadError> {
@@ -0,0 +1,172 @@
+use std::{sync::Arc, time::Duration};
+
+use quinn::{
+    IdleTimeout, MtuDiscoveryConfig, TransportConfig, VarInt, VarIntBoundsExceeded,
+    congestion::{BbrConfig, CubicConfig, NewRenoConfig},
+};
+use serde::{Deserialize, Serialize};
+
+/// Tuning parameters for the QUIC connections used by [`RpcClient`] and
+/// [`RpcServer`].
+///
+/// Every field is optional, unset fields keep the quinn default. The struct is
+/// meant to be embedded in configuration files.
+///
+/// [`RpcClient`]: super::client::RpcClient
+/// [`RpcServer`]: super::server::RpcServer
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuicTransportConfig {
+    /// Time without any traffic after which the connection is closed.
+    ///
+    /// The peers use the lower of both values. `0` disables the timeout.
+    pub idle_timeout_ms: Option<u64>,
+    /// Interval in which keepalive packets are sent to prevent the idle timeout.
+    pub keep_alive_interval_ms: Option<u64>,
+    /// Maximum number of concurrent bidirectional streams the peer may open.
+    pub max_concurrent_bidi_streams: Option<u32>,
+    /// Maximum number of concurrent unidirectional streams the peer may open.
+    pub max_concurrent_uni_streams: Option<u32>,
+    /// Algorithm which controls the send rate, one of `cubic`, `new_reno` or
+    /// `bbr`. Defaults to `cubic`, like quinn.
+    pub congestion_controller: Option<CongestionController>,
+    /// Enables or disables path MTU discovery.
+    pub mtu_discovery: Option<bool>,
+    /// Upper bound for the path MTU discovery.
+    pub max_mtu: Option<u16>,
+    /// Receive window for the whole connection in bytes.
+    pub receive_window: Option<u64>,
+    /// Receive window for a single stream in bytes.
+    pub stream_receive_window: Option<u64>,
+    /// Send window for the whole connection in bytes.
+    pub send_window: Option<u64>,
+}
+
+/// Congestion control algorithms supported by quinn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CongestionController {
+    /// Loss based, the quinn default.
+    Cubic,
+    /// Loss based, grows the window more slowly than Cubic on fast links.
+    NewReno,
+    /// Model based, keeps throughput up on lossy links. Experimental in
+    /// quinn.
+    Bbr,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TransportConfigError {
+    #[error("{field} is out of range: {source}")]
+    OutOfRange {
+        field: &'static str,
+        source: VarIntBoundsExceeded,
+    },
+}
+
+impl QuicTransportConfig {
+    /// Default used by clients and agents.
+    ///
+    /// Sends keepalives well below the idle timeout so connections behind NAT
+    /// are kept open.
+    pub fn client_default() -> Self {
+        Self {
+            idle_timeout_ms: Some(10_000),
+            keep_alive_interval_ms: Some(5_000),
+            ..Default::default()
+        }
+    }
+
+    /// Fills every unset field of `self` with the value from `other`.
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            idle_timeout_ms: self.idle_timeout_ms.or(other.idle_timeout_ms),
+            keep_alive_interval_ms: self.keep_alive_interval_ms.or(other.keep_alive_interval_ms),
+            max_concurrent_bidi_streams: self
+                .max_concurrent_bidi_streams
+                .or(other.max_concurrent_bidi_streams),
+            max_concurrent_uni_streams: self
+                .max_concurrent_uni_streams
+                .or(other.max_concurrent_uni_streams),
+            congestion_controller: self.congestion_controller.or(other.congestion_controller),
+            mtu_discovery: self.mtu_discovery.or(other.mtu_discovery),
+            max_mtu: self.max_mtu.or(other.max_mtu),
+            receive_window: self.receive_window.or(other.receive_window),
+            stream_receive_window: self.stream_receive_window.or(other.stream_receive_window),
+            send_window: self.send_window.or(other.send_window),
+        }
+    }
+
+    pub fn to_quinn(&self) -> Result<TransportConfig, TransportConfigError> {
+        let mut config = TransportConfig::default();
+
+        if let Some(idle_timeout) = self.idle_timeout_ms {
+            if idle_timeout == 0 {
+                config.max_idle_timeout(None);
+            } else {
+                let idle_timeout = IdleTimeout::try_from(Duration::from_millis(idle_timeout))
+                    .map_err(|source| TransportConfigError::OutOfRange {
+                        field: "idle_timeout_ms",
+                        source,
+                    })?;
+                config.max_idle_timeout(Some(idle_timeout));
+            }
+        }
+
+        if let Some(interval) = self.keep_alive_interval_ms {
+            config.keep_alive_interval(Some(Duration::from_millis(interval)));
+        }
+
+        if let Some(streams) = self.max_concurrent_bidi_streams {
+            config.max_concurrent_bidi_streams(streams.into());
+        }
+
+        if let Some(streams) = self.max_concurrent_uni_streams {
+            config.max_concurrent_uni_streams(streams.into());
+        }
+
+        if let Some(controller) = self.congestion_controller {
+            match controller {
+                CongestionController::Cubic => {
+                    config.congestion_controller_factory(Arc::new(CubicConfig::default()))
+                }
+                CongestionController::NewReno => {
+                    config.congestion_controller_factory(Arc::new(NewRenoConfig::default()))
+                }
+                CongestionController::Bbr => {
+                    config.congestion_controller_factory(Arc::new(BbrConfig::default()))
+                }
+            };
+        }
+
+        match (self.mtu_discovery, self.max_mtu) {
+            (Some(false), _) => {
+                config.mtu_discovery_config(None);
+            }
+            (_, Some(max_mtu)) => {
+                let mut mtu_config = MtuDiscoveryConfig::default();
+                mtu_config.upper_bound(max_mtu);
+                config.mtu_discovery_config(Some(mtu_config));
+            }
+            _ => {}
+        }
+
+        if let Some(window) = self.receive_window {
+            config.receive_window(varint("receive_window", window)?);
+        }
+
+        if let Some(window) = self.stream_receive_window {
+            config.stream_receive_window(varint("stream_receive_window", window)?);
+        }
+
+        if let Some(window) = self.send_window {
+            config.send_window(window);
+        }
+
+        Ok(config)
+    }
+}
+
+fn varint(field: &'static str, value: u64) -> Result<VarInt, TransportConfigError> {
+    VarInt::from_u64(value).map_err(|source| TransportConfigError::OutOfRange { field, source })
+}
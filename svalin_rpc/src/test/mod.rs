@@ -16,8 +16,11 @@ use crate::{
         whitelist::WhitelistPermissionHandler,
     },
     rpc::{
-        client::RpcClient, command::handler::HandlerCollection, connection::Connection,
+        client::{RpcClient, sort_addresses, split_host_port},
+        command::handler::HandlerCollection,
+        connection::Connection,
         server::RpcServer,
+        transport_config::{CongestionController, QuicTransportConfig},
     },
     verifiers::skip_verify::{SkipClientVerification, SkipServerVerification},
 };
@@ -67,6 +70,117 @@ async fn ping_test() {
     server.close(Duration::from_secs(1)).await.unwrap();
 }
 
+#[test(tokio::test)]
+async fn ipv6_dual_stack_test() {
+    let credentials = Credential::generate_root().unwrap();
+
+    let permission_handler = AnonymousPermissionHandler::<DummyPermission>::default();
+
+    let commands = HandlerCollection::new(permission_handler);
+    commands.chain().await.add(PingHandler);
+
+    let socket = match RpcServer::create_socket("[::]:1238".parse().unwrap()) {
+        Ok(socket) => socket,
+        Err(err) => {
+            tracing::warn!("skipping IPv6 test, no IPv6 support: {err}");
+            return;
+        }
+    };
+
+    let server = RpcServer::build()
+        .credentials(credentials)
+        .commands(commands)
+        .client_cert_verifier(SkipClientVerification::new())
+        .cancellation_token(CancellationToken::new())
+        .task_tracker(TaskTracker::new())
+        .start_server(socket)
+        .await
+        .unwrap();
+
+    for address in ["[::1]:1238", "127.0.0.1:1238", "localhost:1238"] {
+        let client = RpcClient::connect(
+            address,
+            None,
+            SkipServerVerification::new(),
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        client.upstream_connection().dispatch(Ping).await.unwrap();
+
+        client.close(Duration::from_secs(1)).await.unwrap();
+    }
+
+    server.close(Duration::from_secs(1)).await.unwrap();
+}
+
+#[test]
+fn split_host_port_test() {
+    assert_eq!(
+        split_host_port("example.com:55411").unwrap(),
+        ("example.com", 55411)
+    );
+    assert_eq!(
+        split_host_port("127.0.0.1:55411").unwrap(),
+        ("127.0.0.1", 55411)
+    );
+    assert_eq!(split_host_port("[::1]:55411").unwrap(), ("::1", 55411));
+    assert_eq!(
+        split_host_port("[2001:db8::1]:443").unwrap(),
+        ("2001:db8::1", 443)
+    );
+
+    split_host_port("::1:55411").unwrap_err();
+    split_host_port("[::1:55411").unwrap_err();
+    split_host_port("[example.com]:55411").unwrap_err();
+    split_host_port("example.com").unwrap_err();
+    split_host_port(":55411").unwrap_err();
+    split_host_port("example.com:port").unwrap_err();
+}
+
+#[test]
+fn sort_addresses_test() {
+    let v4a = "127.0.0.1:1".parse().unwrap();
+    let v4b = "127.0.0.2:1".parse().unwrap();
+    let v6a = "[::1]:1".parse().unwrap();
+    let v6b = "[::2]:1".parse().unwrap();
+
+    assert_eq!(
+        sort_addresses(vec![v4a, v4b, v6a, v6b]),
+        vec![v6a, v4a, v6b, v4b]
+    );
+    assert_eq!(sort_addresses(vec![v4a, v4b]), vec![v4a, v4b]);
+}
+
+#[test]
+fn transport_config_test() {
+    QuicTransportConfig::default().to_quinn().unwrap();
+    QuicTransportConfig::client_default().to_quinn().unwrap();
+
+    let config = QuicTransportConfig {
+        idle_timeout_ms: Some(0),
+        congestion_controller: Some(CongestionController::Bbr),
+        mtu_discovery: Some(false),
+        ..Default::default()
+    };
+    config.to_quinn().unwrap();
+
+    let config = QuicTransportConfig {
+        receive_window: Some(u64::MAX),
+        ..Default::default()
+    };
+    config.to_quinn().unwrap_err();
+
+    let merged = QuicTransportConfig {
+        idle_timeout_ms: Some(60_000),
+        ..Default::default()
+    }
+    .or(QuicTransportConfig::client_default());
+    assert_eq!(merged.idle_timeout_ms, Some(60_000));
+    assert_eq!(merged.keep_alive_interval_ms, Some(5_000));
+}
+
 #[test(tokio::test)]
 async fn tls_test() {
     let hook = panic::take_hook();
@@ -0,0 +1,3 @@
+-- Commits and welcomes, members can't continue their groups without them, so
+-- they are never dropped by the retention limits.
+ALTER TABLE mls_messages ADD COLUMN handshake INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,5 @@
+CREATE TABLE renewal_requests (
+    spki_hash BLOB PRIMARY KEY NOT NULL,
+    request BLOB NOT NULL,
+    requested_at INTEGER NOT NULL
+);
@@ -0,0 +1,6 @@
+
+CREATE TABLE trust_store_checkpoints (
+    sequence INTEGER PRIMARY KEY NOT NULL,
+    checkpoint BLOB NOT NULL,
+    state BLOB NOT NULL
+);
@@ -0,0 +1,6 @@
+CREATE TABLE chain_blocks (
+    chain TEXT NOT NULL,
+    sequence INTEGER NOT NULL,
+    data BLOB NOT NULL,
+    PRIMARY KEY (chain, sequence)
+);
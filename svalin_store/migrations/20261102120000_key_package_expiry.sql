@@ -0,0 +1,4 @@
+-- Packages stored before this migration have an unknown lifetime, so they
+-- are treated as expired and replaced by their owners.
+ALTER TABLE key_packages ADD COLUMN not_after INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE key_packages ADD COLUMN last_resort BOOLEAN NOT NULL DEFAULT FALSE;
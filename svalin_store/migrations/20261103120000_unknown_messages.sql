@@ -0,0 +1,10 @@
+-- Messages of types the client has no persistence for, kept so a later
+-- version can still handle them.
+CREATE TABLE unknown_messages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    sender BLOB NOT NULL,
+    tag TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    payload BLOB NOT NULL,
+    received_at INTEGER NOT NULL
+);
@@ -0,0 +1,5 @@
+-- NULL keeps the message until every receiver acknowledged it
+ALTER TABLE mls_messages ADD COLUMN expires_at INTEGER;
+
+CREATE INDEX mls_messages_expires_idx ON mls_messages(expires_at);
+CREATE INDEX mls_messages_received_idx ON mls_messages(received_at);
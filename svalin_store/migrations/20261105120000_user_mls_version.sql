@@ -0,0 +1,2 @@
+-- incremented with every accepted MLS state upload of the user
+ALTER TABLE users ADD COLUMN mls_version INTEGER NOT NULL DEFAULT 0;
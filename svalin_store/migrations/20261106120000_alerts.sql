@@ -0,0 +1,11 @@
+-- Alerts raised by agents, kept until they are acknowledged by any session.
+CREATE TABLE alerts (
+    id BLOB PRIMARY KEY NOT NULL,
+    device BLOB NOT NULL,
+    data BLOB NOT NULL,
+    raised_at INTEGER NOT NULL,
+    acknowledged_by BLOB,
+    acknowledged_at INTEGER
+);
+
+CREATE INDEX alerts_device_idx ON alerts(device);
@@ -0,0 +1,6 @@
+-- Positions of other members which conflict with the local trust store, kept
+-- until an admin resets them.
+CREATE TABLE trust_store_conflicts (
+    signer BLOB PRIMARY KEY NOT NULL,
+    checkpoint BLOB NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Merkle leaves of the trust store blocks. They are kept when the blocks are
+-- pruned, so the Merkle index still covers the whole history.
+CREATE TABLE trust_store_leaves (
+    sequence INTEGER PRIMARY KEY NOT NULL,
+    leaf BLOB NOT NULL
+);
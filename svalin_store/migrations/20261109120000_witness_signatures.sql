@@ -0,0 +1,6 @@
+-- The latest trust store head countersigned by each witness, so members can
+-- still verify new heads after the server restarted.
+CREATE TABLE witness_signatures (
+    signer BLOB PRIMARY KEY NOT NULL,
+    checkpoint BLOB NOT NULL
+);
@@ -0,0 +1,2 @@
+-- The group an unknown message was sent to, NULL for messages stored before.
+ALTER TABLE unknown_messages ADD COLUMN group_id TEXT;
@@ -0,0 +1,4 @@
+-- Messages stored before commits and welcomes were flagged might be either.
+-- They are marked as unchecked, which keeps them from the retention limits
+-- until the server decoded them on its next start.
+UPDATE mls_messages SET handshake = 2 WHERE handshake = 0;
@@ -0,0 +1,115 @@
+use std::{marker::PhantomData, ops::RangeInclusive, path::Path};
+
+use serde::{Serialize, de::DeserializeOwned};
+use svalin_pki::secure_chain::{CheckedBlock, UncheckedBlock, store::BlockStore};
+use tokio::sync::Mutex;
+
+/// Stores the blocks of a [`svalin_pki::secure_chain::Chain`].
+///
+/// Several chains can share the database, they are told apart by their name.
+#[derive(Debug)]
+pub struct ChainBlockStore<T> {
+    pool: sqlx::SqlitePool,
+    chain: String,
+    last_sequence: Mutex<u64>,
+    transaction: PhantomData<fn() -> T>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ChainBlockStoreError {
+    #[error("SQLx error: {0}")]
+    SqlxError(#[from] sqlx::Error),
+    #[error("expected block {expected}, got {actual}")]
+    SequenceMismatch { expected: u64, actual: u64 },
+    #[error("Postcard error: {0}")]
+    PostcardError(#[from] postcard::Error),
+}
+
+impl<T> ChainBlockStore<T> {
+    pub async fn open(
+        pool: sqlx::SqlitePool,
+        chain: impl Into<String>,
+    ) -> Result<Self, sqlx::Error> {
+        let chain = chain.into();
+        let last_sequence = sqlx::query_scalar!(
+            "SELECT MAX(sequence) FROM chain_blocks WHERE chain = ?",
+            chain
+        )
+        .fetch_one(&pool)
+        .await?
+        .unwrap_or(0);
+
+        Ok(Self {
+            pool,
+            chain,
+            last_sequence: Mutex::new(last_sequence as u64),
+            transaction: PhantomData,
+        })
+    }
+
+    /// Opens a database of its own, for chains which aren't kept next to
+    /// another store.
+    pub async fn open_file(
+        filename: impl AsRef<Path>,
+        chain: impl Into<String>,
+    ) -> Result<Self, sqlx::Error> {
+        let pool = crate::open_database(filename).await?;
+        Self::open(pool, chain).await
+    }
+}
+
+impl<T> BlockStore<T> for ChainBlockStore<T>
+where
+    T: Serialize + DeserializeOwned + Send + Sync,
+{
+    type Error = ChainBlockStoreError;
+
+    async fn last_sequence(&self) -> Result<u64, Self::Error> {
+        Ok(*self.last_sequence.lock().await)
+    }
+
+    async fn append(&self, block: &CheckedBlock<T>) -> Result<(), Self::Error> {
+        let mut last_sequence = self.last_sequence.lock().await;
+        if block.sequence() != *last_sequence + 1 {
+            return Err(ChainBlockStoreError::SequenceMismatch {
+                expected: *last_sequence + 1,
+                actual: block.sequence(),
+            });
+        }
+
+        let sequence = block.sequence() as i64;
+        let data = postcard::to_stdvec(block.as_unchecked())?;
+        sqlx::query!(
+            "INSERT INTO chain_blocks (chain, sequence, data) VALUES (?, ?, ?)",
+            self.chain,
+            sequence,
+            data
+        )
+        .execute(&self.pool)
+        .await?;
+        *last_sequence = block.sequence();
+
+        Ok(())
+    }
+
+    async fn load_range(
+        &self,
+        range: RangeInclusive<u64>,
+    ) -> Result<Vec<UncheckedBlock<T>>, Self::Error> {
+        let start = *range.start() as i64;
+        let end = *range.end() as i64;
+        let blocks = sqlx::query_scalar!(
+            "SELECT data FROM chain_blocks WHERE chain = ? AND sequence >= ? AND sequence <= ? ORDER BY sequence ASC",
+            self.chain,
+            start,
+            end
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(blocks
+            .iter()
+            .map(|data| postcard::from_bytes(data))
+            .collect::<Result<_, _>>()?)
+    }
+}
@@ -1,69 +1,154 @@
-use futures::StreamExt;
+use futures::{StreamExt, future::BoxFuture};
 use sqlx::SqlitePool;
-use std::{fmt::Debug, path::Path, sync::Arc};
-use svalin_pki::SpkiHash;
+use std::{collections::HashMap, fmt::Debug, path::Path, sync::Arc};
+use svalin_pki::{
+    SpkiHash, get_current_timestamp,
+    mls::{
+        SvalinGroupId,
+        transport_types::{DecodeMessageError, MessagePayload, SvalinMessage},
+    },
+};
+use uuid::Uuid;
 
 use crate::{close_handle::CloseHandle, trust_store_transaction_store::TrustStoreTransactionStore};
-use persistent::Message;
+use persistent::{
+    Acknowledged, AlertAcknowledgement, AlertState, Message, SvalinAlert, SvalinMetaInfo,
+    SvalinReport,
+};
 
 pub mod persistent;
 
+/// Number of messages of unknown types which are kept, older ones are
+/// deleted.
+const MAX_UNKNOWN_MESSAGES: i64 = 1000;
+
+/// Stores the messages of one payload type, see [`ClientStore::register`].
+pub trait MessagePersistence: Send + Sync {
+    fn persist<'a>(
+        &'a self,
+        pool: &'a SqlitePool,
+        group: &'a SvalinGroupId,
+        sender: &'a SpkiHash,
+        message: &'a SvalinMessage,
+    ) -> BoxFuture<'a, Result<(), Error>>;
+}
+
 pub struct ClientStore {
     pool: SqlitePool,
     transaction_store: Arc<TrustStoreTransactionStore>,
+    persistence: HashMap<(String, u16), Arc<dyn MessagePersistence>>,
 }
 
 impl ClientStore {
     pub async fn open(filename: impl AsRef<Path>) -> Result<Self, Error> {
         let pool = super::open_database(filename).await?;
 
-        Ok(Self {
+        let mut store = Self {
             transaction_store: Arc::new(TrustStoreTransactionStore::open(pool.clone()).await?),
             pool,
-        })
+            persistence: HashMap::new(),
+        };
+        store.register::<SvalinAlert>(AlertPersistence);
+        store.register::<AlertAcknowledgement>(AlertAcknowledgementPersistence);
+
+        Ok(store)
+    }
+
+    /// Registers how messages of the payload type are stored. Messages of
+    /// types without a registered persistence are kept as unknown messages.
+    ///
+    /// Reports and meta info are checked by the MLS client and arrive as
+    /// [`persistent::Message::UpdateSystemReport`] and
+    /// [`persistent::Message::UpdateMetaInfo`] instead.
+    pub fn register<P: MessagePayload>(&mut self, persistence: impl MessagePersistence + 'static) {
+        self.persistence
+            .insert((P::TAG.to_string(), P::VERSION), Arc::new(persistence));
     }
 
     pub async fn update(&self, message: &persistent::Message) -> Result<(), Error> {
         match &message {
             persistent::Message::UpdateSystemReport(spki_hash, system_report) => {
-                let report = postcard::to_stdvec(system_report)?;
-                let spki_hash = spki_hash.as_slice();
-                let generated_at = system_report.system_report.generated_at as i64;
-
-                sqlx::query!("INSERT INTO system_reports (spki_hash, report, generated_at) VALUES (?, ?, ?) ON CONFLICT(spki_hash) DO UPDATE SET report = ? where generated_at < ?", spki_hash, report, generated_at, report, generated_at)
-                    .execute(&self.pool)
-                    .await?;
+                save_report(&self.pool, spki_hash, system_report).await?;
             }
             persistent::Message::UpdateMetaInfo(spki_hash, meta_info) => {
-                let info = postcard::to_stdvec(meta_info)?;
-                let spki_hash = spki_hash.as_slice();
-                let updated_at = meta_info.updated_at as i64;
-
-                sqlx::query!("INSERT INTO meta_info (spki_hash, data, updated_at) VALUES (?, ?, ?) ON CONFLICT(spki_hash) DO UPDATE SET data = ? where updated_at < ?", spki_hash, info, updated_at, info, updated_at)
-                    .execute(&self.pool)
-                    .await?;
+                save_meta_info(&self.pool, spki_hash, meta_info).await?;
             }
             &persistent::Message::UpdateFromMainState(state) => {
                 for (spki_hash, device) in &state.devices {
-                    let spki_hash = spki_hash.as_slice();
                     if let Some(system_report) = device.report() {
-                        let report = postcard::to_stdvec(system_report)?;
-                        let generated_at = system_report.system_report.generated_at as i64;
-
-                        sqlx::query!("INSERT INTO system_reports (spki_hash, report, generated_at) VALUES (?, ?, ?) ON CONFLICT(spki_hash) DO UPDATE SET report = ? where generated_at < ?", spki_hash, report, generated_at, report, generated_at)
-                        .execute(&self.pool)
+                        save_report(&self.pool, spki_hash, system_report).await?;
+                    }
+                    if let Some(meta_info) = device.meta_info() {
+                        save_meta_info(&self.pool, spki_hash, meta_info).await?;
+                    }
+                }
+                for alert in state.alerts.values() {
+                    save_alert(&self.pool, &alert.device, &alert.alert).await?;
+                    if let Some(acknowledged) = &alert.acknowledged {
+                        save_acknowledgement(
+                            &self.pool,
+                            &alert.device,
+                            alert.alert.id,
+                            acknowledged,
+                        )
                         .await?;
                     }
-                    if let Some(meta_info) = &device.meta_info() {
-                        let info = postcard::to_stdvec(meta_info)?;
-                        let updated_at = meta_info.updated_at as i64;
-
-                        sqlx::query!("INSERT INTO meta_info (spki_hash, data, updated_at) VALUES (?, ?, ?) ON CONFLICT(spki_hash) DO UPDATE SET data = ? where updated_at < ?", spki_hash, info, updated_at, info, updated_at)
-                            .execute(&self.pool)
-                            .await?;
+                }
+            }
+            persistent::Message::Received {
+                group,
+                sender,
+                message,
+            } => {
+                let key = (message.tag().to_string(), message.version());
+                match self.persistence.get(&key) {
+                    Some(persistence) => {
+                        persistence
+                            .persist(&self.pool, group, sender, message)
+                            .await?
                     }
+                    None => self.save_unknown_message(group, sender, message).await?,
                 }
             }
+            persistent::Message::MigrateDevice {
+                previous,
+                successor,
+            } => {
+                let previous = previous.as_slice();
+                let successor = successor.as_slice();
+                let mut transaction = self.pool.begin().await?;
+
+                // data already known for the successor is newer and is kept
+                sqlx::query!(
+                    "UPDATE OR IGNORE system_reports SET spki_hash = ? WHERE spki_hash = ?",
+                    successor,
+                    previous
+                )
+                .execute(&mut *transaction)
+                .await?;
+                sqlx::query!("DELETE FROM system_reports WHERE spki_hash = ?", previous)
+                    .execute(&mut *transaction)
+                    .await?;
+                sqlx::query!(
+                    "UPDATE OR IGNORE meta_info SET spki_hash = ? WHERE spki_hash = ?",
+                    successor,
+                    previous
+                )
+                .execute(&mut *transaction)
+                .await?;
+                sqlx::query!("DELETE FROM meta_info WHERE spki_hash = ?", previous)
+                    .execute(&mut *transaction)
+                    .await?;
+                sqlx::query!(
+                    "UPDATE alerts SET device = ? WHERE device = ?",
+                    successor,
+                    previous
+                )
+                .execute(&mut *transaction)
+                .await?;
+
+                transaction.commit().await?;
+            }
         }
 
         Ok(())
@@ -100,9 +185,80 @@ impl ClientStore {
             }
         }
 
+        let mut alerts =
+            sqlx::query!("SELECT device, data, acknowledged_by, acknowledged_at FROM alerts")
+                .fetch(&self.pool);
+
+        while let Some(row) = alerts.next().await {
+            let row = row?;
+            let device = SpkiHash::from_slice(&row.device)
+                .expect("values should have been checked when saving in the db");
+            let acknowledged = match (row.acknowledged_by, row.acknowledged_at) {
+                (Some(by), Some(at)) => Some(Acknowledged {
+                    by: SpkiHash::from_slice(&by)
+                        .expect("values should have been checked when saving in the db"),
+                    at: at as u64,
+                }),
+                _ => None,
+            };
+            match postcard::from_bytes(&row.data) {
+                Ok(alert) => state.insert_alert(AlertState {
+                    device,
+                    alert,
+                    acknowledged,
+                }),
+                Err(err) => tracing::error!("failed to load alert: {}", err),
+            }
+        }
+
         Ok(state)
     }
 
+    async fn save_unknown_message(
+        &self,
+        group: &SvalinGroupId,
+        sender: &SpkiHash,
+        message: &SvalinMessage,
+    ) -> Result<(), Error> {
+        tracing::debug!(
+            "storing message of unknown type {} in version {} from {sender}",
+            message.tag(),
+            message.version()
+        );
+        let group_id = group.to_string();
+        let sender = sender.as_slice();
+        let tag = message.tag();
+        let version = message.version();
+        let payload = message.raw_payload();
+        let received_at = get_current_timestamp() as i64;
+        let max_unknown = MAX_UNKNOWN_MESSAGES;
+
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query!(
+            "INSERT INTO unknown_messages (group_id, sender, tag, version, payload, received_at) VALUES (?, ?, ?, ?, ?, ?)",
+            group_id,
+            sender,
+            tag,
+            version,
+            payload,
+            received_at
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        // any member of a group can send unknown types, so only the newest
+        // messages are kept
+        sqlx::query!(
+            "DELETE FROM unknown_messages WHERE id NOT IN ( SELECT id FROM unknown_messages ORDER BY id DESC LIMIT ? )",
+            max_unknown
+        )
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
     pub fn transaction_store(&self) -> &Arc<TrustStoreTransactionStore> {
         &self.transaction_store
     }
@@ -112,10 +268,138 @@ impl ClientStore {
     }
 }
 
+async fn save_report(
+    pool: &SqlitePool,
+    spki_hash: &SpkiHash,
+    system_report: &SvalinReport,
+) -> Result<(), Error> {
+    let report = postcard::to_stdvec(system_report)?;
+    let spki_hash = spki_hash.as_slice();
+    let generated_at = system_report.system_report.generated_at as i64;
+
+    sqlx::query!("INSERT INTO system_reports (spki_hash, report, generated_at) VALUES (?, ?, ?) ON CONFLICT(spki_hash) DO UPDATE SET report = ? where generated_at < ?", spki_hash, report, generated_at, report, generated_at)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+async fn save_meta_info(
+    pool: &SqlitePool,
+    spki_hash: &SpkiHash,
+    meta_info: &SvalinMetaInfo,
+) -> Result<(), Error> {
+    let info = postcard::to_stdvec(meta_info)?;
+    let spki_hash = spki_hash.as_slice();
+    let updated_at = meta_info.updated_at as i64;
+
+    sqlx::query!("INSERT INTO meta_info (spki_hash, data, updated_at) VALUES (?, ?, ?) ON CONFLICT(spki_hash) DO UPDATE SET data = ? where updated_at < ?", spki_hash, info, updated_at, info, updated_at)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+async fn save_alert(
+    pool: &SqlitePool,
+    device: &SpkiHash,
+    alert: &SvalinAlert,
+) -> Result<(), Error> {
+    let id = alert.id;
+    let device = device.as_slice();
+    let data = postcard::to_stdvec(alert)?;
+    let raised_at = alert.raised_at as i64;
+
+    // an alert delivered again keeps its acknowledgement
+    sqlx::query!(
+        "INSERT OR IGNORE INTO alerts (id, device, data, raised_at) VALUES (?, ?, ?, ?)",
+        id,
+        device,
+        data,
+        raised_at
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+async fn save_acknowledgement(
+    pool: &SqlitePool,
+    device: &SpkiHash,
+    alert: Uuid,
+    acknowledged: &Acknowledged,
+) -> Result<(), Error> {
+    let device = device.as_slice();
+    let acknowledged_by = acknowledged.by.as_slice();
+    let acknowledged_at = acknowledged.at as i64;
+
+    // the first acknowledgement is kept
+    sqlx::query!(
+        "UPDATE alerts SET acknowledged_by = ?, acknowledged_at = ? WHERE id = ? AND device = ? AND acknowledged_by IS NULL",
+        acknowledged_by,
+        acknowledged_at,
+        alert,
+        device
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+struct AlertPersistence;
+
+impl MessagePersistence for AlertPersistence {
+    fn persist<'a>(
+        &'a self,
+        pool: &'a SqlitePool,
+        group: &'a SvalinGroupId,
+        sender: &'a SpkiHash,
+        message: &'a SvalinMessage,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            // alerts are only accepted from the device itself
+            if group != &SvalinGroupId::DeviceGroup(sender.clone()) {
+                return Err(Error::UnexpectedGroup(group.clone()));
+            }
+            save_alert(pool, sender, &message.payload()?).await
+        })
+    }
+}
+
+struct AlertAcknowledgementPersistence;
+
+impl MessagePersistence for AlertAcknowledgementPersistence {
+    fn persist<'a>(
+        &'a self,
+        pool: &'a SqlitePool,
+        group: &'a SvalinGroupId,
+        sender: &'a SpkiHash,
+        message: &'a SvalinMessage,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let SvalinGroupId::DeviceGroup(device) = group else {
+                return Err(Error::UnexpectedGroup(group.clone()));
+            };
+            let acknowledgement: AlertAcknowledgement = message.payload()?;
+            let acknowledged = Acknowledged {
+                by: sender.clone(),
+                at: acknowledgement.acknowledged_at,
+            };
+            save_acknowledgement(pool, device, acknowledgement.alert, &acknowledged).await
+        })
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error(transparent)]
     Postcard(#[from] postcard::Error),
     #[error(transparent)]
     Sqlx(#[from] sqlx::Error),
+    #[error(transparent)]
+    Decode(#[from] DecodeMessageError),
+    #[error("message was sent to an unexpected group: {0:?}")]
+    UnexpectedGroup(SvalinGroupId),
 }
@@ -1,8 +1,19 @@
-use std::{borrow::Cow, collections::HashMap, fmt::Debug};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, hash_map::Entry},
+    fmt::Debug,
+};
 
 use serde::{Deserialize, Serialize};
-use svalin_pki::SpkiHash;
+use svalin_pki::{
+    SpkiHash,
+    mls::{
+        SvalinGroupId,
+        transport_types::{MessagePayload, SvalinMessage},
+    },
+};
 use svalin_sysctl::sytem_report::{OSFamily, SystemReport};
+use uuid::Uuid;
 
 /// This contains the persistent state of the clients available information.
 /// It is not meant to contain live information like current cpu usage or online status.
@@ -13,6 +24,22 @@ use svalin_sysctl::sytem_report::{OSFamily, SystemReport};
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct State {
     pub(crate) devices: HashMap<SpkiHash, DeviceState>,
+    pub(crate) alerts: HashMap<Uuid, AlertState>,
+}
+
+/// The layout of the user's main state before it kept the alerts.
+#[derive(Deserialize)]
+pub struct LegacyState {
+    devices: HashMap<SpkiHash, DeviceState>,
+}
+
+impl From<LegacyState> for State {
+    fn from(legacy: LegacyState) -> Self {
+        Self {
+            devices: legacy.devices,
+            alerts: HashMap::new(),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -20,12 +47,25 @@ pub enum Message {
     UpdateSystemReport(SpkiHash, SvalinReport),
     UpdateMetaInfo(SpkiHash, SvalinMetaInfo),
     UpdateFromMainState(State),
+    /// A message of a type the state doesn't track. It is stored by the
+    /// persistence registered for its type.
+    Received {
+        group: SvalinGroupId,
+        sender: SpkiHash,
+        message: SvalinMessage,
+    },
+    /// Moves the data of a device to the key which replaced its previous key.
+    MigrateDevice {
+        previous: SpkiHash,
+        successor: SpkiHash,
+    },
 }
 
 impl State {
     pub fn empty() -> Self {
         Self {
             devices: HashMap::new(),
+            alerts: HashMap::new(),
         }
     }
 
@@ -52,10 +92,94 @@ impl State {
                         device.report = other_device.report;
                     }
                 }
+                // the first acknowledgement is kept
+                for (id, other_alert) in state.alerts {
+                    match self.alerts.entry(id) {
+                        Entry::Vacant(entry) => {
+                            entry.insert(other_alert);
+                        }
+                        Entry::Occupied(mut entry) => {
+                            let alert = entry.get_mut();
+                            if alert.acknowledged.is_none() {
+                                alert.acknowledged = other_alert.acknowledged;
+                            }
+                        }
+                    }
+                }
+            }
+            Message::Received {
+                group,
+                sender,
+                message,
+            } => self.receive(group, sender, message),
+            Message::MigrateDevice {
+                previous,
+                successor,
+            } => {
+                for alert in self.alerts.values_mut() {
+                    if alert.device == previous {
+                        alert.device = successor.clone();
+                    }
+                }
+                let Some(previous) = self.devices.remove(&previous) else {
+                    return;
+                };
+                let device = self.get_device_entry(successor);
+                if device.report.is_none() {
+                    device.report = previous.report;
+                }
+                if device.meta_info.is_none() {
+                    device.meta_info = previous.meta_info;
+                }
+            }
+        }
+    }
+
+    /// Applies the received messages of the types the state tracks.
+    fn receive(&mut self, group: SvalinGroupId, sender: SpkiHash, message: SvalinMessage) {
+        let SvalinGroupId::DeviceGroup(device) = group else {
+            return;
+        };
+
+        if message.is::<SvalinAlert>() {
+            // alerts are only accepted from the device itself
+            if sender != device {
+                return;
+            }
+            match message.payload::<SvalinAlert>() {
+                Ok(alert) => self.insert_alert(AlertState {
+                    device,
+                    alert,
+                    acknowledged: None,
+                }),
+                Err(err) => tracing::warn!("failed to decode alert from {sender}: {err}"),
+            }
+        } else if message.is::<AlertAcknowledgement>() {
+            match message.payload::<AlertAcknowledgement>() {
+                Ok(acknowledgement) => {
+                    let Some(alert) = self.alerts.get_mut(&acknowledgement.alert) else {
+                        return;
+                    };
+                    // the first acknowledgement is kept
+                    if alert.device == device && alert.acknowledged.is_none() {
+                        alert.acknowledged = Some(Acknowledged {
+                            by: sender,
+                            at: acknowledgement.acknowledged_at,
+                        });
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!("failed to decode alert acknowledgement from {sender}: {err}")
+                }
             }
         }
     }
 
+    /// Keeps the acknowledgement of an alert which is already known.
+    pub(crate) fn insert_alert(&mut self, alert: AlertState) {
+        self.alerts.entry(alert.alert.id).or_insert(alert);
+    }
+
     fn get_device_entry(&mut self, spki_hash: SpkiHash) -> &mut DeviceState {
         self.devices
             .entry(spki_hash.clone())
@@ -69,6 +193,21 @@ impl State {
     pub fn devices(&self) -> &HashMap<SpkiHash, DeviceState> {
         &self.devices
     }
+
+    pub fn alerts(&self) -> &HashMap<Uuid, AlertState> {
+        &self.alerts
+    }
+
+    /// Alerts which weren't acknowledged yet, the oldest first.
+    pub fn pending_alerts(&self) -> Vec<&AlertState> {
+        let mut pending: Vec<&AlertState> = self
+            .alerts
+            .values()
+            .filter(|alert| alert.acknowledged.is_none())
+            .collect();
+        pending.sort_by_key(|alert| alert.alert.raised_at);
+        pending
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -121,3 +260,76 @@ pub struct SvalinMetaInfo {
     pub group: String,
     pub notes: String,
 }
+
+/// Sent by an agent to its device group as soon as one of its alert rules
+/// is triggered.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SvalinAlert {
+    pub id: Uuid,
+    pub raised_at: u64,
+    pub severity: AlertSeverity,
+    pub kind: AlertKind,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AlertSeverity {
+    Warning,
+    Critical,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum AlertKind {
+    DiskAlmostFull {
+        mount_point: String,
+        available_space: u64,
+        total_space: u64,
+    },
+    ServiceDown {
+        service: String,
+    },
+    HighLoad {
+        load_average: f64,
+        threads: usize,
+    },
+}
+
+/// Sent by a session to the device group once an alert was handled, so the
+/// other sessions and users don't show it anymore.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AlertAcknowledgement {
+    pub alert: Uuid,
+    pub acknowledged_at: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AlertState {
+    pub device: SpkiHash,
+    pub alert: SvalinAlert,
+    pub acknowledged: Option<Acknowledged>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Acknowledged {
+    pub by: SpkiHash,
+    pub at: u64,
+}
+
+impl MessagePayload for SvalinReport {
+    const TAG: &'static str = "report";
+    const VERSION: u16 = 1;
+}
+
+impl MessagePayload for SvalinMetaInfo {
+    const TAG: &'static str = "meta_info";
+    const VERSION: u16 = 1;
+}
+
+impl MessagePayload for SvalinAlert {
+    const TAG: &'static str = "alert";
+    const VERSION: u16 = 1;
+}
+
+impl MessagePayload for AlertAcknowledgement {
+    const TAG: &'static str = "alert_acknowledgement";
+    const VERSION: u16 = 1;
+}
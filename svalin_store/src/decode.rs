@@ -0,0 +1,48 @@
+//! Decoding of trust store data which might have been written before blocks
+//! and checkpoints carried a protocol version, see [`svalin_pki::secure_chain::legacy`].
+
+use svalin_pki::{
+    secure_chain::{
+        Checkpoint, UncheckedBlock,
+        legacy::{LegacyCheckpoint, LegacyUncheckedBlock},
+    },
+    trust_store,
+};
+
+pub(crate) fn decode_block(
+    data: &[u8],
+) -> Result<UncheckedBlock<trust_store::Transaction>, postcard::Error> {
+    postcard::from_bytes(data).or_else(|err| {
+        postcard::from_bytes::<LegacyUncheckedBlock<trust_store::Transaction>>(data)
+            .map(Into::into)
+            // report the error of the current layout
+            .map_err(|_| err)
+    })
+}
+
+pub(crate) fn decode_checkpoint(data: &[u8]) -> Result<Checkpoint, postcard::Error> {
+    postcard::from_bytes(data).or_else(|err| {
+        postcard::from_bytes::<LegacyCheckpoint>(data)
+            .map(Into::into)
+            .map_err(|_| err)
+    })
+}
+
+/// Older layouts of an export are prefixes of the data, so only a layout which
+/// consumes everything is accepted.
+pub(crate) fn decode_exported(data: &[u8]) -> Result<trust_store::Exported, postcard::Error> {
+    from_all_bytes(data).or_else(|err| {
+        from_all_bytes::<trust_store::LegacyExported>(data)
+            .map(Into::into)
+            .or_else(|_| from_all_bytes::<trust_store::PreRotationExported>(data).map(Into::into))
+            .map_err(|_| err)
+    })
+}
+
+fn from_all_bytes<'a, T: serde::Deserialize<'a>>(data: &'a [u8]) -> Result<T, postcard::Error> {
+    let (value, rest) = postcard::take_from_bytes(data)?;
+    if !rest.is_empty() {
+        return Err(postcard::Error::DeserializeBadEncoding);
+    }
+    Ok(value)
+}
@@ -1,10 +1,13 @@
 use std::path::Path;
 
-use sqlx::{SqlitePool, sqlite::SqliteConnectOptions};
+use sqlx::{Connection, SqliteConnection, SqlitePool, sqlite::SqliteConnectOptions};
 
 pub mod agent_store;
+pub mod chain_block_store;
 pub mod client_store;
 mod close_handle;
+mod decode;
+mod merkle_leaves;
 pub mod server_store;
 pub mod trust_store_transaction_store;
 
@@ -24,3 +27,26 @@ async fn open_database(filename: impl AsRef<Path>) -> Result<SqlitePool, sqlx::E
 
     Ok(pool)
 }
+
+/// Writes a consistent copy of the database at `source` to `target`.
+///
+/// Uses `VACUUM INTO`, so the database may be in use by other connections,
+/// e.g. a running server. `target` must not exist yet.
+pub async fn snapshot_database(
+    source: impl AsRef<Path>,
+    target: impl AsRef<Path>,
+) -> Result<(), sqlx::Error> {
+    let options = SqliteConnectOptions::new()
+        .create_if_missing(false)
+        .filename(source);
+
+    let mut connection = SqliteConnection::connect_with(&options).await?;
+    let target = target.as_ref().to_string_lossy();
+    sqlx::query("VACUUM INTO ?")
+        .bind(target.as_ref())
+        .execute(&mut connection)
+        .await?;
+    connection.close().await?;
+
+    Ok(())
+}
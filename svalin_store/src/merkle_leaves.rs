@@ -0,0 +1,98 @@
+//! Merkle leaves of the stored trust store blocks, shared by the transaction
+//! stores of the server and the members.
+
+use sqlx::{SqliteConnection, SqliteExecutor, SqlitePool};
+use svalin_pki::{
+    secure_chain::{
+        UncheckedBlock,
+        merkle::{MerkleHash, MerkleIndex},
+    },
+    trust_store,
+};
+
+use crate::decode::decode_block;
+
+pub(crate) async fn add_leaf(
+    executor: impl SqliteExecutor<'_>,
+    block: &UncheckedBlock<trust_store::Transaction>,
+) -> Result<(), sqlx::Error> {
+    let sequence = block.sequence() as i64;
+    let leaf = postcard::to_stdvec(&MerkleHash::leaf(block))
+        .map_err(|err| sqlx::Error::Encode(Box::new(err)))?;
+    sqlx::query!(
+        "INSERT OR REPLACE INTO trust_store_leaves (sequence, leaf) VALUES (?, ?)",
+        sequence,
+        leaf
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Adds the leaves of a history received with a checkpoint, keeping the
+/// leaves which are already known.
+pub(crate) async fn add_history(
+    connection: &mut SqliteConnection,
+    history: &MerkleIndex,
+) -> Result<(), sqlx::Error> {
+    for (sequence, leaf) in (1i64..).zip(history.leaves()) {
+        let leaf = postcard::to_stdvec(leaf).map_err(|err| sqlx::Error::Encode(Box::new(err)))?;
+        sqlx::query!(
+            "INSERT OR IGNORE INTO trust_store_leaves (sequence, leaf) VALUES (?, ?)",
+            sequence,
+            leaf
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Adds the leaves of blocks stored before the leaves were kept.
+pub(crate) async fn add_missing_leaves(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let records = sqlx::query!(
+        "SELECT data FROM trust_store_transactions WHERE sequence NOT IN (SELECT sequence FROM trust_store_leaves)"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for record in records {
+        let block = decode_block(&record.data).map_err(|err| sqlx::Error::Decode(Box::new(err)))?;
+        add_leaf(pool, &block).await?;
+    }
+
+    Ok(())
+}
+
+/// Loads the Merkle index of the history up to `sequence`, `None` if leaves
+/// are missing because the blocks were pruned before the leaves were kept.
+pub(crate) async fn load_index<E>(
+    pool: &SqlitePool,
+    sequence: u64,
+) -> Result<Option<MerkleIndex>, E>
+where
+    E: From<sqlx::Error> + From<postcard::Error>,
+{
+    let sequence = sequence as i64;
+    let records = sqlx::query!(
+        "SELECT sequence, leaf FROM trust_store_leaves WHERE sequence <= ? ORDER BY sequence ASC",
+        sequence
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut leaves = Vec::with_capacity(records.len());
+    for (expected, record) in (1..).zip(records) {
+        if record.sequence != expected {
+            return Ok(None);
+        }
+        leaves.push(postcard::from_bytes(&record.leaf)?);
+    }
+    if leaves.len() as i64 != sequence {
+        return Ok(None);
+    }
+
+    Ok(Some(MerkleIndex::from_leaves(leaves)))
+}
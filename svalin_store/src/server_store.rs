@@ -1,14 +1,18 @@
 mod key_package_store;
 mod message_store;
+mod renewal_store;
 mod session_store;
 mod trust_store_transaction_store;
 mod user_store;
 
 pub use key_package_store::KeyPackageStore;
-pub use message_store::{MessageStore, MessageStoreError};
+pub use message_store::{
+    MessageLimits, MessageStore, MessageStoreError, QueueMetrics, SUBSCRIPTION_BUFFER, Subscription,
+};
+pub use renewal_store::{RenewalStore, RenewalStoreError};
 pub use session_store::{AddSessionError, SessionStore};
 pub use trust_store_transaction_store::{TransactionStoreError, TrustStoreTransactionStore};
-pub use user_store::{GetBySpkiHashError, UserStore};
+pub use user_store::{GetBySpkiHashError, StoredMlsData, UpdateMlsDataError, UserStore};
 
 use sqlx::SqlitePool;
 use std::{path::Path, sync::Arc};
@@ -19,6 +23,7 @@ pub struct ServerStore {
     pub trust_store_transactions: Arc<TrustStoreTransactionStore>,
     pub key_packages: Arc<KeyPackageStore>,
     pub messages: Arc<MessageStore>,
+    pub renewals: Arc<RenewalStore>,
     pub sessions: Arc<SessionStore>,
     pub users: Arc<UserStore>,
     pool: SqlitePool,
@@ -31,7 +36,8 @@ impl ServerStore {
         Ok(Self {
             trust_store_transactions: TrustStoreTransactionStore::open(pool.clone()).await?,
             key_packages: KeyPackageStore::open(pool.clone()),
-            messages: MessageStore::open(pool.clone()),
+            messages: MessageStore::open(pool.clone()).await?,
+            renewals: RenewalStore::open(pool.clone()),
             sessions: SessionStore::open(pool.clone()),
             users: UserStore::open(pool.clone()),
             pool,
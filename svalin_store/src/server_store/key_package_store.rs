@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use svalin_pki::{
-    SpkiHash,
+    SpkiHash, get_current_timestamp,
     mls::key_package::{KeyPackage, UnverifiedKeyPackage},
 };
 
@@ -15,9 +15,13 @@ impl KeyPackageStore {
         Arc::new(Self { pool })
     }
 
+    /// Stores a key package. A last resort key package replaces the previous
+    /// one of the same owner.
     pub async fn add_key_package(&self, key_package: KeyPackage) -> anyhow::Result<()> {
         let spki_hash = key_package.spki_hash().clone();
         let spki_hash_slice = spki_hash.as_slice();
+        let not_after = key_package.not_after() as i64;
+        let last_resort = key_package.is_last_resort();
         let member = key_package.to_unverified();
         if member.spki_hash()? != spki_hash {
             anyhow::bail!("Key package hash mismatch");
@@ -26,23 +30,41 @@ impl KeyPackageStore {
         let data = postcard::to_stdvec(&member)?;
         let id = uuid::Uuid::new_v4().as_hyphenated().to_string();
 
+        let mut transaction = self.pool.begin().await?;
+
+        if last_resort {
+            sqlx::query!(
+                "DELETE FROM key_packages WHERE spki_hash = ? AND last_resort = TRUE",
+                spki_hash_slice
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
         sqlx::query!(
-            "INSERT INTO key_packages (id, spki_hash, data) VALUES (?, ?, ?)",
+            "INSERT INTO key_packages (id, spki_hash, data, not_after, last_resort) VALUES (?, ?, ?, ?, ?)",
             id,
             spki_hash_slice,
-            data
+            data,
+            not_after,
+            last_resort
         )
-        .execute(&self.pool)
+        .execute(&mut *transaction)
         .await?;
 
+        transaction.commit().await?;
+
         Ok(())
     }
 
+    /// Counts the regular key packages of the owner which haven't expired yet.
     pub async fn count_key_packages(&self, owner: &SpkiHash) -> anyhow::Result<u64> {
         let spki_hash = owner.as_slice();
+        let now = get_current_timestamp() as i64;
         let count = sqlx::query_scalar!(
-            "SELECT COUNT(*) as count FROM key_packages WHERE spki_hash = ?",
-            spki_hash
+            "SELECT COUNT(*) as count FROM key_packages WHERE spki_hash = ? AND last_resort = FALSE AND not_after > ?",
+            spki_hash,
+            now
         )
         .fetch_one(&self.pool)
         .await?;
@@ -50,6 +72,49 @@ impl KeyPackageStore {
         Ok(count as u64)
     }
 
+    pub async fn has_last_resort_key_package(&self, owner: &SpkiHash) -> anyhow::Result<bool> {
+        let spki_hash = owner.as_slice();
+        let now = get_current_timestamp() as i64;
+        let count = sqlx::query_scalar!(
+            "SELECT COUNT(*) as count FROM key_packages WHERE spki_hash = ? AND last_resort = TRUE AND not_after > ?",
+            spki_hash,
+            now
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count > 0)
+    }
+
+    /// Deletes the key packages whose lifetime ended before the given
+    /// timestamp and returns how many were deleted.
+    pub async fn delete_expired_key_packages(&self, now: u64) -> anyhow::Result<u64> {
+        let now = now as i64;
+        let result = sqlx::query!("DELETE FROM key_packages WHERE not_after <= ?", now)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Counts the stored key packages of every owner.
+    pub async fn count_all_key_packages(&self) -> anyhow::Result<Vec<(SpkiHash, u64)>> {
+        let counts = sqlx::query!(
+            "SELECT spki_hash, COUNT(*) as count FROM key_packages GROUP BY spki_hash ORDER BY spki_hash"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        counts
+            .into_iter()
+            .map(|row| {
+                let spki_hash = SpkiHash::from_slice(&row.spki_hash)
+                    .map_err(|_| anyhow::anyhow!("invalid spki hash in database"))?;
+                Ok((spki_hash, row.count as u64))
+            })
+            .collect()
+    }
+
     pub async fn get_key_packages(
         &self,
         entities: impl ExactSizeIterator<Item = &SpkiHash>,
@@ -57,20 +122,25 @@ impl KeyPackageStore {
         let mut transaction = self.pool.begin().await?;
 
         let mut key_packages = Vec::with_capacity(entities.len());
+        let now = get_current_timestamp() as i64;
 
         for spki_hash in entities {
             // tracing::trace!("Loading key package for {spki_hash}");
             let spki_hash_slice = spki_hash.as_slice();
+            // the last resort key package is only handed out once no regular one is left
             let key_package = sqlx::query!(
-                "SELECT id, data FROM key_packages WHERE spki_hash == ? LIMIT 1",
+                "SELECT id, data, last_resort FROM key_packages WHERE spki_hash == ? AND not_after > ? ORDER BY last_resort LIMIT 1",
                 spki_hash_slice,
+                now,
             )
             .fetch_one(&mut *transaction)
             .await?;
 
-            sqlx::query!("DELETE FROM key_packages WHERE id = ?", key_package.id)
-                .execute(&mut *transaction)
-                .await?;
+            if !key_package.last_resort {
+                sqlx::query!("DELETE FROM key_packages WHERE id = ?", key_package.id)
+                    .execute(&mut *transaction)
+                    .await?;
+            }
             let key_package: UnverifiedKeyPackage = postcard::from_bytes(&key_package.data)?;
             if &key_package.spki_hash()? != spki_hash {
                 anyhow::bail!("Key package in store does not match the expected owner");
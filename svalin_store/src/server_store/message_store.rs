@@ -1,4 +1,11 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
 
 use futures::{StreamExt, TryStreamExt};
 use svalin_pki::{
@@ -8,32 +15,102 @@ use svalin_pki::{
 use tokio::sync::mpsc;
 use uuid::Uuid;
 
+/// Limits applied to newly stored messages.
+///
+/// Commits and welcomes are exempt, a member which misses one can't continue
+/// its groups.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MessageLimits {
+    /// How long a message is kept if not every receiver acknowledges it.
+    /// `None` keeps it until it is acknowledged.
+    pub ttl: Option<Duration>,
+    /// Maximum number of unacknowledged messages per receiver. Once exceeded,
+    /// the oldest messages of that receiver are dropped.
+    pub max_pending_per_receiver: Option<u64>,
+}
+
+/// Snapshot of the messages waiting on the server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueueMetrics {
+    /// Number of unacknowledged messages per receiver.
+    pub depths: Vec<(SpkiHash, u64)>,
+    /// Number of stored messages, each counted once regardless of its receivers.
+    pub messages: u64,
+    /// When the snapshot was taken.
+    pub collected_at: u64,
+}
+
+impl QueueMetrics {
+    pub fn depth(&self, receiver: &SpkiHash) -> u64 {
+        self.depths
+            .iter()
+            .find(|(spki_hash, _)| spki_hash == receiver)
+            .map(|(_, depth)| *depth)
+            .unwrap_or(0)
+    }
+
+    /// The receiver with the most unacknowledged messages.
+    pub fn deepest(&self) -> Option<(&SpkiHash, u64)> {
+        self.depths
+            .iter()
+            .max_by_key(|(_, depth)| *depth)
+            .map(|(spki_hash, depth)| (spki_hash, *depth))
+    }
+
+    /// Number of pending deliveries over all receivers.
+    pub fn pending(&self) -> u64 {
+        self.depths.iter().map(|(_, depth)| depth).sum()
+    }
+}
+
 #[derive(Debug)]
 pub struct MessageStore {
     pool: sqlx::SqlitePool,
     subscriptions: SubscriptionManager,
+    limits: RwLock<MessageLimits>,
 }
 
 impl MessageStore {
-    pub fn open(pool: sqlx::SqlitePool) -> Arc<Self> {
-        Arc::new(Self {
+    pub async fn open(pool: sqlx::SqlitePool) -> Result<Arc<Self>, sqlx::Error> {
+        check_handshakes(&pool).await?;
+
+        Ok(Arc::new(Self {
             pool,
             subscriptions: SubscriptionManager::initialize(),
-        })
+            limits: RwLock::new(MessageLimits::default()),
+        }))
+    }
+
+    /// Replaces the limits, messages which are already stored keep their
+    /// expiry.
+    pub fn set_limits(&self, limits: MessageLimits) {
+        *self.limits.write().unwrap() = limits;
+    }
+
+    pub fn limits(&self) -> MessageLimits {
+        *self.limits.read().unwrap()
     }
 
     pub async fn add_message(&self, message: MessageToSend) -> Result<(), MessageStoreError> {
+        let limits = self.limits();
         let mut tx = self.pool.begin().await?;
         let message_id = Uuid::new_v4();
         let received_at = get_current_timestamp() as i64;
+        let handshake = is_handshake(&message.message);
+        let expires_at = limits
+            .ttl
+            .filter(|_| !handshake)
+            .map(|ttl| received_at.saturating_add(ttl.as_secs() as i64));
         let data = postcard::to_stdvec(&message.message)?;
         let data = &data;
 
         sqlx::query!(
-            "INSERT INTO mls_messages (id, data, received_at) VALUES (?,?,?)",
+            "INSERT INTO mls_messages (id, data, received_at, expires_at, handshake) VALUES (?,?,?,?,?)",
             message_id,
             data,
-            received_at
+            received_at,
+            expires_at,
+            handshake
         )
         .execute(&mut *tx)
         .await?;
@@ -49,11 +126,43 @@ impl MessageStore {
             .await?;
         }
 
+        if let Some(max_pending) = limits.max_pending_per_receiver {
+            let max_pending = max_pending as i64;
+            let mut dropped = 0;
+            for receiver in &message.receivers {
+                let receiver_slice = receiver.as_slice();
+                let result = sqlx::query!(
+                    "DELETE FROM mls_message_receivers WHERE spki_hash = ? AND message_id IN ( SELECT id FROM mls_messages WHERE handshake = 0 ) AND id NOT IN ( SELECT r.id FROM mls_message_receivers r JOIN mls_messages m ON m.id = r.message_id WHERE r.spki_hash = ? AND m.handshake = 0 ORDER BY m.received_at DESC, r.id DESC LIMIT ? )",
+                    receiver_slice,
+                    receiver_slice,
+                    max_pending
+                )
+                .execute(&mut *tx)
+                .await?;
+
+                if result.rows_affected() > 0 {
+                    tracing::warn!(
+                        "message queue of {receiver} is full, dropped {} messages",
+                        result.rows_affected()
+                    );
+                    dropped += result.rows_affected();
+                }
+            }
+
+            if dropped > 0 {
+                sqlx::query!(
+                    "DELETE FROM mls_messages WHERE id NOT IN ( SELECT message_id FROM mls_message_receivers )"
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
         tx.commit().await?;
 
         tracing::trace!("distributing message with id: {message_id}");
 
-        self.subscriptions.distribute(message_id, message).await;
+        self.subscriptions.distribute(message_id, message);
 
         Ok(())
     }
@@ -62,30 +171,31 @@ impl MessageStore {
         &self,
         receiver: &SpkiHash,
     ) -> Result<Vec<(Uuid, MessageToMemberTransport)>, MessageStoreError> {
-        let receiver = receiver.as_slice();
-        let messages = sqlx::query!(
-            r#"SELECT m.id as "id: uuid::Uuid", m.data, m.received_at FROM mls_messages m JOIN mls_message_receivers r ON m.id = r.message_id WHERE r.spki_hash = ? ORDER BY m.received_at ASC"#,
-            receiver
-        )
-        .fetch(&self.pool)
-        .map(|row| -> Result<_, MessageStoreError> {
-            let row = row?;
-            let message: MessageToMemberTransport = postcard::from_bytes(&row.data)?;
-            Ok((row.id, message))
-        }).try_collect().await?;
-
-        Ok(messages)
+        load_pending(&self.pool, receiver).await
     }
 
-    pub async fn subscribe(
-        &self,
-        receiver: SpkiHash,
-    ) -> mpsc::Receiver<(Uuid, Arc<MessageToMemberTransport>)> {
-        let (send, recv) = mpsc::channel(100);
-
-        self.subscriptions.subscribe(receiver, send).await;
-
-        recv
+    /// Subscribes to the messages of a receiver, starting with the ones which
+    /// are already stored.
+    pub fn subscribe(&self, receiver: SpkiHash) -> Subscription {
+        let (send, recv) = mpsc::channel(SUBSCRIPTION_BUFFER);
+        let id = Uuid::new_v4();
+        // the first call to recv loads the stored messages
+        let lagged = Arc::new(AtomicBool::new(true));
+
+        self.subscriptions
+            .subscribe(receiver.clone(), id, send, lagged.clone());
+
+        Subscription {
+            receiver,
+            id,
+            pool: self.pool.clone(),
+            manager: self.subscriptions.clone(),
+            messages: recv,
+            lagged,
+            backlog: VecDeque::new(),
+            delivered: HashSet::new(),
+            tracked_limit: TRACKED_DELIVERIES,
+        }
     }
 
     pub async fn aknowledge_single_message(
@@ -132,6 +242,252 @@ impl MessageStore {
 
         Ok(())
     }
+
+    /// Deletes all messages received before the given timestamp, regardless
+    /// of whether they were acknowledged. Commits and welcomes are kept.
+    /// Returns the number of deleted messages.
+    pub async fn delete_messages_older_than(&self, timestamp: u64) -> Result<u64, sqlx::Error> {
+        let timestamp = timestamp as i64;
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            "DELETE FROM mls_message_receivers WHERE message_id IN ( SELECT id FROM mls_messages WHERE received_at < ? AND handshake = 0 )",
+            timestamp
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let result = sqlx::query!(
+            "DELETE FROM mls_messages WHERE received_at < ? AND handshake = 0",
+            timestamp
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Deletes all messages whose TTL ended before the given timestamp.
+    /// Commits and welcomes never get a TTL. Returns the number of deleted
+    /// messages.
+    pub async fn delete_expired_messages(&self, now: u64) -> Result<u64, sqlx::Error> {
+        let now = now as i64;
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            "DELETE FROM mls_message_receivers WHERE message_id IN ( SELECT id FROM mls_messages WHERE expires_at < ? )",
+            now
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let result = sqlx::query!("DELETE FROM mls_messages WHERE expires_at < ?", now)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Deletes the queue of a receiver, including the messages no other
+    /// receiver is waiting for.
+    ///
+    /// Returns the number of deleted messages.
+    pub async fn delete_receiver(&self, receiver: &SpkiHash) -> Result<u64, sqlx::Error> {
+        let receiver = receiver.as_slice();
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            "DELETE FROM mls_message_receivers WHERE spki_hash = ?",
+            receiver
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let result = sqlx::query!(
+            "DELETE FROM mls_messages WHERE id NOT IN ( SELECT message_id FROM mls_message_receivers )"
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn queue_metrics(&self) -> Result<QueueMetrics, MessageStoreError> {
+        let depths = self.pending_counts().await?;
+        let messages = sqlx::query_scalar!("SELECT COUNT(*) as count FROM mls_messages")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(QueueMetrics {
+            depths,
+            messages: messages as u64,
+            collected_at: get_current_timestamp(),
+        })
+    }
+
+    /// Counts the messages not yet acknowledged by each receiver.
+    pub async fn pending_counts(&self) -> Result<Vec<(SpkiHash, u64)>, MessageStoreError> {
+        let counts = sqlx::query!(
+            "SELECT spki_hash, COUNT(*) as count FROM mls_message_receivers GROUP BY spki_hash ORDER BY spki_hash"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        counts
+            .into_iter()
+            .map(|row| {
+                let spki_hash = SpkiHash::from_slice(&row.spki_hash)
+                    .map_err(|_| MessageStoreError::InvalidSpkiHash)?;
+                Ok((spki_hash, row.count as u64))
+            })
+            .collect()
+    }
+
+    /// Deletes receiver entries pointing to missing messages and messages
+    /// without any receivers left.
+    ///
+    /// Returns the number of deleted receiver entries and messages.
+    pub async fn prune_orphans(&self) -> Result<(u64, u64), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let receivers = sqlx::query!(
+            "DELETE FROM mls_message_receivers WHERE message_id NOT IN ( SELECT id FROM mls_messages )"
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let messages = sqlx::query!(
+            "DELETE FROM mls_messages WHERE id NOT IN ( SELECT message_id FROM mls_message_receivers )"
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok((receivers.rows_affected(), messages.rows_affected()))
+    }
+}
+
+async fn load_pending(
+    pool: &sqlx::SqlitePool,
+    receiver: &SpkiHash,
+) -> Result<Vec<(Uuid, MessageToMemberTransport)>, MessageStoreError> {
+    let receiver = receiver.as_slice();
+    // expired messages are skipped even if the sweeper didn't delete them yet
+    let now = get_current_timestamp() as i64;
+    let messages = sqlx::query!(
+        r#"SELECT m.id as "id: uuid::Uuid", m.data, m.received_at FROM mls_messages m JOIN mls_message_receivers r ON m.id = r.message_id WHERE r.spki_hash = ? AND (m.expires_at IS NULL OR m.expires_at >= ?) ORDER BY m.received_at ASC, m.rowid ASC"#,
+        receiver,
+        now
+    )
+    .fetch(pool)
+    .map(|row| -> Result<_, MessageStoreError> {
+        let row = row?;
+        let message: MessageToMemberTransport = postcard::from_bytes(&row.data)?;
+        Ok((row.id, message))
+    }).try_collect().await?;
+
+    Ok(messages)
+}
+
+/// Number of messages buffered for a subscriber before it has to catch up
+/// from the database.
+pub const SUBSCRIPTION_BUFFER: usize = 64;
+
+/// Delivered messages remembered by a subscription before the acknowledged
+/// ones are forgotten.
+const TRACKED_DELIVERIES: usize = 1024;
+
+/// The messages of a single receiver.
+///
+/// Live messages are delivered through a bounded channel. If the subscriber
+/// doesn't keep up, new messages are not buffered anymore and are loaded from
+/// the database instead, so a slow subscriber never delays the others.
+///
+/// Every message is delivered once per subscription, even if it wasn't
+/// acknowledged yet when catching up.
+#[derive(Debug)]
+pub struct Subscription {
+    receiver: SpkiHash,
+    id: Uuid,
+    pool: sqlx::SqlitePool,
+    manager: SubscriptionManager,
+    messages: mpsc::Receiver<(Uuid, Arc<MessageToMemberTransport>)>,
+    lagged: Arc<AtomicBool>,
+    backlog: VecDeque<(Uuid, Arc<MessageToMemberTransport>)>,
+    delivered: HashSet<Uuid>,
+    tracked_limit: usize,
+}
+
+impl Subscription {
+    pub fn receiver(&self) -> &SpkiHash {
+        &self.receiver
+    }
+
+    /// Waits for the next message. Returns `None` once the store is closed.
+    pub async fn recv(
+        &mut self,
+    ) -> Result<Option<(Uuid, Arc<MessageToMemberTransport>)>, MessageStoreError> {
+        loop {
+            // catching up also forgets the acknowledged deliveries
+            if self.lagged.swap(false, Ordering::AcqRel)
+                || self.delivered.len() >= self.tracked_limit
+            {
+                self.catch_up().await?;
+            }
+
+            let message = match self.backlog.pop_front() {
+                Some(message) => message,
+                None => match self.messages.recv().await {
+                    Some(message) => message,
+                    None => return Ok(None),
+                },
+            };
+
+            // already delivered from the database
+            if !self.delivered.insert(message.0) {
+                continue;
+            }
+
+            return Ok(Some(message));
+        }
+    }
+
+    async fn catch_up(&mut self) -> Result<(), MessageStoreError> {
+        // everything in the channel is stored as well
+        while self.messages.try_recv().is_ok() {}
+
+        let pending = load_pending(&self.pool, &self.receiver).await?;
+        tracing::trace!(
+            "subscriber {} catching up on {} messages",
+            self.receiver,
+            pending.len()
+        );
+
+        let ids: HashSet<Uuid> = pending.iter().map(|(id, _)| *id).collect();
+        self.delivered.retain(|id| ids.contains(id));
+        // don't reload on every call if the receiver acknowledges slowly
+        self.tracked_limit = TRACKED_DELIVERIES.max(self.delivered.len() * 2);
+        self.backlog = pending
+            .into_iter()
+            .filter(|(id, _)| !self.delivered.contains(id))
+            .map(|(id, message)| (id, Arc::new(message)))
+            .collect();
+
+        Ok(())
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.manager.unsubscribe(self.receiver.clone(), self.id);
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -140,51 +496,71 @@ pub enum MessageStoreError {
     DBError(#[from] sqlx::Error),
     #[error("postcard error: {0}")]
     PostcardError(#[from] postcard::Error),
+    #[error("invalid spki hash in database")]
+    InvalidSpkiHash,
 }
 
 #[derive(Debug)]
 enum SubscriptionRequest {
-    Subscribe(
-        SpkiHash,
-        mpsc::Sender<(Uuid, Arc<MessageToMemberTransport>)>,
-    ),
+    Subscribe(SpkiHash, Subscriber),
+    Unsubscribe(SpkiHash, Uuid),
     Distribute(Uuid, MessageToSend),
 }
 
+#[derive(Debug)]
+struct Subscriber {
+    id: Uuid,
+    sender: mpsc::Sender<(Uuid, Arc<MessageToMemberTransport>)>,
+    lagged: Arc<AtomicBool>,
+}
+
 #[derive(Debug, Clone)]
 struct SubscriptionManager {
-    sender: mpsc::Sender<SubscriptionRequest>,
+    sender: mpsc::UnboundedSender<SubscriptionRequest>,
 }
 
 impl SubscriptionManager {
     pub fn initialize() -> Self {
-        let (sender, mut recv) = mpsc::channel(100);
+        let (sender, mut recv) = mpsc::unbounded_channel();
 
         tokio::spawn(async move {
-            let mut subscribed = HashMap::new();
+            // several sessions of a user may subscribe to the user's messages
+            let mut subscribed: HashMap<SpkiHash, Vec<Subscriber>> = HashMap::new();
 
             while let Some(request) = recv.recv().await {
-                // tracing::trace!("handling subscription request: {:?}", request);
                 match request {
-                    SubscriptionRequest::Subscribe(spki_hash, sender) => {
-                        subscribed.insert(spki_hash, sender);
+                    SubscriptionRequest::Subscribe(spki_hash, subscriber) => {
+                        subscribed.entry(spki_hash).or_default().push(subscriber);
+                    }
+                    SubscriptionRequest::Unsubscribe(spki_hash, id) => {
+                        if let Some(subscribers) = subscribed.get_mut(&spki_hash) {
+                            subscribers.retain(|subscriber| subscriber.id != id);
+                            if subscribers.is_empty() {
+                                subscribed.remove(&spki_hash);
+                            }
+                        }
                     }
                     SubscriptionRequest::Distribute(uuid, message) => {
                         let package = Arc::new(message.message);
                         for receiver in message.receivers {
-                            if let Some(sender) = subscribed.get(&receiver) {
-                                if sender.is_closed() {
-                                    // tracing::trace!("subscriber already closed: {:?}", receiver);
-                                    subscribed.remove(&receiver);
-                                } else {
-                                    // tracing::trace!("sending message to: {:?}", receiver);
-                                    if sender.send((uuid.clone(), package.clone())).await.is_err() {
-                                        tracing::error!("failed to send message to subscriber");
-                                        subscribed.remove(&receiver);
+                            let Some(subscribers) = subscribed.get_mut(&receiver) else {
+                                continue;
+                            };
+
+                            // never wait for a subscriber, the message is
+                            // stored and loaded once it caught up
+                            subscribers.retain(|subscriber| {
+                                match subscriber.sender.try_send((uuid, package.clone())) {
+                                    Ok(()) => true,
+                                    Err(mpsc::error::TrySendError::Full(_)) => {
+                                        subscriber.lagged.store(true, Ordering::Release);
+                                        true
                                     }
+                                    Err(mpsc::error::TrySendError::Closed(_)) => false,
                                 }
-                            } else {
-                                // tracing::trace!("no subscriber found for: {:?}", receiver);
+                            });
+                            if subscribers.is_empty() {
+                                subscribed.remove(&receiver);
                             }
                         }
                     }
@@ -195,29 +571,80 @@ impl SubscriptionManager {
         Self { sender }
     }
 
-    async fn subscribe(
+    fn subscribe(
         &self,
         spki_hash: SpkiHash,
-        receiver: mpsc::Sender<(Uuid, Arc<MessageToMemberTransport>)>,
+        id: Uuid,
+        sender: mpsc::Sender<(Uuid, Arc<MessageToMemberTransport>)>,
+        lagged: Arc<AtomicBool>,
     ) {
+        let subscriber = Subscriber { id, sender, lagged };
         if self
             .sender
-            .send(SubscriptionRequest::Subscribe(spki_hash, receiver))
-            .await
+            .send(SubscriptionRequest::Subscribe(spki_hash, subscriber))
             .is_err()
         {
             tracing::error!("Failed to send subscription request");
         }
     }
 
-    async fn distribute(&self, uuid: Uuid, message: MessageToSend) {
+    fn unsubscribe(&self, spki_hash: SpkiHash, id: Uuid) {
+        // fails only if the store is already closed
+        let _ = self
+            .sender
+            .send(SubscriptionRequest::Unsubscribe(spki_hash, id));
+    }
+
+    fn distribute(&self, uuid: Uuid, message: MessageToSend) {
         if self
             .sender
             .send(SubscriptionRequest::Distribute(uuid, message))
-            .await
             .is_err()
         {
             tracing::error!("Failed to send message to subscription manager");
         }
     }
 }
+
+/// Whether the receivers can't continue their groups without the message.
+fn is_handshake(message: &MessageToMemberTransport) -> bool {
+    matches!(message, MessageToMemberTransport::Welcome(_)) || message.is_commit()
+}
+
+/// Flags the messages which were stored before commits and welcomes were
+/// told apart. They are marked as unchecked by a migration, which keeps them
+/// from the retention limits until they are decoded here.
+async fn check_handshakes(pool: &sqlx::SqlitePool) -> Result<(), sqlx::Error> {
+    let records = sqlx::query!(
+        r#"SELECT id as "id: uuid::Uuid", data FROM mls_messages WHERE handshake = 2"#
+    )
+    .fetch_all(pool)
+    .await?;
+    if records.is_empty() {
+        return Ok(());
+    }
+
+    let mut tx = pool.begin().await?;
+    for record in records {
+        let handshake = match postcard::from_bytes::<MessageToMemberTransport>(&record.data) {
+            Ok(message) => is_handshake(&message),
+            // it can't be delivered anyway
+            Err(err) => {
+                tracing::warn!("failed to decode stored message {}: {err}", record.id);
+                false
+            }
+        };
+        // commits and welcomes never expire
+        sqlx::query!(
+            "UPDATE mls_messages SET handshake = ?, expires_at = CASE WHEN ? THEN NULL ELSE expires_at END WHERE id = ?",
+            handshake,
+            handshake,
+            record.id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use svalin_pki::{RenewalRequest, SpkiHash};
+
+/// Pending certificate renewal requests, waiting to be approved by a user.
+/// There is at most one request per certificate.
+#[derive(Debug)]
+pub struct RenewalStore {
+    pool: sqlx::SqlitePool,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RenewalStoreError {
+    #[error("SQLx error: {0}")]
+    SqlxError(#[from] sqlx::Error),
+    #[error("Postcard error: {0}")]
+    PostcardError(#[from] postcard::Error),
+}
+
+impl RenewalStore {
+    pub fn open(pool: sqlx::SqlitePool) -> Arc<Self> {
+        Arc::new(Self { pool })
+    }
+
+    /// Adds the request, replacing an older request for the same certificate.
+    pub async fn add_request(&self, request: &RenewalRequest) -> Result<(), RenewalStoreError> {
+        let spki_hash = request.certificate().spki_hash().as_slice();
+        let data = postcard::to_stdvec(request)?;
+        let requested_at = request.time() as i64;
+
+        sqlx::query!(
+            "INSERT OR REPLACE INTO renewal_requests (spki_hash, request, requested_at) VALUES (?, ?, ?)",
+            spki_hash,
+            data,
+            requested_at
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn list_requests(&self) -> Result<Vec<RenewalRequest>, RenewalStoreError> {
+        let requests =
+            sqlx::query_scalar!("SELECT request FROM renewal_requests ORDER BY requested_at ASC")
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(requests
+            .into_iter()
+            .map(|data| postcard::from_bytes(&data))
+            .collect::<Result<_, _>>()?)
+    }
+
+    /// Removes the request for the certificate with the given spki hash.
+    /// Returns whether there was one.
+    pub async fn remove_request(&self, spki_hash: &SpkiHash) -> Result<bool, sqlx::Error> {
+        let spki_hash = spki_hash.as_slice();
+        let result = sqlx::query!(
+            "DELETE FROM renewal_requests WHERE spki_hash = ?",
+            spki_hash
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
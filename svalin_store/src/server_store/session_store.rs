@@ -46,6 +46,31 @@ impl SessionStore {
         Ok(())
     }
 
+    /// Replaces the certificate of an existing session by its renewal.
+    /// Returns whether a session of the same issuer was found.
+    pub async fn renew_session(&self, certificate: Certificate) -> Result<bool, AddSessionError> {
+        if certificate.certificate_type() != CertificateType::UserSession {
+            return Err(AddSessionError::InvalidCertificateType(
+                certificate.certificate_type(),
+            ));
+        }
+
+        let spki_hash = certificate.spki_hash().as_slice();
+        let issuer = certificate.issuer().as_slice();
+        let der = certificate.as_der();
+
+        let result = sqlx::query!(
+            "UPDATE sessions SET certificate = ? WHERE spki_hash = ? AND issuer = ?",
+            der,
+            spki_hash,
+            issuer
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
     pub async fn get_session(
         &self,
         spki_hash: &SpkiHash,
@@ -85,4 +110,36 @@ impl SessionStore {
 
         Ok(sessions)
     }
+
+    /// Lists all sessions as pairs of session and issuer spki hash.
+    pub async fn list_sessions(&self) -> anyhow::Result<Vec<(SpkiHash, SpkiHash)>> {
+        let sessions = sqlx::query!(
+            r#"SELECT spki_hash as "spki_hash!", issuer FROM sessions ORDER BY issuer"#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        sessions
+            .into_iter()
+            .map(|session| {
+                let spki_hash = SpkiHash::from_slice(&session.spki_hash)
+                    .map_err(|_| anyhow::anyhow!("invalid session spki hash in database"))?;
+                let issuer = SpkiHash::from_slice(&session.issuer)
+                    .map_err(|_| anyhow::anyhow!("invalid issuer spki hash in database"))?;
+                Ok((spki_hash, issuer))
+            })
+            .collect()
+    }
+
+    /// Deletes all sessions whose issuing user no longer exists.
+    /// Returns the number of deleted sessions.
+    pub async fn prune_orphans(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM sessions WHERE issuer NOT IN ( SELECT spki_hash FROM users )"
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
 }
@@ -7,11 +7,16 @@ use std::{
 };
 
 use svalin_pki::{
-    secure_chain::{CheckedBlock, UncheckedBlock},
+    secure_chain::{CheckedBlock, Checkpoint, UncheckedBlock, merkle::MerkleIndex},
     trust_store,
 };
 use tokio::sync::broadcast;
 
+use crate::{
+    decode::{decode_block, decode_checkpoint, decode_exported},
+    merkle_leaves::{add_leaf, add_missing_leaves, load_index},
+};
+
 #[derive(Debug)]
 pub struct TrustStoreTransactionStore {
     pool: sqlx::SqlitePool,
@@ -42,6 +47,16 @@ impl TrustStoreTransactionStore {
         .await?
         .unwrap_or(1);
 
+        // the transactions up to the latest checkpoint might have been pruned
+        let checkpoint_sequence =
+            sqlx::query_scalar!("SELECT MAX(sequence) FROM trust_store_checkpoints")
+                .fetch_one(&pool)
+                .await?
+                .unwrap_or(0);
+        let current_sequence = current_sequence.max(checkpoint_sequence);
+
+        add_missing_leaves(&pool).await?;
+
         Ok(Arc::new(Self {
             pool,
             current_sequence: AtomicU64::new(current_sequence as u64),
@@ -60,13 +75,16 @@ impl TrustStoreTransactionStore {
             });
         }
         let data = postcard::to_stdvec(&transaction.as_unchecked())?;
+        let mut db_transaction = self.pool.begin().await?;
         sqlx::query!(
             "INSERT INTO trust_store_transactions (sequence, data) VALUES (?, ?)",
             transaction.sequence() as i64,
             &data
         )
-        .execute(&self.pool)
+        .execute(&mut *db_transaction)
         .await?;
+        add_leaf(&mut *db_transaction, transaction.as_unchecked()).await?;
+        db_transaction.commit().await?;
         self.current_sequence
             .store(transaction.sequence(), Ordering::Relaxed);
         self.broadcast
@@ -96,8 +114,7 @@ impl TrustStoreTransactionStore {
         let mut transactions = transactions
             .into_iter()
             .map(|record| {
-                let block: UncheckedBlock<trust_store::Transaction> =
-                    postcard::from_bytes(&record.data)?;
+                let block: UncheckedBlock<trust_store::Transaction> = decode_block(&record.data)?;
                 Ok(block)
             })
             .collect::<Result<Vec<_>, postcard::Error>>()?;
@@ -113,6 +130,181 @@ impl TrustStoreTransactionStore {
     }
 }
 
+impl TrustStoreTransactionStore {
+    /// Stores a checkpoint together with the exported trust store it was
+    /// created from.
+    pub async fn add_checkpoint(
+        &self,
+        checkpoint: &Checkpoint,
+        state: &trust_store::Exported,
+    ) -> Result<(), TransactionStoreError> {
+        let sequence = checkpoint.sequence() as i64;
+        let checkpoint = postcard::to_stdvec(checkpoint)?;
+        let state = postcard::to_stdvec(state)?;
+
+        sqlx::query!(
+            "INSERT OR REPLACE INTO trust_store_checkpoints (sequence, checkpoint, state) VALUES (?, ?, ?)",
+            sequence,
+            checkpoint,
+            state
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn latest_checkpoint(
+        &self,
+    ) -> Result<Option<(Checkpoint, trust_store::Exported)>, LoadTransactionError> {
+        let record = sqlx::query!(
+            "SELECT checkpoint, state FROM trust_store_checkpoints ORDER BY sequence DESC LIMIT 1"
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(record) = record else {
+            return Ok(None);
+        };
+
+        Ok(Some((
+            decode_checkpoint(&record.checkpoint)?,
+            decode_exported(&record.state)?,
+        )))
+    }
+
+    /// The oldest stored checkpoint, from which the remaining transactions can
+    /// be replayed.
+    pub async fn oldest_checkpoint(
+        &self,
+    ) -> Result<Option<(Checkpoint, trust_store::Exported)>, LoadTransactionError> {
+        let record = sqlx::query!(
+            "SELECT checkpoint, state FROM trust_store_checkpoints ORDER BY sequence ASC LIMIT 1"
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(record) = record else {
+            return Ok(None);
+        };
+
+        Ok(Some((
+            decode_checkpoint(&record.checkpoint)?,
+            decode_exported(&record.state)?,
+        )))
+    }
+
+    /// Returns the latest checkpoint if the transactions following `after`
+    /// were already pruned, so a member has to continue from the checkpoint.
+    pub async fn checkpoint_for(
+        &self,
+        after: u64,
+    ) -> Result<Option<(Checkpoint, trust_store::Exported)>, LoadTransactionError> {
+        let first_stored =
+            sqlx::query_scalar!("SELECT MIN(sequence) FROM trust_store_transactions")
+                .fetch_one(&self.pool)
+                .await?;
+
+        let Some(checkpoint) = self.latest_checkpoint().await? else {
+            return Ok(None);
+        };
+
+        let pruned = match first_stored {
+            Some(first_stored) => after + 1 < first_stored as u64,
+            None => after < checkpoint.0.sequence(),
+        };
+
+        Ok(pruned.then_some(checkpoint))
+    }
+
+    /// Replaces the latest countersigned head of a witness.
+    pub async fn add_witness_signature(
+        &self,
+        signature: &Checkpoint,
+    ) -> Result<(), TransactionStoreError> {
+        let signer = signature.signer().as_slice();
+        let checkpoint = postcard::to_stdvec(signature)?;
+        sqlx::query!(
+            "INSERT OR REPLACE INTO witness_signatures (signer, checkpoint) VALUES (?, ?)",
+            signer,
+            checkpoint
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn witness_signatures(&self) -> Result<Vec<Checkpoint>, LoadTransactionError> {
+        let signatures = sqlx::query_scalar!("SELECT checkpoint FROM witness_signatures")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(signatures
+            .iter()
+            .map(|signature| decode_checkpoint(signature))
+            .collect::<Result<_, _>>()?)
+    }
+
+    /// Keeps the leaf of a block which isn't stored itself, like the last
+    /// block of the trust store snapshot.
+    pub async fn keep_leaf(
+        &self,
+        block: &UncheckedBlock<trust_store::Transaction>,
+    ) -> Result<(), sqlx::Error> {
+        add_leaf(&self.pool, block).await
+    }
+
+    /// The Merkle index of the whole history, `None` if transactions were
+    /// pruned before their leaves were kept.
+    pub async fn merkle_index(&self) -> Result<Option<MerkleIndex>, LoadTransactionError> {
+        load_index(&self.pool, self.current_sequence.load(Ordering::Relaxed)).await
+    }
+
+    /// Deletes the transactions up to the oldest of the `keep_checkpoints`
+    /// most recent checkpoints, together with all older checkpoints. The
+    /// Merkle leaves of the transactions are kept.
+    ///
+    /// Returns the number of deleted transactions.
+    pub async fn prune(&self, keep_checkpoints: u32) -> Result<u64, sqlx::Error> {
+        if keep_checkpoints == 0 {
+            return Ok(0);
+        }
+
+        let offset = keep_checkpoints as i64 - 1;
+        let oldest_kept = sqlx::query_scalar!(
+            "SELECT sequence FROM trust_store_checkpoints ORDER BY sequence DESC LIMIT 1 OFFSET ?",
+            offset
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(oldest_kept) = oldest_kept else {
+            return Ok(0);
+        };
+
+        let mut transaction = self.pool.begin().await?;
+
+        let deleted = sqlx::query!(
+            "DELETE FROM trust_store_transactions WHERE sequence <= ?",
+            oldest_kept
+        )
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+        sqlx::query!(
+            "DELETE FROM trust_store_checkpoints WHERE sequence < ?",
+            oldest_kept
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(deleted)
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum LoadTransactionError {
     #[error("sqlx error: {0}")]
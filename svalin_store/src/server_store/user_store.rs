@@ -40,6 +40,14 @@ pub struct StoredUser {
     pub trust_store_digest: EncryptedObject<ChainDigest>,
 }
 
+/// The MLS state a user uploaded, shared by all sessions of the user.
+pub struct StoredMlsData {
+    pub mls_store: ExportedMlsStore,
+    pub persistent_data: EncryptedObject<persistent::State>,
+    /// Incremented with every accepted update.
+    pub version: u64,
+}
+
 #[derive(Debug)]
 pub struct UserStore {
     pool: sqlx::SqlitePool,
@@ -132,6 +140,24 @@ impl UserStore {
         }
     }
 
+    /// Lists the spki hash and username of every user, ordered by username.
+    pub async fn list_users(&self) -> anyhow::Result<Vec<(SpkiHash, Vec<u8>)>> {
+        let users = sqlx::query!(
+            r#"SELECT spki_hash, username as "username: Vec<u8>" FROM users ORDER BY username"#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        users
+            .into_iter()
+            .map(|user| {
+                let spki_hash = SpkiHash::from_slice(&user.spki_hash)
+                    .map_err(|_| anyhow!("invalid spki hash in database"))?;
+                Ok((spki_hash, user.username))
+            })
+            .collect()
+    }
+
     pub async fn get_cert_by_spki_hash(
         &self,
         spki_hash: &SpkiHash,
@@ -150,35 +176,99 @@ impl UserStore {
         }
     }
 
+    pub async fn get_mls_data(
+        &self,
+        spki_hash: &SpkiHash,
+    ) -> anyhow::Result<Option<StoredMlsData>> {
+        let spki = spki_hash.as_slice();
+        let row = sqlx::query!(
+            "SELECT data, mls_version FROM users WHERE spki_hash = ?",
+            spki
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match row {
+            None => Ok(None),
+            Some(row) => {
+                let user: StoredUser = postcard::from_bytes(&row.data)?;
+                Ok(Some(StoredMlsData {
+                    mls_store: user.mls_store,
+                    persistent_data: user.persistent_data,
+                    version: row.mls_version as u64,
+                }))
+            }
+        }
+    }
+
+    /// Replaces the MLS state of a user, if it is still at `expected_version`.
+    ///
+    /// Returns the new version. If another session updated the state in the
+    /// meantime, [`UpdateMlsDataError::VersionConflict`] is returned and
+    /// nothing is changed.
     pub async fn update_mls_data(
         &self,
         spki_hash: &SpkiHash,
+        expected_version: u64,
         mls_store: ExportedMlsStore,
         persistent_data: EncryptedObject<persistent::State>,
-    ) -> anyhow::Result<(), UpdateMlsDataError> {
+    ) -> anyhow::Result<u64, UpdateMlsDataError> {
         let mut tx = self.pool.begin().await?;
 
         let spki = spki_hash.as_slice();
-        let user_data = sqlx::query!("SELECT data FROM users WHERE spki_hash = ?", spki)
-            .fetch_optional(&mut *tx)
-            .await?;
+        let row = sqlx::query!(
+            "SELECT data, mls_version FROM users WHERE spki_hash = ?",
+            spki
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
 
-        let mut user: StoredUser = match user_data {
-            None => return Err(UpdateMlsDataError::UnknownUser(spki_hash.clone())),
-            Some(user_data) => postcard::from_bytes(&user_data.data)?,
+        let Some(row) = row else {
+            return Err(UpdateMlsDataError::UnknownUser(spki_hash.clone()));
         };
 
+        let current = row.mls_version as u64;
+        if current != expected_version {
+            return Err(UpdateMlsDataError::VersionConflict {
+                expected: expected_version,
+                current,
+            });
+        }
+
+        let mut user: StoredUser = postcard::from_bytes(&row.data)?;
         user.mls_store = mls_store;
         user.persistent_data = persistent_data;
 
         let data = postcard::to_stdvec(&user)?;
-        sqlx::query!("UPDATE users SET data = ? WHERE spki_hash = ?", data, spki)
-            .execute(&mut *tx)
-            .await?;
+        let version = current + 1;
+        let (new_version, old_version) = (version as i64, current as i64);
+        // the version check is repeated, in case another connection
+        // committed in between
+        let result = sqlx::query!(
+            "UPDATE users SET data = ?, mls_version = ? WHERE spki_hash = ? AND mls_version = ?",
+            data,
+            new_version,
+            spki,
+            old_version
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            tx.rollback().await?;
+            let current =
+                sqlx::query_scalar!("SELECT mls_version FROM users WHERE spki_hash = ?", spki)
+                    .fetch_one(&self.pool)
+                    .await?;
+            return Err(UpdateMlsDataError::VersionConflict {
+                expected: expected_version,
+                current: current as u64,
+            });
+        }
 
         tx.commit().await?;
 
-        Ok(())
+        Ok(version)
     }
 }
 
@@ -190,6 +280,8 @@ pub enum UpdateMlsDataError {
     UnknownUser(SpkiHash),
     #[error("postcard decode error: {0}")]
     PostcardError(#[from] postcard::Error),
+    #[error("mls data was updated concurrently, expected version {expected} but found {current}")]
+    VersionConflict { expected: u64, current: u64 },
 }
 
 #[derive(Debug, thiserror::Error)]
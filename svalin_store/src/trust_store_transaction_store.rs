@@ -1,9 +1,14 @@
 use svalin_pki::{
-    secure_chain::{CheckedBlock, UncheckedBlock},
+    secure_chain::{CheckedBlock, Checkpoint, UncheckedBlock, merkle::MerkleIndex},
     trust_store,
 };
 use tokio::sync::Mutex;
 
+use crate::{
+    decode::{decode_block, decode_checkpoint, decode_exported},
+    merkle_leaves::{add_history, add_leaf, add_missing_leaves, load_index},
+};
+
 #[derive(Debug)]
 pub struct TrustStoreTransactionStore {
     pool: sqlx::SqlitePool,
@@ -31,6 +36,16 @@ impl TrustStoreTransactionStore {
         .await?
         .unwrap_or(1);
 
+        // the transactions up to the latest checkpoint might have been pruned
+        let checkpoint_sequence =
+            sqlx::query_scalar!("SELECT MAX(sequence) FROM trust_store_checkpoints")
+                .fetch_one(&pool)
+                .await?
+                .unwrap_or(0);
+        let current_sequence = current_sequence.max(checkpoint_sequence);
+
+        add_missing_leaves(&pool).await?;
+
         Ok(Self {
             pool,
             current_sequence: Mutex::new(current_sequence as u64),
@@ -52,23 +67,42 @@ impl TrustStoreTransactionStore {
             )
             .fetch_one(&self.pool)
             .await?;
-            let block: UncheckedBlock<trust_store::Transaction> = postcard::from_bytes(&data)?;
+            let block: UncheckedBlock<trust_store::Transaction> = decode_block(&data)?;
             if &block == transaction.as_unchecked() {
                 return Ok(());
             }
         }
         let data = postcard::to_stdvec(&transaction.as_unchecked())?;
+        let mut db_transaction = self.pool.begin().await?;
         sqlx::query!(
             "INSERT INTO trust_store_transactions (sequence, data) VALUES (?, ?)",
             transaction.sequence() as i64,
             &data
         )
-        .execute(&self.pool)
+        .execute(&mut *db_transaction)
         .await?;
+        add_leaf(&mut *db_transaction, transaction.as_unchecked()).await?;
+        db_transaction.commit().await?;
         *current_sequence = transaction.sequence();
         Ok(())
     }
 
+    /// Keeps the leaf of a block which isn't stored itself, like the last
+    /// block of the trust store snapshot.
+    pub async fn keep_leaf(
+        &self,
+        block: &UncheckedBlock<trust_store::Transaction>,
+    ) -> Result<(), sqlx::Error> {
+        add_leaf(&self.pool, block).await
+    }
+
+    /// The Merkle index of the stored history, `None` if blocks were pruned
+    /// before their leaves were kept.
+    pub async fn merkle_index(&self) -> Result<Option<MerkleIndex>, LoadTransactionError> {
+        let sequence = *self.current_sequence.lock().await;
+        load_index(&self.pool, sequence).await
+    }
+
     pub async fn load_all_after(
         &self,
         after: u64,
@@ -83,14 +117,134 @@ impl TrustStoreTransactionStore {
         let transactions = transactions
             .into_iter()
             .map(|record| {
-                let block: UncheckedBlock<trust_store::Transaction> =
-                    postcard::from_bytes(&record.data)?;
+                let block: UncheckedBlock<trust_store::Transaction> = decode_block(&record.data)?;
                 Ok(block)
             })
             .collect::<Result<Vec<_>, postcard::Error>>()?;
 
         Ok(transactions)
     }
+
+    /// Loads the block at the given sequence, if it wasn't pruned.
+    pub async fn load(
+        &self,
+        sequence: u64,
+    ) -> Result<Option<UncheckedBlock<trust_store::Transaction>>, LoadTransactionError> {
+        let data = sqlx::query_scalar!(
+            "SELECT data FROM trust_store_transactions WHERE sequence = ?",
+            sequence as i64
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(data.map(|data| decode_block(&data)).transpose()?)
+    }
+
+    /// Replaces the local history with a checkpoint received from the server,
+    /// together with the Merkle leaves of its history.
+    ///
+    /// All transactions up to the checkpoint and all older checkpoints are
+    /// deleted.
+    pub async fn add_checkpoint(
+        &self,
+        checkpoint: &Checkpoint,
+        state: &trust_store::Exported,
+        history: Option<&MerkleIndex>,
+    ) -> Result<(), TransactionStoreError> {
+        let mut current_sequence = self.current_sequence.lock().await;
+
+        let sequence = checkpoint.sequence() as i64;
+        let checkpoint = postcard::to_stdvec(checkpoint)?;
+        let state = postcard::to_stdvec(state)?;
+
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query!(
+            "INSERT OR REPLACE INTO trust_store_checkpoints (sequence, checkpoint, state) VALUES (?, ?, ?)",
+            sequence,
+            checkpoint,
+            state
+        )
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM trust_store_checkpoints WHERE sequence < ?",
+            sequence
+        )
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM trust_store_transactions WHERE sequence <= ?",
+            sequence
+        )
+        .execute(&mut *transaction)
+        .await?;
+        if let Some(history) = history {
+            add_history(&mut *transaction, history).await?;
+        }
+        transaction.commit().await?;
+
+        *current_sequence = (*current_sequence).max(sequence as u64);
+
+        Ok(())
+    }
+
+    pub async fn latest_checkpoint(
+        &self,
+    ) -> Result<Option<(Checkpoint, trust_store::Exported)>, LoadTransactionError> {
+        let record = sqlx::query!(
+            "SELECT checkpoint, state FROM trust_store_checkpoints ORDER BY sequence DESC LIMIT 1"
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(record) = record else {
+            return Ok(None);
+        };
+
+        Ok(Some((
+            decode_checkpoint(&record.checkpoint)?,
+            decode_exported(&record.state)?,
+        )))
+    }
+
+    /// Keeps a position of another member which conflicts with the local
+    /// trust store, the first report of each signer is kept.
+    pub async fn add_conflict_report(
+        &self,
+        position: &Checkpoint,
+    ) -> Result<(), TransactionStoreError> {
+        let signer = position.signer().as_slice();
+        let checkpoint = postcard::to_stdvec(position)?;
+        sqlx::query!(
+            "INSERT OR IGNORE INTO trust_store_conflicts (signer, checkpoint) VALUES (?, ?)",
+            signer,
+            checkpoint
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn conflict_reports(&self) -> Result<Vec<Checkpoint>, LoadTransactionError> {
+        let reports = sqlx::query_scalar!("SELECT checkpoint FROM trust_store_conflicts")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(reports
+            .iter()
+            .map(|report| decode_checkpoint(report))
+            .collect::<Result<_, _>>()?)
+    }
+
+    /// Forgets all reported conflicts, once an admin resolved the split view.
+    pub async fn reset_conflict_reports(&self) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM trust_store_conflicts")
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
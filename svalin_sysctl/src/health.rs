@@ -0,0 +1,82 @@
+use std::{collections::HashSet, ffi::OsStr};
+
+use sysinfo::{CpuRefreshKind, Disks, ProcessRefreshKind, ProcessesToUpdate, RefreshKind, System};
+
+/// The values the alert rules of the agent are checked against.
+#[derive(Clone, Debug)]
+pub struct HealthStatus {
+    pub disks: Vec<DiskUsage>,
+    /// Load average of the last five minutes, always 0 on Windows.
+    pub load_average: f64,
+    pub threads: usize,
+    /// The requested processes which are currently running.
+    pub running: HashSet<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DiskUsage {
+    pub mount_point: String,
+    /// The type of the file system, e.g. `ext4`.
+    pub file_system: String,
+    pub total_space: u64,
+    pub available_space: u64,
+    pub read_only: bool,
+}
+
+/// Images which are always full and file systems which only live in memory.
+const PSEUDO_FILE_SYSTEMS: &[&str] = &["squashfs", "iso9660", "udf", "tmpfs", "devtmpfs", "ramfs"];
+
+impl DiskUsage {
+    /// Whether running out of space on the disk affects the system. Read-only
+    /// mounts always report no available space.
+    pub fn can_fill_up(&self) -> bool {
+        !self.read_only && !PSEUDO_FILE_SYSTEMS.contains(&self.file_system.as_str())
+    }
+}
+
+impl HealthStatus {
+    pub async fn collect(processes: Vec<String>) -> anyhow::Result<Self> {
+        Ok(tokio::task::spawn_blocking(move || Self::collect_inner(&processes)).await?)
+    }
+
+    pub fn collect_inner(processes: &[String]) -> Self {
+        let mut sys =
+            System::new_with_specifics(RefreshKind::nothing().with_cpu(CpuRefreshKind::nothing()));
+        if !processes.is_empty() {
+            sys.refresh_processes_specifics(
+                ProcessesToUpdate::All,
+                true,
+                ProcessRefreshKind::nothing(),
+            );
+        }
+
+        let running = processes
+            .iter()
+            .filter(|name| {
+                sys.processes_by_exact_name(OsStr::new(name.as_str()))
+                    .next()
+                    .is_some()
+            })
+            .cloned()
+            .collect();
+
+        let disks = Disks::new_with_refreshed_list()
+            .iter()
+            .filter(|disk| disk.total_space() > 0)
+            .map(|disk| DiskUsage {
+                mount_point: disk.mount_point().to_string_lossy().to_string(),
+                file_system: disk.file_system().to_string_lossy().to_string(),
+                total_space: disk.total_space(),
+                available_space: disk.available_space(),
+                read_only: disk.is_read_only(),
+            })
+            .collect();
+
+        Self {
+            disks,
+            load_average: System::load_average().five,
+            threads: sys.cpus().len(),
+            running,
+        }
+    }
+}
@@ -1,3 +1,4 @@
 #![forbid(unsafe_code)]
+pub mod health;
 pub mod realtime;
 pub mod sytem_report;